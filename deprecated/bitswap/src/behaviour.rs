@@ -6,8 +6,8 @@
 //! The `Bitswap` struct implements the `NetworkBehaviour` trait. When used, it
 //! will allow providing and reciving IPFS blocks.
 use crate::block::Block;
-use crate::ledger::{Ledger, Message, Priority};
-use crate::protocol::{BitswapConfig, MessageWrapper};
+use crate::ledger::{want_entry_len, want_list_message_len, Ledger, Message, Priority};
+use crate::protocol::{BitswapConfig, MessageWrapper, MAX_BUF_SIZE};
 use fnv::FnvHashSet;
 use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
 use hash_hasher::HashedMap;
@@ -27,7 +27,7 @@ use std::{
     mem,
     sync::{
         atomic::{AtomicU64, Ordering},
-        Arc,
+        Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard,
     },
 };
 
@@ -65,6 +65,28 @@ impl Stats {
         self.duplicate_data.fetch_add(bytes, Ordering::Relaxed);
     }
 
+    /// Returns a copy of the counters as they are now. The counters are read one at a time, see
+    /// [`PeerStats::snapshot`] for a copy taken in between the updates.
+    pub fn snapshot(&self) -> Stats {
+        let stats = Stats::default();
+        stats.add_assign(self);
+        stats
+    }
+
+    /// Sets all the counters back to zero.
+    pub fn reset(&self) {
+        for counter in [
+            &self.sent_blocks,
+            &self.sent_data,
+            &self.received_blocks,
+            &self.received_data,
+            &self.duplicate_blocks,
+            &self.duplicate_data,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+
     pub fn add_assign(&self, other: &Stats) {
         self.sent_blocks
             .fetch_add(other.sent_blocks.load(Ordering::Relaxed), Ordering::Relaxed);
@@ -89,6 +111,68 @@ impl Stats {
     }
 }
 
+/// Bitswap statistics of a single peer.
+///
+/// The counters updated together, such as the number of blocks and the bytes received, are only
+/// seen together by [`PeerStats::snapshot`] and [`PeerStats::reset`], so that a snapshot taken
+/// while the stats are being updated never has one of them without the other. The peers of a
+/// [`Bitswap`] share a single lock, under which [`Bitswap::stats`] reads all of them at once.
+#[derive(Debug, Default)]
+pub struct PeerStats {
+    stats: Stats,
+    /// Held shared by the updates, which can still run concurrently with each other, and
+    /// exclusively by the snapshots and resets.
+    consistency: Arc<RwLock<()>>,
+}
+
+impl PeerStats {
+    fn with_lock(consistency: Arc<RwLock<()>>) -> Self {
+        PeerStats {
+            stats: Stats::default(),
+            consistency,
+        }
+    }
+
+    fn shared(&self) -> RwLockReadGuard<'_, ()> {
+        self.consistency
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn exclusive(&self) -> RwLockWriteGuard<'_, ()> {
+        self.consistency
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn update_outgoing(&self, num_blocks: u64) {
+        let _g = self.shared();
+        self.stats.update_outgoing(num_blocks);
+    }
+
+    pub fn update_incoming_unique(&self, bytes: u64) {
+        let _g = self.shared();
+        self.stats.update_incoming_unique(bytes);
+    }
+
+    pub fn update_incoming_duplicate(&self, bytes: u64) {
+        let _g = self.shared();
+        self.stats.update_incoming_duplicate(bytes);
+    }
+
+    /// Returns a copy of the counters as they are now, not in the middle of any update.
+    pub fn snapshot(&self) -> Stats {
+        let _g = self.exclusive();
+        self.stats.snapshot()
+    }
+
+    /// Sets all the counters back to zero, in between the updates.
+    pub fn reset(&self) {
+        let _g = self.exclusive();
+        self.stats.reset();
+    }
+}
+
 /// Network behaviour that handles sending and receiving IPFS blocks.
 pub struct Bitswap {
     /// Queue of events to report to the user.
@@ -106,7 +190,11 @@ pub struct Bitswap {
     pub dont_have_tx: UnboundedSender<(PeerId, Cid)>,
     dont_have_rx: UnboundedReceiver<(PeerId, Cid)>,
     /// Statistics related to peers.
-    pub stats: HashMap<PeerId, Arc<Stats>>,
+    pub stats: HashMap<PeerId, Arc<PeerStats>>,
+    /// The lock shared by the statistics of all the peers.
+    stats_consistency: Arc<RwLock<()>>,
+    /// Upper bound for the encoded size of the wantlist messages sent to a peer.
+    max_message_size: usize,
 }
 
 impl Default for Bitswap {
@@ -124,6 +212,8 @@ impl Default for Bitswap {
             dont_have_tx: dtx,
             ready_blocks: rx,
             stats: Default::default(),
+            stats_consistency: Default::default(),
+            max_message_size: MAX_BUF_SIZE,
         }
     }
 }
@@ -143,18 +233,53 @@ impl Bitswap {
     }
 
     pub fn stats(&self) -> Stats {
+        let _g = self
+            .stats_consistency
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
         self.stats
             .values()
             .fold(Stats::default(), |acc, peer_stats| {
-                acc.add_assign(peer_stats);
+                acc.add_assign(&peer_stats.stats);
                 acc
             })
     }
 
+    /// Returns a snapshot of the statistics of a single peer, if any were recorded.
+    pub fn peer_stats(&self, peer: &PeerId) -> Option<Stats> {
+        self.stats.get(peer).map(|stats| stats.snapshot())
+    }
+
+    /// Zeroes the statistics of all peers, e.g. to sample the throughput periodically.
+    pub fn reset_stats(&self) {
+        let _g = self
+            .stats_consistency
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        for peer_stats in self.stats.values() {
+            peer_stats.stats.reset();
+        }
+    }
+
+    /// Returns the statistics of a peer, sharing the lock of the other peers when new.
+    fn peer_stats_entry(&mut self, peer: PeerId) -> &Arc<PeerStats> {
+        let consistency = &self.stats_consistency;
+        self.stats
+            .entry(peer)
+            .or_insert_with(|| Arc::new(PeerStats::with_lock(Arc::clone(consistency))))
+    }
+
     pub fn peers(&self) -> Vec<PeerId> {
         self.connected_peers.keys().cloned().collect()
     }
 
+    /// Sets the upper bound for the encoded size of a single wantlist message, defaulting to the
+    /// largest message the protocol accepts. Larger wantlists are split over several messages,
+    /// though an entry too large for the bound on its own is still sent in a message of its own.
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.max_message_size = size;
+    }
+
     /// Connect to peer.
     ///
     /// Called from Kademlia behaviour.
@@ -181,19 +306,32 @@ impl Bitswap {
     /// Sends the wantlist to the peer.
     fn send_want_list(&mut self, peer_id: PeerId) {
         if !self.wanted_blocks.is_empty() {
-            // FIXME: this can produce too long a message
             // FIXME: we should shard these across all of our peers by some logic; also, peers may
             // have been discovered to provide some specific wantlist item
+            let mut messages = vec![];
             let mut message = Message::default();
+            let mut entries_len = 0;
             for (cid, priority) in &self.wanted_blocks {
+                let entry_len = want_entry_len(cid, *priority);
+                if !message.is_empty()
+                    && want_list_message_len(entries_len + entry_len) > self.max_message_size
+                {
+                    messages.push(mem::take(&mut message));
+                    entries_len = 0;
+                }
                 message.want_block(cid, *priority);
+                entries_len += entry_len;
+            }
+            messages.push(message);
+
+            for message in messages {
+                self.events
+                    .push_back(NetworkBehaviourAction::NotifyHandler {
+                        peer_id,
+                        event: message,
+                        handler: NotifyHandler::Any,
+                    });
             }
-            self.events
-                .push_back(NetworkBehaviourAction::NotifyHandler {
-                    peer_id,
-                    event: message,
-                    handler: NotifyHandler::Any,
-                });
         }
     }
 
@@ -219,19 +357,18 @@ impl Bitswap {
         self.wanted_blocks.insert(cid, priority);
     }
 
-    /// Remove wanted blocks from ledger that we dont have
+    /// Removes a block we dont have from the ledgers of the peers wanting it, telling the peers
+    /// which asked for a DontHave
     pub fn dont_have(&mut self, cid: Cid) {
         for (_peer_id, ledger) in self.connected_peers.iter_mut() {
-            ledger.received_want_list.remove(&cid);
-            //TODO: Implement dont have in ledger (for 1.2.0 spec), if we are to continue using this
+            ledger.dont_have(&cid);
         }
     }
 
     pub fn dont_have_for_peer(&mut self, peer_id: PeerId, cid: Cid) {
         if let Some(ledger) = self.connected_peers.get_mut(&peer_id) {
-            ledger.received_want_list.remove(&cid);
+            ledger.dont_have(&cid);
             ledger.received_want_list.shrink_to_fit();
-            //TODO: Implement dont have in ledger (for 1.2.0 spec), if we are to continue using this
         }
     }
 
@@ -262,7 +399,7 @@ impl NetworkBehaviour for Bitswap {
                 debug!("bitswap: inject_connected {}", peer_id);
                 self.target_peers.remove(&peer_id);
                 let ledger = Ledger::new();
-                self.stats.entry(peer_id).or_default();
+                self.peer_stats_entry(peer_id);
                 self.connected_peers.insert(peer_id, ledger);
                 self.send_want_list(peer_id);
             }
@@ -330,6 +467,7 @@ impl NetworkBehaviour for Bitswap {
         // Process the incoming cancel list.
         for cid in message.cancel() {
             ledger.received_want_list.remove(cid);
+            ledger.received_dont_have_wants.remove(cid);
 
             let event = BitswapEvent::ReceivedCancel(source, *cid);
             self.events
@@ -343,6 +481,11 @@ impl NetworkBehaviour for Bitswap {
             .filter(|&(cid, _)| !current_wantlist.iter().map(|(c, _)| c).any(|c| c == cid))
         {
             ledger.received_want_list.insert(cid.to_owned(), *priority);
+            if message.send_dont_have().contains(cid) {
+                ledger.received_dont_have_wants.insert(cid.to_owned());
+            } else {
+                ledger.received_dont_have_wants.remove(cid);
+            }
 
             let event = BitswapEvent::ReceivedWant(source, *cid, *priority);
             self.events
@@ -395,3 +538,203 @@ impl NetworkBehaviour for Bitswap {
         Poll::Pending
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libipld::multihash::{Code, MultihashDigest};
+
+    #[test]
+    fn large_wantlist_is_split_under_the_message_size() {
+        const MAX_MESSAGE_SIZE: usize = 4096;
+        const WANTED: u32 = 5000;
+
+        let mut bitswap = Bitswap::default();
+        bitswap.set_max_message_size(MAX_MESSAGE_SIZE);
+        for i in 0..WANTED {
+            let cid = Cid::new_v1(0x55, Code::Sha2_256.digest(&i.to_le_bytes()));
+            bitswap.want_block(cid, 1);
+        }
+
+        let peer_id = PeerId::random();
+        bitswap.send_want_list(peer_id);
+
+        let mut messages = 0;
+        let mut wanted = FnvHashSet::default();
+        for event in bitswap.events.drain(..) {
+            match event {
+                NetworkBehaviourAction::NotifyHandler {
+                    peer_id: target,
+                    event: message,
+                    ..
+                } => {
+                    assert_eq!(target, peer_id);
+                    assert!(message.to_bytes().len() <= MAX_MESSAGE_SIZE);
+                    wanted.extend(message.want().keys().copied());
+                    messages += 1;
+                }
+                other => panic!("unexpected event: {other:?}"),
+            }
+        }
+
+        assert!(messages > 1);
+        assert_eq!(wanted.len(), WANTED as usize);
+        assert_eq!(
+            wanted,
+            bitswap
+                .wanted_blocks
+                .keys()
+                .copied()
+                .collect::<FnvHashSet<_>>()
+        );
+    }
+
+    #[test]
+    fn stats_are_reset_and_queried_per_peer() {
+        let mut bitswap = Bitswap::default();
+        let first = PeerId::random();
+        let second = PeerId::random();
+
+        bitswap.peer_stats_entry(first).update_outgoing(3);
+        bitswap.peer_stats_entry(second).update_incoming_unique(100);
+
+        let snapshot = bitswap.peer_stats(&first).unwrap();
+        assert_eq!(snapshot.sent_blocks.load(Ordering::Relaxed), 3);
+        assert_eq!(snapshot.received_blocks.load(Ordering::Relaxed), 0);
+
+        let stats = bitswap.peer_stats(&second).unwrap();
+        assert_eq!(stats.sent_blocks.load(Ordering::Relaxed), 0);
+        assert_eq!(stats.received_blocks.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.received_data.load(Ordering::Relaxed), 100);
+
+        assert!(bitswap.peer_stats(&PeerId::random()).is_none());
+
+        // a snapshot does not follow later updates
+        bitswap.stats[&first].update_outgoing(1);
+        assert_eq!(snapshot.sent_blocks.load(Ordering::Relaxed), 3);
+        assert_eq!(
+            bitswap
+                .peer_stats(&first)
+                .unwrap()
+                .sent_blocks
+                .load(Ordering::Relaxed),
+            4
+        );
+
+        bitswap.reset_stats();
+        for peer in [first, second] {
+            let stats = bitswap.peer_stats(&peer).unwrap();
+            for counter in [
+                &stats.sent_blocks,
+                &stats.sent_data,
+                &stats.received_blocks,
+                &stats.received_data,
+                &stats.duplicate_blocks,
+                &stats.duplicate_data,
+            ] {
+                assert_eq!(counter.load(Ordering::Relaxed), 0);
+            }
+        }
+        assert_eq!(bitswap.stats().sent_blocks.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn snapshots_are_consistent_with_concurrent_updates() {
+        use std::sync::atomic::AtomicBool;
+
+        const BLOCK_SIZE: u64 = 10;
+
+        let mut bitswap = Bitswap::default();
+        let peer_stats = [PeerId::random(), PeerId::random()]
+            .map(|peer| Arc::clone(bitswap.peer_stats_entry(peer)));
+        let done = Arc::new(AtomicBool::new(false));
+
+        let updaters = (0..4)
+            .map(|i| {
+                let stats = Arc::clone(&peer_stats[i % 2]);
+                let done = Arc::clone(&done);
+                std::thread::spawn(move || {
+                    while !done.load(Ordering::Relaxed) {
+                        stats.update_incoming_unique(BLOCK_SIZE);
+                        stats.update_incoming_duplicate(BLOCK_SIZE);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for i in 0..1000 {
+            let snapshot = match i % 3 {
+                0 => bitswap.stats(),
+                n => peer_stats[n - 1].snapshot(),
+            };
+            let received_blocks = snapshot.received_blocks.load(Ordering::Relaxed);
+            let duplicate_blocks = snapshot.duplicate_blocks.load(Ordering::Relaxed);
+            assert_eq!(
+                snapshot.received_data.load(Ordering::Relaxed),
+                received_blocks * BLOCK_SIZE
+            );
+            assert_eq!(
+                snapshot.duplicate_data.load(Ordering::Relaxed),
+                duplicate_blocks * BLOCK_SIZE
+            );
+            // the duplicate of a block is counted right after it
+            assert!(duplicate_blocks <= received_blocks);
+
+            if i % 100 == 99 {
+                bitswap.reset_stats();
+            }
+        }
+
+        done.store(true, Ordering::Relaxed);
+        for updater in updaters {
+            updater.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn unavailable_wanted_block_is_answered_with_dont_have() {
+        let mut bitswap = Bitswap::default();
+        let peer_id = PeerId::random();
+        bitswap.connected_peers.insert(peer_id, Ledger::new());
+
+        let cid = Cid::new_v1(0x55, Code::Sha2_256.digest(b"missing"));
+        let mut want = Message::default();
+        want.want_block(&cid, 1);
+        want.want_dont_have(&cid);
+        let want = Message::from_bytes(&want.to_bytes()).unwrap();
+        bitswap.on_connection_handler_event(
+            peer_id,
+            ConnectionId::new_unchecked(0),
+            MessageWrapper::Rx(want),
+        );
+        assert_eq!(bitswap.peer_wantlist(&peer_id).unwrap(), vec![(cid, 1)]);
+
+        bitswap.dont_have_for_peer(peer_id, cid);
+        assert!(bitswap.peer_wantlist(&peer_id).unwrap().is_empty());
+
+        let ledger = bitswap.connected_peers.get_mut(&peer_id).unwrap();
+        let message = ledger.send().expect("the peer is told");
+        let message = Message::from_bytes(&message.to_bytes()).unwrap();
+        assert!(message.dont_have().contains(&cid));
+        assert!(message.want().is_empty());
+
+        // the peer is only told once, and not about blocks it doesn't want
+        bitswap.dont_have_for_peer(peer_id, cid);
+        let ledger = bitswap.connected_peers.get_mut(&peer_id).unwrap();
+        assert!(ledger.send().is_none());
+
+        // nor about blocks it wants without asking to be told
+        let mut want = Message::default();
+        want.want_block(&cid, 1);
+        let want = Message::from_bytes(&want.to_bytes()).unwrap();
+        bitswap.on_connection_handler_event(
+            peer_id,
+            ConnectionId::new_unchecked(0),
+            MessageWrapper::Rx(want),
+        );
+        bitswap.dont_have_for_peer(peer_id, cid);
+        assert!(bitswap.peer_wantlist(&peer_id).unwrap().is_empty());
+        let ledger = bitswap.connected_peers.get_mut(&peer_id).unwrap();
+        assert!(ledger.send().is_none());
+    }
+}
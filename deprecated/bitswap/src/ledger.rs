@@ -17,6 +17,8 @@ pub struct Ledger {
     sent_want_list: HashedMap<Cid, Priority>,
     /// The list of wanted blocks received from the peer.
     pub(crate) received_want_list: HashedMap<Cid, Priority>,
+    /// The received wanted blocks for which the peer asked to be told when we don't have them.
+    pub(crate) received_dont_have_wants: HashedSet<Cid>,
     /// Queued message.
     message: Message,
 }
@@ -39,6 +41,15 @@ impl Ledger {
         self.message.cancel_block(cid);
     }
 
+    /// Removes a block we don't have from the blocks wanted by the peer, telling the peer about
+    /// it when it asked for that, so it stops waiting on us for it.
+    pub fn dont_have(&mut self, cid: &Cid) {
+        let wanted = self.received_want_list.remove(cid).is_some();
+        if self.received_dont_have_wants.remove(cid) && wanted {
+            self.message.dont_have_block(cid);
+        }
+    }
+
     /// Returns the blocks wanted by the peer in unspecified order
     pub fn wantlist(&self) -> Vec<(Cid, Priority)> {
         self.received_want_list
@@ -70,6 +81,10 @@ pub struct Message {
     want: HashedMap<Cid, Priority>,
     /// List of blocks to cancel.
     cancel: HashedSet<Cid>,
+    /// List of wanted blocks which are not available.
+    dont_have: HashedSet<Cid>,
+    /// List of wanted blocks to be answered with a DontHave when not available.
+    send_dont_have: HashedSet<Cid>,
     /// Wheather it is the full list of wanted blocks.
     full: bool,
     /// List of blocks to send.
@@ -79,7 +94,10 @@ pub struct Message {
 impl Message {
     /// Checks whether the queued message is empty.
     pub fn is_empty(&self) -> bool {
-        self.want.is_empty() && self.cancel.is_empty() && self.blocks.is_empty()
+        self.want.is_empty()
+            && self.cancel.is_empty()
+            && self.dont_have.is_empty()
+            && self.blocks.is_empty()
     }
 
     /// Returns the list of blocks.
@@ -97,6 +115,16 @@ impl Message {
         &self.cancel
     }
 
+    /// Returns the list of wanted blocks which are not available.
+    pub fn dont_have(&self) -> &HashedSet<Cid> {
+        &self.dont_have
+    }
+
+    /// Returns the list of wanted blocks to be answered with a DontHave when not available.
+    pub fn send_dont_have(&self) -> &HashedSet<Cid> {
+        &self.send_dont_have
+    }
+
     /// Adds a `Block` to the message.
     pub fn add_block(&mut self, block: Block) {
         self.blocks.push(block);
@@ -112,15 +140,26 @@ impl Message {
         self.want.insert(cid.to_owned(), priority);
     }
 
+    /// Asks for a wanted block to be answered with a DontHave when not available.
+    pub fn want_dont_have(&mut self, cid: &Cid) {
+        self.send_dont_have.insert(cid.to_owned());
+    }
+
     /// Adds a block to the cancel list.
     pub fn cancel_block(&mut self, cid: &Cid) {
         self.cancel.insert(cid.to_owned());
     }
 
+    /// Adds a block to the list of wanted blocks which are not available.
+    pub fn dont_have_block(&mut self, cid: &Cid) {
+        self.dont_have.insert(cid.to_owned());
+    }
+
     /// Removes the block from the want list.
     #[allow(unused)]
     pub fn remove_want_block(&mut self, cid: &Cid) {
         self.want.remove(cid);
+        self.send_dont_have.remove(cid);
     }
 }
 
@@ -132,6 +171,7 @@ impl From<&Message> for Vec<u8> {
             let entry = bitswap_pb::message::wantlist::Entry {
                 block: cid.to_bytes(),
                 priority: *priority,
+                send_dont_have: val.send_dont_have().contains(cid),
                 ..Default::default()
             };
             wantlist.entries.push(entry);
@@ -151,6 +191,13 @@ impl From<&Message> for Vec<u8> {
             };
             proto.payload.push(payload);
         }
+        for cid in val.dont_have() {
+            let presence = bitswap_pb::message::BlockPresence {
+                cid: cid.to_bytes(),
+                r#type: bitswap_pb::message::BlockPresenceType::DontHave as i32,
+            };
+            proto.block_presences.push(presence);
+        }
         if !wantlist.entries.is_empty() {
             proto.wantlist = Some(wantlist);
         }
@@ -162,6 +209,24 @@ impl From<&Message> for Vec<u8> {
     }
 }
 
+/// Returns the number of bytes a want entry for `cid` adds to an encoded message.
+pub(crate) fn want_entry_len(cid: &Cid, priority: Priority) -> usize {
+    let entry = bitswap_pb::message::wantlist::Entry {
+        block: cid.to_bytes(),
+        priority,
+        ..Default::default()
+    };
+    prost::encoding::message::encoded_len(1, &entry)
+}
+
+/// Returns the encoded length of a message holding only want entries, taking `entries_len` bytes
+/// in total.
+pub(crate) fn want_list_message_len(entries_len: usize) -> usize {
+    prost::encoding::key_len(1)
+        + prost::encoding::encoded_len_varint(entries_len as u64)
+        + entries_len
+}
+
 impl Message {
     /// Turns this `Message` into a message that can be sent to a substream.
     pub fn to_bytes(&self) -> Vec<u8> {
@@ -191,6 +256,9 @@ impl TryFrom<&[u8]> for Message {
                 message.cancel_block(&cid);
             } else {
                 message.want_block(&cid, entry.priority);
+                if entry.send_dont_have {
+                    message.want_dont_have(&cid);
+                }
             }
         }
         for payload in proto.payload {
@@ -199,6 +267,12 @@ impl TryFrom<&[u8]> for Message {
             let block = Block::new(cid, payload.data)?;
             message.add_block(block);
         }
+        for presence in proto.block_presences {
+            if presence.r#type == bitswap_pb::message::BlockPresenceType::DontHave as i32 {
+                let cid = Cid::try_from(presence.cid)?;
+                message.dont_have_block(&cid);
+            }
+        }
         Ok(message)
     }
 }
@@ -222,6 +296,14 @@ impl std::fmt::Debug for Message {
             }
             write!(fmt, "cancel: {cid}")?;
         }
+        for cid in self.dont_have() {
+            if first {
+                first = false;
+            } else {
+                write!(fmt, ", ")?;
+            }
+            write!(fmt, "dont have: {cid}")?;
+        }
         for block in self.blocks() {
             if first {
                 first = false;
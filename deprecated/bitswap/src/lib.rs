@@ -9,7 +9,7 @@ mod ledger;
 mod prefix;
 mod protocol;
 
-pub use self::behaviour::{Bitswap, BitswapEvent, Stats};
+pub use self::behaviour::{Bitswap, BitswapEvent, PeerStats, Stats};
 pub use self::block::Block;
 pub use self::error::BitswapError;
 pub use self::ledger::Priority;
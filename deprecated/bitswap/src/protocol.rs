@@ -14,7 +14,7 @@ use futures::{
 use libp2p::core::{upgrade, InboundUpgrade, OutboundUpgrade, UpgradeInfo};
 use std::io;
 
-const MAX_BUF_SIZE: usize = 2_097_152;
+pub(crate) const MAX_BUF_SIZE: usize = 2_097_152;
 
 type FutureResult<T, E> = BoxFuture<'static, Result<T, E>>;
 
@@ -1,14 +1,13 @@
 use clap::Parser;
-use futures::{channel::mpsc, pin_mut, FutureExt};
+use futures::{pin_mut, FutureExt};
 use libipld::ipld;
-use libp2p::{futures::StreamExt, swarm::SwarmEvent};
-use rust_ipfs::{BehaviourEvent, Ipfs, Protocol, PubsubEvent};
+use libp2p::futures::StreamExt;
+use rust_ipfs::{Ipfs, ListenerEvent, PubsubEvent, RetryPolicy};
 
 use rust_ipfs::UninitializedIpfsNoop as UninitializedIpfs;
 
 use rustyline_async::{Readline, ReadlineError};
-use std::{io::Write, sync::Arc};
-use tokio::sync::Notify;
+use std::io::Write;
 
 #[derive(Debug, Parser)]
 #[clap(name = "pubsub")]
@@ -44,37 +43,17 @@ async fn main() -> anyhow::Result<()> {
     }
 
     if opt.use_relay {
-        uninitialized = uninitialized.enable_relay(true);
+        // relays are picked among the known peers once the node is found to be behind a NAT
+        uninitialized = uninitialized
+            .enable_relay(true)
+            .enable_autorelay(Default::default());
     }
 
     if opt.use_upnp {
         uninitialized = uninitialized.enable_upnp();
     }
 
-    let (tx, mut rx) = mpsc::unbounded();
-    let ipfs: Ipfs = uninitialized
-        .swarm_events({
-            move |_, event| {
-                if let SwarmEvent::Behaviour(BehaviourEvent::Autonat(
-                    libp2p::autonat::Event::StatusChanged { new, .. },
-                )) = event
-                {
-                    match new {
-                        libp2p::autonat::NatStatus::Public(_) => {
-                            let _ = tx.unbounded_send(true);
-                        }
-                        libp2p::autonat::NatStatus::Private
-                        | libp2p::autonat::NatStatus::Unknown => {
-                            let _ = tx.unbounded_send(false);
-                        }
-                    }
-                }
-            }
-        })
-        .start()
-        .await?;
-
-    
+    let ipfs: Ipfs = uninitialized.start().await?;
 
     let identity = ipfs.identity(None).await?;
     let peer_id = identity.peer_id;
@@ -84,62 +63,14 @@ async fn main() -> anyhow::Result<()> {
         ipfs.default_bootstrap().await?;
         tokio::spawn({
             let ipfs = ipfs.clone();
-            async move { if let Err(_e) = ipfs.bootstrap().await {} }
-        });
-    }
-
-    let cancel = Arc::new(Notify::new());
-    if opt.use_relay {
-        //Until autorelay is implemented and/or functions to use relay more directly, we will manually listen to the relays (using libp2p bootstrap, though you can add your own)
-        tokio::spawn({
-            let ipfs = ipfs.clone();
-            let mut stdout = stdout.clone();
-            let cancel = cancel.clone();
-            async move {
-                let mut listening_addrs = vec![];
-                let mut relay_used = false;
-                loop {
-                    let flag = tokio::select! {
-                        flag = rx.next() => {
-                            flag.unwrap_or_default()
-                        },
-                        _ = cancel.notified() => break
-                    };
-
-                    match flag {
-                        true => {
-                            if relay_used {
-                                writeln!(stdout, "Disabling Relay...")?;
-                                for addr in listening_addrs.drain(..) {
-                                    if let Err(_e) = ipfs.remove_listening_address(addr).await {}
-                                }
-                                relay_used = false;
-                            }
-                        }
-                        false => {
-                            if !relay_used {
-                                writeln!(stdout, "Enabling Relay...")?;
-                                for addr in ipfs.get_bootstraps().await? {
-                                    let circuit = addr.with(Protocol::P2pCircuit);
-                                    if let Ok(addr) =
-                                        ipfs.add_listening_address(circuit.clone()).await
-                                    {
-                                        listening_addrs.push(addr)
-                                    }
-                                }
-                                relay_used = !listening_addrs.is_empty();
-                            }
-                        }
-                    }
-                }
-
-                Ok::<_, anyhow::Error>(())
-            }
+            async move { if let Err(_e) = ipfs.bootstrap_with_retry(RetryPolicy::default()).await {} }
         });
     }
 
     let mut event_stream = ipfs.pubsub_events(&topic).await?;
 
+    let mut listener_events = ipfs.listener_events().await?;
+
     let stream = ipfs.pubsub_subscribe(topic.to_string()).await?;
 
     pin_mut!(stream);
@@ -152,7 +83,7 @@ async fn main() -> anyhow::Result<()> {
         tokio::select! {
             data = stream.next() => {
                 if let Some(msg) = data {
-                    writeln!(stdout, "{}: {}", msg.source.expect("Message should contain a source peer_id"), String::from_utf8_lossy(&msg.data))?;
+                    writeln!(stdout, "{}: {}", msg.source_or_unknown(), String::from_utf8_lossy(&msg.data))?;
                 }
             }
             Some(event) = event_stream.next() => {
@@ -161,6 +92,14 @@ async fn main() -> anyhow::Result<()> {
                     PubsubEvent::Unsubscribe { peer_id } => writeln!(stdout, "{} unsubscribed", peer_id)?,
                 }
             }
+            Some(event) = listener_events.next() => {
+                match event {
+                    ListenerEvent::CircuitListenActive { relay } => writeln!(stdout, "Listening through relay {relay}")?,
+                    ListenerEvent::CircuitListenFailed { relay, error } => writeln!(stdout, "Failed to listen through relay {relay}: {error}")?,
+                    ListenerEvent::ReservationRenewed { relay } => writeln!(stdout, "Renewed reservation with relay {relay}")?,
+                    ListenerEvent::ReservationRenewalFailed { relay, error } => writeln!(stdout, "Failed to renew reservation with relay {relay}: {error}")?,
+                }
+            }
             line = rl.readline().fuse() => match line {
                 Ok(line) => {
                     if let Err(e) = ipfs.pubsub_publish(topic.clone(), line.as_bytes().to_vec()).await {
@@ -169,14 +108,8 @@ async fn main() -> anyhow::Result<()> {
                     }
                     writeln!(stdout, "{peer_id}: {line}")?;
                 }
-                Err(ReadlineError::Eof) => {
-                    cancel.notify_one();
-                    break
-                },
-                Err(ReadlineError::Interrupted) => {
-                    cancel.notify_one();
-                    break
-                },
+                Err(ReadlineError::Eof) => break,
+                Err(ReadlineError::Interrupted) => break,
                 Err(e) => {
                     writeln!(stdout, "Error: {e}")?;
                     writeln!(stdout, "Exiting...")?;
@@ -46,6 +46,9 @@ async fn main() -> anyhow::Result<()> {
                     anyhow::bail!("Unknown error while writting to blockstore");
                 }
             }
+            UnixfsStatus::DeduplicatedStatus { deduplicated } => {
+                println!("{deduplicated} blocks were already stored");
+            }
             UnixfsStatus::CompletedStatus { path, written, .. } => {
                 println!("{written} been stored with path {path}");
             }
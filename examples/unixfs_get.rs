@@ -62,6 +62,7 @@ async fn main() -> anyhow::Result<()> {
                     anyhow::bail!("Unknown error while writting to disk");
                 }
             }
+            UnixfsStatus::DeduplicatedStatus { .. } => {}
             UnixfsStatus::CompletedStatus { written, .. } => {
                 let path = dest;
                 println!("{written} been written successfully to {}", path.display());
@@ -8,7 +8,13 @@ use futures::future::BoxFuture;
 use libp2p::PeerId;
 use tracing::{debug, error, info, warn};
 
-use crate::{block::Block, message::BitswapMessage, network::Network, Store};
+use crate::{
+    block::Block,
+    message::{BitswapMessage, Priority},
+    network::{Network, WantPriority},
+    protocol::BlockFilter,
+    Store,
+};
 
 use self::session::BlockReceiver;
 use self::{peer_manager::PeerManager, session::Session, session_manager::SessionManager};
@@ -29,6 +35,20 @@ pub struct Config {
     /// Overwrites the global rebroadcast delay
     pub rebroadcast_delay: Duration,
     pub simluate_donthaves_on_timeout: bool,
+    /// Which peers are sent want-haves for blocks no session peer is known to have
+    pub want_broadcast: WantBroadcastPolicy,
+}
+
+/// Controls the peers which receive the broadcast want-haves of sessions that have no peers yet.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum WantBroadcastPolicy {
+    /// Broadcast to all connected peers.
+    #[default]
+    All,
+    /// Send to a random subset of at most this many connected peers.
+    Random(usize),
+    /// Do not broadcast, relying on provider discovery instead.
+    None,
 }
 
 impl Default for Config {
@@ -37,6 +57,7 @@ impl Default for Config {
             provider_search_delay: Duration::from_secs(1),
             rebroadcast_delay: Duration::from_secs(60),
             simluate_donthaves_on_timeout: true,
+            want_broadcast: WantBroadcastPolicy::default(),
         }
     }
 }
@@ -103,7 +124,13 @@ impl<S: Store> Client<S> {
             }
         });
 
-        let session_manager = SessionManager::new(self_id, network.clone(), notify.clone()).await;
+        let session_manager = SessionManager::new(
+            self_id,
+            network.clone(),
+            notify.clone(),
+            config.want_broadcast,
+        )
+        .await;
 
         Client {
             network,
@@ -131,6 +158,11 @@ impl<S: Store> Client<S> {
         block
     }
 
+    /// Sends the wants for `key` to the peers at `priority` until the returned guard is dropped.
+    pub fn prioritize_want(&self, key: Cid, priority: Priority) -> WantPriority {
+        self.network.prioritize_want(key, priority)
+    }
+
     pub async fn get_block_with_session_id(
         &self,
         session_id: u64,
@@ -144,6 +176,18 @@ impl<S: Store> Client<S> {
         session.get_block(key).await
     }
 
+    /// Fetches the block asking the `preferred` peers ahead of the providers found otherwise. See
+    /// [`Session::get_block_preferring`].
+    pub async fn get_block_preferring(
+        &self,
+        session_id: u64,
+        key: &Cid,
+        preferred: &[PeerId],
+    ) -> Result<Block> {
+        let session = self.get_or_create_session(session_id).await;
+        session.get_block_preferring(key, preferred).await
+    }
+
     pub async fn get_blocks_with_session_id(
         &self,
         session_id: u64,
@@ -269,6 +313,13 @@ impl<S: Store> Client<S> {
         self.peer_manager().disconnected(peer).await;
     }
 
+    /// Returns a filter telling whether any session is interested in a block, for the inbound
+    /// streams to drop the others.
+    pub(crate) fn block_filter(&self) -> BlockFilter {
+        let interest = self.session_manager.session_interest_manager().clone();
+        Arc::new(move |key| interest.may_be_interested(key))
+    }
+
     /// Returns the current local wantlist (both want-blocks and want-haves).
     pub async fn get_wantlist(&self) -> AHashSet<Cid> {
         self.peer_manager().current_wants().await
@@ -289,6 +340,15 @@ impl<S: Store> Client<S> {
         self.peer_manager().connected_peers().await
     }
 
+    /// Indicates whether the given peer has sent a `DONT_HAVE` for the given `cid`, while
+    /// the `cid` is still being requested.
+    pub async fn peer_does_not_have_block(&self, peer: &PeerId, cid: &Cid) -> bool {
+        self.session_manager
+            .block_presence_manager()
+            .peer_does_not_have_block(peer, cid)
+            .await
+    }
+
     fn peer_manager(&self) -> &PeerManager {
         self.session_manager.peer_manager()
     }
@@ -322,6 +382,23 @@ impl<S: Store> Client<S> {
         Ok(())
     }
 
+    /// Starts remembering the peer which sent each of the blocks received in the session
+    /// `session_id`. See [`Session::record_block_sources`].
+    pub async fn record_block_sources(&self, session_id: u64) {
+        self.get_or_create_session(session_id)
+            .await
+            .record_block_sources();
+    }
+
+    /// Returns and forgets the peer which sent the block `key` in the session `session_id`, if
+    /// the session is still alive and received the block from the network while recording.
+    pub async fn take_block_source(&self, session_id: u64, key: &Cid) -> Option<PeerId> {
+        self.session_manager
+            .get_session(session_id)
+            .await
+            .and_then(|session| session.take_block_source(key))
+    }
+
     /// Returns aggregated statistics about bitswap operations.
     pub async fn stat(&self) -> Result<Stat> {
         todo!()
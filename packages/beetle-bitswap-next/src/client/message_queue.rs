@@ -12,7 +12,7 @@ use tokio::{sync::mpsc, task::JoinHandle};
 use tracing::{debug, error, warn};
 
 use crate::{
-    message::{BitswapMessage, Entry, WantType},
+    message::{BitswapMessage, Entry, Priority, WantType},
     network::{MessageSender, MessageSenderConfig, Network},
 };
 
@@ -40,7 +40,6 @@ enum WantsUpdate {
     },
     Cancels(AHashSet<Cid>),
     #[cfg(test)]
-    #[allow(dead_code)]
     GetWants(tokio::sync::oneshot::Sender<Wants>),
 }
 
@@ -128,7 +127,6 @@ impl MessageQueue {
     }
 
     #[cfg(test)]
-    #[allow(dead_code)]
     pub(crate) async fn wants(&self) -> Result<Wants> {
         let (s, r) = tokio::sync::oneshot::channel();
         self.send_wants_update(WantsUpdate::GetWants(s)).await;
@@ -336,7 +334,6 @@ impl MessageQueueActor {
             bcst_wants: Default::default(),
             peer_wants: Default::default(),
             cancels: Default::default(),
-            priority: config.max_priority,
         };
 
         let dh_timeout_manager = DontHaveTimeoutManager::new(peer, on_dont_have_timeout).await;
@@ -365,14 +362,19 @@ impl MessageQueueActor {
         Ok(())
     }
 
+    /// Returns the priority `cid` is wanted at, see [`Network::prioritize_want`].
+    fn want_priority(&self, cid: &Cid) -> Priority {
+        self.network
+            .want_priority(cid)
+            .min(self.config.max_priority)
+    }
+
     async fn handle_wants_update(&mut self, wants_update: WantsUpdate) {
         match wants_update {
             WantsUpdate::BroadcastWantHaves(want_haves) => {
                 for cid in want_haves {
-                    self.wants
-                        .bcst_wants
-                        .add(cid, self.wants.priority, WantType::Have);
-                    self.wants.priority -= 1;
+                    let priority = self.want_priority(&cid);
+                    self.wants.bcst_wants.add(cid, priority, WantType::Have);
 
                     // Adding a want-have for the cid, so clear any pending cancels.
                     self.wants.cancels.remove(&cid);
@@ -385,20 +387,16 @@ impl MessageQueueActor {
                 want_haves,
             } => {
                 for cid in want_haves {
-                    self.wants
-                        .peer_wants
-                        .add(cid, self.wants.priority, WantType::Have);
-                    self.wants.priority -= 1;
+                    let priority = self.want_priority(&cid);
+                    self.wants.peer_wants.add(cid, priority, WantType::Have);
 
                     // Adding a want-have for the cid, so clear any pending cancels.
                     self.wants.cancels.remove(&cid);
                 }
 
                 for cid in want_blocks {
-                    self.wants
-                        .peer_wants
-                        .add(cid, self.wants.priority, WantType::Block);
-                    self.wants.priority -= 1;
+                    let priority = self.want_priority(&cid);
+                    self.wants.peer_wants.add(cid, priority, WantType::Block);
 
                     // Adding a want-block for the cid, so clear any pending cancels.
                     self.wants.cancels.remove(&cid);
@@ -728,3 +726,43 @@ impl MessageQueueActor {
         let _ = self.outgoing_work.0.try_send(Instant::now());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use futures::FutureExt;
+
+    use crate::block::tests::create_random_block_v1;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn wants_are_sent_at_their_priority() {
+        let network = Network::new(PeerId::random());
+        let on_dont_have_timeout: Arc<dyn DontHaveTimeout> =
+            Arc::new(|_, _| futures::future::ready(()).boxed());
+        let queue =
+            MessageQueue::new(PeerId::random(), network.clone(), on_dont_have_timeout).await;
+
+        let cids = (0..3)
+            .map(|_| *create_random_block_v1().cid())
+            .collect::<Vec<_>>();
+        let _low = network.prioritize_want(cids[0], 1);
+        let high = network.prioritize_want(cids[1], 10);
+        // wanted again at a lower priority, the higher one is kept
+        let lower = network.prioritize_want(cids[1], 5);
+
+        queue.add_wants(&cids[..2], &cids[2..]).await;
+        let wants = queue.wants().await.unwrap();
+        let priority = |cid| wants.peer_wants.pending.get(cid).unwrap().priority;
+        assert_eq!(priority(&cids[0]), 1);
+        assert_eq!(priority(&cids[1]), 10);
+        assert_eq!(priority(&cids[2]), 0);
+
+        drop(high);
+        assert_eq!(network.want_priority(&cids[1]), 5);
+        drop(lower);
+        assert_eq!(network.want_priority(&cids[1]), 0);
+
+        queue.stop().await.unwrap();
+    }
+}
@@ -13,7 +13,6 @@ pub struct Wants {
     pub bcst_wants: RecallWantlist,
     pub peer_wants: RecallWantlist,
     pub cancels: AHashSet<Cid>,
-    pub priority: i32,
 }
 
 impl Wants {
@@ -12,7 +12,10 @@ use tracing::{debug, error, trace, warn};
 
 use crate::network::Network;
 
-use super::{message_queue::MessageQueue, peer_want_manager::PeerWantManager, session::Signaler};
+use super::{
+    message_queue::MessageQueue, peer_want_manager::PeerWantManager, session::Signaler,
+    WantBroadcastPolicy,
+};
 
 #[derive(Debug, Clone)]
 pub struct PeerManager {
@@ -75,6 +78,7 @@ enum Message {
         response: oneshot::Sender<bool>,
     },
     SetCb(#[derivative(Debug = "ignore")] Arc<dyn DontHaveTimeout>),
+    SetBroadcastPolicy(WantBroadcastPolicy),
 }
 
 pub trait DontHaveTimeout:
@@ -107,6 +111,11 @@ impl PeerManager {
             .await;
     }
 
+    /// Sets which peers receive the want-haves passed to `broadcast_want_haves`.
+    pub async fn set_broadcast_policy(&self, policy: WantBroadcastPolicy) {
+        self.send(Message::SetBroadcastPolicy(policy)).await;
+    }
+
     async fn send(&self, message: Message) {
         if let Err(err) = self.sender.send(message).await {
             warn!("failed to send message: {:?}", err);
@@ -142,8 +151,8 @@ impl PeerManager {
             .await;
     }
 
-    /// Broadcasts want-haves to all peers
-    /// (used by the session to discover seeds).
+    /// Broadcasts want-haves to the peers selected by the `WantBroadcastPolicy`, all peers by
+    /// default (used by the session to discover seeds).
     /// For each peer it filters out want-haves that have previously been sent to the peer.
     pub async fn broadcast_want_haves(&self, want_haves: &AHashSet<Cid>) {
         self.send(Message::BroadcastWantHaves(want_haves.to_owned()))
@@ -349,6 +358,9 @@ async fn run(mut actor: PeerManagerActor) {
                     Some(Message::SetCb(cb)) => {
                         actor.on_dont_have_timeout = cb;
                     }
+                    Some(Message::SetBroadcastPolicy(policy)) => {
+                        actor.peer_want_manager.set_broadcast_policy(policy);
+                    }
                     Some(Message::AddPeerToSession{
                         session,
                         peer,
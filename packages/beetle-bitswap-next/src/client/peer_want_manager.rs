@@ -1,10 +1,12 @@
 use ahash::{AHashMap, AHashSet};
 use cid::Cid;
 use libp2p::PeerId;
+use rand::seq::IteratorRandom;
 use tracing::{debug, error};
 
 use super::message_queue::MessageQueue;
 use super::peer_manager::PeerState;
+use super::WantBroadcastPolicy;
 
 /// Keeps track of which want-haves and want-blocks have been sent to each peer,
 /// in order to avoid the `PeerManager` sending duplicates.
@@ -17,6 +19,8 @@ pub struct PeerWantManager {
     want_peers: AHashMap<Cid, AHashSet<PeerId>>,
     /// Current broadcast wants.
     broadcast_wants: AHashSet<Cid>,
+    /// Which peers receive the broadcast wants.
+    broadcast_policy: WantBroadcastPolicy,
 }
 
 #[derive(Debug)]
@@ -74,13 +78,55 @@ impl PeerWantManager {
         }
     }
 
+    pub(super) fn set_broadcast_policy(&mut self, policy: WantBroadcastPolicy) {
+        self.broadcast_policy = policy;
+    }
+
     /// Sends want-haves to any peers that have not yet been sent them.
+    ///
+    /// Unless the policy is `WantBroadcastPolicy::All`, the want-haves are sent as regular
+    /// want-haves to the selected peers only, and are not sent to peers connecting later. With
+    /// `WantBroadcastPolicy::Random`, a want is only sent to further peers once the peers holding
+    /// it have disconnected.
     pub(super) async fn broadcast_want_haves(
         &mut self,
         want_haves: &AHashSet<Cid>,
         peer_queues: &AHashMap<PeerId, PeerState>,
     ) {
         debug!("pwm: broadcast_want_haves: {:?}", want_haves);
+
+        match self.broadcast_policy {
+            WantBroadcastPolicy::All => {}
+            WantBroadcastPolicy::None => return,
+            WantBroadcastPolicy::Random(count) => {
+                // top up the peers holding each want to `count`, so that rebroadcasts do not
+                // spread the want further
+                let mut peer_unsent: AHashMap<PeerId, Vec<Cid>> = AHashMap::new();
+                for cid in want_haves {
+                    let sent_to = self.want_peers.get(cid);
+                    let needed = count.saturating_sub(sent_to.map_or(0, |peers| peers.len()));
+                    let peers = self
+                        .peer_wants
+                        .keys()
+                        .filter(|peer| !sent_to.map_or(false, |peers| peers.contains(*peer)))
+                        .copied()
+                        .choose_multiple(&mut rand::thread_rng(), needed);
+
+                    for peer in peers {
+                        peer_unsent.entry(peer).or_default().push(*cid);
+                    }
+                }
+
+                for (peer, want_haves) in peer_unsent {
+                    if let Some(peer_state) = peer_queues.get(&peer) {
+                        self.send_wants(&peer, &[], &want_haves, &peer_state.message_queue)
+                            .await;
+                    }
+                }
+                return;
+            }
+        }
+
         // want_haves - self.broadcast_wants
         let unsent: AHashSet<_> = want_haves
             .difference(&self.broadcast_wants)
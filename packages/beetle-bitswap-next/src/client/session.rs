@@ -1,6 +1,11 @@
-use std::{ops::Deref, pin::Pin, sync::Arc, time::Duration};
+use std::{
+    ops::Deref,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use ahash::AHashSet;
+use ahash::{AHashMap, AHashSet};
 use anyhow::{anyhow, ensure, Result};
 use cid::Cid;
 use futures::{future, stream, StreamExt};
@@ -13,7 +18,7 @@ use tokio::{
 };
 use tracing::{debug, error, info, warn};
 
-use crate::{network::Network, Block};
+use crate::{error::BlockNotFound, network::Network, Block};
 
 use self::{session_want_sender::SessionWantSender, session_wants::SessionWants};
 
@@ -42,6 +47,9 @@ enum Op {
     Want(Vec<Cid>),
     Cancel(Vec<Cid>),
     Broadcast(AHashSet<Cid>),
+    Declined(AHashSet<Cid>),
+    Prefer(Vec<Cid>),
+    ProvidersSearched(Cid),
     WantsSent(Vec<Cid>),
     UpdateWantSender {
         from: PeerId,
@@ -67,6 +75,10 @@ struct Inner {
     closer: oneshot::Sender<()>,
     worker: JoinHandle<()>,
     notify: async_broadcast::Sender<Block>,
+    not_found: async_broadcast::InactiveReceiver<Cid>,
+    /// The peer which sent each of the blocks received in this session and not yet taken, once
+    /// asked to record them.
+    block_sources: Mutex<Option<AHashMap<Cid, PeerId>>>,
 }
 
 impl Session {
@@ -95,6 +107,8 @@ impl Session {
 
         let session_wants = SessionWants::new(BROADCAST_LIVE_WANTS_LIMIT);
         let (closer_s, mut closer_r) = oneshot::channel();
+        let (mut not_found_s, not_found_r) = async_broadcast::broadcast(128);
+        not_found_s.set_overflow(true);
 
         let mut loop_state = LoopState::new(
             id,
@@ -105,13 +119,19 @@ impl Session {
             peer_manager,
             initial_search_delay,
             incoming_s.clone(),
+            not_found_s,
         );
 
         let rt = tokio::runtime::Handle::current();
         let worker = rt.spawn(async move {
             // Session run loop
 
-            let mut periodic_search_timer = tokio::time::interval(periodic_search_delay);
+            // the first search is due after a delay too, rather than broadcasting the wants made
+            // right away, including those left to the preferred peers
+            let mut periodic_search_timer = tokio::time::interval_at(
+                Instant::now() + periodic_search_delay,
+                periodic_search_delay,
+            );
 
             loop {
                 tokio::select! {
@@ -130,6 +150,7 @@ impl Session {
                                 loop_state.want_blocks(keys).await;
                             },
                             Ok(Op::Cancel(keys)) => {
+                                loop_state.forget_declined(&keys);
                                 loop_state.session_wants.cancel_pending(&keys);
                                 loop_state.session_want_sender.cancel(keys).await;
                             }
@@ -139,7 +160,17 @@ impl Session {
                             Ok(Op::Broadcast(keys)) => {
                                 loop_state.broadcast(Some(keys)).await;
                             },
+                            Ok(Op::Declined(keys)) => {
+                                loop_state.declined(keys).await;
+                            },
+                            Ok(Op::Prefer(keys)) => {
+                                loop_state.preferred.extend(keys);
+                            },
+                            Ok(Op::ProvidersSearched(key)) => {
+                                loop_state.providers_searched(key);
+                            },
                             Ok(Op::UpdateWantSender { from, keys, haves, dont_haves, }) => {
+                                loop_state.candidates_found(keys.iter().chain(&haves));
                                 loop_state
                                     .session_want_sender
                                     .update(from, keys, haves, dont_haves)
@@ -173,8 +204,10 @@ impl Session {
             session_interest_manager,
             incoming: incoming_s,
             notify,
+            not_found: not_found_r.deactivate(),
             closer: closer_s,
             worker,
+            block_sources: Default::default(),
         });
 
         Session { inner }
@@ -262,16 +295,62 @@ impl Session {
             return;
         }
 
+        // Remember who sent the blocks, before they are published to the waiting requests.
+        if let (Some(from), Some(sources)) = (from, &mut *self.inner.block_sources.lock().unwrap())
+        {
+            for key in &keys {
+                sources.entry(*key).or_insert(from);
+            }
+        }
+
         // Inform the session that blocks have been received.
         if let Err(err) = self.inner.incoming.send(Op::Receive(keys)).await {
             warn!("failed to send receive: {:?}", err);
         }
     }
 
+    /// Starts remembering the peer which sent each of the blocks received in this session, to be
+    /// taken with [`Session::take_block_source`].
+    pub fn record_block_sources(&self) {
+        self.inner
+            .block_sources
+            .lock()
+            .unwrap()
+            .get_or_insert_with(Default::default);
+    }
+
+    /// Returns and forgets the peer which sent the block `key` in this session, if it was
+    /// received from one while recording.
+    ///
+    /// Blocks announced locally through [`super::Client::notify_new_blocks`] have no source.
+    pub fn take_block_source(&self, key: &Cid) -> Option<PeerId> {
+        self.inner
+            .block_sources
+            .lock()
+            .unwrap()
+            .as_mut()
+            .and_then(|sources| sources.remove(key))
+    }
+
+    /// Fetches a single block, asking the `preferred` peers for it ahead of any other peer.
+    ///
+    /// Once the preferred peers declined the block it is broadcast to the connected peers and its
+    /// providers are searched for, failing like [`Session::get_block`] if neither finds it.
+    pub async fn get_block_preferring(&self, key: &Cid, preferred: &[PeerId]) -> Result<Block> {
+        let _ = self.inner.incoming.send(Op::Prefer(vec![*key])).await;
+        for provider in preferred {
+            self.add_provider(key, *provider).await;
+        }
+        self.get_block(key).await
+    }
+
     /// Fetches a single block.
+    ///
+    /// Fails with [`BlockNotFound`] once all the peers of the session sent a DONT_HAVE for it and
+    /// neither a broadcast to the connected peers nor a search for providers found another one.
     pub async fn get_block(&self, key: &Cid) -> Result<Block> {
         let r = self.get_blocks(&[*key][..]).await?;
-        let block = r.recv().await?;
+        let block = r.recv().await??;
         Ok(block)
     }
 
@@ -290,7 +369,9 @@ impl Session {
 
     /// Fetches a set of blocks within the context of this session and
     /// returns a channel that found blocks will be returned on. No order is
-    /// guaranteed on the returned blocks.
+    /// guaranteed on the returned blocks. Blocks which all the peers of the
+    /// session declined to have, and no other peer was found for, are returned
+    /// as [`BlockNotFound`].
     pub async fn get_blocks(&self, keys: &[Cid]) -> Result<BlockReceiver> {
         ensure!(!keys.is_empty(), "missing keys");
         debug!("get blocks: {:?}", keys);
@@ -298,6 +379,7 @@ impl Session {
         let (s, r) = async_channel::bounded(8);
         let mut remaining: AHashSet<Cid> = keys.iter().copied().collect();
         let mut block_channel = self.inner.notify.new_receiver();
+        let mut not_found_channel = self.inner.not_found.activate_cloned();
         let incoming = self.inner.incoming.clone();
         let (closer_s, mut closer_r) = oneshot::channel();
         let worker = tokio::task::spawn(async move {
@@ -314,7 +396,7 @@ impl Session {
                                 let cid = *block.cid();
                                 if remaining.contains(&cid) {
                                     debug!("received wanted block {}", cid);
-                                    match s.send(block).await {
+                                    match s.send(Ok(block)).await {
                                         Ok(_) => {
                                             remaining.remove(&cid);
                                         }
@@ -339,6 +421,21 @@ impl Session {
                             }
                         }
                     }
+                    Ok(cid) = not_found_channel.recv() => {
+                        if remaining.remove(&cid) {
+                            debug!("wanted block {} not found", cid);
+                            if s.send(Err(BlockNotFound(cid))).await.is_err() {
+                                break;
+                            }
+                            if let Err(err) = incoming.send(Op::Cancel(vec![cid])).await {
+                                warn!("failed to send cancel: {:?}", err);
+                            }
+                        }
+
+                        if remaining.is_empty() {
+                            break;
+                        }
+                    }
                 }
             }
 
@@ -378,7 +475,16 @@ struct LoopState {
     initial_search_delay: Duration,
     workers: Vec<JoinHandle<Option<()>>>,
     task_controller: tokio_context::task::TaskController,
-    provider_search_queue: Arc<deadqueue::limited::Queue<Cid>>,
+    /// Searches for the providers of a cid, reporting [`Op::ProvidersSearched`] once the search
+    /// is done and the given instant has passed.
+    provider_search_queue: Arc<deadqueue::limited::Queue<(Cid, Option<Instant>)>>,
+    not_found: async_broadcast::Sender<Cid>,
+    /// Wants declined by all the peers of the session, being broadcast and searched for.
+    searching: AHashSet<Cid>,
+    /// Wants which have already been broadcast and searched for after being declined.
+    searched: AHashSet<Cid>,
+    /// Wants asked of preferred peers, which are not broadcast before those peers answered.
+    preferred: AHashSet<Cid>,
 }
 
 impl LoopState {
@@ -392,6 +498,7 @@ impl LoopState {
         peer_manager: PeerManager,
         initial_search_delay: Duration,
         incoming: async_channel::Sender<Op>,
+        not_found: async_broadcast::Sender<Cid>,
     ) -> Self {
         let idle_tick = Box::pin(tokio::time::sleep(initial_search_delay));
         let mut task_controller = tokio_context::task::TaskController::new();
@@ -406,7 +513,7 @@ impl LoopState {
 
             workers.push(task_controller.spawn(async move {
                 loop {
-                    let cid = queue.pop().await;
+                    let (cid, report_at) = queue.pop().await;
                     if let Ok(chan) = network.find_providers(cid, MAX_PROVIDERS).await {
                         let stream = tokio_stream::wrappers::ReceiverStream::new(chan);
                         stream
@@ -444,6 +551,10 @@ impl LoopState {
                             })
                             .await;
                     }
+                    if let Some(report_at) = report_at {
+                        tokio::time::sleep_until(report_at).await;
+                        let _ = incoming.send(Op::ProvidersSearched(cid)).await;
+                    }
                 }
             }));
         }
@@ -462,6 +573,10 @@ impl LoopState {
             workers,
             task_controller,
             provider_search_queue: queue,
+            not_found,
+            searching: Default::default(),
+            searched: Default::default(),
+            preferred: Default::default(),
         }
     }
 
@@ -515,6 +630,69 @@ impl LoopState {
         }
     }
 
+    /// Called when all peers in the session have sent DONT_HAVE for a set of CIDs.
+    /// Broadcasts want-haves for them and searches for their providers, failing the pending
+    /// requests only if neither turns up a peer having them. Wants declined again after that
+    /// are failed right away.
+    async fn declined(&mut self, wants: AHashSet<Cid>) {
+        debug!("session:{}: declined: {}", self.id, wants.len());
+        let mut search = AHashSet::new();
+        for cid in wants {
+            if self.searched.contains(&cid) {
+                self.not_found(cid);
+            } else if self.searching.insert(cid) {
+                search.insert(cid);
+            }
+        }
+
+        if search.is_empty() {
+            return;
+        }
+
+        self.broadcast_want_haves(&search).await;
+        // leave the connected peers as long to answer the want-haves
+        let report_at = Instant::now() + self.initial_search_delay;
+        for cid in search {
+            self.provider_search_queue
+                .push((cid, Some(report_at)))
+                .await;
+        }
+    }
+
+    /// Called when the search for providers of a declined want is done.
+    fn providers_searched(&mut self, cid: Cid) {
+        if self.searching.remove(&cid) {
+            self.searched.insert(cid);
+            self.not_found(cid);
+        }
+    }
+
+    /// Called when a peer sent a HAVE or the block, so the want is no longer to be failed once
+    /// its providers are searched.
+    fn candidates_found<'a>(&mut self, keys: impl IntoIterator<Item = &'a Cid>) {
+        for cid in keys {
+            if self.searching.remove(cid) {
+                self.searched.insert(*cid);
+            }
+        }
+    }
+
+    /// Forgets about the declined wants which were received or cancelled.
+    fn forget_declined(&mut self, keys: &[Cid]) {
+        for cid in keys {
+            self.searching.remove(cid);
+            self.searched.remove(cid);
+            self.preferred.remove(cid);
+        }
+    }
+
+    /// Fails the pending requests for the cid.
+    fn not_found(&mut self, cid: Cid) {
+        debug!("session:{}: not found: {}", self.id, cid);
+        // only fails if there are no pending requests for the cid
+        let _ = self.not_found.try_broadcast(cid);
+    }
+
     /// Called periodically to search for providers of a randomly chosen CID in the sesssion.
     async fn handle_periodic_search(&mut self) {
         debug!("session:{}: periodic search", self.id);
@@ -530,7 +708,7 @@ impl LoopState {
     /// Attempts to find more peers for a session by searching for providers for the given cid.
     async fn find_more_peers(&mut self, cid: &Cid) {
         debug!("session:{}: find_more_peers {}", self.id, cid);
-        self.provider_search_queue.push(*cid).await;
+        self.provider_search_queue.push((*cid, None)).await;
     }
 
     /// Called when the session receives blocks from a peer.
@@ -542,6 +720,7 @@ impl LoopState {
         );
         // Record which blocks have been received and figure out the total latency
         // for fetching the blocks
+        self.forget_declined(&keys);
         let (wanted, total_latency) = self.session_wants.blocks_received(&keys);
         if wanted.is_empty() {
             return;
@@ -587,8 +766,10 @@ impl LoopState {
             return;
         }
 
-        // No peers discovered yet, broadcast some want-haves
-        let keys = self.session_wants.get_next_wants();
+        // No peers discovered yet, broadcast some want-haves, leaving the preferred peers to be
+        // asked first for theirs
+        let mut keys = self.session_wants.get_next_wants();
+        keys.retain(|key| !self.preferred.contains(key));
         debug!(
             "session:{}: initial broadcast, as no peers discovered yet {}",
             self.id,
@@ -656,7 +837,7 @@ impl LatencyTracker {
 #[derive(Debug)]
 pub struct BlockReceiver {
     /// Receives the results.
-    receiver: async_channel::Receiver<Block>,
+    receiver: async_channel::Receiver<Result<Block, BlockNotFound>>,
     guard: BlockReceiverGuard,
 }
 #[derive(Debug)]
@@ -676,13 +857,18 @@ impl Drop for BlockReceiverGuard {
 }
 
 impl BlockReceiver {
-    pub fn into_parts(self) -> (async_channel::Receiver<Block>, BlockReceiverGuard) {
+    pub fn into_parts(
+        self,
+    ) -> (
+        async_channel::Receiver<Result<Block, BlockNotFound>>,
+        BlockReceiverGuard,
+    ) {
         (self.receiver, self.guard)
     }
 }
 
 impl Deref for BlockReceiver {
-    type Target = async_channel::Receiver<Block>;
+    type Target = async_channel::Receiver<Result<Block, BlockNotFound>>;
 
     fn deref(&self) -> &Self::Target {
         &self.receiver
@@ -743,7 +743,7 @@ impl LoopState {
             // If the last available peer in the session has become unavailable
             // then we need to broadcast all pending wants
             if !self.peer_manager.session_has_peers(self.id()).await {
-                self.process_exhausted_wants(wants).await;
+                self.process_exhausted_wants(wants, false).await;
                 return;
             }
         }
@@ -751,29 +751,37 @@ impl LoopState {
         // If all available peers for a cid sent a DONT_HAVE, signal to the session
         // that we've exhausted available peers
         if !wants.is_empty() {
+            let peers = self.peer_manager.peers_for_session(self.id()).await;
             let exhausted = self
                 .block_presence_manager
-                .all_peers_do_not_have_block(
-                    &self.peer_manager.peers_for_session(self.id()).await,
-                    wants,
-                )
+                .all_peers_do_not_have_block(&peers, wants)
+                .await;
+            self.process_exhausted_wants(exhausted, !peers.is_empty())
                 .await;
-            self.process_exhausted_wants(exhausted).await;
         }
     }
 
     /// Filters the list so that only those wants that haven't already been marked as exhausted
     /// are passed to `on_peers_exhausted`.
-    async fn process_exhausted_wants(&mut self, exhausted: impl IntoIterator<Item = Cid>) {
+    ///
+    /// Wants which every peer of the session `declined` with a DONT_HAVE are reported as
+    /// declined, the others are broadcast to find new peers.
+    async fn process_exhausted_wants(
+        &mut self,
+        exhausted: impl IntoIterator<Item = Cid>,
+        declined: bool,
+    ) {
         let newly_exhausted = self.newly_exhausted(exhausted.into_iter());
         if !newly_exhausted.is_empty() {
             // was "on_peers_exhausted"
-            if let Err(err) = self
-                .session_ops
-                .send(super::Op::Broadcast(newly_exhausted.into_iter().collect()))
-                .await
-            {
-                warn!("unabel to send broadcast op: {:?}", err);
+            let keys = newly_exhausted.into_iter().collect();
+            let op = if declined {
+                super::Op::Declined(keys)
+            } else {
+                super::Op::Broadcast(keys)
+            };
+            if let Err(err) = self.session_ops.send(op).await {
+                warn!("unabel to send exhausted op: {:?}", err);
             }
         }
     }
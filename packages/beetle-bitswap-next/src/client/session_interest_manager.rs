@@ -38,6 +38,14 @@ impl SessionInterestManager {
         }
     }
 
+    /// Returns `false` when no session is interested in the block, without waiting for the lock
+    /// and assuming an interest while it is taken.
+    pub fn may_be_interested(&self, key: &Cid) -> bool {
+        self.wants
+            .try_read()
+            .map_or(true, |wants| wants.contains_key(key))
+    }
+
     /// When the session shuts down, this is called.
     /// Returns the keys that no session is interested in anymore.
     pub async fn remove_session(&self, session: u64) -> Vec<Cid> {
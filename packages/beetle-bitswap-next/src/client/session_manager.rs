@@ -19,7 +19,7 @@ use crate::{network::Network, Block};
 
 use super::{
     block_presence_manager::BlockPresenceManager, peer_manager::PeerManager, session::Session,
-    session_interest_manager::SessionInterestManager,
+    session_interest_manager::SessionInterestManager, WantBroadcastPolicy,
 };
 
 #[derive(Clone)]
@@ -48,10 +48,12 @@ impl SessionManager {
         self_id: PeerId,
         network: Network,
         notify: async_broadcast::Sender<Block>,
+        want_broadcast: WantBroadcastPolicy,
     ) -> Self {
         let session_interest_manager = SessionInterestManager::default();
         let block_presence_manager = BlockPresenceManager::new();
         let peer_manager = PeerManager::new(self_id, network.clone()).await;
+        peer_manager.set_broadcast_policy(want_broadcast).await;
 
         let this = SessionManager {
             inner: Arc::new(Inner {
@@ -90,6 +92,10 @@ impl SessionManager {
         &self.inner.session_interest_manager
     }
 
+    pub fn block_presence_manager(&self) -> &BlockPresenceManager {
+        &self.inner.block_presence_manager
+    }
+
     pub async fn stop(self) -> Result<()> {
         let inner = Arc::try_unwrap(self.inner)
             .map_err(|_| anyhow!("session manager refs not shutdown"))?;
@@ -1,3 +1,4 @@
+use cid::Cid;
 use thiserror::Error;
 
 use crate::message::{BlockPresenceType, WantType};
@@ -17,3 +18,8 @@ pub enum Error {
     #[error("Invalid want type {0}")]
     InvalidWantType(#[from] num_enum::TryFromPrimitiveError<WantType>),
 }
+
+/// Returned when every peer of a session answered with a DONT_HAVE for a wanted block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("block {0} was not found on any of the known peers")]
+pub struct BlockNotFound(pub Cid);
@@ -28,7 +28,7 @@ use crate::{
     error::Error,
     message::BitswapMessage,
     network,
-    protocol::{BitswapCodec, ProtocolConfig, ProtocolId},
+    protocol::{BitswapCodec, BlockFilter, ProtocolConfig, ProtocolId},
 };
 
 /// The initial time (in seconds) we set the keep alive for protocol negotiations to occur.
@@ -40,6 +40,9 @@ pub enum BitswapHandlerError {
     /// The message exceeds the maximum transmission size.
     #[error("max transmission size")]
     MaxTransmissionSize,
+    /// The message carries a block exceeding the maximum block size.
+    #[error("max block size")]
+    MaxBlockSize,
     /// Protocol negotiation timeout.
     #[error("negotiation timeout")]
     NegotiationTimeout,
@@ -118,6 +121,9 @@ pub struct BitswapHandler {
 
     /// Flag determining whether to maintain the connection to the peer.
     keep_alive: KeepAlive,
+
+    /// Tells which blocks the inbound substreams want, the others being dropped from the messages.
+    block_filter: Option<BlockFilter>,
 }
 
 impl Debug for BitswapHandler {
@@ -138,6 +144,7 @@ impl Debug for BitswapHandler {
             .field("idle_timeout", &self.idle_timeout)
             .field("upgrade_errors", &self.upgrade_errors)
             .field("keep_alive", &self.keep_alive)
+            .field("block_filter", &self.block_filter.is_some())
             .finish()
     }
 }
@@ -155,8 +162,16 @@ impl BitswapHandler {
             upgrade_errors: VecDeque::new(),
             keep_alive: KeepAlive::Until(Instant::now() + Duration::from_secs(INITIAL_KEEP_ALIVE)),
             events: Default::default(),
+            block_filter: None,
         }
     }
+
+    /// Drops the blocks which `block_filter` does not want from the inbound messages, keeping the
+    /// rest of each message.
+    pub fn with_block_filter(mut self, block_filter: BlockFilter) -> Self {
+        self.block_filter = Some(block_filter);
+        self
+    }
 }
 
 impl ConnectionHandler for BitswapHandler {
@@ -201,8 +216,12 @@ impl ConnectionHandler for BitswapHandler {
     ) {
         match event {
             ConnectionEvent::FullyNegotiatedInbound(FullyNegotiatedInbound {
-                protocol, ..
+                mut protocol,
+                ..
             }) => {
+                protocol
+                    .codec_mut()
+                    .set_block_filter(self.block_filter.clone());
                 let protocol_id = protocol.codec().protocol;
                 if self.protocol.is_none() {
                     self.protocol = Some(protocol_id);
@@ -299,6 +318,11 @@ fn inbound_substream(
                     BitswapHandlerError::MaxTransmissionSize => {
                         warn!("Message exceeded the maximum transmission size");
                     }
+                    BitswapHandlerError::MaxBlockSize => {
+                        // stop reading the rest of the message, the connection is still fine
+                        warn!("Message carries a block exceeding the maximum block size");
+                        break;
+                    }
                     _ => {
                         warn!("Inbound stream error: {}", error);
                         // More serious errors, close this side of the stream. If the
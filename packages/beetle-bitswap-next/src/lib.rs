@@ -4,7 +4,9 @@
 
 use std::collections::HashSet;
 use std::fmt::Debug;
+use std::num::NonZeroU32;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
 use std::time::{Duration, Instant};
@@ -27,10 +29,14 @@ use tokio::sync::{mpsc, oneshot};
 use tokio::task::JoinHandle;
 use tracing::{debug, trace, warn};
 
+pub use self::client::WantBroadcastPolicy;
 use self::client::{Client, Config as ClientConfig};
+pub use self::error::BlockNotFound;
 use self::message::BitswapMessage;
 use self::network::Network;
 use self::network::OutEvent;
+pub use self::network::WantPriority;
+use self::prefix::Prefix;
 pub use self::protocol::ProtocolConfig;
 pub use self::server::{Config as ServerConfig, Server};
 
@@ -71,12 +77,49 @@ pub struct Bitswap<S: Store> {
     pause_dialing: bool,
     client: Client<S>,
     server: Option<Server<S>>,
-    incoming_messages: mpsc::Sender<(PeerId, BitswapMessage)>,
+    incoming_messages: mpsc::Sender<(PeerId, ProtocolId, BitswapMessage)>,
     peers_connected: mpsc::Sender<PeerId>,
     peers_disconnected: mpsc::Sender<PeerId>,
+    inbound_rate: Option<Arc<Mutex<InboundRate>>>,
+    dropped_messages: Arc<AtomicU64>,
+    /// Wants received while the server was disabled, see [`Bitswap::set_server_enabled`].
+    unserved_wants: Arc<AtomicU64>,
     _workers: Arc<Vec<JoinHandle<()>>>,
 }
 
+/// Counts the inbound messages of all peers received within the current second.
+#[derive(Debug)]
+struct InboundRate {
+    limit: u32,
+    window_start: Instant,
+    received: u32,
+}
+
+impl InboundRate {
+    fn new(limit: NonZeroU32) -> Self {
+        InboundRate {
+            limit: limit.get(),
+            window_start: Instant::now(),
+            received: 0,
+        }
+    }
+
+    /// Returns whether another message fits within the limit of the current second.
+    fn allow(&mut self) -> bool {
+        let now = Instant::now();
+        if now.duration_since(self.window_start) >= Duration::from_secs(1) {
+            self.window_start = now;
+            self.received = 0;
+        }
+
+        if self.received == self.limit {
+            return false;
+        }
+        self.received += 1;
+        true
+    }
+}
+
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
 enum PeerState {
     Connected(ConnectionId),
@@ -100,6 +143,9 @@ pub struct Config {
     pub server: Option<ServerConfig>,
     pub protocol: ProtocolConfig,
     pub idle_timeout: Duration,
+    /// Maximum number of messages received from all peers per second, any further messages are
+    /// dropped.
+    pub max_inbound_message_rate: Option<NonZeroU32>,
 }
 
 impl Config {
@@ -118,6 +164,7 @@ impl Default for Config {
             server: Some(ServerConfig::default()),
             protocol: ProtocolConfig::default(),
             idle_timeout: Duration::from_secs(30),
+            max_inbound_message_rate: None,
         }
     }
 }
@@ -141,28 +188,35 @@ impl<S: Store> Bitswap<S> {
         };
         let client = Client::new(network.clone(), store, cb, config.client).await;
 
-        let (sender_msg, mut receiver_msg) = mpsc::channel::<(PeerId, BitswapMessage)>(2048);
+        let (sender_msg, mut receiver_msg) = mpsc::channel::<(PeerId, ProtocolId, BitswapMessage)>(2048);
         let (sender_con, mut receiver_con) = mpsc::channel(2048);
         let (sender_dis, mut receiver_dis) = mpsc::channel(2048);
 
+        let unserved_wants = Arc::new(AtomicU64::new(0));
+
         let mut workers = Vec::new();
         workers.push(tokio::task::spawn({
             let server = server.clone();
             let client = client.clone();
+            let unserved_wants = unserved_wants.clone();
 
             async move {
                 // process messages serially but without blocking the p2p loop
-                while let Some((peer, mut message)) = receiver_msg.recv().await {
+                while let Some((peer, protocol, mut message)) = receiver_msg.recv().await {
                     let message = tokio::task::spawn_blocking(move || {
                         message.verify_blocks();
                         message
                     })
                     .await
                     .expect("cannot spawn blocking thread");
+                    if server.as_ref().map_or(false, |server| !server.is_enabled()) {
+                        let wants = message.wantlist().filter(|entry| !entry.cancel).count();
+                        unserved_wants.fetch_add(wants as u64, Ordering::Relaxed);
+                    }
                     if let Some(ref server) = server {
                         futures::future::join(
                             client.receive_message(&peer, &message),
-                            server.receive_message(&peer, &message),
+                            server.receive_message(&peer, protocol, &message),
                         )
                         .await;
                     } else {
@@ -224,6 +278,11 @@ impl<S: Store> Bitswap<S> {
             incoming_messages: sender_msg,
             peers_connected: sender_con,
             peers_disconnected: sender_dis,
+            inbound_rate: config
+                .max_inbound_message_rate
+                .map(|limit| Arc::new(Mutex::new(InboundRate::new(limit)))),
+            dropped_messages: Default::default(),
+            unserved_wants,
             _workers: Arc::new(workers),
         }
     }
@@ -256,6 +315,17 @@ impl<S: Store> Bitswap<S> {
         Ok(())
     }
 
+    /// Sends the message as is to the peer, bypassing the client and the server. The returned
+    /// future resolves once the message has been sent.
+    pub fn send_message(
+        &self,
+        peer: PeerId,
+        message: BitswapMessage,
+    ) -> impl std::future::Future<Output = Result<()>> + Send + 'static {
+        let network = self.network.clone();
+        async move { network.send_message(peer, message).await }
+    }
+
     /// Called on identify events from swarm, informing us about available protocols of this peer.
     /// TODO: Check within handler after updating to libp2p 0.52 instead of checking identify
     ///       If peer does not contain the protocol, emit an event from the handler to notify
@@ -301,13 +371,53 @@ impl<S: Store> Bitswap<S> {
         }
     }
 
-    fn receive_message(&self, peer: PeerId, message: BitswapMessage) {
+    /// Returns the number of inbound messages dropped, either for exceeding the
+    /// `max_inbound_message_rate` or for arriving faster than they could be processed.
+    pub fn dropped_messages(&self) -> u64 {
+        self.dropped_messages.load(Ordering::Relaxed)
+    }
+
+    /// Starts or stops answering the wants of other peers. The wants received while stopped are
+    /// still recorded, and answered once started again. Has no effect if the server is disabled
+    /// in the [`Config`].
+    pub fn set_server_enabled(&self, enabled: bool) {
+        if let Some(ref server) = self.server {
+            server.set_enabled(enabled);
+        }
+    }
+
+    /// Returns whether the wants of other peers are being answered.
+    pub fn is_server_enabled(&self) -> bool {
+        self.server
+            .as_ref()
+            .map_or(false, |server| server.is_enabled())
+    }
+
+    /// Returns the number of wants received while the server was stopped with
+    /// [`Bitswap::set_server_enabled`].
+    pub fn unserved_wants(&self) -> u64 {
+        self.unserved_wants.load(Ordering::Relaxed)
+    }
+
+    fn receive_message(&self, peer: PeerId, protocol: ProtocolId, message: BitswapMessage) {
+        if let Some(rate) = self.inbound_rate.as_ref() {
+            if !rate.lock().unwrap().allow() {
+                trace!(
+                    "inbound message rate exceeded, dropping message from {}",
+                    peer
+                );
+                self.dropped_messages.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+        }
+
         // TODO: Handle backpressure properly
-        if let Err(err) = self.incoming_messages.try_send((peer, message)) {
+        if let Err(err) = self.incoming_messages.try_send((peer, protocol, message)) {
             warn!(
                 "failed to receive message from {}: {:?}, dropping",
                 peer, err
             );
+            self.dropped_messages.fetch_add(1, Ordering::Relaxed);
         }
     }
 
@@ -404,7 +514,8 @@ impl<S: Store> NetworkBehaviour for Bitswap<S> {
         _: &Multiaddr,
     ) -> std::result::Result<THandler<Self>, ConnectionDenied> {
         let protocol_config = self.protocol_config.clone();
-        Ok(BitswapHandler::new(protocol_config, self.idle_timeout))
+        Ok(BitswapHandler::new(protocol_config, self.idle_timeout)
+            .with_block_filter(self.client.block_filter()))
     }
 
     fn handle_established_outbound_connection(
@@ -415,7 +526,8 @@ impl<S: Store> NetworkBehaviour for Bitswap<S> {
         _: libp2p::core::Endpoint,
     ) -> std::result::Result<THandler<Self>, ConnectionDenied> {
         let protocol_config = self.protocol_config.clone();
-        Ok(BitswapHandler::new(protocol_config, self.idle_timeout))
+        Ok(BitswapHandler::new(protocol_config, self.idle_timeout)
+            .with_block_filter(self.client.block_filter()))
     }
 
     #[allow(clippy::collapsible_match)]
@@ -500,7 +612,7 @@ impl<S: Store> NetworkBehaviour for Bitswap<S> {
             HandlerEvent::Message { message, protocol } => {
                 // mark peer as responsive
                 self.set_peer_state(&peer_id, PeerState::Responsive(connection, protocol));
-                self.receive_message(peer_id, message);
+                self.receive_message(peer_id, protocol, message);
             }
             HandlerEvent::FailedToSendMessage { .. } => {
                 // Handle
@@ -627,12 +739,137 @@ impl<S: Store> NetworkBehaviour for Bitswap<S> {
 
 pub fn verify_hash(cid: &Cid, bytes: &[u8]) -> Option<bool> {
     use cid::multihash::{Code, MultihashDigest};
+    if let Some(mut verifier) = HashVerifier::new(cid) {
+        verifier.update(bytes);
+        return Some(verifier.finish());
+    }
     Code::try_from(cid.hash().code()).ok().map(|code| {
         let calculated_hash = code.digest(bytes);
         &calculated_hash == cid.hash()
     })
 }
 
+/// Verifies the bytes of a block against its [`Cid`] as they arrive, without buffering them.
+///
+/// Only the running state of the hash function is kept, so a block of any size is verified in
+/// constant memory. A block growing past the optional length limit is rejected as soon as the
+/// limit is crossed; any other mismatch can only be told once the last byte has been hashed.
+pub struct HashVerifier {
+    hasher: Box<dyn cid::multihash::Hasher + Send>,
+    prefix: Prefix,
+    expected: Option<Cid>,
+    max_len: Option<usize>,
+    len: usize,
+}
+
+impl HashVerifier {
+    /// Creates a verifier for the block `cid`, or `None` if its hash function cannot be computed
+    /// incrementally.
+    pub fn new(cid: &Cid) -> Option<Self> {
+        let prefix = Prefix {
+            version: cid.version(),
+            codec: cid.codec(),
+            mh_type: cid::multihash::Code::try_from(cid.hash().code()).ok()?,
+            mh_len: cid.hash().digest().len(),
+        };
+        let mut verifier = Self::from_prefix(&prefix)?;
+        verifier.expected = Some(*cid);
+        Some(verifier)
+    }
+
+    /// Creates a verifier hashing a block of which only the [`Prefix`] is known, for the [`Cid`]
+    /// to be told once the last byte has arrived, or `None` if its hash function cannot be
+    /// computed incrementally.
+    pub(crate) fn from_prefix(prefix: &Prefix) -> Option<Self> {
+        use cid::multihash::*;
+
+        let hasher: Box<dyn Hasher + Send> = match prefix.mh_type {
+            Code::Sha2_256 => Box::<Sha2_256>::default(),
+            Code::Sha2_512 => Box::<Sha2_512>::default(),
+            Code::Sha3_224 => Box::<Sha3_224>::default(),
+            Code::Sha3_256 => Box::<Sha3_256>::default(),
+            Code::Sha3_384 => Box::<Sha3_384>::default(),
+            Code::Sha3_512 => Box::<Sha3_512>::default(),
+            Code::Keccak224 => Box::<Keccak224>::default(),
+            Code::Keccak256 => Box::<Keccak256>::default(),
+            Code::Keccak384 => Box::<Keccak384>::default(),
+            Code::Keccak512 => Box::<Keccak512>::default(),
+            Code::Blake2b256 => Box::<Blake2b256>::default(),
+            Code::Blake2b512 => Box::<Blake2b512>::default(),
+            Code::Blake2s128 => Box::<Blake2s128>::default(),
+            Code::Blake2s256 => Box::<Blake2s256>::default(),
+            Code::Blake3_256 => Box::<Blake3_256>::default(),
+            // further codes are only there when other crates enable more multihash features
+            #[allow(unreachable_patterns)]
+            _ => return None,
+        };
+
+        Some(HashVerifier {
+            hasher,
+            prefix: prefix.clone(),
+            expected: None,
+            max_len: None,
+            len: 0,
+        })
+    }
+
+    /// Rejects the block once more than `max_len` bytes have been fed to the verifier.
+    pub fn with_max_len(mut self, max_len: usize) -> Self {
+        self.max_len = Some(max_len);
+        self
+    }
+
+    /// Returns the number of bytes hashed so far.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if no bytes have been hashed yet.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Hashes the next chunk of the block, returning `false` once the block has grown past the
+    /// length limit, in which case the remaining bytes need not be read.
+    pub fn update(&mut self, chunk: &[u8]) -> bool {
+        self.len = self.len.saturating_add(chunk.len());
+        if self.max_len.map_or(false, |max| self.len > max) {
+            return false;
+        }
+        self.hasher.update(chunk);
+        true
+    }
+
+    /// Returns `true` if the bytes hashed so far make up the block.
+    pub fn finish(mut self) -> bool {
+        match self.expected.take() {
+            Some(expected) => self.into_cid() == Some(expected),
+            None => false,
+        }
+    }
+
+    /// Returns the [`Cid`] of the bytes hashed so far, or `None` if they grew past the length
+    /// limit.
+    pub fn into_cid(mut self) -> Option<Cid> {
+        use cid::multihash::Multihash;
+
+        if self.max_len.map_or(false, |max| self.len > max) {
+            return None;
+        }
+        let digest = Multihash::wrap(self.prefix.mh_type.into(), self.hasher.finalize()).ok()?;
+        Cid::new(self.prefix.version, self.prefix.codec, digest).ok()
+    }
+}
+
+impl Debug for HashVerifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HashVerifier")
+            .field("max_len", &self.max_len)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::{Error, ErrorKind};
@@ -680,6 +917,60 @@ mod tests {
         assert_send::<&Bitswap<DummyStore>>();
     }
 
+    #[test]
+    fn tampered_large_block_is_rejected_incrementally() {
+        use cid::multihash::{Code, Hasher, Multihash, Sha2_256};
+
+        const CHUNK: usize = 64 * 1024;
+        const CHUNKS: usize = 256;
+
+        // the payload is produced a chunk at a time into one reused buffer, so at no point is
+        // more than a single chunk of the 16 MiB block held in memory
+        let fill = |index: usize, buf: &mut [u8]| {
+            for (i, byte) in buf.iter_mut().enumerate() {
+                *byte = (index * 31 + i) as u8;
+            }
+        };
+
+        let mut buf = vec![0u8; CHUNK];
+        let mut hasher = Sha2_256::default();
+        for index in 0..CHUNKS {
+            fill(index, &mut buf);
+            hasher.update(&buf);
+        }
+        let digest = Multihash::wrap(Code::Sha2_256.into(), hasher.finalize()).unwrap();
+        let cid = Cid::new_v1(0x55, digest);
+
+        let mut feed = |verifier: &mut HashVerifier, tamper: bool| {
+            let mut fed = 0;
+            for index in 0..CHUNKS {
+                fill(index, &mut buf);
+                if tamper && index == CHUNKS - 1 {
+                    buf[CHUNK - 1] ^= 0xff;
+                }
+                fed += 1;
+                if !verifier.update(&buf) {
+                    break;
+                }
+            }
+            fed
+        };
+
+        let mut verifier = HashVerifier::new(&cid).unwrap();
+        assert_eq!(feed(&mut verifier, false), CHUNKS);
+        assert_eq!(verifier.len(), CHUNK * CHUNKS);
+        assert!(verifier.finish());
+
+        let mut verifier = HashVerifier::new(&cid).unwrap();
+        feed(&mut verifier, true);
+        assert!(!verifier.finish());
+
+        // a block longer than the limit is dropped without reading the rest of it
+        let mut verifier = HashVerifier::new(&cid).unwrap().with_max_len(4 * CHUNK);
+        assert_eq!(feed(&mut verifier, false), 5);
+        assert!(!verifier.finish());
+    }
+
     fn mk_transport() -> (PeerId, Boxed<(PeerId, StreamMuxerBox)>) {
         let local_key = Keypair::generate_ed25519();
 
@@ -886,7 +1177,7 @@ mod tests {
             let ids: Vec<_> = blocks.iter().map(|b| *b.cid()).collect();
             let session = swarm2_bs.client().new_session().await;
             let (blocks_receiver, _guard) = session.get_blocks(&ids).await.unwrap().into_parts();
-            let mut results: Vec<_> = blocks_receiver.collect().await;
+            let mut results: Vec<_> = blocks_receiver.map(Result::unwrap).collect().await;
 
             results.sort();
             blocks.sort();
@@ -903,4 +1194,178 @@ mod tests {
         peer2.abort();
         peer2.await.ok();
     }
+
+    #[tokio::test]
+    async fn dont_have_for_missing_block() {
+        let (peer1_id, trans) = mk_transport();
+        let bs1 = Bitswap::new(peer1_id, TestStore::default(), Config::default()).await;
+        let mut swarm1 = SwarmBuilder::with_tokio_executor(trans, bs1, peer1_id).build();
+
+        let (tx, mut rx) = mpsc::channel::<Multiaddr>(1);
+
+        Swarm::listen_on(&mut swarm1, "/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+
+        let peer1 = tokio::task::spawn(async move {
+            while swarm1.next().now_or_never().is_some() {}
+            let listeners: Vec<_> = Swarm::listeners(&swarm1).collect();
+            for l in listeners {
+                tx.send(l.clone()).await.unwrap();
+            }
+
+            loop {
+                let ev = swarm1.next().await;
+                trace!("peer1: {:?}", ev);
+            }
+        });
+
+        let (peer2_id, trans) = mk_transport();
+        let bs2 = Bitswap::new(peer2_id, TestStore::default(), Config::default()).await;
+        let mut swarm2 = SwarmBuilder::with_tokio_executor(trans, bs2, peer2_id).build();
+
+        let swarm2_bs = swarm2.behaviour().clone();
+        let (connected_tx, mut connected_rx) = mpsc::channel::<()>(1);
+        let peer2 = tokio::task::spawn(async move {
+            let addr = rx.recv().await.unwrap();
+            Swarm::dial(&mut swarm2, addr).unwrap();
+
+            loop {
+                match swarm2.next().await {
+                    Some(SwarmEvent::ConnectionEstablished { peer_id, .. }) => {
+                        swarm2
+                            .behaviour()
+                            .on_identify(&peer_id, &[StreamProtocol::new("/ipfs/bitswap/1.2.0")]);
+                        connected_tx.send(()).await.ok();
+                    }
+                    ev => trace!("peer2: {:?}", ev),
+                }
+            }
+        });
+
+        connected_rx.recv().await.unwrap();
+
+        // peer1 does not have the block, so the request stays pending
+        let block = create_random_block_v1();
+        let request = tokio::task::spawn({
+            let client = swarm2_bs.client().clone();
+            let cid = *block.cid();
+            async move { client.get_block(&cid).await }
+        });
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !swarm2_bs
+                .client()
+                .peer_does_not_have_block(&peer1_id, block.cid())
+                .await
+            {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .expect("DONT_HAVE was not received");
+
+        request.abort();
+        peer1.abort();
+        peer1.await.ok();
+        peer2.abort();
+        peer2.await.ok();
+    }
+
+    #[tokio::test]
+    async fn want_broadcast_to_random_subset() {
+        const PEERS: usize = 4;
+        const SUBSET: usize = 2;
+
+        let mut servers = Vec::new();
+        let mut addrs = Vec::new();
+        let mut tasks = Vec::new();
+
+        for _ in 0..PEERS {
+            let (peer_id, trans) = mk_transport();
+            let bs = Bitswap::new(peer_id, TestStore::default(), Config::default()).await;
+            let mut swarm = SwarmBuilder::with_tokio_executor(trans, bs, peer_id).build();
+            Swarm::listen_on(&mut swarm, "/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+
+            let addr = loop {
+                if let Some(SwarmEvent::NewListenAddr { address, .. }) = swarm.next().await {
+                    break address;
+                }
+            };
+
+            servers.push(swarm.behaviour().clone());
+            addrs.push(addr);
+            tasks.push(tokio::task::spawn(async move {
+                loop {
+                    let ev = swarm.next().await;
+                    trace!("server: {:?}", ev);
+                }
+            }));
+        }
+
+        let (client_id, trans) = mk_transport();
+        let mut config = Config::default();
+        config.client.want_broadcast = WantBroadcastPolicy::Random(SUBSET);
+        config.client.provider_search_delay = Duration::from_secs(60);
+        let bs = Bitswap::new(client_id, TestStore::default(), config).await;
+        let mut swarm = SwarmBuilder::with_tokio_executor(trans, bs, client_id).build();
+        let client = swarm.behaviour().clone();
+
+        for addr in addrs {
+            Swarm::dial(&mut swarm, addr).unwrap();
+        }
+
+        let (connected_tx, mut connected_rx) = mpsc::channel::<()>(PEERS);
+        tasks.push(tokio::task::spawn(async move {
+            loop {
+                match swarm.next().await {
+                    Some(SwarmEvent::ConnectionEstablished { peer_id, .. }) => {
+                        swarm
+                            .behaviour()
+                            .on_identify(&peer_id, &[StreamProtocol::new("/ipfs/bitswap/1.2.0")]);
+                        connected_tx.send(()).await.ok();
+                    }
+                    ev => trace!("client: {:?}", ev),
+                }
+            }
+        }));
+
+        for _ in 0..PEERS {
+            connected_rx.recv().await.unwrap();
+        }
+
+        let block = create_random_block_v1();
+        let request = tokio::task::spawn({
+            let client = client.client().clone();
+            let cid = *block.cid();
+            async move { client.get_block(&cid).await }
+        });
+
+        let receivers = || async {
+            let mut count = 0;
+            for server in &servers {
+                let wants = server.server().unwrap().wantlist_for_peer(&client_id).await;
+                if wants.contains(block.cid()) {
+                    count += 1;
+                }
+            }
+            count
+        };
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while receivers().await < SUBSET {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .expect("want was not received");
+
+        // give the want time to reach any other peer
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(receivers().await, SUBSET);
+
+        request.abort();
+        for task in tasks {
+            task.abort();
+            task.await.ok();
+        }
+    }
 }
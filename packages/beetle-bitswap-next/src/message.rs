@@ -281,6 +281,11 @@ impl BitswapMessage {
         self.blocks.values()
     }
 
+    /// Keeps only the blocks for which `f` returns `true`.
+    pub fn retain_blocks(&mut self, mut f: impl FnMut(&Cid) -> bool) {
+        self.blocks.retain(|cid, _| f(cid));
+    }
+
     pub fn block_presences(&self) -> impl Iterator<Item = BlockPresence> + '_ {
         self.block_presences.iter().map(|(cid, typ)| BlockPresence {
             cid: *cid,
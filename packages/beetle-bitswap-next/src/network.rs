@@ -1,11 +1,12 @@
 use std::{
     collections::HashSet,
     pin::Pin,
-    sync::{atomic::AtomicUsize, Arc},
+    sync::{atomic::AtomicUsize, Arc, Mutex},
     task::{Context, Poll},
     time::Duration,
 };
 
+use ahash::AHashMap;
 use anyhow::{anyhow, bail, Context as _, Result};
 use cid::Cid;
 use futures::Stream;
@@ -14,7 +15,11 @@ use libp2p::{swarm::ConnectionId, PeerId};
 use tokio::sync::{mpsc, oneshot};
 use tracing::{debug, error, info, trace};
 
-use crate::{message::BitswapMessage, protocol::ProtocolId, BitswapEvent};
+use crate::{
+    message::{BitswapMessage, Priority},
+    protocol::ProtocolId,
+    BitswapEvent,
+};
 
 const CONNECT_TIMEOUT: Duration = Duration::from_secs(20);
 const MAX_SEND_TIMEOUT: Duration = Duration::from_secs(3 * 60 + 5);
@@ -29,6 +34,29 @@ pub struct Network {
     network_out_sender: async_channel::Sender<OutEvent>,
     self_id: PeerId,
     dial_id: Arc<AtomicUsize>,
+    want_priorities: Arc<Mutex<AHashMap<Cid, Vec<Priority>>>>,
+}
+
+/// Keeps a block wanted at the priority set with [`Network::prioritize_want`] until dropped.
+#[derive(Debug)]
+pub struct WantPriority {
+    key: Cid,
+    priority: Priority,
+    priorities: Arc<Mutex<AHashMap<Cid, Vec<Priority>>>>,
+}
+
+impl Drop for WantPriority {
+    fn drop(&mut self) {
+        let mut priorities = self.priorities.lock().unwrap();
+        if let Some(wanted) = priorities.get_mut(&self.key) {
+            if let Some(index) = wanted.iter().position(|p| *p == self.priority) {
+                wanted.swap_remove(index);
+            }
+            if wanted.is_empty() {
+                priorities.remove(&self.key);
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -72,6 +100,7 @@ impl Network {
             network_out_sender,
             self_id,
             dial_id: Arc::new(AtomicUsize::new(0)),
+            want_priorities: Default::default(),
         }
     }
 
@@ -79,6 +108,33 @@ impl Network {
         &self.self_id
     }
 
+    /// Sends the wants for `key` to the peers at `priority` instead of the default of zero, until
+    /// the returned guard is dropped. A block wanted at several priorities is wanted at the
+    /// highest of them.
+    pub fn prioritize_want(&self, key: Cid, priority: Priority) -> WantPriority {
+        self.want_priorities
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_default()
+            .push(priority);
+        WantPriority {
+            key,
+            priority,
+            priorities: self.want_priorities.clone(),
+        }
+    }
+
+    /// Returns the priority the wants for `key` are sent to the peers at.
+    pub fn want_priority(&self, key: &Cid) -> Priority {
+        self.want_priorities
+            .lock()
+            .unwrap()
+            .get(key)
+            .and_then(|wanted| wanted.iter().max().copied())
+            .unwrap_or_default()
+    }
+
     pub async fn ping(&self, peer: &PeerId) -> Result<Duration> {
         let (s, r) = oneshot::channel();
         let res = tokio::time::timeout(Duration::from_secs(30), async {
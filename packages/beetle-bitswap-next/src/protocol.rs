@@ -1,9 +1,11 @@
 use std::fmt;
 use std::future::Future;
 use std::pin::Pin;
+use std::sync::Arc;
 
 use asynchronous_codec::{Decoder, Encoder, Framed};
 use bytes::{Bytes, BytesMut};
+use cid::Cid;
 use futures::future;
 use futures::io::{AsyncRead, AsyncWrite};
 use libp2p::core::{InboundUpgrade, OutboundUpgrade, UpgradeInfo};
@@ -15,6 +17,9 @@ use crate::{handler::BitswapHandlerError, message::BitswapMessage};
 
 const MAX_BUF_SIZE: usize = 1024 * 1024 * 2;
 
+/// Tells whether a received block is wanted, the others being dropped from the message.
+pub type BlockFilter = Arc<dyn Fn(&Cid) -> bool + Send + Sync>;
+
 #[derive(Clone, Debug, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum ProtocolId {
     Legacy = 0,
@@ -80,6 +85,9 @@ pub struct ProtocolConfig {
     pub protocol_ids: Vec<ProtocolId>,
     /// Maximum size of a packet.
     pub max_transmit_size: usize,
+    /// Maximum size of a block received. A packet carrying a larger block is rejected as soon as
+    /// the length of the block arrives, before the block itself is buffered.
+    pub max_block_size: usize,
 }
 
 impl Default for ProtocolConfig {
@@ -92,6 +100,7 @@ impl Default for ProtocolConfig {
                 ProtocolId::Legacy,
             ],
             max_transmit_size: MAX_BUF_SIZE,
+            max_block_size: MAX_BUF_SIZE,
         }
     }
 }
@@ -120,7 +129,7 @@ where
         length_codec.set_max_len(self.max_transmit_size);
         Box::pin(future::ok(Framed::new(
             socket,
-            BitswapCodec::new(length_codec, protocol_id),
+            BitswapCodec::new(length_codec, protocol_id).with_max_block_size(self.max_block_size),
         )))
     }
 }
@@ -140,7 +149,7 @@ where
         length_codec.set_max_len(self.max_transmit_size);
         Box::pin(future::ok(Framed::new(
             socket,
-            BitswapCodec::new(length_codec, protocol_id),
+            BitswapCodec::new(length_codec, protocol_id).with_max_block_size(self.max_block_size),
         )))
     }
 }
@@ -150,6 +159,10 @@ pub struct BitswapCodec {
     /// Codec to encode/decode the Unsigned varint length prefix of the frames.
     pub length_codec: codec::UviBytes,
     pub protocol: ProtocolId,
+    max_block_size: usize,
+    block_filter: Option<BlockFilter>,
+    /// Whether the length of the packet being received has been read.
+    receiving: bool,
 }
 
 impl fmt::Debug for BitswapCodec {
@@ -157,6 +170,9 @@ impl fmt::Debug for BitswapCodec {
         f.debug_struct("BitswapCodec")
             .field("length_codec", &"unsigned_varint::codec::UviBytes")
             .field("protocol", &self.protocol)
+            .field("max_block_size", &self.max_block_size)
+            .field("block_filter", &self.block_filter.is_some())
+            .field("receiving", &self.receiving)
             .finish()
     }
 }
@@ -166,10 +182,76 @@ impl BitswapCodec {
         BitswapCodec {
             length_codec,
             protocol,
+            max_block_size: usize::MAX,
+            block_filter: None,
+            receiving: false,
+        }
+    }
+
+    /// Rejects the packets carrying a block larger than `max_block_size`.
+    pub fn with_max_block_size(mut self, max_block_size: usize) -> Self {
+        self.max_block_size = max_block_size;
+        self
+    }
+
+    /// Drops the blocks which `block_filter` does not want from the received packets, keeping the
+    /// rest of them. The blocks are only told apart once the whole packet has been buffered.
+    pub fn set_block_filter(&mut self, block_filter: Option<BlockFilter>) {
+        self.block_filter = block_filter;
+    }
+
+    /// Checks the part of a packet received so far, failing as soon as it declares a block larger
+    /// than the maximum block size.
+    fn check_partial_packet(&self, mut packet: &[u8]) -> Result<(), BitswapHandlerError> {
+        // walks the fields of the message until the part not received yet
+        while let Some((field, len)) = next_field(&mut packet) {
+            let Some(len) = len else {
+                continue;
+            };
+            let value = &packet[..packet.len().min(len)];
+            match field {
+                // the deprecated blocks carry the data directly
+                2 if len > self.max_block_size => return Err(BitswapHandlerError::MaxBlockSize),
+                // the payload blocks carry it in their second field
+                3 => {
+                    let mut block = value;
+                    while let Some((field, len)) = next_field(&mut block) {
+                        if field == 2 && len.map_or(false, |len| len > self.max_block_size) {
+                            return Err(BitswapHandlerError::MaxBlockSize);
+                        }
+                        if let Some(len) = len {
+                            block = &block[block.len().min(len)..];
+                        }
+                    }
+                }
+                _ => {}
+            }
+            if value.len() < len {
+                break;
+            }
+            packet = &packet[len..];
         }
+        Ok(())
     }
 }
 
+/// Reads the key of the next protobuf field, skipping over a varint value, and returns the field
+/// number with the length of a length delimited value. Returns `None` at the end of the buffer,
+/// or when the field is cut short or of a wire type the blocks are never sent as.
+fn next_field(buf: &mut &[u8]) -> Option<(u64, Option<usize>)> {
+    let (key, rest) = unsigned_varint::decode::u64(buf).ok()?;
+    let (len, rest) = match key & 0x7 {
+        0 => (None, unsigned_varint::decode::u64(rest).ok()?.1),
+        2 => {
+            let (len, rest) = unsigned_varint::decode::usize(rest).ok()?;
+            (Some(len), rest)
+        }
+        _ => return None,
+    };
+    *buf = rest;
+    Some((key >> 3, len))
+}
+
 impl Encoder for BitswapCodec {
     type Item = BitswapMessage;
     type Error = BitswapHandlerError;
@@ -198,6 +280,7 @@ impl Decoder for BitswapCodec {
     type Error = BitswapHandlerError;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let buffered = src.len();
         let packet = match self.length_codec.decode(src).map_err(|e| {
             if let std::io::ErrorKind::PermissionDenied = e.kind() {
                 BitswapHandlerError::MaxTransmissionSize
@@ -206,10 +289,32 @@ impl Decoder for BitswapCodec {
             }
         })? {
             Some(p) => p,
-            None => return Ok(None),
+            None => {
+                // the length codec consumes the length of the packet once it has all of it,
+                // leaving only the start of the packet itself buffered
+                self.receiving |= src.len() < buffered;
+                if self.receiving {
+                    if let Err(e) = self.check_partial_packet(src) {
+                        self.receiving = false;
+                        return Err(e);
+                    }
+                }
+                return Ok(None);
+            }
         };
-
-        let message = BitswapMessage::try_from(packet.freeze())?;
+        self.receiving = false;
+
+        let mut message = BitswapMessage::try_from(packet.freeze())?;
+        if let Some(block_filter) = &self.block_filter {
+            // a block is routinely still on its way when the want for it is cancelled
+            message.retain_blocks(|cid| {
+                let wanted = block_filter(cid);
+                if !wanted {
+                    tracing::debug!("dropping unwanted block {cid}");
+                }
+                wanted
+            });
+        }
 
         Ok(Some((message, self.protocol)))
     }
@@ -251,6 +356,101 @@ mod tests {
     //     future::select(Box::pin(server), Box::pin(client)).await;
     // }
 
+    #[test]
+    fn oversized_block_is_rejected_before_it_is_received() {
+        let new_codec = || {
+            let mut length_codec = codec::UviBytes::default();
+            length_codec.set_max_len(MAX_BUF_SIZE);
+            BitswapCodec::new(length_codec, ProtocolId::Bitswap120).with_max_block_size(1024)
+        };
+
+        // the start of a packet with a payload block of `len` bytes, only its first bytes sent
+        let partial = |len: usize| {
+            let mut block = vec![0x0a, 4, 1, 0x55, 0x12, 0x20, 0x12];
+            block.extend_from_slice(unsigned_varint::encode::usize(len, &mut Default::default()));
+            let mut message = vec![0x1a];
+            message.extend_from_slice(unsigned_varint::encode::usize(
+                block.len() + len,
+                &mut Default::default(),
+            ));
+            message.extend_from_slice(&block);
+            message.extend_from_slice(&[0; 16]);
+
+            let mut packet = BytesMut::new();
+            let total = message.len() - 16 + len;
+            packet.extend_from_slice(unsigned_varint::encode::usize(
+                total,
+                &mut Default::default(),
+            ));
+            packet.extend_from_slice(&message);
+            packet
+        };
+
+        assert!(matches!(new_codec().decode(&mut partial(1024)), Ok(None)));
+        assert!(matches!(
+            new_codec().decode(&mut partial(1025)),
+            Err(BitswapHandlerError::MaxBlockSize)
+        ));
+    }
+
+    #[tokio::test]
+    async fn unwanted_block_is_dropped_from_the_message() {
+        use asynchronous_codec::FramedRead;
+        use cid::multihash::{Code, MultihashDigest};
+        use futures::{StreamExt, TryStreamExt};
+
+        use crate::message::BlockPresenceType;
+        use crate::Block;
+
+        const CHUNK: usize = 64 * 1024;
+        const PRESENCES: usize = 4096;
+
+        let data = (0..1024 * 1024).map(|i| (i * 31) as u8).collect::<Vec<_>>();
+        let cid = Cid::new_v1(0x55, Code::Sha2_256.digest(&data));
+
+        let new_codec = |filtered: bool| {
+            let mut length_codec = codec::UviBytes::default();
+            length_codec.set_max_len(4 * MAX_BUF_SIZE);
+            let mut codec = BitswapCodec::new(length_codec, ProtocolId::Bitswap120);
+            if filtered {
+                codec.set_block_filter(Some(Arc::new(move |received| *received == cid)));
+            }
+            codec
+        };
+
+        // a packet with the block followed by presences
+        let packet = |data: Vec<u8>| {
+            let mut message = BitswapMessage::new(false);
+            message.add_block(Block::new(data, cid));
+            for i in 0..PRESENCES as u32 {
+                let presence = Cid::new_v1(0x55, Code::Sha2_256.digest(&i.to_be_bytes()));
+                message.add_block_presence(presence, BlockPresenceType::DontHave);
+            }
+            let mut packet = BytesMut::new();
+            new_codec(false).encode(message, &mut packet).unwrap();
+            packet
+        };
+        // receives the packet a chunk at a time
+        let receive = |packet: BytesMut| async move {
+            let chunks = packet.chunks(CHUNK).map(<[u8]>::to_vec).collect::<Vec<_>>();
+            let stream = futures::stream::iter(chunks).map(Ok::<_, std::io::Error>);
+            let mut framed = FramedRead::new(stream.into_async_read(), new_codec(true));
+            let (message, _) = framed.next().await.unwrap().unwrap();
+            message
+        };
+
+        let message = receive(packet(data.clone())).await;
+        assert_eq!(message.blocks().next().unwrap().cid(), &cid);
+        assert_eq!(message.dont_haves().count(), PRESENCES);
+
+        // the bytes no longer hash to the wanted block
+        let mut tampered = data;
+        *tampered.last_mut().unwrap() ^= 0xff;
+        let message = receive(packet(tampered)).await;
+        assert_eq!(message.blocks_len(), 0);
+        assert_eq!(message.dont_haves().count(), PRESENCES);
+    }
+
     #[test]
     fn test_ord() {
         let mut protocols = [
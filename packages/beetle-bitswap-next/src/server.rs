@@ -6,7 +6,7 @@ use futures::future::BoxFuture;
 use futures::FutureExt;
 
 use libp2p::PeerId;
-use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::sync::{mpsc, oneshot, watch, Mutex};
 use tokio::task::JoinHandle;
 use tracing::{debug, error, trace, warn};
 
@@ -14,7 +14,7 @@ use self::{
     decision::{Config as DecisionConfig, Engine as DecisionEngine, Envelope},
     score_ledger::Receipt,
 };
-use crate::{block::Block, message::BitswapMessage, network::Network, Store};
+use crate::{block::Block, message::BitswapMessage, network::Network, ProtocolId, Store};
 
 mod blockstore_manager;
 mod decision;
@@ -73,6 +73,8 @@ struct Inner {
     new_blocks: mpsc::Sender<Cid>,
     /// Wether or not to make provide announcements.
     provide_enabled: bool,
+    /// Whether the workers send out responses, see [`Server::set_enabled`].
+    serving: watch::Sender<bool>,
     workers: Vec<(oneshot::Sender<()>, JoinHandle<()>)>,
     provide_worker: Option<(oneshot::Sender<()>, JoinHandle<()>)>,
     provide_collector: Option<(oneshot::Sender<()>, JoinHandle<()>)>,
@@ -90,6 +92,7 @@ impl<S: Store> Server<S> {
         let mut provide_collector = None;
 
         let engine = Arc::new(engine);
+        let (serving, _) = watch::channel(true);
 
         // start up workers to handle requests from other nodes for the data on this node
         let rt = tokio::runtime::Handle::current();
@@ -98,16 +101,24 @@ impl<S: Store> Server<S> {
             let outbox = engine.outbox();
             let engine = engine.clone();
             let network = network.clone();
+            let mut serving = serving.subscribe();
 
             let handle = rt.spawn(async move {
                 loop {
+                    let enabled = *serving.borrow_and_update();
                     tokio::select! {
                         biased;
                         _ = &mut closer_r => {
                             // shutdown
                             break;
                         }
-                        envelope = outbox.recv() => {
+                        changed = serving.changed() => {
+                            if changed.is_err() {
+                                break;
+                            }
+                        }
+                        // while disabled, the responses are held back in the outbox
+                        envelope = outbox.recv(), if enabled => {
                             match envelope {
                                 Ok(Ok(envelope)) => {
                                     // let start = Instant::now();
@@ -204,6 +215,7 @@ impl<S: Store> Server<S> {
                 counters: Mutex::new(Stat::default()),
                 new_blocks: new_blocks.0,
                 provide_enabled,
+                serving,
                 workers,
                 provide_worker,
                 provide_collector,
@@ -226,6 +238,17 @@ impl<S: Store> Server<S> {
             .collect()
     }
 
+    /// Starts or stops sending out responses to the wants of other peers. The wants received
+    /// meanwhile are still recorded, and answered once sending is enabled again.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.inner.serving.send_replace(enabled);
+    }
+
+    /// Returns whether responses are being sent out.
+    pub fn is_enabled(&self) -> bool {
+        *self.inner.serving.borrow()
+    }
+
     pub async fn stop(self) -> Result<()> {
         // trigger shutdown of the worker threads
         // wait for all workers to be done
@@ -287,9 +310,14 @@ impl<S: Store> Server<S> {
         Ok(())
     }
 
-    pub async fn receive_message(&self, peer: &PeerId, message: &BitswapMessage) {
+    pub async fn receive_message(
+        &self,
+        peer: &PeerId,
+        protocol: ProtocolId,
+        message: &BitswapMessage,
+    ) {
         trace!("server:receive_message from {}: {:?}", peer, message);
-        self.engine.message_received(peer, message).await;
+        self.engine.message_received(peer, protocol, message).await;
         // TODO: only track useful messages
     }
 
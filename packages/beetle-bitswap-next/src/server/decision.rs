@@ -16,7 +16,7 @@ use crate::{
     client::wantlist,
     message::{BitswapMessage, BlockPresence, BlockPresenceType, Entry, WantType},
     peer_task_queue::{Config as PTQConfig, PeerTaskQueue, Task},
-    Store,
+    ProtocolId, Store,
 };
 
 use super::{
@@ -327,7 +327,15 @@ impl<S: Store> Engine<S> {
     /// MessageReceived is called when a message is received from a remote peer.
     /// For each item in the wantlist, add a want-have or want-block entry to the
     /// request queue (this is later popped off by the workerTasks)
-    pub async fn message_received(&self, peer: &PeerId, message: &BitswapMessage) {
+    ///
+    /// Peers speaking `protocol` bitswap 1.2.0 get a DONT_HAVE for every block missing
+    /// from the blockstore, so they don't have to wait for a timeout.
+    pub async fn message_received(
+        &self,
+        peer: &PeerId,
+        protocol: ProtocolId,
+        message: &BitswapMessage,
+    ) {
         if message.is_empty() {
             info!("received empty message from {}", peer);
         }
@@ -381,8 +389,8 @@ impl<S: Store> Engine<S> {
         }
 
         let send_dont_have = |entries: &mut Vec<_>, new_work_exists: &mut bool, entry: &Entry| {
-            // only add the task to the queue if the requester wants DONT_HAVE
-            if self.send_dont_haves && entry.send_dont_have {
+            // only add the task to the queue if the requester wants or understands DONT_HAVE
+            if self.send_dont_haves && (entry.send_dont_have || protocol.supports_have()) {
                 let cid = entry.cid;
                 *new_work_exists = true;
                 let is_want_block = entry.want_type == WantType::Block;
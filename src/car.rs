@@ -0,0 +1,164 @@
+//! Writing and reading of [CARv1](https://ipld.io/specs/transport/car/carv1/) archives.
+use crate::error::Error;
+use crate::Block;
+use libipld::{cbor::DagCborCodec, codec::Codec, Cid, Ipld};
+use std::collections::BTreeMap;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Upper bound of the length of the header and of a single block section, guarding the
+/// allocations against corrupted archives.
+const MAX_SECTION_LEN: u64 = 8 * 1024 * 1024;
+
+/// Writes the header and the blocks of a CARv1 archive to `W`, in the order given.
+pub(crate) struct CarWriter<W> {
+    writer: W,
+    header_written: bool,
+}
+
+impl<W: AsyncWrite + Unpin> CarWriter<W> {
+    pub fn new(writer: W) -> Self {
+        CarWriter {
+            writer,
+            header_written: false,
+        }
+    }
+
+    /// Writes the header listing the `roots` of the archive. Must be called before any blocks
+    /// are written.
+    pub async fn write_header(&mut self, roots: &[Cid]) -> Result<(), Error> {
+        anyhow::ensure!(!self.header_written, "car header already written");
+
+        let mut header = BTreeMap::new();
+        header.insert(
+            "roots".to_owned(),
+            Ipld::List(roots.iter().copied().map(Ipld::Link).collect()),
+        );
+        header.insert("version".to_owned(), Ipld::Integer(1));
+        let header = DagCborCodec.encode(&Ipld::Map(header))?;
+
+        self.write_varint(header.len() as u64).await?;
+        self.writer.write_all(&header).await?;
+        self.header_written = true;
+        Ok(())
+    }
+
+    pub async fn write_block(&mut self, block: &Block) -> Result<(), Error> {
+        anyhow::ensure!(self.header_written, "car header must be written first");
+
+        let cid = block.cid().to_bytes();
+        self.write_varint((cid.len() + block.data().len()) as u64)
+            .await?;
+        self.writer.write_all(&cid).await?;
+        self.writer.write_all(block.data()).await?;
+        Ok(())
+    }
+
+    /// Flushes the underlying writer and returns it.
+    pub async fn finish(mut self) -> Result<W, Error> {
+        self.writer.flush().await?;
+        Ok(self.writer)
+    }
+
+    async fn write_varint(&mut self, mut value: u64) -> Result<(), Error> {
+        let mut buf = [0u8; 10];
+        let mut len = 0;
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                buf[len] = byte;
+                len += 1;
+                break;
+            }
+            buf[len] = byte | 0x80;
+            len += 1;
+        }
+        self.writer.write_all(&buf[..len]).await?;
+        Ok(())
+    }
+}
+
+/// Reads the header and then the blocks of a CARv1 archive from `R`, verifying each block
+/// against its Cid.
+pub(crate) struct CarReader<R> {
+    reader: R,
+}
+
+impl<R: AsyncRead + Unpin> CarReader<R> {
+    /// Reads the header, returning the reader positioned at the first block along with the
+    /// roots of the archive.
+    pub async fn new(reader: R) -> Result<(Self, Vec<Cid>), Error> {
+        let mut car = CarReader { reader };
+
+        let header = match car.read_section().await? {
+            Some(header) => header,
+            None => anyhow::bail!("car header is missing"),
+        };
+        let header: Ipld = DagCborCodec.decode(&header)?;
+
+        anyhow::ensure!(
+            matches!(header.get("version"), Ok(Ipld::Integer(1))),
+            "unsupported car version"
+        );
+        let roots = match header.get("roots") {
+            Ok(Ipld::List(roots)) => roots
+                .iter()
+                .map(|root| match root {
+                    Ipld::Link(cid) => Ok(*cid),
+                    _ => Err(anyhow::anyhow!("car root is not a link")),
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            _ => anyhow::bail!("car header has no roots"),
+        };
+
+        Ok((car, roots))
+    }
+
+    /// Returns the next block, or `None` once the archive is exhausted.
+    pub async fn next_block(&mut self) -> Result<Option<Block>, Error> {
+        let section = match self.read_section().await? {
+            Some(section) => section,
+            None => return Ok(None),
+        };
+
+        let mut cursor = std::io::Cursor::new(&section);
+        let cid = Cid::read_bytes(&mut cursor)?;
+        let data = section[cursor.position() as usize..].to_vec();
+        Ok(Some(Block::new(cid, data)?))
+    }
+
+    /// Reads a length prefixed section, or returns `None` at the end of the archive.
+    async fn read_section(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        let len = match self.read_varint().await? {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+        anyhow::ensure!(
+            len <= MAX_SECTION_LEN,
+            "car section of {len} bytes is too large"
+        );
+
+        let mut section = vec![0; len as usize];
+        self.reader.read_exact(&mut section).await?;
+        Ok(Some(section))
+    }
+
+    async fn read_varint(&mut self) -> Result<Option<u64>, Error> {
+        let mut value = 0u64;
+        for i in 0..10 {
+            let byte = match self.reader.read_u8().await {
+                Ok(byte) => byte,
+                // the archive may only end between sections
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof && i == 0 => {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e.into()),
+            };
+            value |= u64::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Ok(Some(value));
+            }
+        }
+        anyhow::bail!("car varint is too long")
+    }
+}
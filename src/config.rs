@@ -1,3 +1,17 @@
+use std::{path::Path, time::Duration};
+
+use either::Either;
+use libp2p::Multiaddr;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    p2p::{
+        BitswapConfig, ConnectionDedupPolicy, IdentifyConfiguration, KadConfig, PubsubConfig,
+        RelayConfig, TransportConfig,
+    },
+    IpfsOptions,
+};
+
 pub const BOOTSTRAP_NODES: &[&str] = &[
     "/ip4/104.131.131.82/tcp/4001/p2p/QmaCpDMGvV2BGHeYERUEnRQAwe3N8SzbUtfsmvsqQLuvuJ",
     "/dnsaddr/bootstrap.libp2p.io/p2p/QmNnooDu7bfjPFoTZYxMNLWUQJyrVwtbZg5gBMjTezGAJN",
@@ -6,9 +20,105 @@ pub const BOOTSTRAP_NODES: &[&str] = &[
     "/dnsaddr/bootstrap.libp2p.io/p2p/QmcZf59bWwK5XFi76CZX8cbJ4BhTzzA3gU1ZjYZcYW3dwt",
 ];
 
+/// Settings of a node which can be kept in a file, covering its networking. Any setting missing
+/// from the file takes the same default as in [`IpfsOptions`].
+///
+/// The node is created from the settings with [`crate::UninitializedIpfs::from_config`].
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct NodeConfig {
+    /// Addresses the node listens on.
+    pub listening_addrs: Vec<Multiaddr>,
+    /// Nodes used as bootstrap peers.
+    pub bootstrap: Vec<Multiaddr>,
+    /// Enables mdns for peer discovery and announcement.
+    pub mdns: bool,
+    /// Enables ipv6 for mdns.
+    pub mdns_ipv6: bool,
+    /// Enables the relay client.
+    pub relay: bool,
+    /// Enables the relay server.
+    pub relay_server: bool,
+    /// Relay server configuration.
+    pub relay_server_config: Option<RelayConfig>,
+    /// Enables dcutr.
+    pub dcutr: bool,
+    /// Keeps the connections alive.
+    pub keep_alive: bool,
+    /// Closes connections without any activity for this long, even when kept alive.
+    pub idle_connection_timeout: Option<Duration>,
+    /// Which of several connections to the same peer are kept.
+    pub connection_dedup: ConnectionDedupPolicy,
+    /// Enables port mapping (aka UPnP).
+    pub port_mapping: bool,
+    /// Disables the kademlia protocol.
+    pub disable_kad: bool,
+    /// Disables the bitswap protocol.
+    pub disable_bitswap: bool,
+    /// Disables the pubsub protocol.
+    pub disable_pubsub: bool,
+    /// Disables the identify protocol.
+    pub disable_identify: bool,
+    /// Transport configuration.
+    pub transport: Option<TransportConfig>,
+    /// Bitswap configuration.
+    pub bitswap: Option<BitswapConfig>,
+    /// Kademlia configuration.
+    pub kad: Option<KadConfig>,
+    /// Identify configuration.
+    pub identify: Option<IdentifyConfiguration>,
+    /// Pubsub configuration.
+    pub pubsub: Option<PubsubConfig>,
+}
+
+impl NodeConfig {
+    /// Reads the settings from a JSON file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        let config = serde_json::from_reader(std::io::BufReader::new(file))?;
+        Ok(config)
+    }
+
+    /// Writes the settings out to a JSON file, replacing it if it exists.
+    pub fn to_file<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let file = std::fs::File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+}
+
+impl From<NodeConfig> for IpfsOptions {
+    fn from(config: NodeConfig) -> Self {
+        IpfsOptions {
+            listening_addrs: config.listening_addrs,
+            bootstrap: config.bootstrap,
+            mdns: config.mdns,
+            mdns_ipv6: config.mdns_ipv6,
+            relay: config.relay,
+            relay_server: config.relay_server,
+            relay_server_config: config.relay_server_config,
+            dcutr: config.dcutr,
+            keep_alive: config.keep_alive,
+            idle_connection_timeout: config.idle_connection_timeout,
+            connection_dedup: config.connection_dedup,
+            port_mapping: config.port_mapping,
+            disable_kad: config.disable_kad,
+            disable_bitswap: config.disable_bitswap,
+            disable_pubsub: config.disable_pubsub,
+            disable_identify: config.disable_identify,
+            transport_configuration: config.transport,
+            bitswap_config: config.bitswap,
+            kad_configuration: config.kad.map(Either::Left),
+            identify_configuration: config.identify,
+            pubsub_config: config.pubsub,
+            ..Default::default()
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use libp2p::Multiaddr;
+    use libp2p::{Multiaddr, PeerId};
 
     #[test]
     fn bootstrap_nodes_are_multiaddr_with_peerid() {
@@ -17,4 +127,83 @@ mod tests {
             .try_for_each(|s| s.parse::<Multiaddr>().map(|_| ()))
             .unwrap();
     }
+
+    #[tokio::test]
+    async fn node_is_created_from_a_config_file() {
+        use super::NodeConfig;
+        use crate::{p2p::IdentifyConfiguration, UninitializedIpfsNoop};
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("config.json");
+
+        // never dialed, only added to the routing table
+        let bootstrap: Multiaddr = format!("/ip4/127.0.0.1/tcp/4001/p2p/{}", PeerId::random())
+            .parse()
+            .unwrap();
+
+        let config = NodeConfig {
+            listening_addrs: vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()],
+            bootstrap: vec![bootstrap],
+            disable_pubsub: true,
+            identify: Some(IdentifyConfiguration {
+                agent_version: "configured".into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        config.to_file(&path).unwrap();
+
+        let loaded = NodeConfig::from_file(&path).unwrap();
+        assert_eq!(
+            serde_json::to_value(&loaded).unwrap(),
+            serde_json::to_value(&config).unwrap()
+        );
+
+        let ipfs = UninitializedIpfsNoop::from_config(loaded)
+            .start()
+            .await
+            .unwrap();
+
+        let listening = ipfs.listening_addresses().await.unwrap();
+        assert!(listening
+            .iter()
+            .all(|addr| addr.to_string().starts_with("/ip4/127.0.0.1/tcp/")));
+        // the bootstrap peers are added to the routing table
+        assert_eq!(ipfs.kad_routing_table().await.unwrap().peers, 1);
+
+        let info = ipfs.identity(None).await.unwrap();
+        assert_eq!(info.agent_version, "configured");
+        assert!(!info
+            .protocols
+            .iter()
+            .any(|protocol| protocol.as_ref().starts_with("/meshsub/")));
+        assert!(ipfs.pubsub_subscribe("topic".into()).await.is_err());
+
+        ipfs.exit_daemon().await;
+    }
+
+    #[test]
+    fn missing_settings_take_the_defaults() {
+        let config: super::NodeConfig = serde_json::from_str(r#"{ "mdns": true }"#).unwrap();
+        assert!(config.mdns);
+        assert!(config.listening_addrs.is_empty());
+        assert!(config.bitswap.is_none());
+    }
+
+    #[test]
+    fn partial_sections_take_the_defaults() {
+        use crate::p2p::BitswapConfig;
+
+        let config: super::NodeConfig = serde_json::from_str(
+            r#"{ "bitswap": { "server": false }, "kad": {}, "transport": {}, "pubsub": {} }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.bitswap,
+            Some(BitswapConfig::default().with_server(false))
+        );
+        assert!(config.kad.is_some());
+        assert!(config.transport.is_some());
+        assert!(config.pubsub.is_some());
+    }
 }
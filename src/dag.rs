@@ -18,6 +18,7 @@ use rust_unixfs::{
     dir::{Cache, ShardedLookup},
     resolve, MaybeResolved,
 };
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::error::Error as StdError;
 use std::iter::Peekable;
@@ -242,6 +243,83 @@ impl IpldDag {
         Ok(cid)
     }
 
+    /// Re-encodes the locally available DAG rooted at `root` using the `hash` function instead of
+    /// the one in the original Cids, storing the new blocks in the repo and returning the new root.
+    ///
+    /// Links are rewritten to point to the rehashed children, and the new Cids are always of
+    /// version 1. Blocks already using `hash` are kept as they are unless one of their links was
+    /// rewritten. `rehashed` maps the already rehashed Cids to their replacements, which allows
+    /// DAGs sharing blocks to be rehashed only once. Fails if any block of the DAG is missing.
+    pub(crate) async fn rehash(
+        &self,
+        root: Cid,
+        hash: Code,
+        rehashed: &mut HashMap<Cid, Cid>,
+    ) -> Result<Cid, Error> {
+        let mut decoded: HashMap<Cid, Ipld> = HashMap::new();
+        // post-order traversal: the bool tells whether the children have already been pushed
+        let mut stack = vec![(root, false)];
+
+        while let Some((cid, children_done)) = stack.pop() {
+            if rehashed.contains_key(&cid) {
+                continue;
+            }
+
+            if !children_done {
+                let block = self
+                    .repo
+                    .get_block_now(&cid)
+                    .await?
+                    .ok_or_else(|| anyhow::anyhow!("block {cid} is not available locally"))?;
+                let ipld = block.decode::<IpldCodec, Ipld>()?;
+
+                stack.push((cid, true));
+
+                let mut links = Vec::new();
+                ipld.references(&mut links);
+                stack.extend(
+                    links
+                        .into_iter()
+                        .filter(|link| !rehashed.contains_key(link))
+                        .map(|link| (link, false)),
+                );
+
+                decoded.insert(cid, ipld);
+                continue;
+            }
+
+            let ipld = match decoded.remove(&cid) {
+                Some(ipld) => ipld,
+                None => continue,
+            };
+
+            // blocks already using the hash are only rewritten when one of their links changed
+            let mut links = Vec::new();
+            ipld.references(&mut links);
+            let relinked = links
+                .iter()
+                .any(|link| matches!(rehashed.get(link), Some(new) if new != link));
+            if cid.hash().code() == u64::from(hash) && !relinked {
+                rehashed.insert(cid, cid);
+                continue;
+            }
+            let ipld = relink(ipld, rehashed);
+
+            let codec = IpldCodec::try_from(cid.codec())?;
+            let bytes = codec.encode(&ipld)?;
+            let new_cid = Cid::new_v1(cid.codec(), hash.digest(&bytes));
+            let block = Block::new(new_cid, bytes)?;
+            self.repo.put_block(block).await?;
+
+            rehashed.insert(cid, new_cid);
+        }
+
+        rehashed
+            .get(&root)
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("failed to rehash {root}"))
+    }
+
     /// Resolves a `Cid`-rooted path to a document "node."
     ///
     /// Returns the resolved node as `Ipld`.
@@ -306,6 +384,10 @@ impl IpldDag {
     /// Regardless of the `follow_links` option, HAMT-sharded directories will be resolved through
     /// as a "single step" in the given IpfsPath.
     ///
+    /// Each block is decoded by the codec of its `Cid`, so a path can cross from dag-cbor or
+    /// dag-json documents into dag-pb (UnixFS) nodes and back: dag-pb segments are matched against
+    /// the link names, other segments against map keys and list indices.
+    ///
     /// Returns a node and the remaining path or the path inside the last document.
     pub async fn resolve(
         &self,
@@ -585,6 +667,20 @@ fn resolve_local<'a>(
     }
 }
 
+/// Replaces the links in the document with their rehashed counterparts.
+fn relink(ipld: Ipld, rehashed: &HashMap<Cid, Cid>) -> Ipld {
+    match ipld {
+        Ipld::Link(cid) => Ipld::Link(rehashed.get(&cid).copied().unwrap_or(cid)),
+        Ipld::List(list) => Ipld::List(list.into_iter().map(|i| relink(i, rehashed)).collect()),
+        Ipld::Map(map) => Ipld::Map(
+            map.into_iter()
+                .map(|(k, v)| (k, relink(v, rehashed)))
+                .collect(),
+        ),
+        other => other,
+    }
+}
+
 /// Resolving through dagpb documents is basically just mapping from [`MaybeResolved`] to the
 /// return value, with the exception that a path ending in "Data" is returned as
 /// `ResolvedNode::DagPbData`.
@@ -1115,6 +1211,89 @@ mod tests {
         );
     }
 
+    /// Puts a single block UnixFS file with the given content, returning its Cid.
+    async fn put_unixfs_file(ipfs: &Ipfs, content: &[u8]) -> Cid {
+        let mut adder = rust_unixfs::file::adder::FileAdder::default();
+        let (mut blocks, _) = adder.push(content);
+        assert_eq!(blocks.next(), None);
+
+        let (cid, data) = adder.finish().next().unwrap();
+        ipfs.put_block(Block::new(cid, data).unwrap())
+            .await
+            .unwrap()
+    }
+
+    /// Puts an UnixFS directory with the given links, returning the Cid of the directory.
+    async fn put_unixfs_dir(ipfs: &Ipfs, links: &[(&str, Cid)]) -> Cid {
+        let mut tree = rust_unixfs::dir::builder::BufferingTreeBuilder::new(Default::default());
+        for (name, cid) in links {
+            tree.put_link(&format!("root/{name}"), *cid, 0).unwrap();
+        }
+
+        let mut root = None;
+        let mut iter = tree.build();
+        while let Some(node) = iter.next_borrowed() {
+            let node = node.unwrap();
+            let block = Block::new(node.cid.to_owned(), node.block.into()).unwrap();
+            root = Some(ipfs.put_block(block).await.unwrap());
+        }
+        root.unwrap()
+    }
+
+    async fn cat(ipfs: &Ipfs, path: IpfsPath) -> Vec<u8> {
+        use futures::TryStreamExt;
+        ipfs.cat_unixfs(path, None)
+            .await
+            .unwrap()
+            .try_concat()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn resolve_from_dag_cbor_into_unixfs_file() {
+        let Node { ipfs, .. } = Node::new("test_node").await;
+        let file = put_unixfs_file(&ipfs, b"foobar\n").await;
+        let dir = put_unixfs_dir(&ipfs, &[("file", file)]).await;
+
+        let doc = ipfs
+            .put_dag(ipld!({ "content": { "file": file, "dir": dir } }))
+            .await
+            .unwrap();
+
+        let path = IpfsPath::from(doc).sub_path("content/file").unwrap();
+        match ipfs.dag().resolve(path.clone(), true, &[], false).await {
+            Ok((ResolvedNode::Block(block), _)) => assert_eq!(block.cid(), &file),
+            x => unreachable!("{:?}", x),
+        }
+        assert_eq!(cat(&ipfs, path).await, b"foobar\n");
+
+        let path = IpfsPath::from(doc).sub_path("content/dir/file").unwrap();
+        assert_eq!(cat(&ipfs, path).await, b"foobar\n");
+    }
+
+    #[tokio::test]
+    async fn resolve_from_unixfs_into_dag_json_and_back() {
+        let Node { ipfs, .. } = Node::new("test_node").await;
+        let file = put_unixfs_file(&ipfs, b"foobar\n").await;
+
+        let doc = ipfs
+            .dag()
+            .put(IpldCodec::DagJson, ipld!({ "files": [file] }), None)
+            .await
+            .unwrap();
+        let dir = put_unixfs_dir(&ipfs, &[("doc", doc)]).await;
+
+        let path = IpfsPath::from(dir).sub_path("doc/files").unwrap();
+        assert_eq!(
+            ipfs.dag().get(path, &[], false).await.unwrap(),
+            ipld!([file])
+        );
+
+        let path = IpfsPath::from(dir).sub_path("doc/files/0").unwrap();
+        assert_eq!(cat(&ipfs, path).await, b"foobar\n");
+    }
+
     #[test]
     fn observes_strict_order_of_map_keys() {
         let map = ipld!({
@@ -22,6 +22,7 @@
 // the docs better.
 //#![allow(private_intra_doc_links)]
 
+mod car;
 pub mod config;
 pub mod dag;
 pub mod error;
@@ -52,23 +53,26 @@ use futures::{
 
 use keystore::Keystore;
 use p2p::{
-    BitswapConfig, IdentifyConfiguration, KadConfig, KadStoreConfig, PeerInfo, PubsubConfig,
-    RelayConfig,
+    AutoRelayConfig, BitswapConfig, BitswapMessage, IdentifyConfiguration, KadConfig,
+    KadStoreConfig, PeerInfo, ProviderKeyScheme, PubsubConfig, RelayConfig, SwarmEventSender,
+};
+use repo::{
+    blockstore::transform::BlockTransform, datastore::sled::SledConfig, BlockStore, DataStore,
+    Lock, PinStore,
 };
-use repo::{BlockStore, DataStore, Lock};
 use tokio::task::JoinHandle;
 use tracing::Span;
 use tracing_futures::Instrument;
 use unixfs::{IpfsUnixfs, NodeItem, UnixfsStatus};
 
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     fmt, io,
     ops::{Deref, DerefMut, Range},
     path::{Path, PathBuf},
     sync::atomic::AtomicU64,
     sync::Arc,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use self::{
@@ -78,7 +82,11 @@ use self::{
     repo::Repo,
 };
 
-pub use self::p2p::gossipsub::SubscriptionStream;
+pub use self::p2p::gossipsub::{
+    MultiSubscriptionStream, PubsubMessage, PubsubPublishError, Subscription, SubscriptionStream,
+};
+
+pub use beetle_bitswap_next::BlockNotFound;
 
 pub use self::{
     error::Error,
@@ -90,7 +98,10 @@ pub use self::{
 
 pub type Block = libipld::Block<libipld::DefaultParams>;
 
-use libipld::{Cid, Ipld, IpldCodec};
+use libipld::{
+    multihash::{Code, MultihashDigest},
+    Cid, Ipld, IpldCodec,
+};
 
 pub use libp2p::{
     self,
@@ -106,6 +117,7 @@ pub use libp2p::{
 };
 
 use libp2p::{
+    autonat::Config as AutonatConfig,
     core::{muxing::StreamMuxerBox, transport::Boxed},
     kad::{store::MemoryStoreConfig, KademliaConfig, Mode, Record},
     ping::Config as PingConfig,
@@ -161,6 +173,14 @@ pub struct IpfsOptions {
     /// existing repository.
     pub ipfs_path: StoragePath,
 
+    /// Encrypts the data of the blocks in the block store with this key; the Cids of the blocks
+    /// stay computed over the plain data
+    pub block_encryption_key: Option<[u8; 32]>,
+
+    /// Settings of the sled data store of a repo on disk, used with the `sled_data_store`
+    /// feature
+    pub sled_config: SledConfig,
+
     /// Nodes used as bootstrap peers.
     pub bootstrap: Vec<Multiaddr>,
 
@@ -173,6 +193,13 @@ pub struct IpfsOptions {
     /// Keep connection alive
     pub keep_alive: bool,
 
+    /// Closes connections without any activity for the given duration, even when `keep_alive`
+    /// is enabled
+    pub idle_connection_timeout: Option<Duration>,
+
+    /// Closes the redundant connections to a peer the node is already connected to
+    pub connection_dedup: crate::p2p::ConnectionDedupPolicy,
+
     /// Enables dcutr
     pub dcutr: bool,
 
@@ -185,6 +212,12 @@ pub struct IpfsOptions {
     /// Disables bitswap protocol
     pub disable_bitswap: bool,
 
+    /// Disables pubsub protocol
+    pub disable_pubsub: bool,
+
+    /// Disables identify protocol
+    pub disable_identify: bool,
+
     /// Bitswap configuration
     pub bitswap_config: Option<BitswapConfig>,
 
@@ -194,12 +227,20 @@ pub struct IpfsOptions {
     /// Relay server config
     pub relay_server_config: Option<RelayConfig>,
 
+    /// Listens through relays picked among the known peers while autonat finds the node to be
+    /// behind a NAT. Requires the relay client to be enabled
+    pub autorelay: Option<AutoRelayConfig>,
+
     /// Bound listening addresses; by default the node will not listen on any address.
     pub listening_addrs: Vec<Multiaddr>,
 
     /// Transport configuration
     pub transport_configuration: Option<crate::p2p::TransportConfig>,
 
+    /// Resolves the host names of dns multiaddrs when dialing, instead of the resolver selected
+    /// in the transport configuration
+    pub dns_resolver: Option<Arc<dyn crate::p2p::CustomDnsResolver>>,
+
     /// Swarm configuration
     pub swarm_configuration: Option<crate::p2p::SwarmConfig>,
 
@@ -219,16 +260,52 @@ pub struct IpfsOptions {
     /// Ping Configuration
     pub ping_configuration: Option<PingConfig>,
 
+    /// Autonat Configuration
+    pub autonat_configuration: Option<AutonatConfig>,
+
+    /// Only advertise the listening addresses and the addresses added through
+    /// [`Ipfs::add_external_address`] once an autonat server was able to dial back to them.
+    /// Addresses observed by remote peers are always verified through autonat.
+    pub verify_external_addresses: bool,
+
     /// Enables port mapping (aka UPnP)
     pub port_mapping: bool,
 
     /// Address book configuration
     pub addr_config: Option<AddressBookConfig>,
 
+    /// Seeds kademlia with the peers persisted from previous sessions on start
+    pub seed_dht_from_addressbook: bool,
+
+    /// Asks connected DHT peers for the peers closest to us and adds them to the address book
+    pub peer_exchange: bool,
+
     pub keystore: Keystore,
 
     /// Repo Provider option
     pub provider: RepoProvider,
+
+    /// Window over which provider announcements are batched and deduplicated.
+    /// `None` provides every announced block immediately
+    pub provide_batch_window: Option<Duration>,
+
+    /// Derivation of the kademlia keys used for providing blocks and looking up their providers
+    pub provider_key_scheme: ProviderKeyScheme,
+
+    /// Number of concurrent tasks used by [`Ipfs::gc`] to scan the pinned DAGs and remove blocks
+    pub gc_concurrency: usize,
+
+    /// Maximum number of recursive pins inserted at the same time; any further ones wait for a
+    /// running pin to complete. `None` does not limit the recursive pins
+    pub max_concurrent_recursive_pins: Option<usize>,
+
+    /// Codecs of the Cids of the blocks which are accepted into the repo, both when put locally
+    /// and when received over bitswap. `None` accepts every codec
+    pub allowed_codecs: Option<HashSet<u64>>,
+
+    /// Deadlines applied to the operations of [`Ipfs`] by default, see [`TimeoutPolicy`]
+    pub timeout_policy: TimeoutPolicy,
+
     /// The span for tracing purposes, `None` value is converted to `tracing::trace_span!("ipfs")`.
     ///
     /// All futures returned by `Ipfs`, background task actions and swarm actions are instrumented
@@ -264,20 +341,38 @@ impl Default for IpfsOptions {
             relay: Default::default(),
             disable_kad: Default::default(),
             disable_bitswap: Default::default(),
+            disable_pubsub: Default::default(),
+            disable_identify: Default::default(),
             bitswap_config: Default::default(),
             keep_alive: Default::default(),
+            idle_connection_timeout: Default::default(),
+            connection_dedup: Default::default(),
+            block_encryption_key: Default::default(),
+            sled_config: Default::default(),
             relay_server: Default::default(),
             relay_server_config: Default::default(),
+            autorelay: Default::default(),
             kad_configuration: Default::default(),
             kad_store_config: Default::default(),
             ping_configuration: Default::default(),
+            autonat_configuration: Default::default(),
+            verify_external_addresses: Default::default(),
             identify_configuration: Default::default(),
             addr_config: Default::default(),
+            seed_dht_from_addressbook: Default::default(),
+            peer_exchange: Default::default(),
             provider: Default::default(),
+            provide_batch_window: Default::default(),
+            provider_key_scheme: Default::default(),
+            gc_concurrency: 1,
+            max_concurrent_recursive_pins: None,
+            allowed_codecs: None,
+            timeout_policy: Default::default(),
             keystore: Keystore::in_memory(),
             listening_addrs: vec![],
             port_mapping: false,
             transport_configuration: None,
+            dns_resolver: None,
             pubsub_config: None,
             swarm_configuration: None,
             span: None,
@@ -328,6 +423,10 @@ pub struct Ipfs {
     keystore: Keystore,
     identify_conf: IdentifyConfiguration,
     to_task: Sender<IpfsEvent>,
+    gc_concurrency: usize,
+    timeout_policy: TimeoutPolicy,
+    /// Largest block the bitswap messages of the node can carry.
+    max_block_size: usize,
     record_key_validator: HashMap<String, Arc<dyn Fn(&str) -> anyhow::Result<Key> + Sync + Send>>,
 }
 
@@ -352,6 +451,13 @@ enum IpfsEvent {
     Addresses(Channel<Vec<(PeerId, Vec<Multiaddr>)>>),
     /// Local addresses
     Listeners(Channel<Either<Vec<Multiaddr>, BoxFuture<'static, Vec<Multiaddr>>>>),
+    ListenerDescriptors(OneshotSender<Vec<p2p::ListenerDescriptor>>),
+    /// Reservations held with relays
+    RelayReservations(Channel<Vec<p2p::ReservationInfo>>),
+    /// Circuits relayed by the node
+    RelayedCircuits(Channel<Vec<p2p::CircuitInfo>>),
+    /// Occupancy of the kademlia routing table
+    KadRoutingTable(Channel<p2p::KadTableInfo>),
     /// Local addresses
     ExternalAddresses(Channel<Either<Vec<Multiaddr>, BoxFuture<'static, Vec<Multiaddr>>>>),
     /// Connected peers
@@ -361,23 +467,29 @@ enum IpfsEvent {
     /// Disconnect
     Disconnect(
         PeerId,
+        Option<String>,
         OneshotSender<(ReceiverChannel<()>, ReceiverChannel<()>)>,
     ),
     /// Ban Peer
     Ban(PeerId, Channel<()>),
     /// Unban peer
     Unban(PeerId, Channel<()>),
-    PubsubSubscribe(String, OneshotSender<Option<SubscriptionStream>>),
-    PubsubUnsubscribe(String, OneshotSender<Result<bool, Error>>),
-    PubsubPublish(
-        String,
-        Vec<u8>,
-        OneshotSender<Result<MessageId, PublishError>>,
-    ),
-    PubsubPeers(Option<String>, OneshotSender<Vec<PeerId>>),
+    PubsubSubscribe(String, Channel<Option<SubscriptionStream>>),
+    PubsubUnsubscribe(String, Channel<bool>),
+    PubsubPublish(String, Vec<u8>, Channel<MessageId>),
+    PubsubPeers(Option<String>, Channel<Vec<PeerId>>),
     GetBitswapPeers(OneshotSender<BoxFuture<'static, Vec<PeerId>>>),
     WantList(Option<PeerId>, OneshotSender<BoxFuture<'static, Vec<Cid>>>),
-    PubsubSubscribed(OneshotSender<Vec<String>>),
+    SendBitswapMessage(
+        PeerId,
+        BitswapMessage,
+        OneshotSender<BoxFuture<'static, Result<(), Error>>>,
+    ),
+    BitswapDroppedMessages(OneshotSender<u64>),
+    SetBitswapServer(bool, OneshotSender<()>),
+    BitswapUnservedWants(OneshotSender<u64>),
+    PubsubSubscribed(Channel<Vec<String>>),
+    AddExternalAddress(Multiaddr, Channel<()>),
     AddListeningAddress(
         Multiaddr,
         OneshotSender<anyhow::Result<oneshot::Receiver<Either<Multiaddr, Result<(), io::Error>>>>>,
@@ -387,13 +499,19 @@ enum IpfsEvent {
         OneshotSender<anyhow::Result<oneshot::Receiver<Either<Multiaddr, Result<(), io::Error>>>>>,
     ),
     Bootstrap(Channel<ReceiverChannel<KadResult>>),
+    BootstrapProgress(Channel<BoxStream<'static, Result<KadResult, Error>>>),
     AddPeer(PeerId, Multiaddr, Channel<()>),
     RemovePeer(PeerId, Option<Multiaddr>, Channel<bool>),
+    AddressTtl(PeerId, Multiaddr, OneshotSender<Option<Duration>>),
     GetClosestPeers(PeerId, OneshotSender<ReceiverChannel<KadResult>>),
     FindPeerIdentity(
         PeerId,
         OneshotSender<ReceiverChannel<libp2p::identify::Info>>,
     ),
+    RefreshIdentify(
+        PeerId,
+        OneshotSender<ReceiverChannel<libp2p::identify::Info>>,
+    ),
     FindPeer(
         PeerId,
         bool,
@@ -401,7 +519,17 @@ enum IpfsEvent {
     ),
     WhitelistPeer(PeerId, Channel<()>),
     RemoveWhitelistPeer(PeerId, Channel<()>),
-    GetProviders(Cid, OneshotSender<Option<BoxStream<'static, PeerId>>>),
+    TagPeer(PeerId, String, Channel<()>),
+    UntagPeer(PeerId, String, Channel<()>),
+    PeersWithTag(String, OneshotSender<Vec<PeerId>>),
+    ConnectionLimits(OneshotSender<p2p::ConnectionLimits>),
+    ConnectionInfo(PeerId, OneshotSender<Vec<p2p::ConnectionInfo>>),
+    UpdateConnectionLimits(p2p::ConnectionLimits, OneshotSender<p2p::ConnectionLimits>),
+    GetProviders(
+        Cid,
+        Option<usize>,
+        OneshotSender<Option<BoxStream<'static, PeerId>>>,
+    ),
     Provide(Cid, Channel<ReceiverChannel<KadResult>>),
     DhtMode(DhtMode, Channel<()>),
     DhtGet(Key, OneshotSender<BoxStream<'static, Record>>),
@@ -415,6 +543,12 @@ enum IpfsEvent {
     //event streams
     PubsubEventStream(OneshotSender<UnboundedReceiver<InnerPubsubEvent>>),
 
+    ConnectionEventStream(OneshotSender<UnboundedReceiver<ConnectionEvent>>),
+
+    PeerDiscoveryStream(OneshotSender<UnboundedReceiver<DiscoveredPeer>>),
+
+    ListenerEventStream(OneshotSender<UnboundedReceiver<ListenerEvent>>),
+
     Exit,
 }
 
@@ -435,6 +569,111 @@ impl From<DhtMode> for Option<Mode> {
     }
 }
 
+/// Controls how [`Ipfs::bootstrap_with_retry`] retries a failed bootstrap.
+///
+/// The delay between attempts starts at `initial_backoff` and is multiplied by `multiplier`
+/// after every failed attempt, up to `max_backoff`.
+#[derive(Debug, Copy, Clone)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: usize,
+    /// Delay after the first failed attempt.
+    pub initial_backoff: Duration,
+    /// Upper bound for the delay between attempts.
+    pub max_backoff: Duration,
+    /// Factor the delay grows by after each failed attempt.
+    pub multiplier: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 10,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            multiplier: 2,
+        }
+    }
+}
+
+/// Describes the outcome of [`Ipfs::rehash_pins`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RehashReport {
+    /// Pinned roots and the new roots they were rehashed to.
+    pub rehashed: Vec<(Cid, Cid)>,
+    /// Pinned roots which already used the requested hash function.
+    pub skipped: Vec<Cid>,
+}
+
+/// Default deadlines of the operations of [`Ipfs`], by class of operation. An operation which
+/// does not complete within the deadline of its class fails with [`OperationTimedOut`]. `None`
+/// leaves the operations of the class without a deadline.
+///
+/// Calls can override the policy through [`Ipfs::with_timeout_policy`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TimeoutPolicy {
+    /// Applied to [`Ipfs::connect`].
+    pub connect: Option<Duration>,
+    /// Applied to [`Ipfs::get_block`] and [`Ipfs::get_dag`].
+    pub get: Option<Duration>,
+    /// Applied to [`Ipfs::insert_pin`].
+    pub pin: Option<Duration>,
+    /// Applied to [`Ipfs::find_peer`], [`Ipfs::get_closest_peers`], [`Ipfs::provide`] and
+    /// [`Ipfs::dht_put`].
+    pub dht: Option<Duration>,
+}
+
+/// An operation did not complete within the deadline set by the [`TimeoutPolicy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("{operation} did not complete within {timeout:?}")]
+pub struct OperationTimedOut {
+    pub operation: &'static str,
+    pub timeout: Duration,
+}
+
+/// Runs `fut` to completion, failing with [`OperationTimedOut`] once `timeout` elapses.
+async fn with_deadline<T>(
+    operation: &'static str,
+    timeout: Option<Duration>,
+    fut: impl std::future::Future<Output = Result<T, Error>>,
+) -> Result<T, Error> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, fut)
+            .await
+            .map_err(|_| OperationTimedOut { operation, timeout })?,
+        None => fut.await,
+    }
+}
+
+/// How long [`Ipfs::fetch_dag`] waits for a single block before giving up on the DAG.
+pub const FETCH_DAG_BLOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Describes the outcome of [`Ipfs::fetch_dag`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FetchReport {
+    /// Number of blocks reachable from the root, including the root.
+    pub blocks: usize,
+    /// Number of those blocks which had to be fetched from other peers.
+    pub fetched: usize,
+}
+
+/// Progress of [`Ipfs::pin_remote`].
+#[derive(Debug)]
+pub enum PinProgress {
+    /// Another block of the DAG is present locally, `blocks` in total so far of which `fetched`
+    /// were fetched from other peers.
+    Progress { blocks: usize, fetched: usize },
+    /// All the blocks are present and the root is pinned.
+    Pinned { blocks: usize, fetched: usize },
+    /// A block could not be fetched or the pin could not be inserted. The root is not pinned,
+    /// while the blocks fetched so far are left in the repo.
+    Failed {
+        blocks: usize,
+        fetched: usize,
+        error: Error,
+    },
+}
+
 #[derive(Debug, Clone)]
 pub enum PubsubEvent {
     /// Subscription event to a given topic
@@ -444,6 +683,60 @@ pub enum PubsubEvent {
     Unsubscribe { peer_id: PeerId },
 }
 
+/// Connections to peers being opened or closed, see [`Ipfs::connection_events`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    /// The first connection to the peer was established.
+    PeerConnected { peer_id: PeerId },
+
+    /// The last connection to the peer was closed. `cause` is the reason given to
+    /// [`Ipfs::disconnect_with_reason`] or why the node closed the connection itself, and
+    /// otherwise the error the connection was closed with, if any.
+    PeerDisconnected {
+        peer_id: PeerId,
+        cause: Option<String>,
+    },
+}
+
+/// Listening on relay circuits becoming usable, failing or having its reservation renewed, see
+/// [`Ipfs::listener_events`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenerEvent {
+    /// The reservation with the relay was accepted and the circuit address can be dialed.
+    CircuitListenActive { relay: PeerId },
+
+    /// Listening through the relay could not be established or was lost, e.g. because the relay
+    /// could not be reached or refused the reservation.
+    CircuitListenFailed { relay: PeerId, error: String },
+
+    /// The reservation with the relay was renewed ahead of its expiry.
+    ReservationRenewed { relay: PeerId },
+
+    /// The reservation with the relay could not be renewed, after which the circuit is listened
+    /// on again.
+    ReservationRenewalFailed { relay: PeerId, error: String },
+}
+
+/// Mechanism a peer was discovered through, see [`Ipfs::peer_discovery`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DiscoverySource {
+    /// The peer announced itself on the local network.
+    Mdns,
+    /// The peer was added to the kademlia routing table.
+    Kad,
+    /// The peer identified itself over a connection.
+    Identify,
+}
+
+/// A peer found by one of the enabled discovery mechanisms, see [`Ipfs::peer_discovery`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredPeer {
+    pub peer_id: PeerId,
+    pub source: DiscoverySource,
+    /// Addresses of the peer learned through `source`.
+    pub addresses: Vec<Multiaddr>,
+}
+
 #[derive(Debug, Clone)]
 pub(crate) enum InnerPubsubEvent {
     /// Subscription event to a given topic
@@ -464,6 +757,7 @@ impl From<InnerPubsubEvent> for PubsubEvent {
 
 type TSwarmEvent<C> = <TSwarm<C> as Stream>::Item;
 type TSwarmEventFn<C> = Arc<dyn Fn(&mut TSwarm<C>, &TSwarmEvent<C>) + Sync + Send>;
+type TSwarmEventSubscriberFn<C> = Box<dyn Fn(&TSwarmEvent<C>) + Sync + Send>;
 type TTransportFn = Box<
     dyn Fn(
             &Keypair,
@@ -488,8 +782,12 @@ pub struct UninitializedIpfs<C: NetworkBehaviour<ToSwarm = void::Void> + Send> {
     fdlimit: Option<FDLimit>,
     delay: bool,
     repo_handle: Option<Repo>,
+    block_store: Option<Arc<dyn BlockStore>>,
+    pin_store: Option<Arc<dyn PinStore>>,
+    block_transform: Option<Arc<dyn BlockTransform>>,
     local_external_addr: bool,
     swarm_event: Option<TSwarmEventFn<C>>,
+    swarm_event_subscribers: Vec<TSwarmEventSubscriberFn<C>>,
     // record_validators: HashMap<String, Arc<dyn Fn(&str, &Record) -> bool + Sync + Send>>,
     record_key_validator: HashMap<String, Arc<dyn Fn(&str) -> anyhow::Result<Key> + Sync + Send>>,
     custom_behaviour: Option<C>,
@@ -523,6 +821,13 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
         Self::with_opt(Default::default())
     }
 
+    /// New uninitialized instance configured from the settings in `config`, e.g. as read with
+    /// [`config::NodeConfig::from_file`]. The settings not covered by the config are left at
+    /// their defaults.
+    pub fn from_config(config: config::NodeConfig) -> Self {
+        Self::with_opt(config.into())
+    }
+
     /// Configures a new UninitializedIpfs with from the given options and optionally a span.
     /// If the span is not given, it is defaulted to `tracing::trace_span!("ipfs")`.
     ///
@@ -539,10 +844,14 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
             fdlimit,
             delay,
             repo_handle: None,
+            block_store: None,
+            pin_store: None,
+            block_transform: None,
             // record_validators: Default::default(),
             record_key_validator: Default::default(),
             local_external_addr: false,
             swarm_event: None,
+            swarm_event_subscribers: Vec::new(),
             custom_behaviour: None,
             custom_transport: None,
         }
@@ -598,12 +907,28 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
         self
     }
 
+    /// Set a custom resolver for the host names of dns multiaddrs
+    pub fn set_dns_resolver(mut self, resolver: impl crate::p2p::CustomDnsResolver) -> Self {
+        self.options.dns_resolver = Some(Arc::new(resolver));
+        self
+    }
+
     /// Set swarm configuration
     pub fn set_swarm_configuration(mut self, config: crate::p2p::SwarmConfig) -> Self {
         self.options.swarm_configuration = Some(config);
         self
     }
 
+    /// Limits the number of inbound streams negotiating their protocol at once on a connection,
+    /// see [`p2p::SwarmConfig::max_inbound_stream`]
+    pub fn set_max_negotiating_inbound_streams(mut self, limit: usize) -> Self {
+        self.options
+            .swarm_configuration
+            .get_or_insert_with(Default::default)
+            .max_inbound_stream = limit;
+        self
+    }
+
     /// Set kad configuration
     pub fn set_kad_configuration(mut self, config: KadConfig, store: KadStoreConfig) -> Self {
         self.options.kad_configuration = Some(Either::Left(config));
@@ -617,6 +942,12 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
         self
     }
 
+    /// Set autonat configuration
+    pub fn set_autonat_configuration(mut self, config: AutonatConfig) -> Self {
+        self.options.autonat_configuration = Some(config);
+        self
+    }
+
     /// Set default record validator for IPFS
     /// Note: This will override any keys set for `ipns` prefix
     pub fn default_record_key_validator(mut self) -> Self {
@@ -643,6 +974,20 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
         self
     }
 
+    /// Persist peers supporting the DHT protocol and insert them into kademlia
+    /// on start so queries can be made without bootstrapping first
+    pub fn seed_dht_from_addressbook(mut self, enable: bool) -> Self {
+        self.options.seed_dht_from_addressbook = enable;
+        self
+    }
+
+    /// Learn about more peers from the DHT peers we connect to, widening connectivity
+    /// faster than waiting on bootstrapping
+    pub fn peer_exchange(mut self, enable: bool) -> Self {
+        self.options.peer_exchange = enable;
+        self
+    }
+
     /// Set pubsub configuration
     pub fn set_pubsub_configuration(mut self, config: PubsubConfig) -> Self {
         self.options.pubsub_config = Some(config);
@@ -655,6 +1000,42 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
         self
     }
 
+    /// Batch provider announcements over the given window
+    pub fn set_provide_batch_window(mut self, window: Duration) -> Self {
+        self.options.provide_batch_window = Some(window);
+        self
+    }
+
+    /// Set how the kademlia keys of provider records are derived from the provided `Cid`s
+    pub fn set_provider_key_scheme(mut self, scheme: ProviderKeyScheme) -> Self {
+        self.options.provider_key_scheme = scheme;
+        self
+    }
+
+    /// Set the number of concurrent tasks used when collecting garbage
+    pub fn set_gc_concurrency(mut self, concurrency: usize) -> Self {
+        self.options.gc_concurrency = concurrency;
+        self
+    }
+
+    /// Set the maximum number of recursive pins inserted at the same time
+    pub fn set_max_concurrent_recursive_pins(mut self, limit: usize) -> Self {
+        self.options.max_concurrent_recursive_pins = Some(limit);
+        self
+    }
+
+    /// Set the deadlines applied to the operations by default
+    pub fn set_timeout_policy(mut self, policy: TimeoutPolicy) -> Self {
+        self.options.timeout_policy = policy;
+        self
+    }
+
+    /// Only accept blocks whose Cid has one of the given codecs into the repo
+    pub fn set_allowed_codecs(mut self, codecs: impl IntoIterator<Item = u64>) -> Self {
+        self.options.allowed_codecs = Some(codecs.into_iter().collect());
+        self
+    }
+
     /// Set keypair
     pub fn set_keypair(mut self, keypair: Keypair) -> Self {
         self.keys = keypair;
@@ -667,12 +1048,58 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
         self
     }
 
+    /// Use a custom block store instead of the one of the configured `ipfs_path`.
+    /// Cannot be combined with [`UninitializedIpfs::set_repo`].
+    pub fn with_block_store(mut self, store: Arc<dyn BlockStore>) -> Self {
+        self.block_store = Some(store);
+        self
+    }
+
+    /// Keep the pins in a custom pin store instead of the data store of the configured `ipfs_path`.
+    /// Cannot be combined with [`UninitializedIpfs::set_repo`].
+    pub fn with_pin_store(mut self, store: Arc<dyn PinStore>) -> Self {
+        self.pin_store = Some(store);
+        self
+    }
+
+    /// Pass the blocks through the given transform, e.g. compressing them, before they are stored
+    /// and after they are loaded. Cannot be combined with [`UninitializedIpfs::set_repo`].
+    pub fn with_block_transform(mut self, transform: Arc<dyn BlockTransform>) -> Self {
+        self.block_transform = Some(transform);
+        self
+    }
+
     /// Enable keep alive
     pub fn enable_keepalive(mut self) -> Self {
         self.options.keep_alive = true;
         self
     }
 
+    /// Encrypt the blocks stored in the repo with the given key
+    pub fn set_block_encryption_key(mut self, key: [u8; 32]) -> Self {
+        self.options.block_encryption_key = Some(key);
+        self
+    }
+
+    /// Open the sled data store of the repo on disk with the given settings. Only used with the
+    /// `sled_data_store` feature.
+    pub fn set_sled_config(mut self, config: SledConfig) -> Self {
+        self.options.sled_config = config;
+        self
+    }
+
+    /// Close connections which had no activity for the given duration
+    pub fn set_idle_connection_timeout(mut self, timeout: Duration) -> Self {
+        self.options.idle_connection_timeout = Some(timeout);
+        self
+    }
+
+    /// Close the redundant connections to a peer following the given policy
+    pub fn set_connection_dedup(mut self, policy: crate::p2p::ConnectionDedupPolicy) -> Self {
+        self.options.connection_dedup = policy;
+        self
+    }
+
     /// Disables kademlia
     pub fn disable_kad(mut self) -> Self {
         self.options.disable_kad = true;
@@ -685,6 +1112,18 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
         self
     }
 
+    /// Disable pubsub
+    pub fn disable_pubsub(mut self) -> Self {
+        self.options.disable_pubsub = true;
+        self
+    }
+
+    /// Disable identify
+    pub fn disable_identify(mut self) -> Self {
+        self.options.disable_identify = true;
+        self
+    }
+
     /// Set Bitswap configuration
     pub fn set_bitswap_configuration(mut self, config: BitswapConfig) -> Self {
         self.options.bitswap_config = Some(config);
@@ -717,6 +1156,14 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
         self
     }
 
+    /// Enable relay client and listen through relays selected automatically while autonat
+    /// finds the node to be behind a NAT
+    pub fn enable_autorelay(mut self, config: AutoRelayConfig) -> Self {
+        self.options.relay = true;
+        self.options.autorelay = Some(config);
+        self
+    }
+
     /// Enable port mapping (AKA UPnP)
     pub fn enable_upnp(mut self) -> Self {
         self.options.port_mapping = true;
@@ -729,6 +1176,12 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
         self
     }
 
+    /// Only advertise external addresses after they were verified by an autonat server
+    pub fn verify_external_addresses(mut self) -> Self {
+        self.options.verify_external_addresses = true;
+        self
+    }
+
     /// Set a custom behaviour
     pub fn set_custom_behaviour(mut self, behaviour: C) -> Self {
         self.custom_behaviour = Some(behaviour);
@@ -764,6 +1217,22 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
         self
     }
 
+    /// Deliver the swarm events mapped by `func` to the buffer behind `sender`. Unlike
+    /// [`UninitializedIpfs::swarm_events`], a slow subscriber does not stall the swarm as events
+    /// are dropped according to the [`p2p::DropPolicy`] of the buffer once it is full.
+    pub fn swarm_event_subscriber<T, F>(mut self, sender: SwarmEventSender<T>, func: F) -> Self
+    where
+        T: Send + 'static,
+        F: Fn(&TSwarmEvent<C>) -> Option<T> + Sync + Send + 'static,
+    {
+        self.swarm_event_subscribers.push(Box::new(move |event| {
+            if let Some(event) = func(event) {
+                sender.push(event);
+            }
+        }));
+        self
+    }
+
     /// Initialize the ipfs node. The returned `Ipfs` value is cloneable, send and sync.
     pub async fn start(self) -> Result<Ipfs, Error> {
         let UninitializedIpfs {
@@ -772,11 +1241,15 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
             delay,
             mut options,
             swarm_event,
+            swarm_event_subscribers,
             custom_behaviour,
             custom_transport,
             record_key_validator,
             local_external_addr,
             repo_handle,
+            block_store,
+            pin_store,
+            block_transform,
             ..
         } = self;
 
@@ -798,6 +1271,15 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
         let swarm_span = tracing::trace_span!(parent: &root_span, "swarm");
 
         let repo = match repo_handle {
+            Some(_) if block_store.is_some() || pin_store.is_some() => {
+                anyhow::bail!("Custom block or pin store cannot be used with a given repo");
+            }
+            Some(_) if options.block_encryption_key.is_some() => {
+                anyhow::bail!("Block encryption cannot be enabled on a given repo");
+            }
+            Some(_) if block_transform.is_some() => {
+                anyhow::bail!("Block transform cannot be used with a given repo");
+            }
             Some(repo) => {
                 if repo.is_online() {
                     anyhow::bail!("Repo is already initialized");
@@ -810,11 +1292,41 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
                         tokio::fs::create_dir_all(path).await?;
                     }
                 }
-                Repo::new(options.ipfs_path.clone())
+                let mut repo = match &options.ipfs_path {
+                    StoragePath::Disk(path) => {
+                        Repo::new_fs_with_sled_config(path, options.sled_config)
+                    }
+                    path => Repo::new(path.clone()),
+                };
+                if let Some(store) = block_store {
+                    repo = repo.with_block_store(store);
+                }
+                if let Some(store) = pin_store {
+                    repo = repo.with_pin_store(store);
+                }
+                if let Some(key) = options.block_encryption_key {
+                    repo = repo.with_block_encryption(key);
+                }
+                // applied to the blocks before they are encrypted
+                if let Some(transform) = block_transform {
+                    repo = repo.with_block_transform(transform);
+                }
+                repo
             }
         };
 
+        let repo = match options.max_concurrent_recursive_pins {
+            Some(limit) => repo.with_max_concurrent_recursive_pins(limit),
+            None => repo,
+        };
+
+        let repo = match options.allowed_codecs.clone() {
+            Some(codecs) => repo.with_allowed_codecs(codecs),
+            None => repo,
+        };
+
         repo.init().instrument(init_span.clone()).await?;
+        repo.open().instrument(init_span.clone()).await?;
 
         let repo_events = repo.initialize_channel();
 
@@ -842,6 +1354,13 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
 
         let (to_task, receiver) = channel::<IpfsEvent>(1);
         let id_conf = options.identify_configuration.clone().unwrap_or_default();
+        let push_external_addr_updates = id_conf.push_external_update;
+        let verify_external_addresses = options.verify_external_addresses;
+        let want_permits = options
+            .bitswap_config
+            .as_ref()
+            .and_then(BitswapConfig::max_wantlist_size)
+            .map(task::WantQueue::new);
 
         let keystore = options.keystore.clone();
 
@@ -852,6 +1371,13 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
             key: keys.clone(),
             keystore,
             to_task,
+            gc_concurrency: options.gc_concurrency,
+            timeout_policy: options.timeout_policy,
+            max_block_size: options
+                .bitswap_config
+                .clone()
+                .unwrap_or_default()
+                .max_block_size(),
             record_key_validator,
         };
 
@@ -915,10 +1441,35 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
         let listeners = Default::default();
         let bootstraps = Default::default();
 
+        let reprovide = options
+            .kad_configuration
+            .as_ref()
+            .and_then(|config| config.as_ref().left())
+            .and_then(|config| {
+                config.reprovide_jitter.map(|jitter| {
+                    let interval = config
+                        .provider_publication_interval
+                        .unwrap_or(p2p::DEFAULT_PROVIDER_PUBLICATION_INTERVAL);
+                    p2p::ReprovideSchedule::new(interval, jitter)
+                })
+            });
+
         let IpfsOptions {
-            listening_addrs, ..
+            listening_addrs,
+            seed_dht_from_addressbook,
+            provide_batch_window,
+            provider_key_scheme,
+            peer_exchange,
+            autorelay,
+            relay_server_config,
+            ..
         } = options;
 
+        let dht_peers = match seed_dht_from_addressbook {
+            true => task::load_dht_peers(&repo).await,
+            false => vec![],
+        };
+
         let mut fut = task::IpfsTask {
             repo_events: repo_events.fuse(),
             from_facade: receiver.fuse(),
@@ -926,21 +1477,46 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
             listening_addresses: HashMap::with_capacity(listening_addrs.len()),
             listeners,
             provider_stream: HashMap::new(),
+            bootstrap_progress: Default::default(),
             bitswap_provider_stream: Default::default(),
             record_stream: HashMap::new(),
             dht_peer_lookup: Default::default(),
+            identify_refresh: Default::default(),
             bitswap_sessions: Default::default(),
+            block_fetches: Default::default(),
+            want_permits,
             disconnect_confirmation: Default::default(),
             pubsub_event_stream: Default::default(),
+            connection_event_stream: Default::default(),
+            peer_discovery_stream: Default::default(),
+            listener_event_stream: Default::default(),
+            disconnect_reasons: Default::default(),
             kad_subscriptions,
             listener_subscriptions,
             repo,
             bootstraps,
             swarm_event,
+            swarm_event_subscribers,
             external_listener: Default::default(),
             local_listener: Default::default(),
             timer: Default::default(),
             local_external_addr,
+            verify_external_addresses,
+            persist_dht_peers: seed_dht_from_addressbook,
+            provide_queue: Default::default(),
+            provide_batch_window,
+            reprovide,
+            provider_key_scheme,
+            peer_exchange,
+            exchanged_peers: Default::default(),
+            push_external_addr_updates,
+            known_external_addrs: Default::default(),
+            relay_reservations: Default::default(),
+            lost_relay_reservations: Default::default(),
+            autorelay: autorelay.map(p2p::autorelay::AutoRelay::new),
+            relay_server_config: relay_server_config.unwrap_or_default(),
+            client_reservations: Default::default(),
+            relayed_circuits: Default::default(),
         };
 
         for addr in listening_addrs.into_iter() {
@@ -950,10 +1526,17 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
             };
         }
 
+        if let Some(kad) = fut.swarm.behaviour_mut().kademlia.as_mut() {
+            for (peer_id, addrs) in dht_peers {
+                for addr in addrs {
+                    kad.add_address(&peer_id, addr);
+                }
+            }
+        }
+
         for block in blocks {
             if let Some(kad) = fut.swarm.behaviour_mut().kademlia.as_mut() {
-                let key = Key::from(block.hash().to_bytes());
-                match kad.start_providing(key) {
+                match kad.start_providing(provider_key_scheme.key(&block)) {
                     Ok(id) => {
                         let (tx, _rx) = oneshot_channel();
                         fut.kad_subscriptions.insert(id, tx);
@@ -993,6 +1576,26 @@ impl Ipfs {
         &self.repo
     }
 
+    /// Returns the deadlines applied to the operations by default.
+    pub fn timeout_policy(&self) -> TimeoutPolicy {
+        self.timeout_policy
+    }
+
+    /// Returns a handle to the same node which applies `policy` to its operations instead, e.g.
+    /// to give a single call a longer deadline.
+    pub fn with_timeout_policy(&self, policy: TimeoutPolicy) -> Self {
+        Ipfs {
+            timeout_policy: policy,
+            ..self.clone()
+        }
+    }
+
+    /// Resolves once all the stores of the repo are initialized and opened, and it is safe to issue
+    /// operations on the node.
+    pub async fn repo_ready(&self) {
+        self.repo.ready().instrument(self.span.clone()).await
+    }
+
     /// Returns an [`IpfsFiles`] for files operations
     pub fn unixfs(&self) -> IpfsUnixfs {
         IpfsUnixfs::new(self.clone())
@@ -1016,15 +1619,175 @@ impl Ipfs {
             .map(|(cid, _put_status)| cid)
     }
 
+    /// Computes the Cid `data` would be stored under as a block encoded with `codec` and hashed
+    /// with `hash`, without storing it.
+    ///
+    /// Fails if the combination is not valid for the Cid `version`, e.g. a CIDv0 which is not a
+    /// sha2-256 hashed dag-pb block.
+    pub fn compute_cid(
+        &self,
+        data: &[u8],
+        codec: IpldCodec,
+        hash: Code,
+        version: libipld::cid::Version,
+    ) -> Result<Cid, Error> {
+        Ok(Cid::new(version, codec.into(), hash.digest(data))?)
+    }
+
     /// Retrieves a block from the local blockstore, or starts fetching from the network or join an
     /// already started fetch.
     pub async fn get_block(&self, cid: &Cid) -> Result<Block, Error> {
-        self.repo
-            .get_block(cid, &[], false)
+        let block = self.repo.get_block(cid, &[], false);
+        with_deadline("get_block", self.timeout_policy.get, block)
             .instrument(self.span.clone())
             .await
     }
 
+    /// Fetches every block reachable from `root` which is not yet available locally, asking the
+    /// given `providers` in addition to the connected peers.
+    ///
+    /// Fails with the first block which could not be fetched within [`FETCH_DAG_BLOCK_TIMEOUT`],
+    /// leaving the blocks fetched so far in the repo. Useful before pinning a large DAG
+    /// recursively, as the pinning would otherwise only discover a missing block midway.
+    pub async fn fetch_dag(&self, root: Cid, providers: &[PeerId]) -> Result<FetchReport, Error> {
+        let span = debug_span!(parent: &self.span, "fetch_dag", cid = %root);
+        async move {
+            let mut report = FetchReport::default();
+            let mut queue = std::collections::VecDeque::from([root]);
+            let mut visited = HashSet::from([root]);
+
+            while let Some(cid) = queue.pop_front() {
+                let present = self.repo.contains(&cid).await?;
+
+                let block = tokio::time::timeout(
+                    FETCH_DAG_BLOCK_TIMEOUT,
+                    self.repo.get_block(&cid, providers, false),
+                )
+                .await
+                .map_err(|_| anyhow!("block {cid} is unreachable"))??;
+
+                report.blocks += 1;
+                if !present {
+                    report.fetched += 1;
+                }
+
+                let mut links = Vec::new();
+                block.references(&mut links)?;
+                queue.extend(links.into_iter().filter(|link| visited.insert(*link)));
+            }
+
+            Ok(report)
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Fetches the DAG rooted at `cid` from the connected peers and the given `providers` and pins
+    /// it, or only fetches and pins the root block directly when not `recursive`.
+    ///
+    /// Progress is reported as the blocks become present locally. The pin is inserted only once
+    /// every block is present, so a block which cannot be fetched within
+    /// [`FETCH_DAG_BLOCK_TIMEOUT`] fails the operation without pinning anything.
+    pub fn pin_remote(
+        &self,
+        cid: Cid,
+        recursive: bool,
+        providers: &[PeerId],
+    ) -> BoxStream<'static, PinProgress> {
+        let repo = self.repo.clone();
+        let providers = providers.to_vec();
+
+        let stream = async_stream::stream! {
+            let mut blocks = 0;
+            let mut fetched = 0;
+            let mut queue = std::collections::VecDeque::from([cid]);
+            let mut visited = HashSet::from([cid]);
+
+            while let Some(next) = queue.pop_front() {
+                let present = match repo.contains(&next).await {
+                    Ok(present) => present,
+                    Err(error) => {
+                        yield PinProgress::Failed { blocks, fetched, error };
+                        return;
+                    }
+                };
+
+                let block = match tokio::time::timeout(
+                    FETCH_DAG_BLOCK_TIMEOUT,
+                    repo.get_block(&next, &providers, false),
+                )
+                .await
+                {
+                    Ok(Ok(block)) => block,
+                    Ok(Err(error)) => {
+                        yield PinProgress::Failed { blocks, fetched, error };
+                        return;
+                    }
+                    Err(_) => {
+                        let error = anyhow!("block {next} is unreachable");
+                        yield PinProgress::Failed { blocks, fetched, error };
+                        return;
+                    }
+                };
+
+                blocks += 1;
+                if !present {
+                    fetched += 1;
+                }
+                yield PinProgress::Progress { blocks, fetched };
+
+                if !recursive {
+                    break;
+                }
+
+                let mut links = Vec::new();
+                if let Err(error) = block.references(&mut links) {
+                    yield PinProgress::Failed { blocks, fetched, error };
+                    return;
+                }
+                queue.extend(links.into_iter().filter(|link| visited.insert(*link)));
+            }
+
+            // every block is present, nothing is fetched while pinning
+            match repo.insert_pin(&cid, recursive, true).await {
+                Ok(()) => yield PinProgress::Pinned { blocks, fetched },
+                Err(error) => yield PinProgress::Failed { blocks, fetched, error },
+            }
+        };
+
+        let span = debug_span!(parent: &self.span, "pin_remote", cid = %cid, recursive);
+        stream.instrument(span).boxed()
+    }
+
+    /// Walks the locally available part of the DAG rooted at `root` and returns the linked
+    /// blocks which are not in the blockstore, without fetching anything from the network.
+    ///
+    /// The returned blocks are the ones a [`Ipfs::fetch_dag`] would need to fetch next; their own
+    /// links are unknown until they are fetched. A missing `root` is returned as the only block.
+    pub async fn missing_blocks(&self, root: Cid) -> Result<Vec<Cid>, Error> {
+        let span = debug_span!(parent: &self.span, "missing_blocks", cid = %root);
+        async move {
+            let mut missing = Vec::new();
+            let mut queue = std::collections::VecDeque::from([root]);
+            let mut visited = HashSet::from([root]);
+
+            while let Some(cid) = queue.pop_front() {
+                let Some(block) = self.repo.get_block_now(&cid).await? else {
+                    missing.push(cid);
+                    continue;
+                };
+
+                let mut links = Vec::new();
+                block.references(&mut links)?;
+                queue.extend(links.into_iter().filter(|link| visited.insert(*link)));
+            }
+
+            Ok(missing)
+        }
+        .instrument(span)
+        .await
+    }
+
     /// Remove block from the ipfs repo. A pinned block cannot be removed.
     pub async fn remove_block(&self, cid: Cid) -> Result<Cid, Error> {
         self.repo
@@ -1033,11 +1796,37 @@ impl Ipfs {
             .await
     }
 
-    /// Cleans up of all unpinned blocks
-    /// Note: This is extremely basic and should not be relied on completely
-    ///       until there is additional or extended implementation for a gc
+    /// Removes all blocks which are not reachable from a pin, scanning with the number of
+    /// concurrent tasks configured in [`IpfsOptions::gc_concurrency`].
     pub async fn gc(&self) -> Result<Vec<Cid>, Error> {
-        self.repo.cleanup().instrument(self.span.clone()).await
+        self.repo
+            .gc(self.gc_concurrency)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Limits the total size of the blocks in the repo to `quota` bytes, or removes the limit
+    /// with `None`. Writing a block over the quota removes the unpinned blocks, and fails with
+    /// [`repo::QuotaExceeded`] if that does not free enough space. Pinned blocks are never removed.
+    pub fn set_storage_quota(&self, quota: Option<u64>) {
+        self.repo.set_storage_quota(quota)
+    }
+
+    /// Returns the storage quota set with [`Ipfs::set_storage_quota`].
+    pub fn storage_quota(&self) -> Option<u64> {
+        self.repo.storage_quota()
+    }
+
+    /// Sets the peers asked first for the blocks fetched without explicitly given providers,
+    /// such as with [`Ipfs::get_block`] or a `get` or `cat` passing an empty slice. The providers
+    /// discovered otherwise are still asked as well. An empty list removes the preference.
+    pub fn set_preferred_providers(&self, providers: Vec<PeerId>) {
+        self.repo.set_preferred_providers(providers)
+    }
+
+    /// Returns the providers set with [`Ipfs::set_preferred_providers`].
+    pub fn preferred_providers(&self) -> Vec<PeerId> {
+        self.repo.preferred_providers()
     }
 
     /// Pins a given Cid recursively or directly (non-recursively).
@@ -1061,8 +1850,8 @@ impl Ipfs {
     pub async fn insert_pin(&self, cid: &Cid, recursive: bool) -> Result<(), Error> {
         let span = debug_span!(parent: &self.span, "insert_pin", cid = %cid, recursive);
 
-        self.repo()
-            .insert_pin(cid, recursive, false)
+        let pin = self.repo().insert_pin(cid, recursive, false);
+        with_deadline("insert_pin", self.timeout_policy.pin, pin)
             .instrument(span)
             .await
     }
@@ -1137,6 +1926,22 @@ impl Ipfs {
         self.repo.list_pins(filter).instrument(span).await
     }
 
+    /// Lists a page of at most `limit` pins, optionally filtered by [`PinMode`], in the order of
+    /// the binary representation of their cids. The listing starts after the `start_after` cid,
+    /// so the last cid of a page can be used to request the next one.
+    pub async fn list_pins_page(
+        &self,
+        filter: Option<PinMode>,
+        start_after: Option<Cid>,
+        limit: usize,
+    ) -> Result<Vec<(Cid, PinMode)>, Error> {
+        let span = debug_span!(parent: &self.span, "list_pins_page", ?filter, ?start_after, limit);
+        self.repo
+            .list_pins_page(filter, start_after, limit)
+            .instrument(span)
+            .await
+    }
+
     /// Read specific pins. When `requirement` is `Some`, all pins are required to be of the given
     /// [`PinMode`].
     ///
@@ -1155,11 +1960,63 @@ impl Ipfs {
             .await
     }
 
-    /// Puts an ipld node into the ipfs repo using `dag-cbor` codec and Sha2_256 hash.
+    /// Re-adds the content of every directly or recursively pinned root using the `new_hash`
+    /// function, and pins the new roots the same way the old ones were pinned. When `unpin_old`
+    /// is true, the old roots are unpinned once their replacement has been pinned.
     ///
-    /// Returns Cid version 1 for the document
-    pub async fn put_dag(&self, ipld: Ipld) -> Result<Cid, Error> {
-        self.dag()
+    /// The blocks of a recursive pin not using `new_hash` are rewritten along with the blocks
+    /// linking to them, which requires all of the DAG to be available locally. Roots already using
+    /// `new_hash` are left as is.
+    pub async fn rehash_pins(
+        &self,
+        new_hash: Code,
+        unpin_old: bool,
+    ) -> Result<RehashReport, Error> {
+        use futures::stream::TryStreamExt;
+        let span = debug_span!(parent: &self.span, "rehash_pins", ?new_hash);
+        async move {
+            let pins = self
+                .list_pins(None)
+                .await
+                .try_filter(|(_, mode)| futures::future::ready(*mode != PinMode::Indirect))
+                .try_collect::<Vec<_>>()
+                .await?;
+
+            let dag = self.dag();
+            let mut rehashed = HashMap::new();
+            let mut report = RehashReport::default();
+
+            for (old, mode) in pins {
+                if old.hash().code() == u64::from(new_hash) {
+                    report.skipped.push(old);
+                    continue;
+                }
+
+                let new = dag.rehash(old, new_hash, &mut rehashed).await?;
+                let recursive = mode == PinMode::Recursive;
+
+                if !self.is_pinned(&new).await? {
+                    self.insert_pin(&new, recursive).await?;
+                }
+
+                if unpin_old {
+                    self.remove_pin(&old, recursive).await?;
+                }
+
+                report.rehashed.push((old, new));
+            }
+
+            Ok(report)
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Puts an ipld node into the ipfs repo using `dag-cbor` codec and Sha2_256 hash.
+    ///
+    /// Returns Cid version 1 for the document
+    pub async fn put_dag(&self, ipld: Ipld) -> Result<Cid, Error> {
+        self.dag()
             .put(IpldCodec::DagCbor, ipld, None)
             .instrument(self.span.clone())
             .await
@@ -1169,11 +2026,182 @@ impl Ipfs {
     ///
     /// See [`IpldDag::get`] for more information.
     pub async fn get_dag(&self, path: IpfsPath) -> Result<Ipld, Error> {
-        self.dag()
-            .get(path, &[], false)
+        let dag = self.dag();
+        let ipld = async move { dag.get(path, &[], false).await.map_err(Error::new) };
+        with_deadline("get_dag", self.timeout_policy.get, ipld)
             .instrument(self.span.clone())
             .await
-            .map_err(Error::new)
+    }
+
+    /// Resolves the `path` and writes a CARv1 archive of the DAG rooted at the resolved block to
+    /// `writer`, fetching any missing blocks. Returns the root of the archive.
+    ///
+    /// Only the blocks reachable from the resolved root are written, each of them once.
+    pub async fn export_path_car<W>(&self, path: IpfsPath, writer: W) -> Result<Cid, Error>
+    where
+        W: tokio::io::AsyncWrite + Unpin + Send,
+    {
+        let span = debug_span!(parent: &self.span, "export_path_car", %path);
+        async move {
+            let (resolved, _) = self.dag().resolve(path, true, &[], false).await?;
+            let root = match resolved {
+                dag::ResolvedNode::Link(_, destination) => destination,
+                other => *other.source(),
+            };
+
+            let block = self.repo.get_block(&root, &[], false).await?;
+            let ipld = block.decode::<IpldCodec, Ipld>()?;
+
+            let mut car = car::CarWriter::new(writer);
+            car.write_header(&[root]).await?;
+            car.write_block(&block).await?;
+
+            let mut refs = crate::refs::IpldRefs::default()
+                .with_only_unique()
+                .refs_of_resolved(self.repo(), vec![(root, ipld)])
+                .boxed();
+
+            while let Some(refs::Edge { destination, .. }) = refs.next().await.transpose()? {
+                let block = self.repo.get_block(&destination, &[], false).await?;
+                car.write_block(&block).await?;
+            }
+
+            car.finish().await?;
+            Ok(root)
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Writes every direct and recursive pin, along with the blocks it keeps alive, to `writer`
+    /// as a CARv1 archive which [`Ipfs::restore`] reads back, e.g. to back up or migrate a node.
+    ///
+    /// The root of the archive is a dag-cbor manifest block listing the pins. Unpinned blocks are
+    /// left out, and a pinned block missing from the repo fails the snapshot.
+    pub async fn snapshot<W>(&self, writer: W) -> Result<(), Error>
+    where
+        W: tokio::io::AsyncWrite + Unpin + Send,
+    {
+        use libipld::cbor::DagCborCodec;
+
+        let span = debug_span!(parent: &self.span, "snapshot");
+        async move {
+            let mut pins = Vec::new();
+            let mut listed = self.repo.list_pins(None).await;
+            while let Some((cid, mode)) = listed.next().await.transpose()? {
+                match mode {
+                    PinMode::Direct => pins.push((cid, false)),
+                    PinMode::Recursive => pins.push((cid, true)),
+                    PinMode::Indirect => {}
+                }
+            }
+
+            let manifest = Ipld::List(
+                pins.iter()
+                    .map(|(cid, recursive)| {
+                        Ipld::Map(BTreeMap::from([
+                            ("cid".to_owned(), Ipld::Link(*cid)),
+                            ("recursive".to_owned(), Ipld::Bool(*recursive)),
+                        ]))
+                    })
+                    .collect(),
+            );
+            let manifest = Block::encode(DagCborCodec, Code::Sha2_256, &manifest)?;
+
+            let mut car = car::CarWriter::new(writer);
+            car.write_header(&[*manifest.cid()]).await?;
+            car.write_block(&manifest).await?;
+
+            let mut written = HashSet::new();
+            for (root, recursive) in pins {
+                let block = self
+                    .repo
+                    .get_block_now(&root)
+                    .await?
+                    .ok_or_else(|| anyhow!("pinned block {root} is missing"))?;
+                if written.insert(root) {
+                    car.write_block(&block).await?;
+                }
+
+                if !recursive {
+                    continue;
+                }
+
+                let ipld = block.decode::<IpldCodec, Ipld>()?;
+                let mut refs = crate::refs::IpldRefs::default()
+                    .with_only_unique()
+                    .with_existing_blocks()
+                    .refs_of_resolved(self.repo(), vec![(root, ipld)])
+                    .boxed();
+
+                while let Some(refs::Edge { destination, .. }) = refs.next().await.transpose()? {
+                    if !written.insert(destination) {
+                        continue;
+                    }
+                    let block = self
+                        .repo
+                        .get_block_now(&destination)
+                        .await?
+                        .ok_or_else(|| anyhow!("pinned block {destination} is missing"))?;
+                    car.write_block(&block).await?;
+                }
+            }
+
+            car.finish().await?;
+            Ok(())
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Imports the blocks of an archive written by [`Ipfs::snapshot`] from `reader` and pins
+    /// them the same way they were pinned on the node the snapshot was taken from.
+    ///
+    /// The pins are only inserted once every block was imported, so a truncated or corrupted
+    /// archive fails without pinning anything.
+    pub async fn restore<R>(&self, reader: R) -> Result<(), Error>
+    where
+        R: tokio::io::AsyncRead + Unpin + Send,
+    {
+        let span = debug_span!(parent: &self.span, "restore");
+        async move {
+            let (mut car, roots) = car::CarReader::new(reader).await?;
+            let manifest = match roots.as_slice() {
+                [manifest] => *manifest,
+                _ => anyhow::bail!("snapshot must have the manifest as its only root"),
+            };
+
+            // the blocks are kept from being collected until they are pinned
+            let pending = self.repo.pending_writes();
+            let mut pins = None;
+            while let Some(block) = car.next_block().await? {
+                if *block.cid() == manifest {
+                    pins = Some(block.decode::<IpldCodec, Ipld>()?);
+                    continue;
+                }
+                pending.protect(*block.cid());
+                self.repo.put_block(block).await?;
+            }
+
+            let pins = match pins {
+                Some(Ipld::List(pins)) => pins,
+                Some(_) => anyhow::bail!("snapshot manifest is not a list of pins"),
+                None => anyhow::bail!("snapshot manifest is missing"),
+            };
+
+            for pin in pins {
+                match (pin.get("cid"), pin.get("recursive")) {
+                    (Ok(Ipld::Link(cid)), Ok(Ipld::Bool(recursive))) => {
+                        self.repo.insert_pin(cid, *recursive, true).await?
+                    }
+                    _ => anyhow::bail!("invalid pin in the snapshot manifest"),
+                }
+            }
+
+            Ok(())
+        }
+        .instrument(span)
+        .await
     }
 
     /// Get an ipld path from the datastore.
@@ -1217,7 +2245,7 @@ impl Ipfs {
         unixfs::TraversalFailed,
     > {
         self.unixfs()
-            .cat(starting_point, range, &[], false)
+            .cat(starting_point, range, &[], false, false)
             .instrument(self.span.clone())
             .await
     }
@@ -1258,7 +2286,7 @@ impl Ipfs {
         dest: P,
     ) -> Result<BoxStream<'_, UnixfsStatus>, Error> {
         self.unixfs()
-            .get(path, dest, &[], false)
+            .get(path, dest, &[], false, Default::default())
             .instrument(self.span.clone())
             .await
     }
@@ -1305,7 +2333,7 @@ impl Ipfs {
 
     /// Connects to the peer
     pub async fn connect(&self, target: impl Into<DialOpts>) -> Result<(), Error> {
-        async move {
+        with_deadline("connect", self.timeout_policy.connect, async move {
             let target = target.into();
             let (tx, rx) = oneshot_channel();
             self.to_task
@@ -1316,7 +2344,7 @@ impl Ipfs {
             let subscription = rx.await?;
 
             subscription.await?
-        }
+        })
         .instrument(self.span.clone())
         .await
     }
@@ -1351,6 +2379,106 @@ impl Ipfs {
         .await
     }
 
+    /// Describes the established connections to the peer, including the security protocol and
+    /// the stream multiplexer each of them negotiated. Empty when the peer is not connected.
+    pub async fn connection_info(
+        &self,
+        peer_id: PeerId,
+    ) -> Result<Vec<p2p::ConnectionInfo>, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task
+                .clone()
+                .send(IpfsEvent::ConnectionInfo(peer_id, tx))
+                .await?;
+
+            rx.await.map_err(anyhow::Error::from)
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Returns the connection limits currently in effect.
+    pub async fn connection_limits(&self) -> Result<p2p::ConnectionLimits, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task
+                .clone()
+                .send(IpfsEvent::ConnectionLimits(tx))
+                .await?;
+
+            rx.await.map_err(anyhow::Error::from)
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Replaces the connection limits of the running node, returning the previous ones. The
+    /// limits apply to the subsequent connection attempts, so lowering them does not close the
+    /// connections already established.
+    pub async fn update_connection_limits(
+        &self,
+        limits: p2p::ConnectionLimits,
+    ) -> Result<p2p::ConnectionLimits, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task
+                .clone()
+                .send(IpfsEvent::UpdateConnectionLimits(limits, tx))
+                .await?;
+
+            rx.await.map_err(anyhow::Error::from)
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Attaches an application defined tag, such as "backend", to the peer. Tags only group the
+    /// peers for [`Ipfs::peers_with_tag`] and do not affect the connections to them.
+    pub async fn tag_peer(&self, peer_id: PeerId, tag: impl Into<String>) -> Result<(), Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task
+                .clone()
+                .send(IpfsEvent::TagPeer(peer_id, tag.into(), tx))
+                .await?;
+
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Removes a tag attached to the peer with [`Ipfs::tag_peer`].
+    pub async fn untag_peer(&self, peer_id: PeerId, tag: impl Into<String>) -> Result<(), Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task
+                .clone()
+                .send(IpfsEvent::UntagPeer(peer_id, tag.into(), tx))
+                .await?;
+
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Returns the peers which have the given tag attached.
+    pub async fn peers_with_tag(&self, tag: impl Into<String>) -> Result<Vec<PeerId>, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+            self.to_task
+                .clone()
+                .send(IpfsEvent::PeersWithTag(tag.into(), tx))
+                .await?;
+
+            rx.await.map_err(anyhow::Error::from)
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
     /// Returns known peer addresses
     pub async fn addrs(&self) -> Result<Vec<(PeerId, Vec<Multiaddr>)>, Error> {
         async move {
@@ -1376,6 +2504,34 @@ impl Ipfs {
         .await
     }
 
+    /// Waits until there is an established connection to a peer, returning immediately if
+    /// there already is one. Fails if the peer does not connect within `timeout`.
+    ///
+    /// Does not dial the peer itself.
+    pub async fn wait_connected(&self, peer_id: PeerId, timeout: Duration) -> Result<(), Error> {
+        // subscribe before checking so a connection landing in between is not missed
+        let mut events = self.connection_events().await?;
+
+        if self.is_connected(peer_id).await? {
+            return Ok(());
+        }
+
+        let connected = async move {
+            while let Some(event) = events.next().await {
+                if matches!(event, ConnectionEvent::PeerConnected { peer_id: id } if id == peer_id)
+                {
+                    return Ok(());
+                }
+            }
+            Err(anyhow!("ipfs is shutting down"))
+        };
+
+        tokio::time::timeout(timeout, connected)
+            .instrument(self.span.clone())
+            .await
+            .map_err(|_| anyhow!("timed out waiting for {peer_id} to connect"))?
+    }
+
     /// Returns the connected peers
     pub async fn connected(&self) -> Result<Vec<PeerId>, Error> {
         async move {
@@ -1389,11 +2545,26 @@ impl Ipfs {
 
     /// Disconnects a given peer.
     pub async fn disconnect(&self, target: PeerId) -> Result<(), Error> {
+        self.disconnect_peer(target, None).await
+    }
+
+    /// Disconnects a given peer, logging `reason` and reporting it as the cause of the
+    /// [`ConnectionEvent::PeerDisconnected`] event. libp2p has no way to signal the reason to the
+    /// peer, so it is only known locally.
+    pub async fn disconnect_with_reason(
+        &self,
+        target: PeerId,
+        reason: impl Into<String>,
+    ) -> Result<(), Error> {
+        self.disconnect_peer(target, Some(reason.into())).await
+    }
+
+    async fn disconnect_peer(&self, target: PeerId, reason: Option<String>) -> Result<(), Error> {
         async move {
             let (tx, rx) = oneshot_channel();
             self.to_task
                 .clone()
-                .send(IpfsEvent::Disconnect(target, tx))
+                .send(IpfsEvent::Disconnect(target, reason, tx))
                 .await?;
             let (rx_nb, rx_swarm) = rx.await?;
             let (result_nb, result_swarm) = futures::join!(rx_nb, rx_swarm);
@@ -1433,6 +2604,27 @@ impl Ipfs {
         .await
     }
 
+    /// Requests the identity information of a connected peer anew instead of returning the one
+    /// received earlier, which could be outdated after the peer changed its protocols or
+    /// addresses. The cached information is replaced with the response.
+    ///
+    /// Identify on its own only requests the information once a connection is established and at
+    /// its interval after that.
+    pub async fn refresh_identify(&self, peer_id: PeerId) -> Result<PeerInfo, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task
+                .clone()
+                .send(IpfsEvent::RefreshIdentify(peer_id, tx))
+                .await?;
+
+            rx.await?.await?.map(PeerInfo::from)
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
     /// Returns the peer identity information. If no peer id is supplied the local node identity is used.
     pub async fn identity(&self, peer_id: Option<PeerId>) -> Result<PeerInfo, Error> {
         async move {
@@ -1497,9 +2689,17 @@ impl Ipfs {
     }
 
     /// Subscribes to a given topic. Can be done at most once without unsubscribing in the between.
-    /// The subscription can be unsubscribed by dropping the stream or calling
-    /// [`Ipfs::pubsub_unsubscribe`].
-    pub async fn pubsub_subscribe(&self, topic: String) -> Result<SubscriptionStream, Error> {
+    /// The subscription can be unsubscribed by dropping it, calling [`Subscription::unsubscribe`]
+    /// or calling [`Ipfs::pubsub_unsubscribe`].
+    pub async fn pubsub_subscribe(&self, topic: String) -> Result<Subscription, Error> {
+        let stream = self.pubsub_subscribe_stream(topic.clone()).await?;
+        Ok(Subscription::new(self.clone(), topic, stream))
+    }
+
+    pub(crate) async fn pubsub_subscribe_stream(
+        &self,
+        topic: String,
+    ) -> Result<SubscriptionStream, Error> {
         async move {
             let (tx, rx) = oneshot_channel();
 
@@ -1508,13 +2708,99 @@ impl Ipfs {
                 .send(IpfsEvent::PubsubSubscribe(topic.clone(), tx))
                 .await?;
 
-            rx.await?
+            rx.await??
                 .ok_or_else(|| format_err!("already subscribed to {:?}", topic))
         }
         .instrument(self.span.clone())
         .await
     }
 
+    /// Subscribes to all of the given topics, returning a single stream yielding the messages of
+    /// every topic tagged with the topic they were received on. Further topics can be added or
+    /// removed through [`MultiSubscriptionStream::add_topic`] and
+    /// [`MultiSubscriptionStream::remove_topic`].
+    pub async fn pubsub_subscribe_many(
+        &self,
+        topics: Vec<String>,
+    ) -> Result<MultiSubscriptionStream, Error> {
+        let mut stream = MultiSubscriptionStream::new(self.clone());
+        for topic in topics {
+            stream.add_topic(topic).await?;
+        }
+        Ok(stream)
+    }
+
+    /// Stream of [`ConnectionEvent`] for the peers connecting and disconnecting from now on.
+    pub async fn connection_events(&self) -> Result<BoxStream<'static, ConnectionEvent>, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task
+                .clone()
+                .send(IpfsEvent::ConnectionEventStream(tx))
+                .await?;
+
+            Ok(rx.await?.boxed())
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Stream of [`ListenerEvent`] for the relay circuits listened on from now on.
+    pub async fn listener_events(&self) -> Result<BoxStream<'static, ListenerEvent>, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task
+                .clone()
+                .send(IpfsEvent::ListenerEventStream(tx))
+                .await?;
+
+            Ok(rx.await?.boxed())
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Stream of the peers discovered from now on through mdns, kademlia and identify, as far
+    /// as they are enabled. A peer is reported again by the same mechanism only when it brings
+    /// addresses not seen before, and then only with those addresses. Peers not rediscovered
+    /// within ten minutes are forgotten, and reported in full the next time they are.
+    pub async fn peer_discovery(&self) -> Result<BoxStream<'static, DiscoveredPeer>, Error> {
+        const FORGET_AFTER: Duration = Duration::from_secs(10 * 60);
+
+        async move {
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task
+                .clone()
+                .send(IpfsEvent::PeerDiscoveryStream(tx))
+                .await?;
+
+            let mut seen: HashMap<(PeerId, DiscoverySource), (Instant, HashSet<Multiaddr>)> =
+                HashMap::new();
+
+            let stream = rx.await?.filter_map(move |mut discovered: DiscoveredPeer| {
+                let now = Instant::now();
+                seen.retain(|_, (at, _)| now.duration_since(*at) < FORGET_AFTER);
+
+                let key = (discovered.peer_id, discovered.source);
+                let first = !seen.contains_key(&key);
+                let (at, known) = seen.entry(key).or_insert_with(|| (now, HashSet::new()));
+                *at = now;
+                discovered
+                    .addresses
+                    .retain(|addr| known.insert(addr.clone()));
+                let report = first || !discovered.addresses.is_empty();
+                futures::future::ready(report.then_some(discovered))
+            });
+
+            Ok(stream.boxed())
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
     /// Stream that returns [`PubsubEvent`] for a given topic
     pub async fn pubsub_events(
         &self,
@@ -1554,6 +2840,11 @@ impl Ipfs {
     }
 
     /// Publishes to the topic which may have been subscribed to earlier
+    ///
+    /// Fails with a [`PubsubPublishError::RateLimited`] when the publish rate configured through
+    /// [`PubsubConfig::publish_rate`] is exceeded for the topic, or with a
+    /// [`PubsubPublishError::MessageTooLarge`] when the data is larger than
+    /// [`PubsubConfig::max_transmit_size`].
     pub async fn pubsub_publish(&self, topic: String, data: Vec<u8>) -> Result<MessageId, Error> {
         async move {
             let (tx, rx) = oneshot_channel();
@@ -1562,7 +2853,7 @@ impl Ipfs {
                 .clone()
                 .send(IpfsEvent::PubsubPublish(topic, data, tx))
                 .await?;
-            Ok(rx.await??)
+            rx.await?
         }
         .instrument(self.span.clone())
         .await
@@ -1597,7 +2888,7 @@ impl Ipfs {
                 .send(IpfsEvent::PubsubPeers(topic, tx))
                 .await?;
 
-            Ok(rx.await?)
+            rx.await?
         }
         .instrument(self.span.clone())
         .await
@@ -1613,7 +2904,7 @@ impl Ipfs {
                 .send(IpfsEvent::PubsubSubscribed(tx))
                 .await?;
 
-            Ok(rx.await?)
+            rx.await?
         }
         .instrument(self.span.clone())
         .await
@@ -1635,6 +2926,90 @@ impl Ipfs {
         .await
     }
 
+    /// Sends a crafted bitswap message, with any wants, block presences or blocks, to the peer
+    /// without going through the bitswap client or server. Meant for testing other bitswap
+    /// implementations.
+    pub async fn send_bitswap_message(
+        &self,
+        peer: PeerId,
+        message: BitswapMessage,
+    ) -> Result<(), Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task
+                .clone()
+                .send(IpfsEvent::SendBitswapMessage(peer, message, tx))
+                .await?;
+
+            rx.await?.await
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Returns the number of blocks in the local wantlist. Wants queued because of
+    /// [`BitswapConfig::with_max_wantlist_size`] are not in the wantlist yet.
+    pub async fn wantlist_len(&self) -> Result<usize, Error> {
+        self.bitswap_wantlist(None)
+            .await
+            .map(|wantlist| wantlist.len())
+    }
+
+    /// Returns the number of inbound bitswap messages dropped, either for exceeding
+    /// [`BitswapConfig::with_max_inbound_message_rate`] or for arriving faster than they could be
+    /// processed.
+    pub async fn bitswap_dropped_messages(&self) -> Result<u64, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task
+                .clone()
+                .send(IpfsEvent::BitswapDroppedMessages(tx))
+                .await?;
+
+            Ok(rx.await?)
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Starts or stops answering the wants of other peers, e.g. to stop uploading blocks under
+    /// bandwidth pressure while still fetching. Has no effect if [`BitswapConfig::server`] is
+    /// disabled.
+    pub async fn set_bitswap_server(&self, enabled: bool) -> Result<(), Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task
+                .clone()
+                .send(IpfsEvent::SetBitswapServer(enabled, tx))
+                .await?;
+
+            Ok(rx.await?)
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Returns the number of wants received from other peers while the bitswap server was
+    /// stopped with [`Ipfs::set_bitswap_server`]. Their answers are held back until the server
+    /// is started again.
+    pub async fn bitswap_unserved_wants(&self) -> Result<u64, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task
+                .clone()
+                .send(IpfsEvent::BitswapUnservedWants(tx))
+                .await?;
+
+            Ok(rx.await?)
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
     /// Returns a list of local blocks
     ///
     /// This implementation is subject to change into a stream, which might only include the pinned
@@ -1662,6 +3037,74 @@ impl Ipfs {
         .await
     }
 
+    /// Returns the listeners of the node along with the transport each of them listens on and
+    /// their concrete addresses, unlike [`Ipfs::listening_addresses`] which flattens them.
+    pub async fn listeners(&self) -> Result<Vec<p2p::ListenerDescriptor>, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task
+                .clone()
+                .send(IpfsEvent::ListenerDescriptors(tx))
+                .await?;
+
+            Ok(rx.await?)
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Returns how many peers the kademlia routing table holds in each of its buckets. A sparse
+    /// table means few peers to route the DHT lookups through. Fails if kademlia is disabled.
+    pub async fn kad_routing_table(&self) -> Result<p2p::KadTableInfo, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task
+                .clone()
+                .send(IpfsEvent::KadRoutingTable(tx))
+                .await?;
+
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Returns the reservations the node holds with relays, which relay the circuits to the
+    /// node. Fails if the relay client is disabled.
+    pub async fn relay_reservations(&self) -> Result<Vec<p2p::ReservationInfo>, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task
+                .clone()
+                .send(IpfsEvent::RelayReservations(tx))
+                .await?;
+
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Returns the circuits the node relays between other peers. Fails if the relay server is
+    /// disabled.
+    pub async fn relayed_circuits(&self) -> Result<Vec<p2p::CircuitInfo>, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task
+                .clone()
+                .send(IpfsEvent::RelayedCircuits(tx))
+                .await?;
+
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
     /// Returns external addresses
     pub async fn external_addresses(&self) -> Result<Vec<Multiaddr>, Error> {
         async move {
@@ -1684,6 +3127,25 @@ impl Ipfs {
         .await
     }
 
+    /// Add a given multiaddr as an external address of this node, advertised to other peers.
+    ///
+    /// With [`IpfsOptions::verify_external_addresses`] the address is only advertised once an
+    /// autonat server was able to dial back to it.
+    pub async fn add_external_address(&self, addr: Multiaddr) -> Result<(), Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task
+                .clone()
+                .send(IpfsEvent::AddExternalAddress(addr, tx))
+                .await?;
+
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
     /// Add a given multiaddr as a listening address. Will fail if the address is unsupported, or
     /// if it is already being listened on. Currently will invoke `Swarm::listen_on` internally,
     /// keep the ListenerId for later `remove_listening_address` use in a HashMap.
@@ -1752,7 +3214,7 @@ impl Ipfs {
     /// when it's finished, the newly added DHT records are checked for the existence of the desired
     /// `peer_id` and if it's there, the list of its known addresses is returned.
     pub async fn find_peer(&self, peer_id: PeerId) -> Result<Vec<Multiaddr>, Error> {
-        async move {
+        with_deadline("find_peer", self.timeout_policy.dht, async move {
             let (tx, rx) = oneshot_channel();
 
             self.to_task
@@ -1779,7 +3241,7 @@ impl Ipfs {
                     }
                 }
             }
-        }
+        })
         .instrument(self.span.clone())
         .await
     }
@@ -1793,7 +3255,7 @@ impl Ipfs {
 
             self.to_task
                 .clone()
-                .send(IpfsEvent::GetProviders(cid, tx))
+                .send(IpfsEvent::GetProviders(cid, None, tx))
                 .await?;
 
             rx.await?.ok_or_else(|| anyhow!("Provider already exist"))
@@ -1802,63 +3264,97 @@ impl Ipfs {
         .await
     }
 
-    /// Establishes the node as a provider of a block with the given Cid: it publishes a provider
-    /// record with the given key (Cid) and the node's PeerId to the peers closest to the key. The
-    /// publication of provider records is periodically repeated as per the interval specified in
-    /// `libp2p`'s  `KademliaConfig`.
-    pub async fn provide(&self, cid: Cid) -> Result<(), Error> {
-        // don't provide things we don't actually have
-        if self.repo.get_block_now(&cid).await?.is_none() {
-            return Err(anyhow!(
-                "Error: block {} not found locally, cannot provide",
-                cid
-            ));
-        }
-
-        let kad_result = async move {
+    /// Performs a DHT lookup for providers like [`Ipfs::get_providers`], ending the stream once
+    /// `max_results` distinct providers were found or once `timeout` elapsed, whichever comes
+    /// first. Reaching `max_results` also stops the lookup.
+    pub async fn get_providers_bounded(
+        &self,
+        cid: Cid,
+        max_results: Option<usize>,
+        timeout: Option<Duration>,
+    ) -> Result<BoxStream<'static, PeerId>, Error> {
+        async move {
             let (tx, rx) = oneshot_channel();
 
             self.to_task
                 .clone()
-                .send(IpfsEvent::Provide(cid, tx))
+                .send(IpfsEvent::GetProviders(cid, max_results, tx))
                 .await?;
 
-            rx.await?
+            let providers = rx.await?.ok_or_else(|| anyhow!("Provider already exist"))?;
+
+            Ok(match timeout {
+                Some(timeout) => providers.take_until(tokio::time::sleep(timeout)).boxed(),
+                None => providers,
+            })
         }
         .instrument(self.span.clone())
-        .await?
-        .await;
+        .await
+    }
+
+    /// Establishes the node as a provider of a block with the given Cid: it publishes a provider
+    /// record with the given key (Cid) and the node's PeerId to the peers closest to the key. The
+    /// publication of provider records is periodically repeated as per the interval specified in
+    /// `libp2p`'s  `KademliaConfig`.
+    pub async fn provide(&self, cid: Cid) -> Result<(), Error> {
+        with_deadline("provide", self.timeout_policy.dht, async move {
+            // don't provide things we don't actually have
+            if self.repo.get_block_now(&cid).await?.is_none() {
+                return Err(anyhow!(
+                    "Error: block {} not found locally, cannot provide",
+                    cid
+                ));
+            }
+
+            let kad_result = async move {
+                let (tx, rx) = oneshot_channel();
 
-        match kad_result? {
-            Ok(KadResult::Complete) => Ok(()),
-            Ok(_) => unreachable!(),
-            Err(e) => Err(anyhow!(e)),
-        }
+                self.to_task
+                    .clone()
+                    .send(IpfsEvent::Provide(cid, tx))
+                    .await?;
+
+                rx.await?
+            }
+            .instrument(self.span.clone())
+            .await?
+            .await;
+
+            match kad_result? {
+                Ok(KadResult::Complete) => Ok(()),
+                Ok(_) => unreachable!(),
+                Err(e) => Err(anyhow!(e)),
+            }
+        })
+        .await
     }
 
     /// Returns a list of peers closest to the given `PeerId`, as suggested by the DHT. The
     /// node must have at least one known peer in its routing table in order for the query
     /// to return any values.
     pub async fn get_closest_peers(&self, peer_id: PeerId) -> Result<Vec<PeerId>, Error> {
-        let kad_result = async move {
-            let (tx, rx) = oneshot_channel();
+        with_deadline("get_closest_peers", self.timeout_policy.dht, async move {
+            let kad_result = async move {
+                let (tx, rx) = oneshot_channel();
 
-            self.to_task
-                .clone()
-                .send(IpfsEvent::GetClosestPeers(peer_id, tx))
-                .await?;
+                self.to_task
+                    .clone()
+                    .send(IpfsEvent::GetClosestPeers(peer_id, tx))
+                    .await?;
 
-            Ok(rx.await?).map_err(|e: String| anyhow!(e))
-        }
-        .instrument(self.span.clone())
-        .await?
-        .await;
+                Ok(rx.await?).map_err(|e: String| anyhow!(e))
+            }
+            .instrument(self.span.clone())
+            .await?
+            .await;
 
-        match kad_result? {
-            Ok(KadResult::Peers(closest)) => Ok(closest),
-            Ok(_) => unreachable!(),
-            Err(e) => Err(anyhow!(e)),
-        }
+            match kad_result? {
+                Ok(KadResult::Peers(closest)) => Ok(closest),
+                Ok(_) => unreachable!(),
+                Err(e) => Err(anyhow!(e)),
+            }
+        })
+        .await
     }
 
     /// Change the DHT mode
@@ -1920,39 +3416,42 @@ impl Ipfs {
         value: Vec<u8>,
         quorum: Quorum,
     ) -> Result<(), Error> {
-        let kad_result = async move {
-            let key = key.as_ref();
+        with_deadline("dht_put", self.timeout_policy.dht, async move {
+            let kad_result = async move {
+                let key = key.as_ref();
 
-            let key_str = String::from_utf8_lossy(key);
+                let key_str = String::from_utf8_lossy(key);
 
-            let key = if let Ok((prefix, _)) = split_dht_key(&key_str) {
-                if let Some(key_fn) = self.record_key_validator.get(prefix) {
-                    key_fn(&key_str)?
+                let key = if let Ok((prefix, _)) = split_dht_key(&key_str) {
+                    if let Some(key_fn) = self.record_key_validator.get(prefix) {
+                        key_fn(&key_str)?
+                    } else {
+                        Key::from(key.to_vec())
+                    }
                 } else {
                     Key::from(key.to_vec())
-                }
-            } else {
-                Key::from(key.to_vec())
-            };
+                };
 
-            let (tx, rx) = oneshot_channel();
+                let (tx, rx) = oneshot_channel();
 
-            self.to_task
-                .clone()
-                .send(IpfsEvent::DhtPut(key, value, quorum, tx))
-                .await?;
+                self.to_task
+                    .clone()
+                    .send(IpfsEvent::DhtPut(key, value, quorum, tx))
+                    .await?;
 
-            Ok(rx.await?).map_err(|e: String| anyhow!(e))
-        }
-        .instrument(self.span.clone())
-        .await??
-        .await;
+                Ok(rx.await?).map_err(|e: String| anyhow!(e))
+            }
+            .instrument(self.span.clone())
+            .await??
+            .await;
 
-        match kad_result? {
-            Ok(KadResult::Complete) => Ok(()),
-            Ok(_) => unreachable!(),
-            Err(e) => Err(anyhow!(e)),
-        }
+            match kad_result? {
+                Ok(KadResult::Complete) => Ok(()),
+                Ok(_) => unreachable!(),
+                Err(e) => Err(anyhow!(e)),
+            }
+        })
+        .await
     }
 
     // TBD
@@ -2103,6 +3602,73 @@ impl Ipfs {
         Ok(bootstrap_task)
     }
 
+    /// Bootstraps the local node like [`Ipfs::bootstrap`], reporting the progress of the query.
+    ///
+    /// Every step of the query yields a [`KadResult::Peers`] with the peers added to the routing
+    /// table since the previous step, possibly none, and the query ends with a
+    /// [`KadResult::Complete`], or an error if it timed out.
+    pub async fn bootstrap_progress(
+        &self,
+    ) -> Result<BoxStream<'static, Result<KadResult, Error>>, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task
+                .clone()
+                .send(IpfsEvent::BootstrapProgress(tx))
+                .await?;
+
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Bootstraps the local node like [`Ipfs::bootstrap`], retrying with backoff according to
+    /// `policy` when an attempt fails, e.g. because no peers are known yet. Every failed attempt
+    /// is logged along with the delay until the next one.
+    ///
+    /// Returns the result of the first successful attempt, or the error of the last attempt once
+    /// the policy is exhausted.
+    pub async fn bootstrap_with_retry(&self, policy: RetryPolicy) -> Result<KadResult, Error> {
+        let mut backoff = policy.initial_backoff;
+        let mut attempt = 1;
+
+        loop {
+            let result = match self.bootstrap().await {
+                Ok(handle) => handle
+                    .await
+                    .map_err(anyhow::Error::from)
+                    .and_then(|res| res),
+                Err(e) => Err(e),
+            };
+
+            let e = match result {
+                Ok(res) => {
+                    debug!("bootstrap succeeded on attempt {attempt}");
+                    return Ok(res);
+                }
+                Err(e) => e,
+            };
+
+            if attempt >= policy.max_attempts {
+                error!("bootstrap failed after {attempt} attempts: {e}");
+                return Err(e.context(format!("bootstrap failed after {attempt} attempts")));
+            }
+
+            warn!(
+                "bootstrap attempt {attempt}/{} failed: {e}; retrying in {backoff:?}",
+                policy.max_attempts
+            );
+
+            tokio::time::sleep(backoff).await;
+            backoff = backoff
+                .checked_mul(policy.multiplier)
+                .map_or(policy.max_backoff, |b| b.min(policy.max_backoff));
+            attempt += 1;
+        }
+    }
+
     /// Add address of a peer to the address book
     pub async fn add_peer(&self, peer_id: PeerId, mut addr: Multiaddr) -> Result<(), Error> {
         if matches!(addr.iter().last(), Some(Protocol::P2p(_))) {
@@ -2149,6 +3715,28 @@ impl Ipfs {
         rx.await.map_err(anyhow::Error::from)?
     }
 
+    /// Returns how long the address of the peer stays in the address book unless confirmed
+    /// again, see [`p2p::AddressBookConfig::ttl`]. `None` when the address is unknown or no ttl
+    /// is configured.
+    pub async fn address_ttl(
+        &self,
+        peer_id: PeerId,
+        mut addr: Multiaddr,
+    ) -> Result<Option<Duration>, Error> {
+        if matches!(addr.iter().last(), Some(Protocol::P2p(_))) {
+            addr.pop();
+        }
+
+        let (tx, rx) = oneshot::channel();
+
+        self.to_task
+            .clone()
+            .send(IpfsEvent::AddressTtl(peer_id, addr, tx))
+            .await?;
+
+        rx.await.map_err(anyhow::Error::from)
+    }
+
     /// Returns the Bitswap peers for the a `Node`.
     pub async fn get_bitswap_peers(&self) -> Result<Vec<PeerId>, Error> {
         let (tx, rx) = oneshot_channel();
@@ -2328,7 +3916,7 @@ mod node {
         #[allow(clippy::type_complexity)]
         pub fn get_subscriptions(
             &self,
-        ) -> &parking_lot::Mutex<HashMap<Cid, Vec<oneshot::Sender<Result<Block, String>>>>>
+        ) -> &parking_lot::Mutex<HashMap<Cid, Vec<oneshot::Sender<Result<Block, BlockNotFound>>>>>
         {
             &self.ipfs.repo.subscriptions
         }
@@ -2396,6 +3984,198 @@ mod tests {
         assert_eq!(block, new_block);
     }
 
+    #[tokio::test]
+    async fn repo_ready_after_start() {
+        use crate::repo::{
+            blockstore::memory::MemBlockStore, datastore::sled::SledDataStore, lock::MemLock,
+        };
+
+        // pins are kept in the sled database, which is only opened by `init`
+        let tmp = tempfile::TempDir::new().unwrap();
+        let opts = IpfsOptions {
+            ipfs_path: StoragePath::Custom {
+                blockstore: Arc::new(MemBlockStore::new(Default::default())),
+                datastore: Arc::new(SledDataStore::new(tmp.path().into())),
+                lock: Arc::new(MemLock),
+            },
+            ..Default::default()
+        };
+        let ipfs = UninitializedIpfsNoop::with_opt(opts).start().await.unwrap();
+
+        tokio::time::timeout(Duration::from_secs(5), ipfs.repo_ready())
+            .await
+            .expect("repo is ready once started");
+
+        let data = b"pinned once ready\n".to_vec();
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+        ipfs.put_block(Block::new(cid, data).unwrap())
+            .await
+            .unwrap();
+        ipfs.insert_pin(&cid, false).await.unwrap();
+        assert!(ipfs.is_pinned(&cid).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn compute_cid_without_storing() {
+        use libipld::cid::Version;
+
+        let ipfs = Node::new("test_node").await;
+
+        // echo foobar > file1 && ipfs add file1
+        let data = hex_literal::hex!("0a0d08021207666f6f6261720a1807");
+        let cid = ipfs
+            .compute_cid(&data, IpldCodec::DagPb, Code::Sha2_256, Version::V0)
+            .unwrap();
+        assert_eq!(
+            cid.to_string(),
+            "QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL"
+        );
+        assert!(!ipfs.repo.contains(&cid).await.unwrap());
+
+        ipfs.compute_cid(&data, IpldCodec::Raw, Code::Sha2_256, Version::V0)
+            .unwrap_err();
+        ipfs.compute_cid(&data, IpldCodec::DagPb, Code::Blake2b256, Version::V0)
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn custom_block_and_pin_store() {
+        use crate::repo::{blockstore::memory::MemBlockStore, datastore::memory::MemDataStore};
+
+        let block_store = Arc::new(MemBlockStore::new(Default::default()));
+        let pin_store = Arc::new(MemDataStore::new(Default::default()));
+        let ipfs = UninitializedIpfsNoop::empty()
+            .with_block_store(block_store.clone())
+            .with_pin_store(pin_store.clone())
+            .start()
+            .await
+            .unwrap();
+
+        let data = b"custom store\n".to_vec();
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+        let block = Block::new(cid, data).unwrap();
+
+        ipfs.put_block(block.clone()).await.unwrap();
+        assert_eq!(block_store.get(&cid).await.unwrap(), Some(block.clone()));
+
+        let data = b"put into the store\n".to_vec();
+        let other = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+        let other_block = Block::new(other, data).unwrap();
+        block_store.put(other_block.clone()).await.unwrap();
+        assert_eq!(ipfs.get_block(&other).await.unwrap(), other_block);
+
+        ipfs.insert_pin(&cid, false).await.unwrap();
+        assert!(pin_store.is_pinned(&cid).await.unwrap());
+        assert!(!ipfs.repo().data_store().is_pinned(&cid).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn custom_pin_store_is_initialized() {
+        use crate::repo::datastore::sled::SledDataStore;
+
+        let dir = tempfile::tempdir().unwrap();
+        let pin_store = Arc::new(SledDataStore::new(dir.path().to_path_buf()));
+        let ipfs = UninitializedIpfsNoop::empty()
+            .with_pin_store(pin_store.clone())
+            .start()
+            .await
+            .unwrap();
+
+        let cid = ipfs.put_dag(ipld!("pinned")).await.unwrap();
+        ipfs.insert_pin(&cid, false).await.unwrap();
+        assert!(pin_store.is_pinned(&cid).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn disallowed_codecs_are_rejected() {
+        use libipld::cbor::DagCborCodec;
+
+        let ipfs = UninitializedIpfsNoop::empty()
+            .set_allowed_codecs([IpldCodec::DagPb.into(), IpldCodec::Raw.into()])
+            .start()
+            .await
+            .unwrap();
+
+        let data = b"raw block\n".to_vec();
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+        ipfs.put_block(Block::new(cid, data).unwrap())
+            .await
+            .unwrap();
+
+        let block = Block::encode(DagCborCodec, Code::Sha2_256, &ipld!({ "a": 1 })).unwrap();
+        let cid = *block.cid();
+        let error = ipfs.put_block(block).await.unwrap_err();
+        let error = error.downcast_ref::<repo::CodecNotAllowed>().unwrap();
+        assert_eq!(error.codec, u64::from(IpldCodec::DagCbor));
+        assert!(!ipfs.repo().contains(&cid).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn recursive_pins_are_limited() {
+        use crate::repo::datastore::flatfs::FsDataStore;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // unlike the in-memory store, the fs store collects the references of concurrent pins
+        // concurrently
+        let tmp = tempfile::TempDir::new().unwrap();
+        let pin_store = Arc::new(FsDataStore::new(tmp.path().into()));
+        pin_store.init().await.unwrap();
+
+        let ipfs = UninitializedIpfsNoop::empty()
+            .with_pin_store(pin_store)
+            .set_max_concurrent_recursive_pins(2)
+            .start()
+            .await
+            .unwrap();
+        let repo = ipfs.repo();
+
+        let active = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+
+        let raw_cid = |data: String| {
+            Cid::new_v1(
+                IpldCodec::Raw.into(),
+                Code::Sha2_256.digest(data.as_bytes()),
+            )
+        };
+        let roots = (0..6)
+            .map(|i| (raw_cid(format!("root {i}")), raw_cid(format!("child {i}"))))
+            .collect::<Vec<_>>();
+        // upgrading a direct pin walks the references under the same limit
+        for (root, _) in &roots[3..] {
+            repo.insert_direct_pin(root).await.unwrap();
+        }
+
+        let pins = roots.iter().enumerate().map(|(i, (root, child))| {
+            let active = active.clone();
+            let peak = peak.clone();
+            let child = *child;
+            let refs = futures::stream::once(async move {
+                let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                active.fetch_sub(1, Ordering::SeqCst);
+                Ok(child)
+            })
+            .boxed();
+            if i < 3 {
+                repo.pin_store().insert_recursive_pin(root, refs)
+            } else {
+                repo.pin_store().upgrade_to_recursive(root, refs)
+            }
+        });
+
+        for result in futures::future::join_all(pins).await {
+            result.unwrap();
+        }
+
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        for (root, child) in &roots {
+            assert!(ipfs.is_pinned(root).await.unwrap());
+            assert!(ipfs.is_pinned(child).await.unwrap());
+        }
+    }
+
     #[tokio::test]
     async fn test_put_and_get_dag() {
         let ipfs = Node::new("test_node").await;
@@ -2418,4 +4198,341 @@ mod tests {
         ipfs.remove_pin(&cid, false).await.unwrap();
         assert!(!ipfs.is_pinned(&cid).await.unwrap());
     }
+
+    #[tokio::test]
+    async fn rehash_pins() {
+        let ipfs = Node::new("test_node").await;
+
+        let leaf = ipfs.put_dag(ipld!("leaf")).await.unwrap();
+        let root = ipfs.put_dag(ipld!({ "child": leaf })).await.unwrap();
+        ipfs.insert_pin(&root, true).await.unwrap();
+
+        let report = ipfs.rehash_pins(Code::Blake3_256, true).await.unwrap();
+        assert_eq!(report.rehashed.len(), 1);
+        assert!(report.skipped.is_empty());
+
+        let (old, new) = report.rehashed[0];
+        assert_eq!(old, root);
+        assert_eq!(new.hash().code(), u64::from(Code::Blake3_256));
+
+        assert!(!ipfs.is_pinned(&root).await.unwrap());
+        let pins = ipfs.query_pins(vec![new], None).await.unwrap();
+        assert!(matches!(pins[0], (cid, PinKind::Recursive(_)) if cid == new));
+
+        let child = match ipfs.get_dag(new.into()).await.unwrap() {
+            Ipld::Map(mut map) => map.remove("child"),
+            other => panic!("unexpected document: {other:?}"),
+        };
+        let child = match child {
+            Some(Ipld::Link(cid)) => cid,
+            other => panic!("unexpected child: {other:?}"),
+        };
+        assert_eq!(child.hash().code(), u64::from(Code::Blake3_256));
+        assert!(ipfs.is_pinned(&child).await.unwrap());
+        assert_eq!(ipfs.get_dag(child.into()).await.unwrap(), ipld!("leaf"));
+
+        // rehashing again has nothing left to do
+        let report = ipfs.rehash_pins(Code::Blake3_256, true).await.unwrap();
+        assert!(report.rehashed.is_empty());
+        assert_eq!(report.skipped, vec![new]);
+    }
+
+    #[tokio::test]
+    async fn rehash_pins_keeps_blocks_already_using_the_hash() {
+        use libipld::cbor::DagCborCodec;
+        let ipfs = Node::new("test_node").await;
+
+        let leaf = Block::encode(DagCborCodec, Code::Blake3_256, &ipld!("leaf")).unwrap();
+        let leaf = ipfs.put_block(leaf).await.unwrap();
+        let root = ipfs.put_dag(ipld!({ "child": leaf })).await.unwrap();
+        ipfs.insert_pin(&root, true).await.unwrap();
+
+        let report = ipfs.rehash_pins(Code::Blake3_256, true).await.unwrap();
+        let (_, new) = report.rehashed[0];
+        assert_eq!(
+            ipfs.get_dag(new.into()).await.unwrap(),
+            ipld!({ "child": leaf })
+        );
+    }
+
+    #[tokio::test]
+    async fn dedup_stat() {
+        let ipfs = Node::new("test_node").await;
+
+        let shared_leaf = ipfs.put_dag(ipld!("shared leaf")).await.unwrap();
+        let shared = ipfs.put_dag(ipld!({ "leaf": shared_leaf })).await.unwrap();
+        let a_leaf = ipfs.put_dag(ipld!("a")).await.unwrap();
+        let b_leaf = ipfs.put_dag(ipld!("b")).await.unwrap();
+        let a = ipfs
+            .put_dag(ipld!({ "leaf": a_leaf, "shared": shared }))
+            .await
+            .unwrap();
+        let b = ipfs
+            .put_dag(ipld!({ "leaf": b_leaf, "shared": shared }))
+            .await
+            .unwrap();
+
+        ipfs.insert_pin(&a, true).await.unwrap();
+        ipfs.insert_pin(&b, true).await.unwrap();
+
+        let stat = ipfs.repo().dedup_stat().await.unwrap();
+        assert_eq!(stat.roots, 2);
+        // `shared` and `shared_leaf`
+        assert_eq!(stat.shared_blocks, 2);
+        // both roots and their own leaves
+        assert_eq!(stat.unique_blocks, 4);
+
+        let mut shared_size = 0;
+        for cid in [shared, shared_leaf] {
+            shared_size += ipfs.get_block(&cid).await.unwrap().data().len() as u64;
+        }
+        assert_eq!(stat.shared_size, shared_size);
+    }
+
+    #[tokio::test]
+    async fn missing_blocks() {
+        let ipfs = Node::new("test_node").await;
+
+        let present = ipfs.put_dag(ipld!("present")).await.unwrap();
+        let absent = ipfs.put_dag(ipld!("absent")).await.unwrap();
+        let root = ipfs
+            .put_dag(ipld!({ "present": present, "absent": absent }))
+            .await
+            .unwrap();
+        ipfs.remove_block(absent).await.unwrap();
+
+        assert_eq!(ipfs.missing_blocks(root).await.unwrap(), vec![absent]);
+        assert!(ipfs.missing_blocks(present).await.unwrap().is_empty());
+
+        ipfs.remove_block(root).await.unwrap();
+        assert_eq!(ipfs.missing_blocks(root).await.unwrap(), vec![root]);
+    }
+
+    #[tokio::test]
+    async fn concurrent_gc_matches_serial_gc() {
+        use libipld::cbor::DagCborCodec;
+
+        async fn populate() -> Repo {
+            let repo = Repo::new_memory();
+            repo.init().await.unwrap();
+
+            let mut roots = Vec::new();
+            for i in 0..50 {
+                let mut links = Vec::new();
+                for j in 0..20 {
+                    // pairs of consecutive DAGs share some of their leaves
+                    let leaf = (i / 2) * 20 + j / 2 + (i % 2) * j;
+                    let data = format!("leaf {leaf}").into_bytes();
+                    let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+                    let block = Block::new(cid, data).unwrap();
+                    repo.put_block(block).await.unwrap();
+                    links.push(Ipld::Link(cid));
+                }
+                let document = ipld!({ "links": links });
+                let block = Block::encode(DagCborCodec, Code::Sha2_256, &document).unwrap();
+                roots.push(*block.cid());
+                repo.put_block(block).await.unwrap();
+            }
+
+            for root in roots.iter().step_by(3) {
+                repo.insert_pin(root, true, true).await.unwrap();
+            }
+            repo.insert_pin(&roots[1], false, true).await.unwrap();
+            repo
+        }
+
+        let serial = populate().await;
+        let concurrent = populate().await;
+
+        let mut removed_serial = serial.gc(1).await.unwrap();
+        let mut removed_concurrent = concurrent.gc(8).await.unwrap();
+        removed_serial.sort();
+        removed_concurrent.sort();
+
+        assert!(!removed_serial.is_empty());
+        assert_eq!(removed_serial, removed_concurrent);
+
+        let mut remaining_serial = serial.list_blocks().await.unwrap();
+        let mut remaining_concurrent = concurrent.list_blocks().await.unwrap();
+        remaining_serial.sort();
+        remaining_concurrent.sort();
+        assert_eq!(remaining_serial, remaining_concurrent);
+
+        for cid in remaining_concurrent {
+            assert!(concurrent.is_pinned(&cid).await.unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn storage_quota() {
+        let ipfs = Node::new("test_node").await;
+        ipfs.set_storage_quota(Some(300));
+        assert_eq!(ipfs.storage_quota(), Some(300));
+
+        let block = |i: u8| {
+            let data = vec![i; 100];
+            let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+            Block::new(cid, data).unwrap()
+        };
+
+        let mut unpinned = Vec::new();
+        for i in 0..3 {
+            unpinned.push(ipfs.put_block(block(i)).await.unwrap());
+        }
+
+        // going over the quota removes the unpinned blocks
+        let cid = ipfs.put_block(block(3)).await.unwrap();
+        ipfs.insert_pin(&cid, false).await.unwrap();
+        for cid in &unpinned {
+            assert!(!ipfs.repo().contains(cid).await.unwrap());
+        }
+
+        for i in 4..6 {
+            let cid = ipfs.put_block(block(i)).await.unwrap();
+            ipfs.insert_pin(&cid, false).await.unwrap();
+        }
+
+        let error = ipfs.put_block(block(6)).await.unwrap_err();
+        assert!(error.downcast_ref::<repo::QuotaExceeded>().is_some());
+        assert_eq!(ipfs.repo().list_blocks().await.unwrap().len(), 3);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn concurrent_writes_stay_within_the_quota() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let repo = Repo::new_fs(tmp.path());
+        repo.init().await.unwrap();
+        repo.set_storage_quota(Some(250));
+
+        let puts = (0..8u8).map(|i| {
+            let data = vec![i; 100];
+            let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+            let repo = repo.clone();
+            tokio::spawn(async move { repo.put_block(Block::new(cid, data).unwrap()).await })
+        });
+        for res in futures::future::join_all(puts).await {
+            res.unwrap().unwrap();
+        }
+
+        assert!(repo.get_total_size().await.unwrap() <= 250);
+    }
+
+    #[tokio::test]
+    async fn peer_tags() {
+        let ipfs = Node::new("test_node").await;
+        let backend = PeerId::random();
+        let other = PeerId::random();
+
+        ipfs.tag_peer(backend, "backend").await.unwrap();
+        ipfs.tag_peer(other, "backend").await.unwrap();
+        ipfs.tag_peer(other, "untrusted").await.unwrap();
+
+        let mut tagged = ipfs.peers_with_tag("backend").await.unwrap();
+        tagged.sort();
+        let mut expected = vec![backend, other];
+        expected.sort();
+        assert_eq!(tagged, expected);
+
+        ipfs.untag_peer(other, "backend").await.unwrap();
+        assert_eq!(ipfs.peers_with_tag("backend").await.unwrap(), vec![backend]);
+        assert_eq!(ipfs.peers_with_tag("untrusted").await.unwrap(), vec![other]);
+        assert!(ipfs.peers_with_tag("unknown").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn export_path_car() {
+        use libipld::codec::Codec;
+
+        fn read_varint(bytes: &mut &[u8]) -> usize {
+            let mut value = 0;
+            for (i, byte) in bytes.iter().enumerate() {
+                value |= ((byte & 0x7f) as usize) << (7 * i);
+                if byte & 0x80 == 0 {
+                    *bytes = &bytes[i + 1..];
+                    return value;
+                }
+            }
+            panic!("unterminated varint");
+        }
+
+        let ipfs = Node::new("test_node").await;
+
+        let a = ipfs.put_dag(ipld!("a")).await.unwrap();
+        let b = ipfs.put_dag(ipld!("b")).await.unwrap();
+        let c = ipfs.put_dag(ipld!("c")).await.unwrap();
+        let deeper = ipfs.put_dag(ipld!({ "c": c, "b": b })).await.unwrap();
+        let sub = ipfs
+            .put_dag(ipld!({ "b": b, "deeper": deeper }))
+            .await
+            .unwrap();
+        let root = ipfs.put_dag(ipld!({ "a": a, "sub": sub })).await.unwrap();
+
+        let path = IpfsPath::from(root).sub_path("sub").unwrap();
+        let mut out = Vec::new();
+        let exported = ipfs.export_path_car(path, &mut out).await.unwrap();
+        assert_eq!(exported, sub);
+
+        let mut bytes = &out[..];
+        let len = read_varint(&mut bytes);
+        let header = libipld::cbor::DagCborCodec
+            .decode::<Ipld>(&bytes[..len])
+            .unwrap();
+        assert_eq!(header, ipld!({ "roots": [sub], "version": 1 }));
+        bytes = &bytes[len..];
+
+        let mut blocks = Vec::new();
+        while !bytes.is_empty() {
+            let len = read_varint(&mut bytes);
+            let (section, rest) = bytes.split_at(len);
+            let mut reader = std::io::Cursor::new(section);
+            let cid = Cid::read_bytes(&mut reader).unwrap();
+            let data = &section[reader.position() as usize..];
+            Block::new(cid, data.to_vec()).unwrap();
+            blocks.push(cid);
+            bytes = rest;
+        }
+
+        assert_eq!(blocks[0], sub);
+        assert_eq!(blocks.len(), 4);
+        assert_eq!(
+            blocks.into_iter().collect::<HashSet<_>>(),
+            HashSet::from([sub, deeper, b, c])
+        );
+    }
+
+    #[tokio::test]
+    async fn snapshot_round_trip() {
+        use futures::TryStreamExt;
+
+        async fn pins(ipfs: &Ipfs) -> Vec<(Cid, PinMode)> {
+            let mut pins: Vec<_> = ipfs.list_pins(None).await.try_collect().await.unwrap();
+            pins.sort_by_key(|(cid, _)| *cid);
+            pins
+        }
+
+        let a = Node::new("a").await;
+
+        let leaf = a.put_dag(ipld!("leaf")).await.unwrap();
+        let first = a.put_dag(ipld!({ "leaf": leaf })).await.unwrap();
+        let other = a.put_dag(ipld!("other")).await.unwrap();
+        let second = a.put_dag(ipld!([other, leaf])).await.unwrap();
+        let unpinned = a.put_dag(ipld!("unpinned")).await.unwrap();
+        let direct = a.put_dag(ipld!({ "unpinned": unpinned })).await.unwrap();
+
+        a.insert_pin(&first, true).await.unwrap();
+        a.insert_pin(&second, true).await.unwrap();
+        a.insert_pin(&direct, false).await.unwrap();
+
+        let mut snapshot = Vec::new();
+        a.snapshot(&mut snapshot).await.unwrap();
+
+        let b = Node::new("b").await;
+        b.restore(&snapshot[..]).await.unwrap();
+
+        assert_eq!(pins(&b).await, pins(&a).await);
+        for cid in [leaf, first, other, second, direct] {
+            assert!(b.repo().contains(&cid).await.unwrap(), "{cid} is missing");
+        }
+        assert!(!b.repo().contains(&unpinned).await.unwrap());
+    }
 }
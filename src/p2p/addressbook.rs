@@ -1,6 +1,7 @@
 use std::{
     collections::{hash_map::Entry, HashMap, VecDeque},
     task::{Context, Poll},
+    time::Duration,
 };
 
 use libp2p::{
@@ -13,17 +14,28 @@ use libp2p::{
     },
     Multiaddr, PeerId,
 };
+use tokio::time::{Instant, Interval, MissedTickBehavior};
 
 #[derive(Default, Debug, Copy, Clone)]
 pub struct Config {
     /// Store peer address on an established connection
     pub store_on_connection: bool,
+
+    /// How long an address is kept without being confirmed again, by identify or by a connection
+    /// established through it. Addresses are kept until removed when unset.
+    pub ttl: Option<Duration>,
 }
 
+/// Longest time between two sweeps of the expired addresses.
+const MAX_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
 #[derive(Default, Debug)]
 pub struct Behaviour {
     events: VecDeque<ToSwarm<<Self as NetworkBehaviour>::ToSwarm, THandlerInEvent<Self>>>,
     peer_addresses: HashMap<PeerId, Vec<Multiaddr>>,
+    // When each address expires, only tracked with a configured ttl
+    expirations: HashMap<PeerId, HashMap<Multiaddr, Instant>>,
+    sweep: Option<Interval>,
     config: Config,
 }
 
@@ -34,11 +46,15 @@ impl Behaviour {
             ..Default::default()
         }
     }
+    /// Adds the address of the peer, returning false if it was already known, in which case its
+    /// ttl is renewed.
     pub fn add_address(&mut self, peer_id: PeerId, mut addr: Multiaddr) -> bool {
         if matches!(addr.iter().last(), Some(Protocol::P2p(_))) {
             addr.pop();
         }
 
+        self.renew(peer_id, &addr);
+
         match self.peer_addresses.entry(peer_id) {
             Entry::Occupied(mut e) => {
                 let entry = e.get_mut();
@@ -55,7 +71,34 @@ impl Behaviour {
         true
     }
 
+    /// Renews the ttl of a known address of the peer, returning false if the address is unknown.
+    pub fn confirm_address(&mut self, peer_id: PeerId, mut addr: Multiaddr) -> bool {
+        if matches!(addr.iter().last(), Some(Protocol::P2p(_))) {
+            addr.pop();
+        }
+
+        if !self.contains(&peer_id, &addr) {
+            return false;
+        }
+
+        self.renew(peer_id, &addr);
+        true
+    }
+
+    /// Returns how long the address of the peer is kept unless confirmed again, or `None` if the
+    /// address is unknown or no ttl is configured.
+    pub fn remaining_ttl(&self, peer_id: &PeerId, addr: &Multiaddr) -> Option<Duration> {
+        let expires_at = self.expirations.get(peer_id)?.get(addr)?;
+        Some(expires_at.saturating_duration_since(Instant::now()))
+    }
+
     pub fn remove_address(&mut self, peer_id: &PeerId, addr: &Multiaddr) -> bool {
+        if let Entry::Occupied(mut e) = self.expirations.entry(*peer_id) {
+            e.get_mut().remove(addr);
+            if e.get().is_empty() {
+                e.remove();
+            }
+        }
         if let Entry::Occupied(mut e) = self.peer_addresses.entry(*peer_id) {
             let entry = e.get_mut();
             if !entry.contains(addr) {
@@ -67,6 +110,7 @@ impl Behaviour {
     }
 
     pub fn remove_peer(&mut self, peer_id: &PeerId) -> bool {
+        self.expirations.remove(peer_id);
         self.peer_addresses.remove(peer_id).is_some()
     }
 
@@ -83,6 +127,45 @@ impl Behaviour {
     pub fn iter(&self) -> impl Iterator<Item = (&PeerId, &Vec<Multiaddr>)> {
         self.peer_addresses.iter()
     }
+
+    fn renew(&mut self, peer_id: PeerId, addr: &Multiaddr) {
+        if let Some(ttl) = self.config.ttl {
+            self.expirations
+                .entry(peer_id)
+                .or_default()
+                .insert(addr.clone(), Instant::now() + ttl);
+        }
+    }
+
+    /// Stores the address of an established connection, or only confirms it when the addresses
+    /// of the connections are not stored.
+    fn on_connection(&mut self, peer_id: PeerId, addr: &Multiaddr) {
+        if self.config.store_on_connection {
+            self.add_address(peer_id, addr.clone());
+        } else {
+            self.confirm_address(peer_id, addr.clone());
+        }
+    }
+
+    fn remove_expired(&mut self) {
+        let now = Instant::now();
+        let peer_addresses = &mut self.peer_addresses;
+        self.expirations.retain(|peer_id, expirations| {
+            expirations.retain(|addr, expires_at| {
+                if *expires_at > now {
+                    return true;
+                }
+                if let Entry::Occupied(mut e) = peer_addresses.entry(*peer_id) {
+                    e.get_mut().retain(|item| item != addr);
+                    if e.get().is_empty() {
+                        e.remove();
+                    }
+                }
+                false
+            });
+            !expirations.is_empty()
+        });
+    }
 }
 
 impl NetworkBehaviour for Behaviour {
@@ -120,23 +203,7 @@ impl NetworkBehaviour for Behaviour {
         _: &Multiaddr,
         remote: &Multiaddr,
     ) -> Result<THandler<Self>, ConnectionDenied> {
-        if self.config.store_on_connection {
-            let mut addr = remote.clone();
-            if matches!(addr.iter().last(), Some(Protocol::P2p(_))) {
-                addr.pop();
-            }
-            match self.peer_addresses.entry(peer_id) {
-                Entry::Occupied(mut e) => {
-                    let entry = e.get_mut();
-                    if !entry.contains(&addr) {
-                        entry.push(addr);
-                    }
-                }
-                Entry::Vacant(e) => {
-                    e.insert(vec![addr]);
-                }
-            }
-        }
+        self.on_connection(peer_id, remote);
         Ok(DummyConnectionHandler)
     }
 
@@ -147,23 +214,7 @@ impl NetworkBehaviour for Behaviour {
         addr: &Multiaddr,
         _: Endpoint,
     ) -> Result<THandler<Self>, ConnectionDenied> {
-        if self.config.store_on_connection {
-            let mut addr = addr.clone();
-            if matches!(addr.iter().last(), Some(Protocol::P2p(_))) {
-                addr.pop();
-            }
-            match self.peer_addresses.entry(peer_id) {
-                Entry::Occupied(mut e) => {
-                    let entry = e.get_mut();
-                    if !entry.contains(&addr) {
-                        entry.push(addr);
-                    }
-                }
-                Entry::Vacant(e) => {
-                    e.insert(vec![addr]);
-                }
-            }
-        }
+        self.on_connection(peer_id, addr);
         Ok(DummyConnectionHandler)
     }
 
@@ -198,14 +249,10 @@ impl NetworkBehaviour for Behaviour {
                     new.pop();
                 }
 
-                if let Entry::Occupied(mut e) = self.peer_addresses.entry(peer_id) {
-                    let entry = e.get_mut();
-                    if !entry.contains(&new) {
-                        entry.push(new);
-                    }
-
-                    if entry.contains(&old) {
-                        entry.retain(|addr| addr != &old);
+                if self.peer_addresses.contains_key(&peer_id) {
+                    self.add_address(peer_id, new);
+                    if self.contains(&peer_id, &old) {
+                        self.remove_address(&peer_id, &old);
                     }
                 }
             }
@@ -226,12 +273,31 @@ impl NetworkBehaviour for Behaviour {
 
     fn poll(
         &mut self,
-        _: &mut Context,
+        cx: &mut Context,
         _: &mut impl PollParameters,
     ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
         if let Some(event) = self.events.pop_front() {
             return Poll::Ready(event);
         }
+
+        if let Some(ttl) = self.config.ttl {
+            let sweep = self.sweep.get_or_insert_with(|| {
+                // an address outlives its ttl by at most half of it
+                let period = (ttl / 2).clamp(Duration::from_millis(1), MAX_SWEEP_INTERVAL);
+                let mut sweep = tokio::time::interval_at(Instant::now() + period, period);
+                sweep.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                sweep
+            });
+
+            let mut expired = false;
+            while sweep.poll_tick(cx).is_ready() {
+                expired = true;
+            }
+            if expired {
+                self.remove_expired();
+            }
+        }
+
         Poll::Pending
     }
 }
@@ -398,12 +464,14 @@ mod test {
         let key = Keypair::generate_ed25519();
         let pubkey = key.public();
         let peer_id = pubkey.to_peer_id();
-        let transport = build_transport(key, None, Default::default()).unwrap();
+        let transport =
+            build_transport(key, None, Default::default(), None, Default::default()).unwrap();
 
         let behaviour = Behaviour {
             peer_book: peerbook.then_some(peerbook::Behaviour::default()).into(),
             address_book: super::Behaviour::with_config(super::Config {
                 store_on_connection,
+                ..Default::default()
             }),
         };
 
@@ -417,4 +485,58 @@ mod test {
 
         panic!("no new addrs")
     }
+
+    #[tokio::test(start_paused = true)]
+    async fn unconfirmed_addresses_expire() {
+        use libp2p::core::{muxing::StreamMuxerBox, transport::dummy::DummyTransport};
+        use libp2p::Transport;
+        use std::time::Duration;
+        use tokio::time::timeout;
+
+        let ttl = Duration::from_secs(30);
+        let behaviour = super::Behaviour::with_config(super::Config {
+            ttl: Some(ttl),
+            ..Default::default()
+        });
+        let transport = DummyTransport::<(PeerId, StreamMuxerBox)>::new().boxed();
+        let mut swarm =
+            SwarmBuilder::without_executor(transport, behaviour, PeerId::random()).build();
+
+        let peer = PeerId::random();
+        let confirmed: Multiaddr = "/ip4/127.0.0.1/tcp/4001".parse().unwrap();
+        let stale: Multiaddr = "/ip4/127.0.0.1/tcp/4002".parse().unwrap();
+
+        let book = swarm.behaviour_mut();
+        assert!(book.add_address(peer, confirmed.clone()));
+        assert!(book.add_address(peer, stale.clone()));
+        assert_eq!(book.remaining_ttl(&peer, &confirmed), Some(ttl));
+
+        // drives the swarm, and with it the sweeps, while the clock advances
+        timeout(Duration::from_secs(20), swarm.select_next_some())
+            .await
+            .unwrap_err();
+
+        let book = swarm.behaviour_mut();
+        assert!(book.confirm_address(peer, confirmed.clone()));
+        assert_eq!(book.remaining_ttl(&peer, &confirmed), Some(ttl));
+        assert_eq!(
+            book.remaining_ttl(&peer, &stale),
+            Some(Duration::from_secs(10))
+        );
+
+        timeout(Duration::from_secs(15), swarm.select_next_some())
+            .await
+            .unwrap_err();
+
+        let book = swarm.behaviour();
+        assert!(book.contains(&peer, &confirmed));
+        assert!(!book.contains(&peer, &stale));
+        assert_eq!(book.remaining_ttl(&peer, &stale), None);
+
+        timeout(Duration::from_secs(30), swarm.select_next_some())
+            .await
+            .unwrap_err();
+
+        assert!(swarm.behaviour().get_peer_addresses(&peer).is_none());
+    }
 }
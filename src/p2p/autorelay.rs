@@ -0,0 +1,151 @@
+//! Selects relays automatically for a node which autonat found to be behind a NAT.
+//!
+//! The identified peers are kept as candidates, those advertising the relay hop protocol first.
+//! Once autonat reports the node as private, a circuit is listened on through each of the best
+//! candidates up to the configured number of relays, and a relay whose circuit closes is replaced
+//! by the next candidate. The circuits are closed again once the node is found to be public.
+
+use std::collections::{HashMap, HashSet};
+
+use libp2p::{
+    core::transport::ListenerId, multiaddr::Protocol, relay::HOP_PROTOCOL_NAME, Multiaddr, PeerId,
+    StreamProtocol,
+};
+
+use super::MultiaddrExt;
+
+/// Configuration of the automatic relay selection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AutoRelayConfig {
+    /// Maximum number of relays holding a reservation for the node at the same time.
+    pub max_relays: usize,
+
+    /// Also tries the identified peers which do not advertise the relay hop protocol, once the
+    /// ones which do are used up.
+    pub use_unadvertised: bool,
+}
+
+impl Default for AutoRelayConfig {
+    fn default() -> Self {
+        Self {
+            max_relays: 2,
+            use_unadvertised: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Candidate {
+    addrs: Vec<Multiaddr>,
+    hop: bool,
+}
+
+#[derive(Debug)]
+pub(crate) struct AutoRelay {
+    config: AutoRelayConfig,
+    private: bool,
+    candidates: HashMap<PeerId, Candidate>,
+    /// Relays whose circuit could not be listened on or was closed, skipped until identified
+    /// again.
+    failed: HashSet<PeerId>,
+    listeners: HashMap<ListenerId, PeerId>,
+}
+
+impl AutoRelay {
+    pub(crate) fn new(config: AutoRelayConfig) -> Self {
+        Self {
+            config,
+            private: false,
+            candidates: Default::default(),
+            failed: Default::default(),
+            listeners: Default::default(),
+        }
+    }
+
+    /// Records an identified peer as a candidate relay, when it can be one.
+    pub(crate) fn on_identify(
+        &mut self,
+        peer_id: PeerId,
+        listen_addrs: &[Multiaddr],
+        protocols: &[StreamProtocol],
+    ) {
+        self.failed.remove(&peer_id);
+
+        let hop = protocols.iter().any(|p| HOP_PROTOCOL_NAME.eq(p));
+        let addrs = listen_addrs
+            .iter()
+            .filter(|addr| !addr.is_relay())
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if addrs.is_empty() || !(hop || self.config.use_unadvertised) {
+            self.candidates.remove(&peer_id);
+            return;
+        }
+
+        self.candidates.insert(peer_id, Candidate { addrs, hop });
+    }
+
+    pub(crate) fn on_disconnected(&mut self, peer_id: &PeerId) {
+        self.candidates.remove(peer_id);
+    }
+
+    /// Sets whether the node is behind a NAT.
+    pub(crate) fn set_private(&mut self, private: bool) {
+        self.private = private;
+    }
+
+    /// Returns the circuits to listen on to reach the maximum number of relays, along with the
+    /// relays they go through.
+    pub(crate) fn next_circuits(&self) -> Vec<(PeerId, Multiaddr)> {
+        if !self.private {
+            return vec![];
+        }
+
+        let missing = self.config.max_relays.saturating_sub(self.listeners.len());
+        if missing == 0 {
+            return vec![];
+        }
+
+        let used = self.listeners.values().collect::<HashSet<_>>();
+        let mut candidates = self
+            .candidates
+            .iter()
+            .filter(|(peer_id, _)| !used.contains(peer_id) && !self.failed.contains(peer_id))
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|(peer_id, candidate)| (!candidate.hop, peer_id.to_bytes()));
+
+        candidates
+            .into_iter()
+            .take(missing)
+            .map(|(peer_id, candidate)| (*peer_id, circuit(*peer_id, &candidate.addrs[0])))
+            .collect()
+    }
+
+    pub(crate) fn insert_listener(&mut self, id: ListenerId, relay: PeerId) {
+        self.listeners.insert(id, relay);
+    }
+
+    /// Marks the relay as failed, so that another candidate is used in its place.
+    pub(crate) fn mark_failed(&mut self, relay: PeerId) {
+        self.failed.insert(relay);
+    }
+
+    /// Forgets a closed listener, returning the relay its circuit went through.
+    pub(crate) fn remove_listener(&mut self, id: &ListenerId) -> Option<PeerId> {
+        self.listeners.remove(id)
+    }
+
+    /// Forgets all the listeners, returning them to be removed from the swarm.
+    pub(crate) fn take_listeners(&mut self) -> Vec<ListenerId> {
+        self.listeners.drain().map(|(id, _)| id).collect()
+    }
+}
+
+fn circuit(relay: PeerId, addr: &Multiaddr) -> Multiaddr {
+    let mut addr = addr.clone();
+    if addr.peer_id().is_none() {
+        addr.push(Protocol::P2p(relay));
+    }
+    addr.with(Protocol::P2pCircuit)
+}
@@ -1,10 +1,12 @@
 use super::gossipsub::GossipsubStream;
-use super::{addressbook, protocol};
+use super::{addressbook, dedup, identify_request, idle, protocol};
 use bytes::Bytes;
 use libp2p_allow_block_list::BlockedPeers;
 
 use super::peerbook::{self, ConnectionLimits};
 use either::Either;
+use futures::future::BoxFuture;
+use futures::FutureExt;
 use serde::{Deserialize, Serialize};
 
 use crate::error::Error;
@@ -12,7 +14,7 @@ use crate::error::Error;
 use crate::p2p::{MultiaddrExt, SwarmOptions};
 use crate::repo::Repo;
 
-use beetle_bitswap_next::{Bitswap, ProtocolId};
+use beetle_bitswap_next::{message::BitswapMessage, Bitswap, ProtocolId};
 use libipld::Cid;
 use libp2p::core::Multiaddr;
 use libp2p::dcutr::Behaviour as Dcutr;
@@ -20,9 +22,9 @@ use libp2p::identify::{Behaviour as Identify, Config as IdentifyConfig};
 use libp2p::identity::{Keypair, PeerId};
 use libp2p::kad::record::{
     store::{MemoryStore, MemoryStoreConfig},
-    Record,
+    Key, Record,
 };
-use libp2p::kad::{Kademlia, KademliaBucketInserts, KademliaConfig, KademliaStoreInserts};
+use libp2p::kad::{Kademlia, KademliaBucketInserts, KademliaConfig, KademliaStoreInserts, QueryId};
 use libp2p::mdns::{tokio::Behaviour as Mdns, Config as MdnsConfig};
 use libp2p::ping::Behaviour as Ping;
 use libp2p::relay::client::Behaviour as RelayClient;
@@ -32,10 +34,12 @@ use libp2p::swarm::behaviour::toggle::Toggle;
 use libp2p::swarm::keep_alive::Behaviour as KeepAliveBehaviour;
 use libp2p::swarm::NetworkBehaviour;
 use libp2p::{autonat, StreamProtocol};
+use rand::Rng;
 use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::num::{NonZeroU32, NonZeroUsize};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 /// Behaviour type.
 #[derive(NetworkBehaviour)]
@@ -48,9 +52,12 @@ where
     pub bitswap: Toggle<Bitswap<Repo>>,
     pub kademlia: Toggle<Kademlia<MemoryStore>>,
     pub ping: Ping,
-    pub identify: Identify,
+    pub identify: Toggle<Identify>,
+    pub identify_request: Toggle<identify_request::Behaviour>,
     pub keepalive: Toggle<KeepAliveBehaviour>,
-    pub pubsub: GossipsubStream,
+    pub idle: Toggle<idle::Behaviour>,
+    pub dedup: Toggle<dedup::Behaviour>,
+    pub pubsub: Toggle<GossipsubStream>,
     pub autonat: autonat::Behaviour,
     pub upnp: Toggle<libp2p_nat::Behaviour>,
     pub block_list: libp2p_allow_block_list::Behaviour<BlockedPeers>,
@@ -68,7 +75,8 @@ where
 pub enum KadResult {
     /// The query has been exhausted.
     Complete,
-    /// The query successfully returns `GetClosestPeers` or `GetProviders` results.
+    /// The query successfully returns `GetClosestPeers` or `GetProviders` results, or a
+    /// bootstrap step added these peers to the routing table.
     Peers(Vec<PeerId>),
     /// The query successfully returns a `GetRecord` result.
     Records(Vec<Record>),
@@ -77,6 +85,7 @@ pub enum KadResult {
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
 pub struct RelayConfig {
     pub max_reservations: usize,
     pub max_reservations_per_peer: usize,
@@ -90,12 +99,32 @@ pub struct RelayConfig {
     pub circuit_src_rate_limiters: Vec<RateLimit>,
 }
 
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            max_reservations: 128,
+            max_reservations_per_peer: 4,
+            reservation_duration: Duration::from_secs(60 * 60),
+            reservation_rate_limiters: vec![],
+            max_circuits: 16,
+            max_circuits_per_peer: 4,
+            max_circuit_duration: Duration::from_secs(2 * 60),
+            max_circuit_bytes: 1 << 17,
+            circuit_src_rate_limiters: vec![],
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
 pub struct IdentifyConfiguration {
     pub protocol_version: String,
     pub agent_version: String,
     pub interval: Duration,
     pub push_update: bool,
+    /// Push the identify info to the connected peers as soon as the external addresses change,
+    /// instead of waiting for the next interval.
+    pub push_external_update: bool,
     pub cache: usize,
 }
 
@@ -106,6 +135,7 @@ impl Default for IdentifyConfiguration {
             agent_version: "rust-ipfs".into(),
             interval: Duration::from_secs(5 * 60),
             push_update: true,
+            push_external_update: true,
             cache: 100,
         }
     }
@@ -172,7 +202,7 @@ impl From<RelayConfig> for libp2p::relay::Config {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub enum RateLimit {
     PerPeer {
         limit: NonZeroU32,
@@ -188,7 +218,56 @@ pub enum RateLimit {
 pub struct KadStoreConfig {
     pub memory: Option<MemoryStoreConfig>,
 }
-#[derive(Clone, Debug)]
+
+impl KadStoreConfig {
+    /// Caps the number of records held in the store. Values below 1 are raised to 1.
+    pub fn with_max_records(mut self, max: usize) -> Self {
+        self.memory_mut().max_records = max.max(1);
+        self
+    }
+
+    /// Caps the number of keys provider records are stored for. Values below 1 are raised to 1.
+    pub fn with_max_provided_keys(mut self, max: usize) -> Self {
+        self.memory_mut().max_provided_keys = max.max(1);
+        self
+    }
+
+    /// Caps the number of providers stored for a single key. Values below 1 are raised to 1.
+    pub fn with_max_providers_per_key(mut self, max: usize) -> Self {
+        self.memory_mut().max_providers_per_key = max.max(1);
+        self
+    }
+
+    /// Maximum number of records held in the store.
+    pub fn max_records(&self) -> usize {
+        self.memory_config().max_records
+    }
+
+    /// Maximum number of keys provider records are stored for.
+    pub fn max_provided_keys(&self) -> usize {
+        self.memory_config().max_provided_keys
+    }
+
+    /// Maximum number of providers stored for a single key.
+    pub fn max_providers_per_key(&self) -> usize {
+        self.memory_config().max_providers_per_key
+    }
+
+    fn memory_mut(&mut self) -> &mut MemoryStoreConfig {
+        self.memory.get_or_insert_with(Default::default)
+    }
+
+    fn memory_config(&self) -> MemoryStoreConfig {
+        self.memory.clone().unwrap_or_default()
+    }
+
+    pub(crate) fn into_store(self, peer_id: PeerId) -> MemoryStore {
+        MemoryStore::with_config(peer_id, self.memory.unwrap_or_default())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
 pub struct KadConfig {
     pub protocol: Option<Vec<Cow<'static, str>>>,
     pub disjoint_query_paths: bool,
@@ -196,24 +275,55 @@ pub struct KadConfig {
     pub parallelism: Option<NonZeroUsize>,
     pub publication_interval: Option<Duration>,
     pub provider_record_ttl: Option<Duration>,
+    /// Interval at which the provided keys are republished, 12 hours when `None`.
+    pub provider_publication_interval: Option<Duration>,
+    /// Republishes every provided key up to this long ahead of the
+    /// [`KadConfig::provider_publication_interval`], at an offset chosen at random per key, so
+    /// that the keys provided at the same time are not all republished at once.
+    pub reprovide_jitter: Option<Duration>,
     pub insert_method: KadInserts,
     pub store_filter: KadStoreInserts,
 }
 
-#[derive(Clone, Debug, Default, Copy)]
+/// Republishing interval of the provided keys used by libp2p.
+pub const DEFAULT_PROVIDER_PUBLICATION_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Copy)]
 pub enum KadInserts {
     #[default]
     Auto,
     Manual,
 }
 
-#[derive(Clone, Debug, Default, Copy)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Copy)]
 pub enum KadStoreInserts {
     #[default]
     Unfiltered,
     Filtered,
 }
 
+/// How the kademlia key of a provider record is derived from the provided `Cid`.
+///
+/// Providing and looking up providers only meet when both peers use the same scheme.
+#[derive(Clone, Debug, Default, Copy, PartialEq, Eq)]
+pub enum ProviderKeyScheme {
+    /// Key on the multihash of the `Cid`, as done by go-ipfs.
+    #[default]
+    Multihash,
+    /// Key on the whole `Cid`, including its version and codec.
+    CidBytes,
+}
+
+impl ProviderKeyScheme {
+    /// Returns the key under which the provider records of `cid` are stored.
+    pub fn key(&self, cid: &Cid) -> Key {
+        match self {
+            ProviderKeyScheme::Multihash => Key::from(cid.hash().to_bytes()),
+            ProviderKeyScheme::CidBytes => Key::from(cid.to_bytes()),
+        }
+    }
+}
+
 impl From<KadStoreInserts> for KademliaStoreInserts {
     fn from(value: KadStoreInserts) -> Self {
         match value {
@@ -249,6 +359,17 @@ impl From<KadConfig> for KademliaConfig {
         }
         kad_config.set_publication_interval(config.publication_interval);
         kad_config.set_provider_record_ttl(config.provider_record_ttl);
+        match config.reprovide_jitter {
+            // the provided keys are republished by the node instead, see `ReprovideSchedule`
+            Some(_) => {
+                kad_config.set_provider_publication_interval(None);
+            }
+            None => {
+                if let Some(interval) = config.provider_publication_interval {
+                    kad_config.set_provider_publication_interval(Some(interval));
+                }
+            }
+        }
         kad_config.set_kbucket_inserts(config.insert_method.into());
         kad_config.set_record_filtering(config.store_filter.into());
         kad_config
@@ -264,17 +385,30 @@ impl Default for KadConfig {
             parallelism: Some(2.try_into().unwrap()),
             provider_record_ttl: None,
             publication_interval: None,
+            provider_publication_interval: None,
+            reprovide_jitter: None,
             insert_method: Default::default(),
             store_filter: Default::default(),
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// Size of a bitswap message when [`BitswapConfig::max_buf_size`] is not set.
+const DEFAULT_MAX_BUF_SIZE: usize = 2 * 1024 * 1024;
+
+/// Room left in a bitswap message for everything but the block data, e.g. the block prefix and
+/// the block presences sent along.
+const MESSAGE_OVERHEAD: usize = 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
 pub struct BitswapConfig {
     protocol: Vec<BitswapProtocol>,
     max_buf_size: Option<usize>,
     server: bool,
+    want_broadcast: WantBroadcastPolicy,
+    max_wantlist_size: Option<usize>,
+    max_inbound_message_rate: Option<NonZeroU32>,
 }
 
 impl Default for BitswapConfig {
@@ -288,11 +422,104 @@ impl Default for BitswapConfig {
             ],
             max_buf_size: None,
             server: true,
+            want_broadcast: WantBroadcastPolicy::default(),
+            max_wantlist_size: None,
+            max_inbound_message_rate: None,
         }
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Hash, PartialOrd, Ord)]
+impl BitswapConfig {
+    /// Sets the protocols to speak. The newest of them which a peer speaks as well is used with
+    /// that peer. An empty list falls back to the default protocols.
+    pub fn with_protocols(mut self, protocols: Vec<BitswapProtocol>) -> Self {
+        self.protocol = if protocols.is_empty() {
+            Self::default().protocol
+        } else {
+            protocols
+        };
+        self
+    }
+
+    /// Sets the maximum size of a bitswap message.
+    pub fn with_max_buf_size(mut self, size: usize) -> Self {
+        self.max_buf_size = Some(size);
+        self
+    }
+
+    /// Sets whether the wants of other peers are answered. The server can be toggled at runtime
+    /// with [`crate::Ipfs::set_bitswap_server`] as long as it is enabled here.
+    pub fn with_server(mut self, server: bool) -> Self {
+        self.server = server;
+        self
+    }
+
+    /// Sets which connected peers are sent wants for blocks no peer is known to have.
+    pub fn with_want_broadcast(mut self, policy: WantBroadcastPolicy) -> Self {
+        self.want_broadcast = policy;
+        self
+    }
+
+    /// Sets the maximum number of blocks wanted at the same time. Any further wants wait, by
+    /// their priority and then in the order they were made, for one of the active wants to
+    /// resolve.
+    pub fn with_max_wantlist_size(mut self, size: usize) -> Self {
+        self.max_wantlist_size = Some(size);
+        self
+    }
+
+    /// Sets the maximum number of bitswap messages received from all peers per second. Any
+    /// further messages are dropped and counted in [`crate::Ipfs::bitswap_dropped_messages`].
+    pub fn with_max_inbound_message_rate(mut self, rate: NonZeroU32) -> Self {
+        self.max_inbound_message_rate = Some(rate);
+        self
+    }
+
+    /// Returns whether the wants of other peers are answered.
+    pub fn server(&self) -> bool {
+        self.server
+    }
+
+    /// Returns the maximum number of blocks wanted at the same time, if limited.
+    pub fn max_wantlist_size(&self) -> Option<usize> {
+        self.max_wantlist_size
+    }
+
+    /// Largest block a bitswap message of this size can carry to a peer.
+    pub fn max_block_size(&self) -> usize {
+        self.max_buf_size
+            .unwrap_or(DEFAULT_MAX_BUF_SIZE)
+            .saturating_sub(MESSAGE_OVERHEAD)
+    }
+}
+
+/// Controls the peers which are sent wants for blocks no peer is known to have.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum WantBroadcastPolicy {
+    /// Broadcast to all connected peers.
+    #[default]
+    All,
+    /// Send to a random subset of at most this many connected peers.
+    Random(usize),
+    /// Do not broadcast, relying on provider discovery instead.
+    None,
+}
+
+impl From<WantBroadcastPolicy> for beetle_bitswap_next::WantBroadcastPolicy {
+    fn from(value: WantBroadcastPolicy) -> Self {
+        match value {
+            WantBroadcastPolicy::All => beetle_bitswap_next::WantBroadcastPolicy::All,
+            WantBroadcastPolicy::Random(count) => {
+                beetle_bitswap_next::WantBroadcastPolicy::Random(count)
+            }
+            WantBroadcastPolicy::None => beetle_bitswap_next::WantBroadcastPolicy::None,
+        }
+    }
+}
+
+#[derive(
+    Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Default, Hash, PartialOrd, Ord,
+)]
 pub enum BitswapProtocol {
     ProtocolLegacy,
     Protocol100,
@@ -314,15 +541,28 @@ impl From<BitswapProtocol> for ProtocolId {
 
 impl From<BitswapConfig> for beetle_bitswap_next::Config {
     fn from(value: BitswapConfig) -> Self {
-        beetle_bitswap_next::Config {
+        let mut config = beetle_bitswap_next::Config {
             client: Default::default(),
             server: value.server.then_some(Default::default()),
             protocol: beetle_bitswap_next::ProtocolConfig {
-                protocol_ids: value.protocol.iter().map(|proto| (*proto).into()).collect(),
-                max_transmit_size: value.max_buf_size.unwrap_or(1024 * 1024 * 2),
+                // a deserialized config can still carry an empty list
+                protocol_ids: if value.protocol.is_empty() {
+                    beetle_bitswap_next::ProtocolConfig::default().protocol_ids
+                } else {
+                    // offered newest first, as the first protocol both peers speak is used
+                    let mut protocols = value.protocol.clone();
+                    protocols.sort_unstable_by(|a, b| b.cmp(a));
+                    protocols.dedup();
+                    protocols.into_iter().map(Into::into).collect()
+                },
+                max_transmit_size: value.max_buf_size.unwrap_or(DEFAULT_MAX_BUF_SIZE),
+                max_block_size: value.max_block_size(),
             },
+            max_inbound_message_rate: value.max_inbound_message_rate,
             ..Default::default()
-        }
+        };
+        config.client.want_broadcast = value.want_broadcast.into();
+        config
     }
 }
 
@@ -353,13 +593,8 @@ where
         }
         .into();
 
-        let store = {
-            //TODO: Make customizable
-            //TODO: Use persistent store for kad
-            let config = options.kad_store_config.memory.unwrap_or_default();
-
-            MemoryStore::with_config(peer_id, config)
-        };
+        //TODO: Use persistent store for kad
+        let store = options.kad_store_config.into_store(peer_id);
 
         let kad_config = match options
             .kad_config
@@ -383,23 +618,48 @@ where
             }
         }
 
-        let autonat = autonat::Behaviour::new(peer_id, Default::default());
+        let autonat =
+            autonat::Behaviour::new(peer_id, options.autonat_config.clone().unwrap_or_default());
         let bitswap = (!options.disable_bitswap)
-            .then_some(Bitswap::new(peer_id, repo, Default::default()).await)
+            .then_some(
+                Bitswap::new(
+                    peer_id,
+                    repo,
+                    options.bitswap_config.clone().unwrap_or_default().into(),
+                )
+                .await,
+            )
             .into();
 
         let keepalive = options.keep_alive.then(KeepAliveBehaviour::default).into();
 
+        let idle = options
+            .idle_connection_timeout
+            .map(idle::Behaviour::new)
+            .into();
+
+        let dedup = (options.connection_dedup != dedup::ConnectionDedupPolicy::Disabled)
+            .then(|| dedup::Behaviour::new(options.connection_dedup, peer_id))
+            .into();
+
         let ping = Ping::new(options.ping_config.unwrap_or_default());
 
-        let identify = Identify::new(
-            options
-                .identify_config
-                .unwrap_or_default()
-                .into(keypair.public()),
-        );
+        let identify = Toggle::from((!options.disable_identify).then(|| {
+            Identify::new(
+                options
+                    .identify_config
+                    .unwrap_or_default()
+                    .into(keypair.public()),
+            )
+        }));
+
+        let identify_request = (!options.disable_identify)
+            .then(identify_request::Behaviour::default)
+            .into();
 
-        let pubsub = {
+        let pubsub = if options.disable_pubsub {
+            None
+        } else {
             let pubsub_config = options.pubsub_config.unwrap_or_default();
             let mut builder = libp2p::gossipsub::ConfigBuilder::default();
 
@@ -423,8 +683,15 @@ where
             )
             .map_err(|e| anyhow::anyhow!("{}", e))?;
 
-            GossipsubStream::from(gossipsub)
+            let mut gossipsub = GossipsubStream::from(gossipsub)
+                .with_max_message_size(pubsub_config.max_transmit_size);
+            if let Some(rate) = pubsub_config.publish_rate {
+                gossipsub = gossipsub.with_publish_rate(rate);
+            }
+
+            Some(gossipsub)
         };
+        let pubsub = Toggle::from(pubsub);
 
         // Maybe have this enable in conjunction with RelayClient?
         let dcutr = Toggle::from(options.dcutr.then_some(Dcutr::new(peer_id)));
@@ -453,7 +720,10 @@ where
             false => (None, None.into()),
         };
 
-        let mut peerbook = peerbook::Behaviour::default();
+        // without identify there is no peer info to wait on once connected
+        let mut peerbook = peerbook::Behaviour::new(peerbook::Config {
+            wait_on_identify: !options.disable_identify,
+        });
         peerbook.set_connection_limit(limits);
 
         let addressbook =
@@ -469,8 +739,11 @@ where
                 kademlia,
                 bitswap,
                 keepalive,
+                idle,
+                dedup,
                 ping,
                 identify,
+                identify_request,
                 autonat,
                 pubsub,
                 dcutr,
@@ -505,11 +778,35 @@ where
         self.peerbook.connected_peers_addrs().collect()
     }
 
+    /// Starts providing a block stored in the repo, returning the id of the query publishing the
+    /// provider record. Fails if kademlia is disabled.
+    pub fn start_providing_block(
+        &mut self,
+        cid: &Cid,
+        scheme: ProviderKeyScheme,
+    ) -> Result<QueryId, Error> {
+        info!("Providing block {}", cid.to_string());
+        let key = scheme.key(cid);
+        let kad = self
+            .kademlia
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("kad protocol is disabled"))?;
+        kad.start_providing(key).map_err(|e| {
+            error!("kad: can't provide a key: {:?}", e);
+            anyhow::anyhow!("kad: can't provide the key: {:?}", e)
+        })
+    }
+
     pub fn stop_providing_block(&mut self, cid: &Cid) {
+        self.stop_providing_block_with_scheme(cid, ProviderKeyScheme::default())
+    }
+
+    /// Stops providing a block whose provider record was keyed through `scheme`.
+    pub fn stop_providing_block_with_scheme(&mut self, cid: &Cid, scheme: ProviderKeyScheme) {
         info!("Finished providing block {}", cid.to_string());
-        let key = cid.hash().to_bytes();
+        let key = scheme.key(cid);
         if let Some(kad) = self.kademlia.as_mut() {
-            kad.stop_providing(&key.into());
+            kad.stop_providing(&key);
         }
     }
 
@@ -535,8 +832,31 @@ where
         }
     }
 
-    pub fn pubsub(&mut self) -> &mut GossipsubStream {
-        &mut self.pubsub
+    /// Sends a crafted bitswap message to the peer, bypassing the wants and responses of the
+    /// bitswap client and server. The returned future resolves once the message has been sent.
+    pub fn send_bitswap_message(
+        &mut self,
+        peer: PeerId,
+        message: BitswapMessage,
+    ) -> BoxFuture<'static, Result<(), Error>> {
+        match self.bitswap.as_ref() {
+            Some(bitswap) => bitswap.send_message(peer, message).boxed(),
+            None => futures::future::ready(Err(anyhow::anyhow!("bitswap is disabled"))).boxed(),
+        }
+    }
+
+    /// Starts or stops answering the wants of other peers, e.g. to stop uploading blocks for a
+    /// while. Wants received while stopped are recorded, counted in
+    /// [`crate::Ipfs::bitswap_unserved_wants`], and only answered once started again. Has no
+    /// effect if bitswap or [`BitswapConfig::server`] is disabled.
+    pub fn set_bitswap_server(&mut self, enabled: bool) {
+        if let Some(bitswap) = self.bitswap.as_ref() {
+            bitswap.set_server_enabled(enabled);
+        }
+    }
+
+    pub fn pubsub(&mut self) -> Option<&mut GossipsubStream> {
+        self.pubsub.as_mut()
     }
 
     pub fn bitswap(&mut self) -> Option<&mut Bitswap<Repo>> {
@@ -544,6 +864,97 @@ where
     }
 }
 
+/// Collects provider announcements so they can be deduplicated and issued together
+/// once per batching window instead of calling `start_providing` for every block.
+#[derive(Debug, Default)]
+pub struct ProvideQueue {
+    keys: Vec<Key>,
+    queued: HashSet<Key>,
+}
+
+impl ProvideQueue {
+    /// Queues a key to be provided, returning false if it is already queued
+    pub fn push(&mut self, key: Key) -> bool {
+        if !self.queued.insert(key.clone()) {
+            return false;
+        }
+        self.keys.push(key);
+        true
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Takes the queued keys in the order they were first announced
+    pub fn take(&mut self) -> Vec<Key> {
+        self.queued.clear();
+        std::mem::take(&mut self.keys)
+    }
+}
+
+/// Delay before providing again a key which failed to be provided, at most the interval.
+const REPROVIDE_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Schedules republishing the provided keys, each at its own offset within the interval so that
+/// the keys provided together are spread out instead of being republished all at once.
+#[derive(Debug)]
+pub struct ReprovideSchedule {
+    interval: Duration,
+    jitter: Duration,
+    /// When each key is republished next, `None` while it is being republished
+    next: HashMap<Key, Option<Instant>>,
+}
+
+impl ReprovideSchedule {
+    pub fn new(interval: Duration, jitter: Duration) -> Self {
+        Self {
+            interval,
+            jitter: jitter.min(interval),
+            next: HashMap::new(),
+        }
+    }
+
+    /// Schedules the next republishing of a key which was provided, a full interval later for a
+    /// key republished and at a random offset within the interval for one not seen before
+    pub fn provided(&mut self, key: Key, now: Instant) {
+        let at = if self.next.contains_key(&key) {
+            now + self.interval
+        } else {
+            now + self.interval - self.jitter.mul_f64(rand::thread_rng().gen::<f64>())
+        };
+        self.next.insert(key, Some(at));
+    }
+
+    /// Schedules another attempt at a key which failed to be provided
+    pub fn failed(&mut self, key: Key, now: Instant) {
+        let at = now + REPROVIDE_RETRY_DELAY.min(self.interval);
+        self.next.insert(key, Some(at));
+    }
+
+    /// Forgets a key which is no longer provided
+    pub fn remove(&mut self, key: &Key) {
+        self.next.remove(key);
+    }
+
+    /// Takes the keys due to be republished, which are not due again until they were either
+    /// [provided](Self::provided) or [failed](Self::failed) to be
+    pub fn due(&mut self, now: Instant) -> Vec<Key> {
+        let mut due = Vec::new();
+        for (key, at) in self.next.iter_mut() {
+            if matches!(at, Some(at) if *at <= now) {
+                due.push(key.clone());
+                *at = None;
+            }
+        }
+        due
+    }
+}
+
 /// Create a IPFS behaviour with the IPFS bootstrap nodes.
 pub async fn build_behaviour<C>(
     keypair: &Keypair,
@@ -558,3 +969,185 @@ where
 {
     Behaviour::new(keypair, options, repo, limits, custom).await
 }
+
+#[cfg(test)]
+mod test {
+    use super::{BitswapConfig, BitswapProtocol, KadStoreConfig, ProvideQueue, ReprovideSchedule};
+    use beetle_bitswap_next::ProtocolId;
+    use libipld::{
+        multihash::{Code, MultihashDigest},
+        Cid, IpldCodec,
+    };
+    use libp2p::identity::PeerId;
+    use libp2p::kad::record::{store::RecordStore, Key, ProviderRecord, Record};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn provide_queue_coalesces_announcements() {
+        let keys = (0..50)
+            .map(|i: u32| {
+                let cid = Cid::new_v1(
+                    IpldCodec::Raw.into(),
+                    Code::Sha2_256.digest(&i.to_be_bytes()),
+                );
+                Key::from(cid.hash().to_bytes())
+            })
+            .collect::<Vec<_>>();
+
+        let mut queue = ProvideQueue::default();
+
+        // the same blocks being announced repeatedly during a bulk import
+        let mut announcements = 0;
+        for _ in 0..10 {
+            for key in &keys {
+                queue.push(key.clone());
+                announcements += 1;
+            }
+        }
+
+        assert_eq!(queue.len(), keys.len());
+
+        let batch = queue.take();
+        assert!(batch.len() * 10 <= announcements);
+        assert_eq!(batch, keys);
+        assert!(queue.is_empty());
+
+        // keys can be queued again once the batch was issued
+        assert!(queue.push(keys[0].clone()));
+    }
+
+    #[test]
+    fn reprovide_schedule_spreads_keys() {
+        let keys = (0..1000)
+            .map(|i: u32| {
+                let cid = Cid::new_v1(
+                    IpldCodec::Raw.into(),
+                    Code::Sha2_256.digest(&i.to_be_bytes()),
+                );
+                Key::from(cid.hash().to_bytes())
+            })
+            .collect::<Vec<_>>();
+
+        let interval = Duration::from_secs(12 * 60 * 60);
+        let jitter = interval / 2;
+        let mut schedule = ReprovideSchedule::new(interval, jitter);
+
+        // all of the keys provided at once
+        let start = Instant::now();
+        for key in &keys {
+            schedule.provided(key.clone(), start);
+        }
+
+        let mut buckets = [0; 4];
+        for key in &keys {
+            let offset = schedule.next[key].unwrap() - start;
+            assert!(offset > interval - jitter && offset <= interval);
+            let bucket = (interval - offset).as_secs_f64() / jitter.as_secs_f64() * 4.0;
+            buckets[bucket as usize] += 1;
+        }
+        assert!(
+            buckets.iter().all(|count| *count > 150),
+            "republishing is bunched: {buckets:?}"
+        );
+
+        assert!(schedule.due(start + interval - jitter).is_empty());
+
+        // the keys are not due again until their republishing completed
+        let end = start + interval;
+        assert_eq!(schedule.due(end).len(), keys.len());
+        assert!(schedule.due(end + interval).is_empty());
+
+        for key in &keys[10..] {
+            schedule.provided(key.clone(), end);
+            assert_eq!(schedule.next[key], Some(end + interval));
+        }
+        for key in &keys[..10] {
+            schedule.failed(key.clone(), end);
+        }
+        assert_eq!(schedule.due(end + interval / 2).len(), 10);
+        assert!(schedule
+            .due(end + interval - Duration::from_secs(1))
+            .is_empty());
+        assert_eq!(schedule.due(end + interval).len(), keys.len() - 10);
+
+        // keys which are no longer provided are forgotten
+        for key in &keys[10..] {
+            schedule.remove(key);
+        }
+        assert_eq!(schedule.next.len(), 10);
+    }
+
+    #[test]
+    fn kad_store_limits() {
+        let config = KadStoreConfig::default()
+            .with_max_records(2)
+            .with_max_providers_per_key(1)
+            .with_max_provided_keys(0);
+
+        assert_eq!(config.max_records(), 2);
+        assert_eq!(config.max_provided_keys(), 1);
+
+        let mut store = config.into_store(PeerId::random());
+
+        for i in 0..2u8 {
+            store.put(Record::new(vec![i], vec![i])).unwrap();
+        }
+        assert!(store.put(Record::new(vec![2], vec![2])).is_err());
+
+        let key = Key::new(&b"key");
+        store
+            .add_provider(ProviderRecord::new(key.clone(), PeerId::random(), vec![]))
+            .unwrap();
+        store
+            .add_provider(ProviderRecord::new(key.clone(), PeerId::random(), vec![]))
+            .unwrap();
+        assert_eq!(store.providers(&key).len(), 1);
+
+        assert!(store
+            .add_provider(ProviderRecord::new(
+                Key::new(&b"other"),
+                PeerId::random(),
+                vec![]
+            ))
+            .is_err());
+    }
+
+    #[test]
+    fn bitswap_config_builder_converts() {
+        let config: beetle_bitswap_next::Config = BitswapConfig::default()
+            .with_protocols(vec![BitswapProtocol::Protocol120])
+            .with_max_buf_size(4096)
+            .with_server(false)
+            .into();
+
+        assert_eq!(config.protocol.protocol_ids, vec![ProtocolId::Bitswap120]);
+        assert_eq!(config.protocol.max_transmit_size, 4096);
+        assert_eq!(config.protocol.max_block_size, 4096 - 1024);
+        assert!(config.server.is_none());
+    }
+
+    #[test]
+    fn empty_bitswap_protocols_fall_back_to_the_defaults() {
+        let defaults = beetle_bitswap_next::ProtocolConfig::default().protocol_ids;
+
+        let config = BitswapConfig::default().with_protocols(Vec::new());
+        assert_eq!(config.protocol, BitswapConfig::default().protocol);
+
+        let config: beetle_bitswap_next::Config = BitswapConfig {
+            protocol: Vec::new(),
+            ..Default::default()
+        }
+        .into();
+        assert_eq!(config.protocol.protocol_ids, defaults);
+        assert!(config.server.is_some());
+    }
+
+    #[test]
+    fn bitswap_protocols_are_offered_newest_first() {
+        let config: beetle_bitswap_next::Config = BitswapConfig::default().into();
+        assert_eq!(
+            config.protocol.protocol_ids,
+            beetle_bitswap_next::ProtocolConfig::default().protocol_ids
+        );
+    }
+}
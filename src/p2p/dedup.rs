@@ -0,0 +1,295 @@
+//! Closes the redundant connections to a peer the node is already connected to, following a
+//! [`ConnectionDedupPolicy`].
+//!
+//! A node can end up with several connections to the same peer, e.g. when dialing it on more than
+//! one of its addresses or when both of them dial each other at the same time. The connections
+//! beyond the kept one are closed from here, while the kept one is left alone.
+//!
+//! Both peers have to agree on the kept connection, or each of them could close the one the other
+//! kept. A connection dialed by the peer with the lower [`PeerId`] is always kept over one dialed
+//! by the other peer, which both of them tell apart the same way. Among the connections dialed by
+//! the same peer the policy picks one, and only the dialing peer closes the others.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use libp2p::{
+    core::Endpoint,
+    swarm::{
+        dummy, CloseConnection, ConnectionClosed, ConnectionDenied, ConnectionId, FromSwarm,
+        NetworkBehaviour, PollParameters, THandler, THandlerInEvent, THandlerOutEvent, ToSwarm,
+    },
+    Multiaddr, PeerId,
+};
+use serde::{Deserialize, Serialize};
+use void::Void;
+
+/// Which of the connections dialed by the node to a peer is kept once it has more than one of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ConnectionDedupPolicy {
+    /// Keep all of the connections.
+    #[default]
+    Disabled,
+    /// Keep the connection established first, closing any later one right away.
+    KeepFirst,
+    /// Keep the connection with the lowest ping round trip time, closing the others once each
+    /// of the connections has been pinged.
+    KeepLowestLatency,
+}
+
+#[derive(Debug)]
+pub struct Behaviour {
+    policy: ConnectionDedupPolicy,
+    local_peer_id: PeerId,
+    /// The connections kept to each peer, in the order they were established.
+    connections: HashMap<PeerId, Vec<Connection>>,
+    redundant: VecDeque<(PeerId, ConnectionId)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Connection {
+    id: ConnectionId,
+    /// Whether the connection was dialed by the node rather than by the peer.
+    dialed: bool,
+    /// The round trip time of the latest ping over the connection.
+    rtt: Option<Duration>,
+}
+
+impl Behaviour {
+    pub fn new(policy: ConnectionDedupPolicy, local_peer_id: PeerId) -> Self {
+        Self {
+            policy,
+            local_peer_id,
+            connections: HashMap::new(),
+            redundant: VecDeque::new(),
+        }
+    }
+
+    /// Whether the connection was dialed by the peer with the lower id, which both peers agree on.
+    fn dialed_by_lower(&self, peer_id: &PeerId, dialed: bool) -> bool {
+        match dialed {
+            true => self.local_peer_id < *peer_id,
+            false => *peer_id < self.local_peer_id,
+        }
+    }
+
+    /// Records the round trip time of a ping over the connection, closing the slower connections
+    /// to the peer once all of them have one.
+    pub fn on_ping(&mut self, peer_id: PeerId, connection_id: ConnectionId, rtt: Duration) {
+        if self.policy != ConnectionDedupPolicy::KeepLowestLatency {
+            return;
+        }
+
+        let Some(connections) = self.connections.get_mut(&peer_id) else {
+            return;
+        };
+
+        if let Some(connection) = connections.iter_mut().find(|c| c.id == connection_id) {
+            connection.rtt = Some(rtt);
+        }
+
+        // the kept connections were all dialed by the same peer, which picks one of them
+        let dialed = connections.iter().all(|c| c.dialed);
+        if !dialed || connections.len() < 2 || connections.iter().any(|c| c.rtt.is_none()) {
+            return;
+        }
+
+        let fastest = connections
+            .iter()
+            .min_by_key(|c| c.rtt)
+            .map(|c| c.id)
+            .expect("there are several connections");
+
+        for connection in connections.iter().filter(|c| c.id != fastest) {
+            self.redundant.push_back((peer_id, connection.id));
+        }
+        connections.retain(|c| c.id == fastest);
+    }
+
+    fn on_connection_established(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        dialed: bool,
+    ) {
+        let preferred = self.dialed_by_lower(&peer_id, dialed);
+        let kept_preferred = self
+            .connections
+            .get(&peer_id)
+            .and_then(|connections| connections.first())
+            .map(|kept| self.dialed_by_lower(&peer_id, kept.dialed));
+        let connections = self.connections.entry(peer_id).or_default();
+
+        match kept_preferred {
+            // the other peer closes this connection as well, so it can be closed by either
+            Some(true) if !preferred => {
+                self.redundant.push_back((peer_id, connection_id));
+                return;
+            }
+            Some(false) if preferred => {
+                for connection in connections.drain(..) {
+                    self.redundant.push_back((peer_id, connection.id));
+                }
+            }
+            _ => {}
+        }
+
+        // only the peer which dialed the connections picks one of them, the other one could have
+        // seen them established in another order
+        let connection = Connection {
+            id: connection_id,
+            dialed,
+            rtt: None,
+        };
+        match self.policy {
+            ConnectionDedupPolicy::KeepFirst if dialed && !connections.is_empty() => {
+                self.redundant.push_back((peer_id, connection_id));
+            }
+            _ => connections.push(connection),
+        }
+    }
+}
+
+impl NetworkBehaviour for Behaviour {
+    type ConnectionHandler = dummy::ConnectionHandler;
+    type ToSwarm = Void;
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        _: ConnectionId,
+        _: PeerId,
+        _: &Multiaddr,
+        _: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        _: ConnectionId,
+        _: PeerId,
+        _: &Multiaddr,
+        _: Endpoint,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        _: PeerId,
+        _: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        void::unreachable(event)
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm<Self::ConnectionHandler>) {
+        match event {
+            FromSwarm::ConnectionEstablished(established) => self.on_connection_established(
+                established.peer_id,
+                established.connection_id,
+                established.endpoint.is_dialer(),
+            ),
+            FromSwarm::ConnectionClosed(ConnectionClosed {
+                peer_id,
+                connection_id,
+                ..
+            }) => {
+                if let Some(connections) = self.connections.get_mut(&peer_id) {
+                    connections.retain(|c| c.id != connection_id);
+                    if connections.is_empty() {
+                        self.connections.remove(&peer_id);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn poll(
+        &mut self,
+        _: &mut Context,
+        _: &mut impl PollParameters,
+    ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        match self.redundant.pop_front() {
+            Some((peer_id, connection_id)) => Poll::Ready(ToSwarm::CloseConnection {
+                peer_id,
+                connection: CloseConnection::One(connection_id),
+            }),
+            None => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_fastest_connection_once_all_are_pinged() {
+        let mut dedup = Behaviour::new(ConnectionDedupPolicy::KeepLowestLatency, PeerId::random());
+        let peer_id = PeerId::random();
+        let (slow, fast) = (
+            ConnectionId::new_unchecked(1),
+            ConnectionId::new_unchecked(2),
+        );
+        dedup.on_connection_established(peer_id, slow, true);
+        dedup.on_connection_established(peer_id, fast, true);
+
+        dedup.on_ping(peer_id, slow, Duration::from_millis(30));
+        assert!(dedup.redundant.is_empty());
+
+        dedup.on_ping(peer_id, fast, Duration::from_millis(10));
+        assert_eq!(dedup.redundant, [(peer_id, slow)]);
+        assert_eq!(
+            dedup.connections[&peer_id],
+            [Connection {
+                id: fast,
+                dialed: true,
+                rtt: Some(Duration::from_millis(10))
+            }]
+        );
+    }
+
+    #[test]
+    fn both_peers_keep_the_same_connection() {
+        let (low, high) = {
+            let (a, b) = (PeerId::random(), PeerId::random());
+            (a.min(b), a.max(b))
+        };
+
+        // both dial each other at once, seeing the connections established in opposite orders
+        let (by_low, by_high) = (
+            ConnectionId::new_unchecked(1),
+            ConnectionId::new_unchecked(2),
+        );
+        for policy in [
+            ConnectionDedupPolicy::KeepFirst,
+            ConnectionDedupPolicy::KeepLowestLatency,
+        ] {
+            let mut at_low = Behaviour::new(policy, low);
+            at_low.on_connection_established(high, by_high, false);
+            at_low.on_connection_established(high, by_low, true);
+
+            let mut at_high = Behaviour::new(policy, high);
+            at_high.on_connection_established(low, by_low, false);
+            at_high.on_connection_established(low, by_high, true);
+
+            assert_eq!(at_low.redundant, [(high, by_high)]);
+            assert_eq!(at_high.redundant, [(low, by_high)]);
+        }
+
+        // the connections dialed by the other peer are left for it to pick from
+        let (first, second) = (
+            ConnectionId::new_unchecked(3),
+            ConnectionId::new_unchecked(4),
+        );
+        let mut at_high = Behaviour::new(ConnectionDedupPolicy::KeepFirst, high);
+        at_high.on_connection_established(low, first, false);
+        at_high.on_connection_established(low, second, false);
+        assert!(at_high.redundant.is_empty());
+    }
+}
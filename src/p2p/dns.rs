@@ -0,0 +1,252 @@
+//! Transport resolving the host names of `/dns`, `/dns4`, `/dns6` and `/dnsaddr` multiaddrs with
+//! a user provided [`CustomDnsResolver`] before dialing them with the inner transport.
+//!
+//! Without a resolver the addresses are passed to the inner transport as is, which resolves them
+//! with the resolver configured in [`super::TransportConfig::dns_resolver`].
+
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::io;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use async_trait::async_trait;
+use futures::future::{BoxFuture, MapErr};
+use futures::{FutureExt, TryFutureExt};
+use libp2p::core::transport::{ListenerId, TransportError, TransportEvent};
+use libp2p::core::Endpoint;
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, Transport};
+use parking_lot::Mutex;
+
+/// Upper bound on the lookups done to resolve a single address, guarding against `/dnsaddr`
+/// records referring to each other.
+const MAX_DNS_LOOKUPS: usize = 32;
+
+/// Resolves the host names of dns multiaddrs, e.g. with DNS over HTTPS or a stub for tests.
+#[async_trait]
+pub trait CustomDnsResolver: Debug + Send + Sync + 'static {
+    /// Returns the addresses of the host name.
+    async fn lookup_ip(&self, name: &str) -> io::Result<Vec<IpAddr>>;
+
+    /// Returns the TXT records of the name, used to resolve `/dnsaddr` addresses. Resolves to no
+    /// records by default.
+    async fn lookup_txt(&self, name: &str) -> io::Result<Vec<String>> {
+        let _ = name;
+        Ok(vec![])
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CustomDnsError<E> {
+    #[error(transparent)]
+    Transport(E),
+    #[error("dns resolution failed")]
+    Resolve(#[source] io::Error),
+    #[error("too many dns lookups")]
+    TooManyLookups,
+    #[error("{0} could not be resolved to any address")]
+    Unresolved(Multiaddr),
+    #[error("resolved address {0} is not supported")]
+    MultiaddrNotSupported(Multiaddr),
+}
+
+#[derive(Debug)]
+pub(crate) struct CustomDnsTransport<T> {
+    inner: Arc<Mutex<T>>,
+    resolver: Option<Arc<dyn CustomDnsResolver>>,
+}
+
+impl<T> CustomDnsTransport<T> {
+    pub(crate) fn new(inner: T, resolver: Option<Arc<dyn CustomDnsResolver>>) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(inner)),
+            resolver,
+        }
+    }
+}
+
+impl<T> Transport for CustomDnsTransport<T>
+where
+    T: Transport + Send + Unpin + 'static,
+    T::Error: Send,
+    T::Dial: Send,
+{
+    type Output = T::Output;
+    type Error = CustomDnsError<T::Error>;
+    type ListenerUpgrade = MapErr<T::ListenerUpgrade, fn(T::Error) -> Self::Error>;
+    type Dial = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+    fn listen_on(
+        &mut self,
+        id: ListenerId,
+        addr: Multiaddr,
+    ) -> Result<(), TransportError<Self::Error>> {
+        self.inner
+            .lock()
+            .listen_on(id, addr)
+            .map_err(|e| e.map(CustomDnsError::Transport))
+    }
+
+    fn remove_listener(&mut self, id: ListenerId) -> bool {
+        self.inner.lock().remove_listener(id)
+    }
+
+    fn dial(&mut self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>> {
+        self.do_dial(addr, Endpoint::Dialer)
+    }
+
+    fn dial_as_listener(
+        &mut self,
+        addr: Multiaddr,
+    ) -> Result<Self::Dial, TransportError<Self::Error>> {
+        self.do_dial(addr, Endpoint::Listener)
+    }
+
+    fn address_translation(&self, listen: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+        self.inner.lock().address_translation(listen, observed)
+    }
+
+    fn poll(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<TransportEvent<Self::ListenerUpgrade, Self::Error>> {
+        let mut inner = self.inner.lock();
+        Transport::poll(Pin::new(&mut *inner), cx).map(|event| {
+            event
+                .map_upgrade(|upgrade| upgrade.map_err::<_, fn(_) -> _>(CustomDnsError::Transport))
+                .map_err(CustomDnsError::Transport)
+        })
+    }
+}
+
+impl<T> CustomDnsTransport<T>
+where
+    T: Transport + Send + Unpin + 'static,
+    T::Error: Send,
+    T::Dial: Send,
+{
+    fn do_dial(
+        &mut self,
+        addr: Multiaddr,
+        role: Endpoint,
+    ) -> Result<<Self as Transport>::Dial, TransportError<<Self as Transport>::Error>> {
+        let resolver = match self.resolver.clone() {
+            Some(resolver) if addr.iter().any(|protocol| is_dns(&protocol)) => resolver,
+            _ => {
+                return dial_inner(&self.inner, addr, role)
+                    .map(|dial| dial.map_err(CustomDnsError::Transport).boxed())
+                    .map_err(|e| e.map(CustomDnsError::Transport));
+            }
+        };
+
+        let inner = self.inner.clone();
+
+        Ok(async move {
+            let resolved = resolve(&*resolver, addr.clone()).await?;
+
+            let mut last_err = None;
+            for addr in resolved {
+                let dial = match dial_inner(&inner, addr, role) {
+                    Ok(dial) => dial,
+                    Err(TransportError::MultiaddrNotSupported(addr)) => {
+                        last_err = Some(CustomDnsError::MultiaddrNotSupported(addr));
+                        continue;
+                    }
+                    Err(TransportError::Other(e)) => {
+                        last_err = Some(CustomDnsError::Transport(e));
+                        continue;
+                    }
+                };
+
+                match dial.await {
+                    Ok(output) => return Ok(output),
+                    Err(e) => last_err = Some(CustomDnsError::Transport(e)),
+                }
+            }
+
+            Err(last_err.unwrap_or(CustomDnsError::Unresolved(addr)))
+        }
+        .boxed())
+    }
+}
+
+fn dial_inner<T: Transport>(
+    inner: &Mutex<T>,
+    addr: Multiaddr,
+    role: Endpoint,
+) -> Result<T::Dial, TransportError<T::Error>> {
+    let mut inner = inner.lock();
+    match role {
+        Endpoint::Dialer => inner.dial(addr),
+        Endpoint::Listener => inner.dial_as_listener(addr),
+    }
+}
+
+fn is_dns(protocol: &Protocol<'_>) -> bool {
+    matches!(
+        protocol,
+        Protocol::Dns(_) | Protocol::Dns4(_) | Protocol::Dns6(_) | Protocol::Dnsaddr(_)
+    )
+}
+
+/// Replaces the dns components of the address until only addresses without them are left.
+async fn resolve<E>(
+    resolver: &dyn CustomDnsResolver,
+    addr: Multiaddr,
+) -> Result<Vec<Multiaddr>, CustomDnsError<E>> {
+    let mut resolved = Vec::new();
+    let mut unresolved = VecDeque::from([addr]);
+    let mut lookups = 0;
+
+    while let Some(addr) = unresolved.pop_front() {
+        let Some((index, protocol)) = addr.iter().enumerate().find(|(_, p)| is_dns(p)) else {
+            resolved.push(addr);
+            continue;
+        };
+
+        if lookups == MAX_DNS_LOOKUPS {
+            return Err(CustomDnsError::TooManyLookups);
+        }
+        lookups += 1;
+
+        let (name, accept): (_, fn(&IpAddr) -> bool) = match protocol {
+            Protocol::Dns(name) => (name, |_| true),
+            Protocol::Dns4(name) => (name, IpAddr::is_ipv4),
+            Protocol::Dns6(name) => (name, IpAddr::is_ipv6),
+            Protocol::Dnsaddr(name) => {
+                // the records are full addresses, which need to end with the rest of this one
+                let suffix = addr.iter().skip(index + 1).collect::<Multiaddr>();
+                let records = resolver
+                    .lookup_txt(&format!("_dnsaddr.{name}"))
+                    .await
+                    .map_err(CustomDnsError::Resolve)?;
+
+                unresolved.extend(
+                    records
+                        .iter()
+                        .filter_map(|record| record.strip_prefix("dnsaddr="))
+                        .filter_map(|record| record.parse::<Multiaddr>().ok())
+                        .filter(|record| record.ends_with(&suffix)),
+                );
+                continue;
+            }
+            _ => unreachable!("only dns protocols are found"),
+        };
+
+        let ips = resolver
+            .lookup_ip(&name)
+            .await
+            .map_err(CustomDnsError::Resolve)?;
+
+        unresolved.extend(
+            ips.into_iter()
+                .filter(accept)
+                .filter_map(|ip| addr.replace(index, |_| Some(Protocol::from(ip)))),
+        );
+    }
+
+    Ok(resolved)
+}
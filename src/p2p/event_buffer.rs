@@ -0,0 +1,158 @@
+//! Bounded delivery of swarm events to subscribers which may consume slower than the swarm
+//! produces events.
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use futures::{task::AtomicWaker, Stream};
+use parking_lot::Mutex;
+
+/// What to do with an event when the buffer of a subscriber is full.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Drop the oldest buffered event to make room for the new one.
+    #[default]
+    DropOldest,
+    /// Drop the new event, keeping the buffered ones.
+    DropNewest,
+}
+
+#[derive(Debug)]
+struct Shared<T> {
+    queue: Mutex<VecDeque<T>>,
+    capacity: usize,
+    policy: DropPolicy,
+    dropped: AtomicU64,
+    closed: AtomicBool,
+    waker: AtomicWaker,
+}
+
+/// Creates a buffer holding at most `capacity` events, dropping events according to `policy`
+/// once full. The sender is given to [`crate::UninitializedIpfs::swarm_event_subscriber`].
+pub fn swarm_event_buffer<T>(
+    capacity: usize,
+    policy: DropPolicy,
+) -> (SwarmEventSender<T>, SwarmEventReceiver<T>) {
+    assert!(capacity > 0, "capacity must be greater than zero");
+    let shared = Arc::new(Shared {
+        queue: Mutex::new(VecDeque::with_capacity(capacity)),
+        capacity,
+        policy,
+        dropped: AtomicU64::new(0),
+        closed: AtomicBool::new(false),
+        waker: AtomicWaker::new(),
+    });
+
+    (
+        SwarmEventSender {
+            shared: shared.clone(),
+        },
+        SwarmEventReceiver { shared },
+    )
+}
+
+/// Sending half of [`swarm_event_buffer`], never blocks.
+#[derive(Debug)]
+pub struct SwarmEventSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> SwarmEventSender<T> {
+    /// Buffers the event, dropping an event if the buffer is full.
+    pub fn push(&self, event: T) {
+        {
+            let mut queue = self.shared.queue.lock();
+            if queue.len() >= self.shared.capacity {
+                self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+                match self.shared.policy {
+                    DropPolicy::DropOldest => {
+                        queue.pop_front();
+                    }
+                    DropPolicy::DropNewest => return,
+                }
+            }
+            queue.push_back(event);
+        }
+        self.shared.waker.wake();
+    }
+
+    /// Returns the number of events dropped so far.
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> Drop for SwarmEventSender<T> {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+        self.shared.waker.wake();
+    }
+}
+
+/// Receiving half of [`swarm_event_buffer`], ends once the node has shut down.
+#[derive(Debug)]
+pub struct SwarmEventReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> SwarmEventReceiver<T> {
+    /// Returns the number of events dropped so far.
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> Stream for SwarmEventReceiver<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.shared.waker.register(cx.waker());
+
+        if let Some(event) = self.shared.queue.lock().pop_front() {
+            return Poll::Ready(Some(event));
+        }
+
+        if self.shared.closed.load(Ordering::Acquire) {
+            return Poll::Ready(None);
+        }
+
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::{swarm_event_buffer, DropPolicy};
+
+    #[tokio::test]
+    async fn drop_oldest() {
+        let (tx, rx) = swarm_event_buffer(2, DropPolicy::DropOldest);
+        for i in 0..5 {
+            tx.push(i);
+        }
+        drop(tx);
+
+        assert_eq!(rx.dropped(), 3);
+        assert_eq!(rx.collect::<Vec<_>>().await, vec![3, 4]);
+    }
+
+    #[tokio::test]
+    async fn drop_newest() {
+        let (tx, rx) = swarm_event_buffer(2, DropPolicy::DropNewest);
+        for i in 0..5 {
+            tx.push(i);
+        }
+        drop(tx);
+
+        assert_eq!(rx.dropped(), 3);
+        assert_eq!(rx.collect::<Vec<_>>().await, vec![0, 1]);
+    }
+}
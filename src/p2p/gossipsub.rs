@@ -8,8 +8,12 @@ use std::pin::Pin;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 use tracing::debug;
 
+use super::RateLimit;
+use crate::Ipfs;
+
 use libp2p::core::{Endpoint, Multiaddr};
 use libp2p::identity::PeerId;
 
@@ -22,12 +26,50 @@ use libp2p::swarm::{
     ToSwarm,
 };
 
+/// A pubsub message received on a [`SubscriptionStream`].
+///
+/// The `source` is optional: it is absent when the publisher uses anonymous message
+/// authenticity, or when the message is otherwise forwarded without the author peer id. Use
+/// [`PubsubMessage::source_or_unknown`] when displaying the author.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PubsubMessage {
+    /// Peer id of the author of the message, if known.
+    pub source: Option<PeerId>,
+    /// Content of the message.
+    pub data: Vec<u8>,
+    /// Sequence number of the message, if included by the author.
+    pub sequence_number: Option<u64>,
+    /// Topic the message was published to.
+    pub topic: TopicHash,
+}
+
+impl PubsubMessage {
+    /// Returns the author of the message for display purposes, or `"unknown"` if the message
+    /// has no source.
+    pub fn source_or_unknown(&self) -> String {
+        self.source
+            .map(|peer_id| peer_id.to_string())
+            .unwrap_or_else(|| String::from("unknown"))
+    }
+}
+
+impl From<GossipsubMessage> for PubsubMessage {
+    fn from(message: GossipsubMessage) -> Self {
+        PubsubMessage {
+            source: message.source,
+            data: message.data,
+            sequence_number: message.sequence_number,
+            topic: message.topic,
+        }
+    }
+}
+
 /// Currently a thin wrapper around Gossipsub.
 /// Allows single subscription to a topic with only unbounded senders. Tracks the peers subscribed
 /// to different topics.
 pub struct GossipsubStream {
     // Tracks the topic subscriptions.
-    streams: HashMap<TopicHash, async_broadcast::Sender<GossipsubMessage>>,
+    streams: HashMap<TopicHash, async_broadcast::Sender<PubsubMessage>>,
 
     active_streams: HashMap<TopicHash, Arc<AtomicUsize>>,
 
@@ -40,6 +82,84 @@ pub struct GossipsubStream {
         channel::UnboundedSender<TopicHash>,
         channel::UnboundedReceiver<TopicHash>,
     ),
+
+    // Limits the rate of local publishes, per topic.
+    publish_limiter: Option<PublishLimiter>,
+
+    // Limits the size of the data of local publishes.
+    max_message_size: Option<usize>,
+}
+
+/// Returned by [`GossipsubStream::publish`].
+#[derive(Debug, thiserror::Error)]
+pub enum PubsubPublishError {
+    /// The configured publish rate of the topic was exceeded, so the message was not published.
+    #[error("publish rate limit of topic {topic} exceeded")]
+    RateLimited { topic: String },
+
+    /// The data is larger than the configured maximum message size, so the message was not
+    /// published.
+    #[error("message of {size} bytes to topic {topic} exceeds the maximum of {max} bytes")]
+    MessageTooLarge {
+        topic: String,
+        size: usize,
+        max: usize,
+    },
+
+    #[error(transparent)]
+    Publish(#[from] PublishError),
+}
+
+/// Token buckets limiting the publishes of each topic. Every topic starts with `limit` tokens,
+/// refilled at `limit` tokens per `interval`, and a publish takes one. The buckets refilled in
+/// full are dropped, as they are no different from the ones of the topics not published to yet.
+#[derive(Debug)]
+struct PublishLimiter {
+    limit: f64,
+    interval: Duration,
+    buckets: HashMap<TopicHash, (f64, Instant)>,
+}
+
+impl PublishLimiter {
+    fn new(rate: RateLimit) -> Self {
+        let (RateLimit::PerPeer { limit, interval } | RateLimit::PerIp { limit, interval }) = rate;
+        PublishLimiter {
+            limit: f64::from(limit.get()),
+            interval,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Returns the tokens a bucket holding `tokens` at `refilled_at` holds at `now`.
+    fn tokens_at(&self, tokens: f64, refilled_at: Instant, now: Instant) -> f64 {
+        let refill = if self.interval.is_zero() {
+            self.limit
+        } else {
+            let elapsed = now.duration_since(refilled_at);
+            self.limit * elapsed.as_secs_f64() / self.interval.as_secs_f64()
+        };
+        (tokens + refill).min(self.limit)
+    }
+
+    /// Takes a token of the topic, returning false if there was none left.
+    fn try_acquire(&mut self, topic: TopicHash) -> bool {
+        let now = Instant::now();
+
+        let mut buckets = std::mem::take(&mut self.buckets);
+        for (tokens, refilled_at) in buckets.values_mut() {
+            *tokens = self.tokens_at(*tokens, *refilled_at, now);
+            *refilled_at = now;
+        }
+        buckets.retain(|_, (tokens, _)| *tokens < self.limit);
+
+        let (tokens, _) = buckets.entry(topic).or_insert((self.limit, now));
+        let acquired = *tokens >= 1.0;
+        if acquired {
+            *tokens -= 1.0;
+        }
+        self.buckets = buckets;
+        acquired
+    }
 }
 
 impl core::ops::Deref for GossipsubStream {
@@ -59,7 +179,7 @@ impl core::ops::DerefMut for GossipsubStream {
 pub struct SubscriptionStream {
     on_drop: Option<channel::UnboundedSender<TopicHash>>,
     topic: Option<TopicHash>,
-    inner: async_broadcast::Receiver<GossipsubMessage>,
+    inner: async_broadcast::Receiver<PubsubMessage>,
     counter: Arc<AtomicUsize>,
 }
 
@@ -111,7 +231,7 @@ impl fmt::Debug for SubscriptionStream {
 }
 
 impl Stream for SubscriptionStream {
-    type Item = GossipsubMessage;
+    type Item = PubsubMessage;
 
     fn poll_next(mut self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Option<Self::Item>> {
         use futures::stream::StreamExt;
@@ -134,6 +254,158 @@ impl FusedStream for SubscriptionStream {
     }
 }
 
+/// Subscription to a pubsub topic returned by [`Ipfs::pubsub_subscribe`], yielding the messages
+/// received on the topic.
+///
+/// Dropping the subscription unsubscribes once no other subscription to the topic remains, like
+/// dropping a [`SubscriptionStream`]. [`Subscription::unsubscribe`] leaves the topic explicitly
+/// and returns once the node has left it.
+pub struct Subscription {
+    ipfs: Ipfs,
+    topic: String,
+    stream: SubscriptionStream,
+}
+
+impl fmt::Debug for Subscription {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Subscription")
+            .field("topic", &self.topic)
+            .field("is_terminated", &self.stream.is_terminated())
+            .finish()
+    }
+}
+
+impl Subscription {
+    pub(crate) fn new(ipfs: Ipfs, topic: String, stream: SubscriptionStream) -> Self {
+        Self {
+            ipfs,
+            topic,
+            stream,
+        }
+    }
+
+    /// Returns the topic subscribed to.
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// Leaves the topic, ending every subscription to it like [`Ipfs::pubsub_unsubscribe`].
+    /// Returns false if the node was no longer subscribed to the topic.
+    pub async fn unsubscribe(mut self) -> anyhow::Result<bool> {
+        // the topic is left here, dropping the stream must not leave a later subscription
+        self.stream.on_drop.take();
+        self.ipfs.pubsub_unsubscribe(&self.topic).await
+    }
+}
+
+impl Stream for Subscription {
+    type Item = PubsubMessage;
+
+    fn poll_next(mut self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Option<Self::Item>> {
+        use futures::stream::StreamExt;
+        self.stream.poll_next_unpin(ctx)
+    }
+}
+
+impl FusedStream for Subscription {
+    fn is_terminated(&self) -> bool {
+        self.stream.is_terminated()
+    }
+}
+
+/// Stream combining the subscriptions of several topics, yielding the received messages tagged
+/// with the topic they were received on.
+///
+/// Topics can be added and removed while the stream is in use; each topic keeps its own
+/// [`SubscriptionStream`], so removing a topic does not affect the subscriptions of the others.
+/// A topic whose subscription ends, for example through [`Ipfs::pubsub_unsubscribe`], is removed
+/// from the stream. The stream never ends by itself, even when it has no topics left.
+pub struct MultiSubscriptionStream {
+    ipfs: Ipfs,
+    streams: HashMap<String, SubscriptionStream>,
+    /// Position of the topic polled first, moved past the topic which yielded last so that a busy
+    /// topic cannot starve the others.
+    start: usize,
+}
+
+impl fmt::Debug for MultiSubscriptionStream {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("MultiSubscriptionStream")
+            .field("topics", &self.streams.keys())
+            .finish()
+    }
+}
+
+impl MultiSubscriptionStream {
+    pub(crate) fn new(ipfs: Ipfs) -> Self {
+        Self {
+            ipfs,
+            streams: HashMap::new(),
+            start: 0,
+        }
+    }
+
+    /// Subscribes to `topic` and adds its messages to this stream. Adding a topic which is
+    /// already part of the stream does nothing.
+    pub async fn add_topic(&mut self, topic: impl Into<String>) -> anyhow::Result<()> {
+        let topic = topic.into();
+        if self.streams.contains_key(&topic) {
+            return Ok(());
+        }
+
+        let stream = self.ipfs.pubsub_subscribe_stream(topic.clone()).await?;
+        self.streams.insert(topic, stream);
+        Ok(())
+    }
+
+    /// Removes `topic` from this stream, dropping its subscription. Returns false if the topic was
+    /// not part of the stream.
+    pub fn remove_topic(&mut self, topic: &str) -> bool {
+        self.streams.remove(topic).is_some()
+    }
+
+    /// Returns the topics currently part of this stream.
+    pub fn topics(&self) -> Vec<String> {
+        self.streams.keys().cloned().collect()
+    }
+}
+
+impl Stream for MultiSubscriptionStream {
+    type Item = (String, PubsubMessage);
+
+    fn poll_next(mut self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Option<Self::Item>> {
+        use futures::stream::StreamExt;
+        let mut ended = vec![];
+        let mut next = None;
+
+        let this = &mut *self;
+        let start = this.start.checked_rem(this.streams.len()).unwrap_or(0);
+        let mut streams = this.streams.iter_mut().collect::<Vec<_>>();
+        streams.rotate_left(start);
+
+        for (i, (topic, stream)) in streams.into_iter().enumerate() {
+            match stream.poll_next_unpin(ctx) {
+                Poll::Ready(Some(message)) => {
+                    next = Some((topic.clone(), message));
+                    this.start = start + i + 1;
+                    break;
+                }
+                Poll::Ready(None) => ended.push(topic.clone()),
+                Poll::Pending => {}
+            }
+        }
+
+        for topic in ended {
+            self.streams.remove(&topic);
+        }
+
+        match next {
+            Some(item) => Poll::Ready(Some(item)),
+            None => Poll::Pending,
+        }
+    }
+}
+
 impl From<Gossipsub> for GossipsubStream {
     fn from(gossipsub: Gossipsub) -> Self {
         let (tx, rx) = channel::unbounded();
@@ -142,11 +414,27 @@ impl From<Gossipsub> for GossipsubStream {
             gossipsub,
             unsubscriptions: (tx, rx),
             active_streams: Default::default(),
+            publish_limiter: None,
+            max_message_size: None,
         }
     }
 }
 
 impl GossipsubStream {
+    /// Limits the rate of publishes to each topic. The `limit` and `interval` of the given rate
+    /// apply to every topic separately, whichever the variant.
+    pub fn with_publish_rate(mut self, rate: RateLimit) -> Self {
+        self.publish_limiter = Some(PublishLimiter::new(rate));
+        self
+    }
+
+    /// Limits the size of the data published, failing larger publishes before they take from the
+    /// publish rate of the topic.
+    pub fn with_max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = Some(size);
+        self
+    }
+
     /// Subscribes to a currently unsubscribed topic.
     /// Returns a receiver for messages sent to the topic or `None` if subscription existed
     /// already.
@@ -213,12 +501,34 @@ impl GossipsubStream {
     }
 
     /// Publish to subscribed topic
+    ///
+    /// Fails without publishing with [`PubsubPublishError::MessageTooLarge`] when the data is
+    /// larger than the maximum message size, or with [`PubsubPublishError::RateLimited`] when the
+    /// publish rate of the topic is exceeded.
     pub fn publish(
         &mut self,
         topic: impl Into<String>,
         data: impl Into<Vec<u8>>,
-    ) -> Result<MessageId, PublishError> {
-        self.gossipsub.publish(Topic::new(topic), data)
+    ) -> Result<MessageId, PubsubPublishError> {
+        let topic = Topic::new(topic);
+        let data = data.into();
+        if let Some(max) = self.max_message_size {
+            if data.len() > max {
+                return Err(PubsubPublishError::MessageTooLarge {
+                    topic: topic.to_string(),
+                    size: data.len(),
+                    max,
+                });
+            }
+        }
+        if let Some(limiter) = self.publish_limiter.as_mut() {
+            if !limiter.try_acquire(topic.hash()) {
+                return Err(PubsubPublishError::RateLimited {
+                    topic: topic.to_string(),
+                });
+            }
+        }
+        Ok(self.gossipsub.publish(topic, data)?)
     }
 
     /// Returns the known peers subscribed to any topic
@@ -339,7 +649,7 @@ impl NetworkBehaviour for GossipsubStream {
                     let topic = message.topic.clone();
                     if let Entry::Occupied(oe) = self.streams.entry(topic) {
                         if let Err(TrySendError::Closed(_)) =
-                            oe.get().try_broadcast(message.clone())
+                            oe.get().try_broadcast(PubsubMessage::from(message))
                         {
                             // receiver has dropped
                             let (topic, _) = oe.remove_entry();
@@ -374,3 +684,142 @@ impl NetworkBehaviour for GossipsubStream {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::StreamExt;
+    use libp2p::gossipsub::{ConfigBuilder, MessageAuthenticity, PublishError, ValidationMode};
+    use libp2p::identity::Keypair;
+
+    use super::{
+        Gossipsub, GossipsubMessage, GossipsubStream, PubsubMessage, PubsubPublishError, RateLimit,
+        Topic,
+    };
+
+    #[tokio::test]
+    async fn message_without_source() {
+        let config = ConfigBuilder::default()
+            .validation_mode(ValidationMode::Anonymous)
+            .build()
+            .unwrap();
+        let gossipsub = Gossipsub::new(MessageAuthenticity::Anonymous, config).unwrap();
+        let mut gossipsub = GossipsubStream::from(gossipsub);
+
+        let mut stream = gossipsub.subscribe("topic").unwrap();
+
+        let message = GossipsubMessage {
+            source: None,
+            data: b"foobar".to_vec(),
+            sequence_number: None,
+            topic: Topic::new("topic").hash(),
+        };
+
+        gossipsub.streams[&message.topic]
+            .try_broadcast(PubsubMessage::from(message))
+            .unwrap();
+
+        let received = stream.next().await.unwrap();
+        assert_eq!(received.source, None);
+        assert_eq!(received.data, b"foobar");
+        assert_eq!(received.source_or_unknown(), "unknown");
+    }
+
+    fn rate_limited(limit: u32, interval: Duration) -> GossipsubStream {
+        let gossipsub = Gossipsub::new(
+            MessageAuthenticity::Signed(Keypair::generate_ed25519()),
+            Default::default(),
+        )
+        .unwrap();
+        GossipsubStream::from(gossipsub).with_publish_rate(RateLimit::PerPeer {
+            limit: limit.try_into().unwrap(),
+            interval,
+        })
+    }
+
+    #[tokio::test]
+    async fn publishes_beyond_the_rate_are_rejected() {
+        let mut gossipsub = rate_limited(5, Duration::from_secs(3600));
+
+        let mut limited = 0;
+        for i in 0..20u8 {
+            match gossipsub.publish("topic", vec![i]) {
+                Err(PubsubPublishError::RateLimited { topic }) => {
+                    assert_eq!(topic, "topic");
+                    limited += 1;
+                }
+                // without peers the message is handed to gossipsub but goes nowhere
+                Err(PubsubPublishError::Publish(PublishError::InsufficientPeers)) => {}
+                other => panic!("unexpected publish result: {other:?}"),
+            }
+        }
+        assert_eq!(limited, 15);
+
+        // the limit is kept per topic
+        assert!(!matches!(
+            gossipsub.publish("other", vec![0]),
+            Err(PubsubPublishError::RateLimited { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn publish_rate_refills_over_time() {
+        let mut gossipsub = rate_limited(2, Duration::from_millis(100));
+
+        let publish = |gossipsub: &mut GossipsubStream| {
+            !matches!(
+                gossipsub.publish("topic", vec![0]),
+                Err(PubsubPublishError::RateLimited { .. })
+            )
+        };
+
+        assert!(publish(&mut gossipsub));
+        assert!(publish(&mut gossipsub));
+        assert!(!publish(&mut gossipsub));
+
+        tokio::time::sleep(Duration::from_millis(120)).await;
+
+        assert!(publish(&mut gossipsub));
+        assert!(publish(&mut gossipsub));
+        assert!(!publish(&mut gossipsub));
+    }
+
+    #[tokio::test]
+    async fn refilled_publish_buckets_are_dropped() {
+        let mut gossipsub = rate_limited(2, Duration::from_millis(100));
+        let buckets = |gossipsub: &GossipsubStream| {
+            let limiter = gossipsub.publish_limiter.as_ref().unwrap();
+            limiter.buckets.len()
+        };
+
+        let _ = gossipsub.publish("topic", vec![0]);
+        let _ = gossipsub.publish("other", vec![0]);
+        assert_eq!(buckets(&gossipsub), 2);
+
+        tokio::time::sleep(Duration::from_millis(120)).await;
+
+        let _ = gossipsub.publish("topic", vec![0]);
+        assert_eq!(buckets(&gossipsub), 1);
+    }
+
+    #[tokio::test]
+    async fn publishes_beyond_the_message_size_are_rejected() {
+        let mut gossipsub = rate_limited(1, Duration::from_secs(3600)).with_max_message_size(4);
+
+        match gossipsub.publish("topic", vec![0; 5]) {
+            Err(PubsubPublishError::MessageTooLarge { topic, size, max }) => {
+                assert_eq!(topic, "topic");
+                assert_eq!(size, 5);
+                assert_eq!(max, 4);
+            }
+            other => panic!("unexpected publish result: {other:?}"),
+        }
+
+        // the rejected message did not take from the publish rate
+        assert!(!matches!(
+            gossipsub.publish("topic", vec![0; 4]),
+            Err(PubsubPublishError::RateLimited { .. })
+        ));
+    }
+}
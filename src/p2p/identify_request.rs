@@ -0,0 +1,452 @@
+//! Requests the identify info of a connected peer on demand.
+//!
+//! The identify behaviour only requests the info of a peer once a connection to it is established
+//! and at its interval after that. This opens an identify stream over an established connection
+//! whenever asked to, which the identify behaviour of the peer answers like any other request.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::{future::BoxFuture, stream::FuturesUnordered, AsyncReadExt, FutureExt, StreamExt};
+use libp2p::{
+    core::{
+        upgrade::{DeniedUpgrade, ReadyUpgrade},
+        Endpoint,
+    },
+    identify::{Info, PROTOCOL_NAME},
+    identity::PublicKey,
+    swarm::{
+        handler::{ConnectionEvent, DialUpgradeError, FullyNegotiatedOutbound},
+        ConnectionClosed, ConnectionDenied, ConnectionHandler, ConnectionHandlerEvent,
+        ConnectionId, FromSwarm, KeepAlive, NetworkBehaviour, NotifyHandler, PollParameters,
+        SubstreamProtocol, THandler, THandlerInEvent, THandlerOutEvent, ToSwarm,
+    },
+    Multiaddr, PeerId, Stream, StreamProtocol,
+};
+use void::Void;
+
+/// Same limit as the identify behaviour puts on the size of the info.
+const MAX_MESSAGE_SIZE: usize = 4096;
+
+/// How long the peer has to answer once the stream is open.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub enum Event {
+    Received { peer_id: PeerId, info: Box<Info> },
+    Failed { peer_id: PeerId, error: String },
+}
+
+#[derive(Debug, Default)]
+pub struct Behaviour {
+    events: VecDeque<ToSwarm<Event, THandlerInEvent<Self>>>,
+    connections: HashMap<PeerId, Vec<ConnectionId>>,
+    /// Requests not answered yet, failed when their connection closes.
+    pending: HashMap<ConnectionId, usize>,
+}
+
+impl Behaviour {
+    /// Requests the identify info of the peer over one of the connections to it, returning false
+    /// when there is none.
+    pub fn request(&mut self, peer_id: PeerId) -> bool {
+        let Some(connection_id) = self
+            .connections
+            .get(&peer_id)
+            .and_then(|connections| connections.first())
+        else {
+            return false;
+        };
+
+        self.events.push_back(ToSwarm::NotifyHandler {
+            peer_id,
+            handler: NotifyHandler::One(*connection_id),
+            event: (),
+        });
+        *self.pending.entry(*connection_id).or_default() += 1;
+        true
+    }
+}
+
+impl NetworkBehaviour for Behaviour {
+    type ConnectionHandler = Handler;
+    type ToSwarm = Event;
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        _: ConnectionId,
+        _: PeerId,
+        _: &Multiaddr,
+        _: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        Ok(Handler::default())
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        _: ConnectionId,
+        _: PeerId,
+        _: &Multiaddr,
+        _: Endpoint,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        Ok(Handler::default())
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        if let Some(pending) = self.pending.get_mut(&connection_id) {
+            *pending -= 1;
+            if *pending == 0 {
+                self.pending.remove(&connection_id);
+            }
+        }
+
+        let event = match event {
+            Ok(info) if info.public_key.to_peer_id() != peer_id => Event::Failed {
+                peer_id,
+                error: "the info is not signed by the key of the peer".into(),
+            },
+            Ok(info) => Event::Received {
+                peer_id,
+                info: Box::new(info),
+            },
+            Err(error) => Event::Failed { peer_id, error },
+        };
+        self.events.push_back(ToSwarm::GenerateEvent(event));
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm<Self::ConnectionHandler>) {
+        match event {
+            FromSwarm::ConnectionEstablished(established) => {
+                self.connections
+                    .entry(established.peer_id)
+                    .or_default()
+                    .push(established.connection_id);
+            }
+            FromSwarm::ConnectionClosed(ConnectionClosed {
+                peer_id,
+                connection_id,
+                ..
+            }) => {
+                if let Some(connections) = self.connections.get_mut(&peer_id) {
+                    connections.retain(|id| *id != connection_id);
+                    if connections.is_empty() {
+                        self.connections.remove(&peer_id);
+                    }
+                }
+
+                // the handler is gone along with the requests it had not answered
+                if self.pending.remove(&connection_id).is_some() {
+                    self.events.push_back(ToSwarm::GenerateEvent(Event::Failed {
+                        peer_id,
+                        error: "the connection was closed".into(),
+                    }));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn poll(
+        &mut self,
+        _: &mut Context,
+        _: &mut impl PollParameters,
+    ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        if let Some(event) = self.events.pop_front() {
+            return Poll::Ready(event);
+        }
+        Poll::Pending
+    }
+}
+
+#[derive(Default)]
+pub struct Handler {
+    /// Requests waiting for a stream to be opened.
+    requested: usize,
+    /// Streams being opened.
+    negotiating: usize,
+    responses: FuturesUnordered<BoxFuture<'static, io::Result<Info>>>,
+    failures: VecDeque<String>,
+}
+
+impl ConnectionHandler for Handler {
+    type FromBehaviour = ();
+    type ToBehaviour = Result<Info, String>;
+    type Error = Void;
+    type InboundProtocol = DeniedUpgrade;
+    type OutboundProtocol = ReadyUpgrade<StreamProtocol>;
+    type InboundOpenInfo = ();
+    type OutboundOpenInfo = ();
+
+    fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol, Self::InboundOpenInfo> {
+        SubstreamProtocol::new(DeniedUpgrade, ())
+    }
+
+    fn connection_keep_alive(&self) -> KeepAlive {
+        if self.requested > 0 || self.negotiating > 0 || !self.responses.is_empty() {
+            KeepAlive::Yes
+        } else {
+            KeepAlive::No
+        }
+    }
+
+    fn on_behaviour_event(&mut self, _: Self::FromBehaviour) {
+        self.requested += 1;
+    }
+
+    fn on_connection_event(
+        &mut self,
+        event: ConnectionEvent<
+            Self::InboundProtocol,
+            Self::OutboundProtocol,
+            Self::InboundOpenInfo,
+            Self::OutboundOpenInfo,
+        >,
+    ) {
+        match event {
+            ConnectionEvent::FullyNegotiatedOutbound(FullyNegotiatedOutbound {
+                protocol: stream,
+                ..
+            }) => {
+                self.negotiating -= 1;
+                self.responses.push(
+                    async move {
+                        tokio::time::timeout(READ_TIMEOUT, read_info(stream))
+                            .await
+                            .map_err(|_| {
+                                io::Error::new(io::ErrorKind::TimedOut, "the peer did not answer")
+                            })?
+                    }
+                    .boxed(),
+                );
+            }
+            ConnectionEvent::DialUpgradeError(DialUpgradeError { error, .. }) => {
+                self.negotiating -= 1;
+                self.failures.push_back(error.to_string());
+            }
+            _ => {}
+        }
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<
+        ConnectionHandlerEvent<
+            Self::OutboundProtocol,
+            Self::OutboundOpenInfo,
+            Self::ToBehaviour,
+            Self::Error,
+        >,
+    > {
+        if let Some(error) = self.failures.pop_front() {
+            return Poll::Ready(ConnectionHandlerEvent::NotifyBehaviour(Err(error)));
+        }
+
+        if self.requested > 0 {
+            self.requested -= 1;
+            self.negotiating += 1;
+            return Poll::Ready(ConnectionHandlerEvent::OutboundSubstreamRequest {
+                protocol: SubstreamProtocol::new(ReadyUpgrade::new(PROTOCOL_NAME), ()),
+            });
+        }
+
+        if let Poll::Ready(Some(response)) = self.responses.poll_next_unpin(cx) {
+            let response = response.map_err(|e| e.to_string());
+            return Poll::Ready(ConnectionHandlerEvent::NotifyBehaviour(response));
+        }
+
+        Poll::Pending
+    }
+}
+
+/// Reads the length prefixed info the peer answers with.
+async fn read_info(mut stream: Stream) -> io::Result<Info> {
+    let mut len = 0usize;
+    for i in 0.. {
+        let mut byte = [0u8];
+        stream.read_exact(&mut byte).await?;
+        len |= usize::from(byte[0] & 0x7f) << (7 * i);
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+        if i == 2 {
+            return Err(invalid_data("length prefix overflows"));
+        }
+    }
+
+    if len > MAX_MESSAGE_SIZE {
+        return Err(invalid_data("info exceeds the maximum size"));
+    }
+
+    let mut buf = vec![0; len];
+    stream.read_exact(&mut buf).await?;
+    decode_info(&buf)
+}
+
+/// Decodes the `Identify` protobuf message.
+fn decode_info(mut buf: &[u8]) -> io::Result<Info> {
+    let mut public_key = None;
+    let mut protocol_version = String::new();
+    let mut agent_version = String::new();
+    let mut listen_addrs = vec![];
+    let mut protocols = vec![];
+    let mut observed_addr = Multiaddr::empty();
+
+    while !buf.is_empty() {
+        let key = read_varint(&mut buf)?;
+        // all of the fields are length delimited
+        if key & 0x7 != 2 {
+            return Err(invalid_data("unexpected wire type"));
+        }
+        let len = read_varint(&mut buf)? as usize;
+        if len > buf.len() {
+            return Err(invalid_data("field exceeds the message"));
+        }
+        let (value, rest) = buf.split_at(len);
+        buf = rest;
+
+        match key >> 3 {
+            1 => {
+                let key = PublicKey::try_decode_protobuf(value)
+                    .map_err(|_| invalid_data("invalid public key"))?;
+                public_key = Some(key);
+            }
+            2 => {
+                if let Ok(addr) = Multiaddr::try_from(value.to_vec()) {
+                    listen_addrs.push(addr);
+                }
+            }
+            3 => {
+                let protocol = String::from_utf8_lossy(value).into_owned();
+                if let Ok(protocol) = StreamProtocol::try_from_owned(protocol) {
+                    protocols.push(protocol);
+                }
+            }
+            4 => {
+                if let Ok(addr) = Multiaddr::try_from(value.to_vec()) {
+                    observed_addr = addr;
+                }
+            }
+            5 => protocol_version = String::from_utf8_lossy(value).into_owned(),
+            6 => agent_version = String::from_utf8_lossy(value).into_owned(),
+            _ => {}
+        }
+    }
+
+    Ok(Info {
+        public_key: public_key.ok_or_else(|| invalid_data("missing public key"))?,
+        protocol_version,
+        agent_version,
+        listen_addrs,
+        protocols,
+        observed_addr,
+    })
+}
+
+fn read_varint(buf: &mut &[u8]) -> io::Result<u64> {
+    let mut value = 0u64;
+    for i in 0..10 {
+        let (&byte, rest) = buf
+            .split_first()
+            .ok_or_else(|| invalid_data("truncated varint"))?;
+        *buf = rest;
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid_data("varint overflows"))
+}
+
+fn invalid_data(error: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p::{
+        core::{ConnectedPoint, Endpoint},
+        identity::Keypair,
+        swarm::{
+            behaviour::ConnectionEstablished, ConnectionClosed, ConnectionId, FromSwarm,
+            NetworkBehaviour, ToSwarm,
+        },
+        PeerId, StreamProtocol,
+    };
+
+    use super::{Behaviour, Event, Handler};
+
+    #[test]
+    fn requests_fail_when_their_connection_closes() {
+        let mut behaviour = Behaviour::default();
+        let peer_id = PeerId::random();
+        let connection_id = ConnectionId::new_unchecked(0);
+        let endpoint = ConnectedPoint::Dialer {
+            address: "/ip4/127.0.0.1/tcp/4001".parse().unwrap(),
+            role_override: Endpoint::Dialer,
+        };
+
+        behaviour.on_swarm_event(FromSwarm::ConnectionEstablished(ConnectionEstablished {
+            peer_id,
+            connection_id,
+            endpoint: &endpoint,
+            failed_addresses: &[],
+            other_established: 0,
+        }));
+        assert!(behaviour.request(peer_id));
+        behaviour.events.clear();
+
+        behaviour.on_swarm_event(FromSwarm::ConnectionClosed(ConnectionClosed {
+            peer_id,
+            connection_id,
+            endpoint: &endpoint,
+            handler: Handler::default(),
+            remaining_established: 0,
+        }));
+
+        assert!(matches!(
+            behaviour.events.pop_front(),
+            Some(ToSwarm::GenerateEvent(Event::Failed { peer_id: failed, .. })) if failed == peer_id
+        ));
+        assert!(!behaviour.request(peer_id));
+    }
+
+    #[test]
+    fn decodes_the_identify_message() {
+        let public_key = Keypair::generate_ed25519().public();
+        let key = public_key.encode_protobuf();
+        let addr = "/ip4/127.0.0.1/tcp/4001"
+            .parse::<libp2p::Multiaddr>()
+            .unwrap();
+
+        let mut message = vec![];
+        for (field, value) in [
+            (5, &b"/ipfs/0.1.0"[..]),
+            (6, b"rust-ipfs"),
+            (1, &key),
+            (2, &addr.to_vec()),
+            (3, b"/ipfs/id/1.0.0"),
+            // not a protocol, skipped
+            (3, b"id"),
+        ] {
+            message.push(field << 3 | 2);
+            message.push(value.len() as u8);
+            message.extend_from_slice(value);
+        }
+
+        let info = super::decode_info(&message).unwrap();
+        assert_eq!(info.public_key, public_key);
+        assert_eq!(info.protocol_version, "/ipfs/0.1.0");
+        assert_eq!(info.agent_version, "rust-ipfs");
+        assert_eq!(info.listen_addrs, [addr]);
+        assert_eq!(info.protocols, [StreamProtocol::new("/ipfs/id/1.0.0")]);
+    }
+}
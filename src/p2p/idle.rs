@@ -0,0 +1,156 @@
+//! Closes connections which have been idle for longer than a configured timeout.
+//!
+//! The handler of every protocol of a connection is polled whenever there is activity on the
+//! connection, be it data on one of its streams, a new stream being negotiated or an event sent to
+//! a handler. The handler here resets its deadline on each of those polls and closes the whole
+//! connection once the deadline passes without any activity, regardless of what the handlers of
+//! the other protocols report as their keep alive.
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::FutureExt;
+use libp2p::{
+    core::{upgrade::DeniedUpgrade, Endpoint},
+    swarm::{
+        handler::ConnectionEvent, ConnectionDenied, ConnectionHandler, ConnectionHandlerEvent,
+        ConnectionId, FromSwarm, KeepAlive, NetworkBehaviour, PollParameters, SubstreamProtocol,
+        THandler, THandlerInEvent, THandlerOutEvent, ToSwarm,
+    },
+    Multiaddr, PeerId,
+};
+use tokio::time::{Instant, Sleep};
+use void::Void;
+
+#[derive(Debug)]
+pub struct Behaviour {
+    timeout: Duration,
+}
+
+impl Behaviour {
+    pub fn new(timeout: Duration) -> Self {
+        Self { timeout }
+    }
+}
+
+impl NetworkBehaviour for Behaviour {
+    type ConnectionHandler = Handler;
+    type ToSwarm = Void;
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        _: ConnectionId,
+        _: PeerId,
+        _: &Multiaddr,
+        _: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        Ok(Handler::new(self.timeout))
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        _: ConnectionId,
+        _: PeerId,
+        _: &Multiaddr,
+        _: Endpoint,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        Ok(Handler::new(self.timeout))
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        _: PeerId,
+        _: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        void::unreachable(event)
+    }
+
+    fn on_swarm_event(&mut self, _: FromSwarm<Self::ConnectionHandler>) {}
+
+    fn poll(
+        &mut self,
+        _: &mut Context,
+        _: &mut impl PollParameters,
+    ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        Poll::Pending
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("connection was idle for {0:?}")]
+pub struct IdleTimeout(Duration);
+
+#[derive(Debug)]
+pub struct Handler {
+    timeout: Duration,
+    deadline: Pin<Box<Sleep>>,
+}
+
+impl Handler {
+    fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            deadline: Box::pin(tokio::time::sleep(timeout)),
+        }
+    }
+}
+
+impl ConnectionHandler for Handler {
+    type FromBehaviour = Void;
+    type ToBehaviour = Void;
+    type Error = IdleTimeout;
+    type InboundProtocol = DeniedUpgrade;
+    type OutboundProtocol = DeniedUpgrade;
+    type InboundOpenInfo = ();
+    type OutboundOpenInfo = Void;
+
+    fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol, Self::InboundOpenInfo> {
+        SubstreamProtocol::new(DeniedUpgrade, ())
+    }
+
+    fn connection_keep_alive(&self) -> KeepAlive {
+        KeepAlive::No
+    }
+
+    fn on_behaviour_event(&mut self, event: Self::FromBehaviour) {
+        void::unreachable(event)
+    }
+
+    fn on_connection_event(
+        &mut self,
+        _: ConnectionEvent<
+            Self::InboundProtocol,
+            Self::OutboundProtocol,
+            Self::InboundOpenInfo,
+            Self::OutboundOpenInfo,
+        >,
+    ) {
+    }
+
+    #[allow(deprecated)]
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<
+        ConnectionHandlerEvent<
+            Self::OutboundProtocol,
+            Self::OutboundOpenInfo,
+            Self::ToBehaviour,
+            Self::Error,
+        >,
+    > {
+        if self.deadline.poll_unpin(cx).is_ready() {
+            return Poll::Ready(ConnectionHandlerEvent::Close(IdleTimeout(self.timeout)));
+        }
+
+        // being polled means there was activity on the connection
+        self.deadline.as_mut().reset(Instant::now() + self.timeout);
+        let _ = self.deadline.poll_unpin(cx);
+
+        Poll::Pending
+    }
+}
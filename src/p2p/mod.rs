@@ -1,38 +1,60 @@
 //! P2P handling for IPFS nodes.
 use std::convert::TryInto;
 use std::num::{NonZeroU8, NonZeroUsize};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use crate::error::Error;
 use crate::repo::Repo;
 use crate::{IpfsOptions, TTransportFn};
 
 use either::Either;
+use libp2p::autonat::Config as AutonatConfig;
+use libp2p::core::transport::ListenerId;
 use libp2p::gossipsub::ValidationMode;
 use libp2p::identify::Info as IdentifyInfo;
 use libp2p::identity::{Keypair, PublicKey};
 use libp2p::kad::KademliaConfig;
+use libp2p::multiaddr::Protocol;
 use libp2p::ping::Config as PingConfig;
 use libp2p::swarm::NetworkBehaviour;
 use libp2p::{Multiaddr, PeerId};
 use libp2p::{StreamProtocol, Swarm};
+use serde::{Deserialize, Serialize};
 use tracing::Span;
 
 pub(crate) mod addr;
 pub(crate) mod addressbook;
+pub(crate) mod autorelay;
+mod dedup;
+mod dns;
+mod event_buffer;
+pub(crate) mod identify_request;
+mod idle;
 pub(crate) mod peerbook;
 pub mod protocol;
 
 mod behaviour;
 pub use self::addressbook::Config as AddressBookConfig;
+pub use self::autorelay::AutoRelayConfig;
 pub use self::behaviour::BehaviourEvent;
 pub use self::behaviour::IdentifyConfiguration;
-pub use self::behaviour::{BitswapConfig, BitswapProtocol};
-pub use self::behaviour::{KadConfig, KadInserts, KadStoreConfig};
+pub use self::behaviour::DEFAULT_PROVIDER_PUBLICATION_INTERVAL;
+pub use self::behaviour::{BitswapConfig, BitswapProtocol, WantBroadcastPolicy};
+pub use self::behaviour::{KadConfig, KadInserts, KadStoreConfig, ProviderKeyScheme};
+pub(crate) use self::behaviour::{ProvideQueue, ReprovideSchedule};
 pub use self::behaviour::{RateLimit, RelayConfig};
-pub use self::peerbook::ConnectionLimits;
+pub use self::dedup::ConnectionDedupPolicy;
+pub use self::dns::{CustomDnsError, CustomDnsResolver};
+pub use self::event_buffer::{
+    swarm_event_buffer, DropPolicy, SwarmEventReceiver, SwarmEventSender,
+};
+pub use self::peerbook::{ConnectionInfo, ConnectionLimitError, ConnectionLimits};
 pub use self::transport::{
-    DnsResolver, MultiPlexOption, TransportConfig, UpdateMode, UpgradeVersion,
+    DnsResolver, MultiPlexOption, MuxerProtocol, SecurityProtocol, TransportConfig, UpdateMode,
+    UpgradeVersion,
 };
+pub use beetle_bitswap_next::message::{BitswapMessage, Priority, WantType};
 pub(crate) mod gossipsub;
 mod transport;
 
@@ -106,6 +128,95 @@ impl From<IdentifyInfo> for PeerInfo {
     }
 }
 
+/// Transport a listener accepts connections over, derived from its addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TransportKind {
+    Tcp,
+    Quic,
+    WebSocket,
+    SecureWebSocket,
+    WebRtc,
+    Memory,
+    /// Listening through a relay circuit.
+    Relay,
+    Other,
+}
+
+impl From<&Multiaddr> for TransportKind {
+    fn from(addr: &Multiaddr) -> Self {
+        // the innermost transport protocol of the address wins, e.g. tcp is overridden by ws
+        let mut kind = TransportKind::Other;
+        for protocol in addr.iter() {
+            kind = match protocol {
+                Protocol::Tcp(_) => TransportKind::Tcp,
+                Protocol::Quic | Protocol::QuicV1 => TransportKind::Quic,
+                Protocol::Ws(_) => TransportKind::WebSocket,
+                Protocol::Wss(_) => TransportKind::SecureWebSocket,
+                Protocol::WebRTCDirect => TransportKind::WebRtc,
+                Protocol::Memory(_) => TransportKind::Memory,
+                Protocol::P2pCircuit => return TransportKind::Relay,
+                _ => continue,
+            };
+        }
+        kind
+    }
+}
+
+/// Describes one of the listeners of the node, see [`crate::Ipfs::listeners`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListenerDescriptor {
+    pub id: ListenerId,
+    pub transport: TransportKind,
+    /// The concrete addresses the listener is listening on.
+    pub addresses: Vec<Multiaddr>,
+}
+
+/// Occupancy of the kademlia routing table, see [`crate::Ipfs::kad_routing_table`].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct KadTableInfo {
+    /// The buckets holding any peers, ordered by their distance to the node.
+    pub buckets: Vec<KadBucketInfo>,
+    /// Number of peers in all of the buckets.
+    pub peers: usize,
+}
+
+/// One of the k-buckets of the kademlia routing table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KadBucketInfo {
+    /// Base-2 logarithm of the distance of the peers in the bucket, which are between
+    /// `2^distance` and `2^(distance + 1) - 1` away from the node.
+    pub distance: u32,
+    /// Number of peers in the bucket.
+    pub peers: usize,
+}
+
+/// Reservation the node holds with a relay, see [`crate::Ipfs::relay_reservations`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReservationInfo {
+    /// The relay holding the reservation.
+    pub relay: PeerId,
+    /// When the relay last accepted or renewed the reservation.
+    pub renewed_at: Instant,
+    /// Longest the relay keeps a circuit to the node open, if limited.
+    pub circuit_duration: Option<Duration>,
+    /// Most bytes the relay passes over a circuit to the node, if limited.
+    pub circuit_bytes: Option<u64>,
+}
+
+/// Circuit relayed by the node, see [`crate::Ipfs::relayed_circuits`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CircuitInfo {
+    /// The peer which opened the circuit.
+    pub src: PeerId,
+    /// The peer the circuit leads to, holding a reservation with the node.
+    pub dst: PeerId,
+    pub established_at: Instant,
+    /// When the circuit is closed at the latest.
+    pub expires_at: Instant,
+    /// Most bytes passed over the circuit before it is closed.
+    pub max_bytes: u64,
+}
+
 /// Defines the configuration for an IPFS swarm.
 pub struct SwarmOptions {
     /// The peers to connect to on startup.
@@ -118,6 +229,10 @@ pub struct SwarmOptions {
     pub disable_kad: bool,
     /// disable bitswap
     pub disable_bitswap: bool,
+    /// disable pubsub
+    pub disable_pubsub: bool,
+    /// disable identify
+    pub disable_identify: bool,
     /// Relay Server
     pub relay_server: bool,
     /// Relay Server Configuration
@@ -126,6 +241,8 @@ pub struct SwarmOptions {
     pub kad_config: Option<Either<KadConfig, KademliaConfig>>,
     /// Ping Configuration
     pub ping_config: Option<PingConfig>,
+    /// Autonat Configuration
+    pub autonat_config: Option<AutonatConfig>,
     /// bitswap config
     pub bitswap_config: Option<BitswapConfig>,
     /// identify configuration
@@ -141,10 +258,16 @@ pub struct SwarmOptions {
     pub portmapping: bool,
     /// Keep alive
     pub keep_alive: bool,
+    /// Closes connections without any activity for this long, even when kept alive
+    pub idle_connection_timeout: Option<Duration>,
+    /// Which of several connections to the same peer are kept
+    pub connection_dedup: ConnectionDedupPolicy,
     /// Relay client
     pub relay: bool,
     /// Enables dcutr
     pub dcutr: bool,
+    /// Resolves the host names of dns multiaddrs instead of the resolver of the transport
+    pub dns_resolver: Option<Arc<dyn CustomDnsResolver>>,
 }
 
 impl From<&IpfsOptions> for SwarmOptions {
@@ -158,22 +281,30 @@ impl From<&IpfsOptions> for SwarmOptions {
         let relay = options.relay;
         let kad_config = options.kad_configuration.clone();
         let ping_config = options.ping_configuration.clone();
+        let autonat_config = options.autonat_configuration.clone();
         let kad_store_config = options.kad_store_config.clone();
         let disable_kad = options.disable_kad;
         let disable_bitswap = options.disable_bitswap;
+        let disable_pubsub = options.disable_pubsub;
+        let disable_identify = options.disable_identify;
         let bitswap_config = options.bitswap_config.clone();
 
         let keep_alive = options.keep_alive;
+        let idle_connection_timeout = options.idle_connection_timeout;
+        let connection_dedup = options.connection_dedup;
         let identify_config = options.identify_configuration.clone();
         let portmapping = options.port_mapping;
         let pubsub_config = options.pubsub_config.clone();
         let addrbook_config = options.addr_config;
+        let dns_resolver = options.dns_resolver.clone();
 
         SwarmOptions {
             bootstrap,
             mdns,
             disable_kad,
             disable_bitswap,
+            disable_pubsub,
+            disable_identify,
             bitswap_config,
             mdns_ipv6,
             relay_server,
@@ -183,21 +314,27 @@ impl From<&IpfsOptions> for SwarmOptions {
             kad_config,
             kad_store_config,
             ping_config,
+            autonat_config,
             keep_alive,
+            idle_connection_timeout,
+            connection_dedup,
             identify_config,
             portmapping,
             addrbook_config,
             pubsub_config,
+            dns_resolver,
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
 pub struct PubsubConfig {
     /// Custom protocol name
     pub custom_protocol_id: Option<String>,
 
-    /// Max size that can be transmitted over gossipsub
+    /// Max size that can be transmitted over gossipsub. Local publishes of larger data fail with
+    /// [`crate::PubsubPublishError::MessageTooLarge`].
     pub max_transmit_size: usize,
 
     /// Floodsub compatibility
@@ -205,9 +342,13 @@ pub struct PubsubConfig {
 
     /// Validation
     pub validate: PubsubValidation,
+
+    /// Limits the rate of local publishes to each topic. Publishes exceeding it fail with
+    /// [`crate::PubsubPublishError::RateLimited`].
+    pub publish_rate: Option<RateLimit>,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum PubsubValidation {
     /// See [`ValidationMode::Strict`]
     Strict,
@@ -240,6 +381,7 @@ impl Default for PubsubConfig {
             max_transmit_size: 2 * 1024 * 1024,
             validate: PubsubValidation::Strict,
             floodsub_compat: false,
+            publish_rate: None,
         }
     }
 }
@@ -250,6 +392,9 @@ pub struct SwarmConfig {
     pub dial_concurrency_factor: NonZeroU8,
     pub notify_handler_buffer_size: NonZeroUsize,
     pub connection_event_buffer_size: usize,
+    /// Maximum number of inbound streams negotiating their protocol at once on a connection.
+    /// Streams opened by the peer beyond the limit are reset, so a peer opening a burst of
+    /// streams cannot stall the negotiation of the others.
     pub max_inbound_stream: usize,
 }
 
@@ -282,6 +427,7 @@ where
 {
     let keypair = keypair.clone();
     let peer_id = keypair.public().to_peer_id();
+    let dns_resolver = options.dns_resolver.clone();
 
     let (behaviour, relay_transport) =
         behaviour::build_behaviour(&keypair, options, repo, swarm_config.connection, custom)
@@ -290,7 +436,13 @@ where
     // Set up an encrypted TCP transport over the Yamux and Mplex protocol. If relay transport is supplied, that will be apart
     let transport = match custom_transport {
         Some(transport) => transport(&keypair, relay_transport)?,
-        None => transport::build_transport(keypair, relay_transport, transport_config)?,
+        None => transport::build_transport(
+            keypair,
+            relay_transport,
+            transport_config,
+            dns_resolver,
+            behaviour.peerbook.negotiations(),
+        )?,
     };
 
     // Create a Swarm
@@ -24,7 +24,9 @@ use wasm_timer::Interval;
 
 use std::collections::{HashMap, HashSet, VecDeque};
 
-#[derive(Debug, Clone, Copy, Default)]
+use super::transport::{MuxerProtocol, NegotiationLog, SecurityProtocol};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct ConnectionLimits {
     max_pending_incoming: Option<u32>,
     max_pending_outgoing: Option<u32>,
@@ -118,6 +120,8 @@ impl ConnectionLimits {
     }
 }
 
+/// Returned when a connection, or dialing a peer, would exceed the configured
+/// [`ConnectionLimits`].
 #[derive(Debug, thiserror::Error)]
 #[error("Limit: {limit}, Current: {current}")]
 pub struct ConnectionLimitError {
@@ -125,6 +129,29 @@ pub struct ConnectionLimitError {
     current: u32,
 }
 
+impl ConnectionLimitError {
+    pub fn limit(&self) -> u32 {
+        self.limit
+    }
+
+    pub fn current(&self) -> u32 {
+        self.current
+    }
+}
+
+/// Describes an established connection to a peer, see [`crate::Ipfs::connection_info`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// Address of the remote end of the connection.
+    pub address: Multiaddr,
+    /// Security protocol the connection was secured with, unknown for connections made through a
+    /// custom transport.
+    pub security: Option<SecurityProtocol>,
+    /// Stream multiplexer the connection negotiated, unknown for connections made through a
+    /// custom transport.
+    pub muxer: Option<MuxerProtocol>,
+}
+
 #[derive(Debug)]
 #[allow(clippy::type_complexity)]
 pub struct Behaviour {
@@ -144,8 +171,14 @@ pub struct Behaviour {
     peer_rtt: HashMap<PeerId, [Duration; 3]>,
     peer_connections: HashMap<PeerId, Vec<(ConnectionId, Multiaddr)>>,
 
+    // Protocols negotiated by the transport, see `NegotiationLog`
+    negotiations: NegotiationLog,
+    connection_protocols: HashMap<ConnectionId, (SecurityProtocol, MuxerProtocol)>,
+
     whitelist: HashSet<PeerId>,
 
+    peer_tags: HashMap<String, HashSet<PeerId>>,
+
     // For connection limits (took from libp2p pr)
     pending_inbound_connections: HashSet<ConnectionId>,
     pending_outbound_connections: HashSet<ConnectionId>,
@@ -172,7 +205,10 @@ impl Default for Behaviour {
             peer_info: Default::default(),
             peer_rtt: Default::default(),
             peer_connections: Default::default(),
+            negotiations: Default::default(),
+            connection_protocols: Default::default(),
             whitelist: Default::default(),
+            peer_tags: Default::default(),
             pending_inbound_connections: Default::default(),
             pending_outbound_connections: Default::default(),
             established_inbound_connections: Default::default(),
@@ -207,6 +243,19 @@ impl Behaviour {
     pub fn connect(&mut self, opt: impl Into<DialOpts>) -> oneshot::Receiver<anyhow::Result<()>> {
         let opts: DialOpts = opt.into();
         let (tx, rx) = oneshot::channel();
+
+        let is_whitelisted = opts
+            .get_peer_id()
+            .map(|peer_id| self.whitelist.contains(&peer_id))
+            .unwrap_or_default();
+
+        if !is_whitelisted {
+            if let Err(e) = self.check_outbound_limit() {
+                let _ = tx.send(Err(e.into()));
+                return rx;
+            }
+        }
+
         let id = opts.connection_id();
         self.events.push_back(ToSwarm::Dial { opts });
         self.pending_connections.insert(id, tx);
@@ -236,7 +285,19 @@ impl Behaviour {
     }
 
     pub fn set_connection_limit(&mut self, limit: ConnectionLimits) {
-        self.limits = limit;
+        self.update_connection_limit(limit);
+    }
+
+    /// Returns the limits currently applied to the connections.
+    pub fn connection_limit(&self) -> ConnectionLimits {
+        self.limits
+    }
+
+    /// Replaces the limits while the swarm is running, returning the previous ones. The new limits
+    /// apply to the subsequent connection attempts; the connections already established are kept
+    /// even if they exceed them.
+    pub fn update_connection_limit(&mut self, limit: ConnectionLimits) -> ConnectionLimits {
+        std::mem::replace(&mut self.limits, limit)
     }
 
     pub fn add(&mut self, peer_id: PeerId) {
@@ -247,6 +308,27 @@ impl Behaviour {
         self.whitelist.remove(&peer_id);
     }
 
+    /// Attaches an application defined tag to the peer. Tags have no effect on the connections.
+    pub fn tag_peer(&mut self, peer_id: PeerId, tag: String) {
+        self.peer_tags.entry(tag).or_default().insert(peer_id);
+    }
+
+    pub fn untag_peer(&mut self, peer_id: PeerId, tag: &str) {
+        if let Some(peers) = self.peer_tags.get_mut(tag) {
+            peers.remove(&peer_id);
+            if peers.is_empty() {
+                self.peer_tags.remove(tag);
+            }
+        }
+    }
+
+    pub fn peers_with_tag(&self, tag: &str) -> Vec<PeerId> {
+        self.peer_tags
+            .get(tag)
+            .map(|peers| peers.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
     pub fn inject_peer_info(&mut self, info: Info) {
         let peer_id = info.public_key.to_peer_id();
         self.peer_info.insert(peer_id, info);
@@ -305,6 +387,53 @@ impl Behaviour {
             .map(|list| list.iter().map(|(_, addr)| addr).cloned().collect())
     }
 
+    /// Describes the established connections to the peer.
+    pub fn connection_info(&self, peer_id: PeerId) -> Vec<ConnectionInfo> {
+        let Some(list) = self.peer_connections.get(&peer_id) else {
+            return vec![];
+        };
+
+        list.iter()
+            .map(|(id, address)| {
+                let negotiated = self.connection_protocols.get(id);
+                ConnectionInfo {
+                    address: address.clone(),
+                    security: negotiated.map(|(security, _)| *security),
+                    muxer: negotiated.map(|(_, muxer)| *muxer),
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the log the transport records the negotiated protocols of the connections to.
+    pub(crate) fn negotiations(&self) -> NegotiationLog {
+        self.negotiations.clone()
+    }
+
+    /// Checks the dials which are still in progress along with the established outbound
+    /// connections against the limits before issuing another dial.
+    fn check_outbound_limit(&self) -> Result<(), ConnectionLimitError> {
+        let dialing = self.pending_connections.len();
+        let established = self.established_outbound_connections.len();
+
+        for (limit, current) in [
+            (self.limits.max_pending_outgoing, dialing),
+            (self.limits.max_established_outgoing, established + dialing),
+        ] {
+            let Some(limit) = limit else {
+                continue;
+            };
+
+            let current = current as u32;
+
+            if current >= limit {
+                return Err(ConnectionLimitError { limit, current });
+            }
+        }
+
+        Ok(())
+    }
+
     fn check_limit(&mut self, limit: Option<u32>, current: usize) -> Result<(), ConnectionDenied> {
         let limit = limit.unwrap_or(u32::MAX);
         let current = current as u32;
@@ -370,9 +499,11 @@ impl NetworkBehaviour for Behaviour {
         connection_id: ConnectionId,
         peer_id: PeerId,
         _: &Multiaddr,
-        _: &Multiaddr,
+        remote_addr: &Multiaddr,
     ) -> Result<THandler<Self>, ConnectionDenied> {
         self.pending_inbound_connections.remove(&connection_id);
+        // taken whether the connection is accepted or not, so that denied ones do not linger
+        let negotiated = self.negotiations.take(peer_id, remote_addr);
 
         if !self.whitelist.contains(&peer_id) {
             self.check_limit(
@@ -393,6 +524,10 @@ impl NetworkBehaviour for Behaviour {
             )?;
         }
 
+        if let Some(negotiated) = negotiated {
+            self.connection_protocols.insert(connection_id, negotiated);
+        }
+
         Ok(DummyConnectionHandler)
     }
 
@@ -400,10 +535,12 @@ impl NetworkBehaviour for Behaviour {
         &mut self,
         connection_id: ConnectionId,
         peer_id: PeerId,
-        _: &Multiaddr,
+        addr: &Multiaddr,
         _: Endpoint,
     ) -> Result<THandler<Self>, ConnectionDenied> {
         self.pending_outbound_connections.remove(&connection_id);
+        // taken whether the connection is accepted or not, so that denied ones do not linger
+        let negotiated = self.negotiations.take(peer_id, addr);
 
         if !self.whitelist.contains(&peer_id) {
             self.check_limit(
@@ -424,6 +561,10 @@ impl NetworkBehaviour for Behaviour {
             )?;
         }
 
+        if let Some(negotiated) = negotiated {
+            self.connection_protocols.insert(connection_id, negotiated);
+        }
+
         Ok(DummyConnectionHandler)
     }
 
@@ -464,6 +605,7 @@ impl NetworkBehaviour for Behaviour {
                         }
                     }
                 }
+
                 let multiaddr = match endpoint {
                     ConnectedPoint::Dialer { address, .. } => {
                         self.established_outbound_connections.insert(connection_id);
@@ -491,6 +633,8 @@ impl NetworkBehaviour for Behaviour {
                 ..
             }) => {
                 self.pending_outbound_connections.remove(&connection_id);
+                // denied by a behaviour after this one
+                self.connection_protocols.remove(&connection_id);
                 if let Some(ch) = self.pending_connections.remove(&connection_id) {
                     let _ = ch.send(Err(anyhow::anyhow!("{error}")));
                 }
@@ -502,6 +646,7 @@ impl NetworkBehaviour for Behaviour {
             }) => {
                 self.established_inbound_connections.remove(&connection_id);
                 self.established_outbound_connections.remove(&connection_id);
+                self.connection_protocols.remove(&connection_id);
                 self.established_per_peer
                     .entry(peer_id)
                     .or_default()
@@ -534,6 +679,7 @@ impl NetworkBehaviour for Behaviour {
             }
             FromSwarm::ListenFailure(ListenFailure { connection_id, .. }) => {
                 self.pending_inbound_connections.remove(&connection_id);
+                self.connection_protocols.remove(&connection_id);
             }
             _ => {}
         }
@@ -579,7 +725,10 @@ impl NetworkBehaviour for Behaviour {
 #[cfg(test)]
 mod test {
     use super::Behaviour as PeerBook;
-    use crate::p2p::{peerbook::ConnectionLimits, transport::build_transport};
+    use crate::p2p::{
+        peerbook::{ConnectionLimitError, ConnectionLimits},
+        transport::build_transport,
+    };
     use futures::StreamExt;
     use libp2p::{
         identify::{self, Config},
@@ -666,6 +815,153 @@ mod test {
         assert!(!list.contains(&peer4));
     }
 
+    #[tokio::test]
+    async fn raised_connection_limit_accepts_new_connections() {
+        let (_, addr1, mut swarm1) = build_swarm(false).await;
+        let (peer2, _, mut swarm2) = build_swarm(false).await;
+        let (peer3, _, mut swarm3) = build_swarm(false).await;
+        let (peer4, _, mut swarm4) = build_swarm(false).await;
+
+        let limits = ConnectionLimits::default().with_max_established_incoming(Some(1));
+        swarm1.behaviour_mut().peerbook.set_connection_limit(limits);
+        assert_eq!(swarm1.behaviour().peerbook.connection_limit(), limits);
+
+        let mut oneshot = swarm2.behaviour_mut().peerbook.connect(addr1.clone());
+
+        loop {
+            tokio::select! {
+                biased;
+                _ = swarm1.next() => {},
+                _ = swarm2.next() => {},
+                conn_res = (&mut oneshot) => {
+                    conn_res.unwrap().unwrap();
+                    break;
+                }
+            }
+        }
+
+        let mut oneshot = swarm3.behaviour_mut().peerbook.connect(addr1.clone());
+
+        loop {
+            tokio::select! {
+                biased;
+                e = swarm1.select_next_some() => {
+                    if matches!(e, SwarmEvent::IncomingConnectionError { .. }) {
+                        break;
+                    }
+                },
+                _ = swarm3.next() => {},
+                conn_res = (&mut oneshot) => {
+                    assert!(conn_res.unwrap().is_err());
+                    break;
+                }
+            }
+        }
+
+        assert!(!swarm1.is_connected(&peer3));
+
+        let raised = limits.with_max_established_incoming(Some(2));
+        let previous = swarm1
+            .behaviour_mut()
+            .peerbook
+            .update_connection_limit(raised);
+        assert_eq!(previous, limits);
+        assert_eq!(swarm1.behaviour().peerbook.connection_limit(), raised);
+
+        let mut oneshot = swarm4.behaviour_mut().peerbook.connect(addr1.clone());
+
+        loop {
+            tokio::select! {
+                biased;
+                _ = swarm1.next() => {},
+                _ = swarm4.next() => {},
+                conn_res = (&mut oneshot) => {
+                    conn_res.unwrap().unwrap();
+                    break;
+                }
+            }
+        }
+
+        let list = swarm1.connected_peers().copied().collect::<Vec<_>>();
+
+        assert!(list.contains(&peer2));
+        assert!(list.contains(&peer4));
+    }
+
+    #[tokio::test]
+    async fn outbound_connection_limits() {
+        let (_, _, mut swarm1) = build_swarm(false).await;
+        let (_, addr2, mut swarm2) = build_swarm(false).await;
+        let (_, addr3, _) = build_swarm(false).await;
+        let (_, addr4, _) = build_swarm(false).await;
+
+        swarm1
+            .behaviour_mut()
+            .peerbook
+            .set_connection_limit(ConnectionLimits {
+                max_established_outgoing: Some(1),
+                ..Default::default()
+            });
+
+        let mut oneshot = swarm1.behaviour_mut().peerbook.connect(addr2);
+
+        for addr in [addr3, addr4] {
+            let result = swarm1.behaviour_mut().peerbook.connect(addr).await.unwrap();
+
+            let error = result.unwrap_err();
+            let reached = error
+                .downcast_ref::<ConnectionLimitError>()
+                .expect("dial is refused");
+            assert_eq!(reached.limit(), 1);
+        }
+
+        loop {
+            tokio::select! {
+                biased;
+                _ = swarm1.next() => {},
+                _ = swarm2.next() => {},
+                conn_res = (&mut oneshot) => {
+                    conn_res.unwrap().unwrap();
+                    break;
+                }
+            }
+        }
+
+        assert_eq!(swarm1.connected_peers().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn denied_connections_do_not_keep_their_negotiations() {
+        let (_, addr1, mut swarm1) = build_swarm(false).await;
+        let (_, _, mut swarm2) = build_swarm(false).await;
+
+        swarm1
+            .behaviour_mut()
+            .peerbook
+            .set_connection_limit(ConnectionLimits {
+                max_established_incoming: Some(0),
+                ..Default::default()
+            });
+
+        let mut oneshot = swarm2.behaviour_mut().peerbook.connect(addr1);
+
+        loop {
+            tokio::select! {
+                biased;
+                e = swarm1.select_next_some() => {
+                    if matches!(e, SwarmEvent::IncomingConnectionError { .. }) {
+                        break;
+                    }
+                },
+                _ = swarm2.next() => {},
+                _ = (&mut oneshot) => {}
+            }
+        }
+
+        assert!(swarm1.behaviour().peerbook.negotiations().is_empty());
+        assert!(swarm1.behaviour().peerbook.connection_protocols.is_empty());
+    }
+
     #[tokio::test]
     async fn connect_without_identify() {
         let (_, addr1, mut swarm1) = build_swarm(false).await;
@@ -811,10 +1107,12 @@ mod test {
         let key = Keypair::generate_ed25519();
         let pubkey = key.public();
         let peer_id = pubkey.to_peer_id();
-        let transport = build_transport(key, None, Default::default()).unwrap();
+        let peerbook = PeerBook::default();
+        let transport =
+            build_transport(key, None, Default::default(), None, peerbook.negotiations()).unwrap();
 
         let behaviour = Behaviour {
-            peerbook: PeerBook::default(),
+            peerbook,
             identify: Toggle::from(identify.then_some(identify::Behaviour::new(Config::new(
                 "/peerbook/0.1".into(),
                 pubkey,
@@ -5,23 +5,31 @@ use libp2p::core::transport::timeout::TransportTimeout;
 use libp2p::core::transport::upgrade::Version;
 use libp2p::core::transport::{Boxed, MemoryTransport, OrTransport};
 use libp2p::core::upgrade::SelectUpgrade;
+use libp2p::core::ConnectedPoint;
 use libp2p::dns::{ResolverConfig, ResolverOpts, TokioDnsConfig};
+use libp2p::quic::tokio::Transport as TokioQuicTransport;
+use libp2p::quic::Config as QuicConfig;
 use libp2p::relay::client::Transport as ClientTransport;
 use libp2p::tcp::{tokio::Transport as TokioTcpTransport, Config as GenTcpConfig};
 use libp2p::yamux::{Config as YamuxConfig, WindowUpdateMode};
 use libp2p::{identity, noise};
-use libp2p::{PeerId, Transport};
+use libp2p::{Multiaddr, PeerId, Transport};
 use libp2p_mplex::MplexConfig;
-use libp2p::quic::tokio::Transport as TokioQuicTransport;
-use libp2p::quic::Config as QuicConfig;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
 use std::io::{self, ErrorKind};
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use trust_dns_resolver::system_conf;
 
+use super::dns::{CustomDnsResolver, CustomDnsTransport};
+
 /// Transport type.
 pub(crate) type TTransport = Boxed<(PeerId, StreamMuxerBox)>;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
 pub struct TransportConfig {
     pub yamux_max_buffer_size: usize,
     pub yamux_receive_window_size: u32,
@@ -40,7 +48,90 @@ pub struct TransportConfig {
     pub enable_webrtc: bool,
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Security protocol a connection was encrypted and authenticated with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SecurityProtocol {
+    Noise,
+    /// TLS 1.3, as built into QUIC.
+    Tls,
+}
+
+/// Stream multiplexer a connection negotiated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MuxerProtocol {
+    Yamux,
+    Mplex,
+    /// The streams native to QUIC.
+    Quic,
+}
+
+/// How long the protocols negotiated by a connection are kept for the behaviours to take them.
+/// The connections denied before they reach the peerbook never have theirs taken.
+const NEGOTIATION_TTL: Duration = Duration::from_secs(60);
+
+type Negotiated = VecDeque<(Instant, SecurityProtocol, MuxerProtocol)>;
+
+/// Protocols negotiated by the connections upgraded by the transport, kept until the swarm
+/// hands the established connections to the behaviours.
+#[derive(Debug, Clone)]
+pub(crate) struct NegotiationLog {
+    inner: Arc<Mutex<HashMap<(PeerId, Multiaddr), Negotiated>>>,
+    ttl: Duration,
+}
+
+impl Default for NegotiationLog {
+    fn default() -> Self {
+        Self {
+            inner: Default::default(),
+            ttl: NEGOTIATION_TTL,
+        }
+    }
+}
+
+impl NegotiationLog {
+    fn record(
+        &self,
+        peer_id: PeerId,
+        endpoint: &ConnectedPoint,
+        security: SecurityProtocol,
+        muxer: MuxerProtocol,
+    ) {
+        let addr = endpoint.get_remote_address().clone();
+        let now = Instant::now();
+        let mut inner = self.inner.lock();
+        inner.retain(|_, negotiated| {
+            negotiated.retain(|(at, ..)| now.duration_since(*at) < self.ttl);
+            !negotiated.is_empty()
+        });
+        inner
+            .entry((peer_id, addr))
+            .or_default()
+            .push_back((now, security, muxer));
+    }
+
+    /// Takes the protocols negotiated by the connection to `peer_id` at `remote_addr`.
+    pub(crate) fn take(
+        &self,
+        peer_id: PeerId,
+        remote_addr: &Multiaddr,
+    ) -> Option<(SecurityProtocol, MuxerProtocol)> {
+        let key = (peer_id, remote_addr.clone());
+        let mut inner = self.inner.lock();
+        let negotiated = inner.get_mut(&key)?;
+        let taken = negotiated.pop_front();
+        if negotiated.is_empty() {
+            inner.remove(&key);
+        }
+        taken.map(|(_, security, muxer)| (security, muxer))
+    }
+
+    #[cfg(test)]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.inner.lock().is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum MultiPlexOption {
     Yamux,
     YmuxAndMplex,
@@ -68,7 +159,9 @@ impl Default for TransportConfig {
     }
 }
 
-#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(
+    Default, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
 pub enum DnsResolver {
     /// Google DNS Resolver
     Google,
@@ -92,7 +185,9 @@ impl From<DnsResolver> for (ResolverConfig, ResolverOpts) {
     }
 }
 
-#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(
+    Default, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
 pub enum UpdateMode {
     /// See [`WindowUpdateMode::on_receive`]
     #[default]
@@ -111,7 +206,9 @@ impl From<UpdateMode> for WindowUpdateMode {
     }
 }
 
-#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(
+    Default, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
 pub enum UpgradeVersion {
     /// See [`Version::V1`]
     #[default]
@@ -151,6 +248,8 @@ pub(crate) fn build_transport(
         support_quic_draft_29,
         ..
     }: TransportConfig,
+    custom_dns_resolver: Option<Arc<dyn CustomDnsResolver>>,
+    negotiations: NegotiationLog,
 ) -> io::Result<TTransport> {
     let noise_config =
         noise::Config::new(&keypair).map_err(|e| io::Error::new(ErrorKind::Other, e))?;
@@ -194,7 +293,10 @@ pub(crate) fn build_transport(
 
     let transport = TokioDnsConfig::custom(transport_timeout, cfg, opts)?;
 
+    let transport = CustomDnsTransport::new(transport, custom_dns_resolver);
+
     let version = version.unwrap_or_default();
+    let log = negotiations.clone();
 
     let transport = match relay {
         Some(relay) => {
@@ -205,7 +307,11 @@ pub(crate) fn build_transport(
                 .authenticate(noise_config)
                 .multiplex(multiplex_upgrade)
                 .timeout(timeout)
-                .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)))
+                .map(move |(peer_id, muxer), endpoint| {
+                    let muxer_protocol = muxer_protocol(&muxer);
+                    log.record(peer_id, &endpoint, SecurityProtocol::Noise, muxer_protocol);
+                    (peer_id, StreamMuxerBox::new(muxer))
+                })
                 .boxed()
         }
         None => transport
@@ -213,7 +319,11 @@ pub(crate) fn build_transport(
             .authenticate(noise_config)
             .multiplex(multiplex_upgrade)
             .timeout(timeout)
-            .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)))
+            .map(move |(peer_id, muxer), endpoint| {
+                let muxer_protocol = muxer_protocol(&muxer);
+                log.record(peer_id, &endpoint, SecurityProtocol::Noise, muxer_protocol);
+                (peer_id, StreamMuxerBox::new(muxer))
+            })
             .boxed(),
     };
 
@@ -225,8 +335,16 @@ pub(crate) fn build_transport(
             let quic_transport = TokioQuicTransport::new(quic_config);
 
             OrTransport::new(quic_transport, transport)
-                .map(|either_output, _| match either_output {
-                    FutureEither::Left((peer_id, muxer)) => (peer_id, StreamMuxerBox::new(muxer)),
+                .map(move |either_output, endpoint| match either_output {
+                    FutureEither::Left((peer_id, muxer)) => {
+                        negotiations.record(
+                            peer_id,
+                            &endpoint,
+                            SecurityProtocol::Tls,
+                            MuxerProtocol::Quic,
+                        );
+                        (peer_id, StreamMuxerBox::new(muxer))
+                    }
                     FutureEither::Right((peer_id, muxer)) => (peer_id, StreamMuxerBox::new(muxer)),
                 })
                 .boxed()
@@ -237,6 +355,14 @@ pub(crate) fn build_transport(
     Ok(transport)
 }
 
+/// Tells the muxer selected by the multiplex upgrade of [`build_transport`].
+fn muxer_protocol<Y, M>(muxer: &FutureEither<Y, FutureEither<Y, M>>) -> MuxerProtocol {
+    match muxer {
+        FutureEither::Left(_) | FutureEither::Right(FutureEither::Left(_)) => MuxerProtocol::Yamux,
+        FutureEither::Right(FutureEither::Right(_)) => MuxerProtocol::Mplex,
+    }
+}
+
 #[allow(dead_code)]
 pub(crate) fn memory_transport(
     keypair: &identity::Keypair,
@@ -262,3 +388,47 @@ pub(crate) fn memory_transport(
 
     Ok(transport)
 }
+
+#[cfg(test)]
+mod tests {
+    use libp2p::core::{ConnectedPoint, Endpoint};
+    use libp2p::PeerId;
+    use std::time::Duration;
+
+    use super::{MuxerProtocol, NegotiationLog, SecurityProtocol};
+
+    #[test]
+    fn negotiations_never_taken_expire() {
+        let log = NegotiationLog {
+            ttl: Duration::ZERO,
+            ..Default::default()
+        };
+        let endpoint = ConnectedPoint::Dialer {
+            address: "/ip4/127.0.0.1/tcp/4001".parse().unwrap(),
+            role_override: Endpoint::Dialer,
+        };
+
+        // as if an earlier behaviour had denied the first connection
+        let denied = PeerId::random();
+        log.record(
+            denied,
+            &endpoint,
+            SecurityProtocol::Noise,
+            MuxerProtocol::Yamux,
+        );
+        let peer_id = PeerId::random();
+        log.record(
+            peer_id,
+            &endpoint,
+            SecurityProtocol::Noise,
+            MuxerProtocol::Yamux,
+        );
+
+        assert_eq!(log.take(denied, endpoint.get_remote_address()), None);
+        assert_eq!(
+            log.take(peer_id, endpoint.get_remote_address()),
+            Some((SecurityProtocol::Noise, MuxerProtocol::Yamux))
+        );
+        assert!(log.is_empty());
+    }
+}
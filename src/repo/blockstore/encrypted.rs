@@ -0,0 +1,177 @@
+//! Block store encrypting the block data before handing it to another store.
+use crate::error::Error;
+use crate::repo::{BlockPut, BlockRm, BlockRmError, BlockStore};
+use crate::Block;
+use async_trait::async_trait;
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use libipld::Cid;
+use rand::RngCore;
+use std::fmt;
+use std::sync::Arc;
+
+const NONCE_LEN: usize = 12;
+
+/// Describes a block store which encrypts the data of the blocks with ChaCha20-Poly1305 before
+/// storing them in `inner`, and decrypts them when read.
+///
+/// The blocks keep their original Cid, which is computed over the plaintext, so the content
+/// addressing is unaffected. The Cid is authenticated along with the data, so a block moved under
+/// another Cid fails to decrypt. The encrypted data is read from `inner` with
+/// [`BlockStore::get_raw`], and only [`BlockStore::get`] verifies the decrypted data.
+pub struct EncryptedBlockStore {
+    inner: Arc<dyn BlockStore>,
+    cipher: ChaCha20Poly1305,
+}
+
+impl fmt::Debug for EncryptedBlockStore {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("EncryptedBlockStore")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl EncryptedBlockStore {
+    /// Creates a store encrypting the blocks stored in `inner` with the given `key`.
+    pub fn new(inner: Arc<dyn BlockStore>, key: [u8; 32]) -> Self {
+        EncryptedBlockStore {
+            inner,
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&key)),
+        }
+    }
+
+    fn seal(&self, block: &Block) -> Result<Block, Error> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let aad = block.cid().to_bytes();
+        let payload = Payload {
+            msg: block.data(),
+            aad: &aad,
+        };
+        let sealed = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), payload)
+            .map_err(|_| anyhow::anyhow!("failed to encrypt block {}", block.cid()))?;
+
+        let mut data = Vec::with_capacity(NONCE_LEN + sealed.len());
+        data.extend_from_slice(&nonce);
+        data.extend_from_slice(&sealed);
+        Ok(Block::new_unchecked(*block.cid(), data))
+    }
+
+    fn open_sealed(&self, block: Block) -> Result<Block, Error> {
+        let (cid, data) = block.into_inner();
+        if data.len() < NONCE_LEN {
+            anyhow::bail!("encrypted block {cid} is truncated");
+        }
+
+        let (nonce, sealed) = data.split_at(NONCE_LEN);
+        let aad = cid.to_bytes();
+        let payload = Payload {
+            msg: sealed,
+            aad: &aad,
+        };
+        let data = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| anyhow::anyhow!("failed to decrypt block {cid}"))?;
+
+        Ok(Block::new_unchecked(cid, data))
+    }
+}
+
+#[async_trait]
+impl BlockStore for EncryptedBlockStore {
+    async fn init(&self) -> Result<(), Error> {
+        self.inner.init().await
+    }
+
+    async fn open(&self) -> Result<(), Error> {
+        self.inner.open().await
+    }
+
+    async fn contains(&self, cid: &Cid) -> Result<bool, Error> {
+        self.inner.contains(cid).await
+    }
+
+    async fn get(&self, cid: &Cid) -> Result<Option<Block>, Error> {
+        match self.get_raw(cid).await? {
+            Some(block) => {
+                let (cid, data) = block.into_inner();
+                Block::new(cid, data).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the decrypted data, which is not the plain block data when another store wrapping
+    /// this one has transformed it before it was encrypted.
+    async fn get_raw(&self, cid: &Cid) -> Result<Option<Block>, Error> {
+        match self.inner.get_raw(cid).await? {
+            Some(block) => self.open_sealed(block).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    async fn put(&self, block: Block) -> Result<(Cid, BlockPut), Error> {
+        if self.inner.contains(block.cid()).await? {
+            return Ok((*block.cid(), BlockPut::Existed));
+        }
+        self.inner.put(self.seal(&block)?).await
+    }
+
+    async fn remove(&self, cid: &Cid) -> Result<Result<BlockRm, BlockRmError>, Error> {
+        self.inner.remove(cid).await
+    }
+
+    async fn list(&self) -> Result<Vec<Cid>, Error> {
+        self.inner.list().await
+    }
+
+    async fn wipe(&self) {
+        self.inner.wipe().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::blockstore::flatfs::FsBlockStore;
+    use libipld::{
+        multihash::{Code, MultihashDigest},
+        IpldCodec,
+    };
+
+    #[tokio::test]
+    async fn blocks_are_encrypted_at_rest() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let inner = Arc::new(FsBlockStore::new(tmp.path().into()));
+        let store = EncryptedBlockStore::new(inner.clone(), [7; 32]);
+        store.init().await.unwrap();
+        store.open().await.unwrap();
+
+        let data = b"secret block data".to_vec();
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+        let block = Block::new(cid, data.clone()).unwrap();
+
+        assert_eq!(
+            store.put(block.clone()).await.unwrap().1,
+            BlockPut::NewBlock
+        );
+        assert_eq!(store.put(block.clone()).await.unwrap().1, BlockPut::Existed);
+
+        let stored = inner.get_raw(&cid).await.unwrap().unwrap();
+        assert_ne!(stored.data(), &data[..]);
+        assert!(!stored
+            .data()
+            .windows(data.len())
+            .any(|window| window == &data[..]));
+
+        assert_eq!(store.get(&cid).await.unwrap(), Some(block));
+
+        let other_key = EncryptedBlockStore::new(inner, [8; 32]);
+        assert!(other_key.get(&cid).await.is_err());
+    }
+}
@@ -142,7 +142,33 @@ impl BlockStore for FsBlockStore {
         Ok(metadata.is_file())
     }
 
+    async fn size(&self, cid: &Cid) -> Result<Option<usize>, Error> {
+        if let WriteCompletion::KnownBad = self.write_completion(cid).await {
+            return Ok(None);
+        }
+
+        let path = block_path(self.path.clone(), cid);
+
+        let metadata = match fs::metadata(path).await {
+            Ok(m) => m,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(metadata.is_file().then_some(metadata.len() as usize))
+    }
+
     async fn get(&self, cid: &Cid) -> Result<Option<Block>, Error> {
+        match self.get_raw(cid).await? {
+            Some(block) => {
+                let (cid, data) = block.into_inner();
+                Block::new(cid, data).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn get_raw(&self, cid: &Cid) -> Result<Option<Block>, Error> {
         let span = tracing::trace_span!("get block", cid = %cid);
 
         async move {
@@ -169,7 +195,7 @@ impl BlockStore for FsBlockStore {
 
                 let mut data = Vec::with_capacity(len as usize);
                 file.read_to_end(&mut data)?;
-                let block = Block::new(cid, data)?;
+                let block = Block::new_unchecked(cid, data);
                 Ok(Some(block))
             })
             .await?
@@ -497,6 +523,24 @@ mod tests {
         std::fs::remove_dir_all(tmp).ok();
     }
 
+    #[tokio::test]
+    async fn corrupted_block_is_not_returned() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let store = FsBlockStore::new(tmp.path().into());
+        store.init().await.unwrap();
+        store.open().await.unwrap();
+
+        let data = b"1".to_vec();
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+        store.put(Block::new(cid, data).unwrap()).await.unwrap();
+
+        std::fs::write(block_path(tmp.path().into(), &cid), b"2").unwrap();
+
+        assert!(store.get(&cid).await.is_err());
+        let raw = store.get_raw(&cid).await.unwrap().unwrap();
+        assert_eq!(raw.data(), b"2");
+    }
+
     #[tokio::test]
     async fn test_fs_blockstore_open() {
         let mut tmp = temp_dir();
@@ -546,6 +590,36 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn open_with_recovery_removes_corrupt_blocks() {
+        let tmp = tempfile::TempDir::new().unwrap();
+
+        let repo = crate::repo::Repo::new_fs(tmp.path());
+        repo.init().await.unwrap();
+
+        let mut blocks = Vec::new();
+        for data in &[b"1", b"2"] {
+            let data = data.to_vec();
+            let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+            let block = Block::new(cid, data).unwrap();
+            repo.put_block(block.clone()).await.unwrap();
+            blocks.push(block);
+        }
+
+        let (good, corrupt) = (blocks[0].cid(), blocks[1].cid());
+
+        // truncate the block as if the write had been interrupted
+        let path = block_path(tmp.path().join("blockstore"), corrupt);
+        std::fs::write(path, b"").unwrap();
+
+        let repo = crate::repo::Repo::new_fs(tmp.path());
+        let report = repo.open_with_recovery(true).await.unwrap();
+
+        assert_eq!(report.removed, vec![*corrupt]);
+        assert!(!repo.contains(corrupt).await.unwrap());
+        assert!(repo.contains(good).await.unwrap());
+    }
+
     #[tokio::test]
     async fn race_to_insert_new() {
         // FIXME: why not tempdir?
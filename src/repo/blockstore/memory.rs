@@ -40,6 +40,16 @@ impl BlockStore for MemBlockStore {
         Ok(contains)
     }
 
+    async fn size(&self, cid: &Cid) -> Result<Option<usize>, Error> {
+        let size = self
+            .blocks
+            .lock()
+            .await
+            .get(cid)
+            .map(|block| block.data().len());
+        Ok(size)
+    }
+
     async fn get(&self, cid: &Cid) -> Result<Option<Block>, Error> {
         let block = self
             .blocks
@@ -1,2 +1,5 @@
+pub mod encrypted;
 pub mod flatfs;
 pub mod memory;
+pub mod tiered;
+pub mod transform;
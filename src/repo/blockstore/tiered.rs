@@ -0,0 +1,263 @@
+//! Block store keeping the recently used blocks on a fast store and the rest on a slower one.
+use crate::error::Error;
+use crate::repo::{BlockPut, BlockRm, BlockRmError, BlockStore};
+use crate::Block;
+use async_trait::async_trait;
+use libipld::Cid;
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+
+/// Describes a block store split in a hot and a cold tier.
+///
+/// New blocks are written to the hot tier, and blocks read from the cold tier are promoted to the
+/// hot tier. Once the hot tier holds more than `hot_capacity` blocks, the least recently used
+/// blocks are demoted to the cold tier.
+#[derive(Debug)]
+pub struct TieredStore {
+    hot: Arc<dyn BlockStore>,
+    cold: Arc<dyn BlockStore>,
+    hot_capacity: usize,
+    recency: Mutex<Recency>,
+}
+
+/// Tracks the order in which the blocks of the hot tier were last used.
+#[derive(Debug, Default)]
+struct Recency {
+    tick: u64,
+    by_cid: HashMap<Cid, u64>,
+    by_tick: BTreeMap<u64, Cid>,
+}
+
+impl Recency {
+    fn touch(&mut self, cid: Cid) {
+        self.tick += 1;
+        if let Some(previous) = self.by_cid.insert(cid, self.tick) {
+            self.by_tick.remove(&previous);
+        }
+        self.by_tick.insert(self.tick, cid);
+    }
+
+    fn remove(&mut self, cid: &Cid) {
+        if let Some(tick) = self.by_cid.remove(cid) {
+            self.by_tick.remove(&tick);
+        }
+    }
+
+    /// Removes and returns the least recently used block once there are more than `capacity`.
+    fn pop_overflow(&mut self, capacity: usize) -> Option<Cid> {
+        if self.by_cid.len() <= capacity {
+            return None;
+        }
+        let (_, cid) = self.by_tick.pop_first()?;
+        self.by_cid.remove(&cid);
+        Some(cid)
+    }
+
+    fn clear(&mut self) {
+        self.by_cid.clear();
+        self.by_tick.clear();
+    }
+}
+
+impl TieredStore {
+    /// Creates a store keeping at most `hot_capacity` blocks in `hot`, and the rest in `cold`.
+    pub fn new(hot: Arc<dyn BlockStore>, cold: Arc<dyn BlockStore>, hot_capacity: usize) -> Self {
+        TieredStore {
+            hot,
+            cold,
+            hot_capacity: hot_capacity.max(1),
+            recency: Default::default(),
+        }
+    }
+
+    /// Returns whether the block is currently in the hot tier.
+    pub async fn is_hot(&self, cid: &Cid) -> Result<bool, Error> {
+        self.hot.contains(cid).await
+    }
+
+    /// Reads the block from either tier, promoting it to the hot tier when found in the cold one.
+    async fn fetch(&self, cid: &Cid, raw: bool) -> Result<Option<Block>, Error> {
+        let hot = match raw {
+            true => self.hot.get_raw(cid).await?,
+            false => self.hot.get(cid).await?,
+        };
+        if let Some(block) = hot {
+            self.recency.lock().touch(*cid);
+            return Ok(Some(block));
+        }
+
+        let cold = match raw {
+            true => self.cold.get_raw(cid).await?,
+            false => self.cold.get(cid).await?,
+        };
+        match cold {
+            Some(block) => {
+                self.promote(block.clone()).await?;
+                Ok(Some(block))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Moves a block read from the cold tier to the hot tier.
+    async fn promote(&self, block: Block) -> Result<(), Error> {
+        let cid = *block.cid();
+        self.hot.put(block).await?;
+        self.cold.remove(&cid).await?.ok();
+        self.recency.lock().touch(cid);
+        self.demote_overflow().await
+    }
+
+    /// Moves the least recently used blocks to the cold tier until the hot tier fits.
+    async fn demote_overflow(&self) -> Result<(), Error> {
+        loop {
+            let cid = match self.recency.lock().pop_overflow(self.hot_capacity) {
+                Some(cid) => cid,
+                None => return Ok(()),
+            };
+
+            if let Some(block) = self.hot.get_raw(&cid).await? {
+                trace!(cid = %cid, "demoting block");
+                self.cold.put(block).await?;
+                self.hot.remove(&cid).await?.ok();
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl BlockStore for TieredStore {
+    async fn init(&self) -> Result<(), Error> {
+        self.hot.init().await?;
+        self.cold.init().await
+    }
+
+    async fn open(&self) -> Result<(), Error> {
+        self.hot.open().await?;
+        self.cold.open().await?;
+
+        let cids = self.hot.list().await?;
+        {
+            let mut recency = self.recency.lock();
+            recency.clear();
+            for cid in cids {
+                recency.touch(cid);
+            }
+        }
+
+        self.demote_overflow().await
+    }
+
+    async fn contains(&self, cid: &Cid) -> Result<bool, Error> {
+        Ok(self.hot.contains(cid).await? || self.cold.contains(cid).await?)
+    }
+
+    async fn get(&self, cid: &Cid) -> Result<Option<Block>, Error> {
+        self.fetch(cid, false).await
+    }
+
+    async fn get_raw(&self, cid: &Cid) -> Result<Option<Block>, Error> {
+        self.fetch(cid, true).await
+    }
+
+    async fn size(&self, cid: &Cid) -> Result<Option<usize>, Error> {
+        // unlike getting a block, asking for its size does not promote it
+        match self.hot.size(cid).await? {
+            Some(size) => Ok(Some(size)),
+            None => self.cold.size(cid).await,
+        }
+    }
+
+    async fn put(&self, block: Block) -> Result<(Cid, BlockPut), Error> {
+        let cid = *block.cid();
+
+        if self.cold.contains(&cid).await? {
+            self.promote(block).await?;
+            return Ok((cid, BlockPut::Existed));
+        }
+
+        let res = self.hot.put(block).await?;
+        self.recency.lock().touch(cid);
+        self.demote_overflow().await?;
+        Ok(res)
+    }
+
+    async fn remove(&self, cid: &Cid) -> Result<Result<BlockRm, BlockRmError>, Error> {
+        self.recency.lock().remove(cid);
+        let hot = self.hot.remove(cid).await?;
+        let cold = self.cold.remove(cid).await?;
+
+        match (hot, cold) {
+            (Ok(removed), _) | (_, Ok(removed)) => Ok(Ok(removed)),
+            (Err(e), Err(_)) => Ok(Err(e)),
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<Cid>, Error> {
+        let mut cids = self.hot.list().await?.into_iter().collect::<HashSet<_>>();
+        cids.extend(self.cold.list().await?);
+        Ok(cids.into_iter().collect())
+    }
+
+    async fn wipe(&self) {
+        self.recency.lock().clear();
+        self.hot.wipe().await;
+        self.cold.wipe().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::blockstore::memory::MemBlockStore;
+    use libipld::{
+        multihash::{Code, MultihashDigest},
+        IpldCodec,
+    };
+
+    fn block(data: &[u8]) -> Block {
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(data));
+        Block::new(cid, data.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn accessed_block_stays_hot() {
+        let hot = Arc::new(MemBlockStore::default());
+        let cold = Arc::new(MemBlockStore::default());
+        let store = TieredStore::new(hot.clone(), cold.clone(), 2);
+        store.init().await.unwrap();
+        store.open().await.unwrap();
+
+        let accessed = block(b"accessed");
+        let untouched = block(b"untouched");
+        store.put(accessed.clone()).await.unwrap();
+        store.put(untouched.clone()).await.unwrap();
+
+        for data in [b"1", b"2", b"3"] {
+            store.get(accessed.cid()).await.unwrap().unwrap();
+            store.put(block(data)).await.unwrap();
+        }
+
+        assert!(store.is_hot(accessed.cid()).await.unwrap());
+        assert!(!store.is_hot(untouched.cid()).await.unwrap());
+        assert!(cold.contains(untouched.cid()).await.unwrap());
+        assert_eq!(hot.list().await.unwrap().len(), 2);
+        assert_eq!(store.list().await.unwrap().len(), 5);
+
+        // asking for the size of the demoted block leaves it cold
+        assert_eq!(
+            store.size(untouched.cid()).await.unwrap(),
+            Some(untouched.data().len())
+        );
+        assert!(!store.is_hot(untouched.cid()).await.unwrap());
+
+        // reading the demoted block promotes it again
+        assert_eq!(
+            store.get(untouched.cid()).await.unwrap(),
+            Some(untouched.clone())
+        );
+        assert!(store.is_hot(untouched.cid()).await.unwrap());
+        assert!(!cold.contains(untouched.cid()).await.unwrap());
+    }
+}
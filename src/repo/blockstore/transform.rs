@@ -0,0 +1,197 @@
+//! Block store transforming the block data, e.g. compressing it, before handing it to another
+//! store.
+use crate::error::Error;
+use crate::repo::{BlockPut, BlockRm, BlockRmError, BlockStore};
+use crate::Block;
+use async_trait::async_trait;
+use libipld::Cid;
+use std::fmt;
+use std::sync::Arc;
+
+/// Transforms the data of the blocks on their way into and out of the block store.
+///
+/// [`BlockTransform::on_load`] must undo [`BlockTransform::on_store`], as the Cid of a block is
+/// always the one of its original data.
+pub trait BlockTransform: fmt::Debug + Send + Sync {
+    /// Transforms the original data of the block into the data stored for it.
+    fn on_store(&self, cid: &Cid, data: &[u8]) -> Result<Vec<u8>, Error>;
+
+    /// Restores the original data of the block from the data stored for it.
+    fn on_load(&self, cid: &Cid, stored: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+/// Describes a block store which passes the data of the blocks through a [`BlockTransform`]
+/// before storing them in `inner`, and back through it when read.
+///
+/// The blocks keep their original Cid and the restored data is verified against it, so the
+/// content addressing is unaffected. The stored data is read from `inner` with
+/// [`BlockStore::get_raw`].
+pub struct TransformedBlockStore {
+    inner: Arc<dyn BlockStore>,
+    transform: Arc<dyn BlockTransform>,
+}
+
+impl fmt::Debug for TransformedBlockStore {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("TransformedBlockStore")
+            .field("inner", &self.inner)
+            .field("transform", &self.transform)
+            .finish()
+    }
+}
+
+impl TransformedBlockStore {
+    /// Creates a store transforming the blocks stored in `inner` with `transform`.
+    pub fn new(inner: Arc<dyn BlockStore>, transform: Arc<dyn BlockTransform>) -> Self {
+        TransformedBlockStore { inner, transform }
+    }
+}
+
+#[async_trait]
+impl BlockStore for TransformedBlockStore {
+    async fn init(&self) -> Result<(), Error> {
+        self.inner.init().await
+    }
+
+    async fn open(&self) -> Result<(), Error> {
+        self.inner.open().await
+    }
+
+    async fn contains(&self, cid: &Cid) -> Result<bool, Error> {
+        self.inner.contains(cid).await
+    }
+
+    async fn get(&self, cid: &Cid) -> Result<Option<Block>, Error> {
+        match self.get_raw(cid).await? {
+            Some(block) => {
+                let (cid, data) = block.into_inner();
+                Block::new(cid, data).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn get_raw(&self, cid: &Cid) -> Result<Option<Block>, Error> {
+        match self.inner.get_raw(cid).await? {
+            Some(block) => {
+                let data = self.transform.on_load(cid, block.data())?;
+                Ok(Some(Block::new_unchecked(*cid, data)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn put(&self, block: Block) -> Result<(Cid, BlockPut), Error> {
+        if self.inner.contains(block.cid()).await? {
+            return Ok((*block.cid(), BlockPut::Existed));
+        }
+        let data = self.transform.on_store(block.cid(), block.data())?;
+        self.inner
+            .put(Block::new_unchecked(*block.cid(), data))
+            .await
+    }
+
+    async fn remove(&self, cid: &Cid) -> Result<Result<BlockRm, BlockRmError>, Error> {
+        self.inner.remove(cid).await
+    }
+
+    async fn list(&self) -> Result<Vec<Cid>, Error> {
+        self.inner.list().await
+    }
+
+    async fn wipe(&self) {
+        self.inner.wipe().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::blockstore::flatfs::FsBlockStore;
+    use libipld::{
+        multihash::{Code, MultihashDigest},
+        IpldCodec,
+    };
+
+    /// Run-length encodes the data as pairs of count and byte.
+    #[derive(Debug)]
+    struct RunLength;
+
+    impl BlockTransform for RunLength {
+        fn on_store(&self, _: &Cid, data: &[u8]) -> Result<Vec<u8>, Error> {
+            let mut stored: Vec<u8> = vec![];
+            for &byte in data {
+                match stored.as_mut_slice() {
+                    [.., count, last] if *last == byte && *count < u8::MAX => *count += 1,
+                    _ => stored.extend_from_slice(&[1, byte]),
+                }
+            }
+            Ok(stored)
+        }
+
+        fn on_load(&self, _: &Cid, stored: &[u8]) -> Result<Vec<u8>, Error> {
+            if stored.len() % 2 != 0 {
+                anyhow::bail!("truncated run");
+            }
+            Ok(stored
+                .chunks(2)
+                .flat_map(|run| std::iter::repeat(run[1]).take(run[0].into()))
+                .collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn blocks_are_compressed_at_rest() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let inner = Arc::new(FsBlockStore::new(tmp.path().into()));
+        let store = TransformedBlockStore::new(inner.clone(), Arc::new(RunLength));
+        store.init().await.unwrap();
+        store.open().await.unwrap();
+
+        let data = [vec![0u8; 1000], vec![1u8; 300]].concat();
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+        let block = Block::new(cid, data.clone()).unwrap();
+
+        assert_eq!(
+            store.put(block.clone()).await.unwrap().1,
+            BlockPut::NewBlock
+        );
+        assert_eq!(store.put(block.clone()).await.unwrap().1, BlockPut::Existed);
+
+        let stored = inner.get_raw(&cid).await.unwrap().unwrap();
+        assert!(stored.data().len() < data.len());
+
+        let loaded = store.get(&cid).await.unwrap().unwrap();
+        assert_eq!(loaded.cid(), &cid);
+        assert_eq!(loaded.data(), &data[..]);
+    }
+
+    #[tokio::test]
+    async fn transformed_blocks_are_encrypted_in_the_repo() {
+        use crate::repo::Repo;
+
+        let tmp = tempfile::TempDir::new().unwrap();
+        let repo = Repo::new_fs(tmp.path())
+            .with_block_encryption([7; 32])
+            .with_block_transform(Arc::new(RunLength));
+        repo.init().await.unwrap();
+
+        let data = [vec![0u8; 1000], vec![1u8; 300]].concat();
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+        repo.put_block(Block::new(cid, data.clone()).unwrap())
+            .await
+            .unwrap();
+
+        let stored = FsBlockStore::new(tmp.path().join("blockstore"))
+            .get_raw(&cid)
+            .await
+            .unwrap()
+            .unwrap();
+        // compressed before being encrypted, which adds the nonce and the tag
+        assert!(stored.data().len() < data.len());
+
+        let loaded = repo.get_block_now(&cid).await.unwrap().unwrap();
+        assert_eq!(loaded.cid(), &cid);
+        assert_eq!(loaded.data(), &data[..]);
+    }
+}
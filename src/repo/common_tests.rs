@@ -329,6 +329,170 @@ macro_rules! pinstore_interface_tests {
                 // go-ipfs it's different than path resolving
                 assert_eq!(e.to_string(), "already pinned recursively");
             }
+
+            #[tokio::test]
+            async fn downgrade_and_upgrade_recursive_pin() {
+                let repo = DSTestContext::with($factory).await;
+
+                // root/nested/deeper: QmX5S2xLu32K6WxWnyLeChQFbDHy79ULV9feJYH2Hy9bgp
+                let root = Cid::try_from("QmX5S2xLu32K6WxWnyLeChQFbDHy79ULV9feJYH2Hy9bgp").unwrap();
+                let empty =
+                    Cid::try_from("QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH").unwrap();
+
+                let refs = || futures::stream::iter(vec![Ok(empty.clone())]).boxed();
+
+                repo.insert_recursive_pin(&root, refs()).await.unwrap();
+
+                // the root must not be seen unpinned while it is downgraded
+                let (downgraded, _) =
+                    futures::future::join(repo.downgrade_to_direct(&root, refs()), async {
+                        for _ in 0..10 {
+                            assert!(repo.is_pinned(&root).await.unwrap());
+                            tokio::task::yield_now().await;
+                        }
+                    })
+                    .await;
+                downgraded.unwrap();
+
+                let mut pins = repo
+                    .list(None)
+                    .await
+                    .try_collect::<HashedMap<Cid, PinMode>>()
+                    .await
+                    .unwrap();
+
+                assert_eq!(pins.remove(&root), Some(PinMode::Direct));
+                assert!(pins.is_empty(), "{:?}", pins);
+                assert!(!repo.is_pinned(&empty).await.unwrap());
+
+                repo.downgrade_to_direct(&root, refs()).await.unwrap_err();
+
+                repo.upgrade_to_recursive(&root, refs()).await.unwrap();
+
+                let mut pins = repo
+                    .list(None)
+                    .await
+                    .try_collect::<HashedMap<Cid, PinMode>>()
+                    .await
+                    .unwrap();
+
+                assert_eq!(pins.remove(&root), Some(PinMode::Recursive));
+                assert_eq!(pins.remove(&empty), Some(PinMode::Indirect));
+                assert!(pins.is_empty(), "{:?}", pins);
+
+                repo.upgrade_to_recursive(&root, refs()).await.unwrap_err();
+            }
+
+            #[tokio::test]
+            async fn sync_to_desired_pins() {
+                use libipld::multihash::{Code, MultihashDigest};
+                use libipld::IpldCodec;
+
+                let repo = DSTestContext::with($factory).await;
+
+                // root/nested/deeper: QmX5S2xLu32K6WxWnyLeChQFbDHy79ULV9feJYH2Hy9bgp
+                let root = Cid::try_from("QmX5S2xLu32K6WxWnyLeChQFbDHy79ULV9feJYH2Hy9bgp").unwrap();
+                let empty =
+                    Cid::try_from("QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH").unwrap();
+                let [a, b, c] = [b"a", b"b", b"c"]
+                    .map(|data| Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(data)));
+
+                let refs = move |cid: &Cid| {
+                    if *cid == root || *cid == c {
+                        futures::stream::iter(vec![Ok(empty)]).boxed()
+                    } else {
+                        futures::stream::empty().boxed()
+                    }
+                };
+
+                repo.insert_direct_pin(&a).await.unwrap();
+                repo.insert_direct_pin(&b).await.unwrap();
+                repo.insert_recursive_pin(&root, refs(&root)).await.unwrap();
+
+                let mut report = repo
+                    .sync(
+                        vec![
+                            (b, PinMode::Direct),
+                            (root, PinMode::Direct),
+                            (c, PinMode::Recursive),
+                        ],
+                        &refs,
+                    )
+                    .await
+                    .unwrap();
+
+                report.added.sort_by_key(|(cid, _)| cid.to_bytes());
+                report.removed.sort_by_key(|(cid, _)| cid.to_bytes());
+
+                let mut added = vec![(root, PinMode::Direct), (c, PinMode::Recursive)];
+                added.sort_by_key(|(cid, _)| cid.to_bytes());
+                let mut removed = vec![(a, PinMode::Direct), (root, PinMode::Recursive)];
+                removed.sort_by_key(|(cid, _)| cid.to_bytes());
+
+                assert_eq!(report.added, added);
+                assert_eq!(report.removed, removed);
+
+                let mut pins = repo
+                    .list(None)
+                    .await
+                    .try_collect::<HashedMap<Cid, PinMode>>()
+                    .await
+                    .unwrap();
+
+                assert_eq!(pins.remove(&b), Some(PinMode::Direct));
+                assert_eq!(pins.remove(&root), Some(PinMode::Direct));
+                assert_eq!(pins.remove(&c), Some(PinMode::Recursive));
+                assert_eq!(pins.remove(&empty), Some(PinMode::Indirect));
+
+                assert!(pins.is_empty(), "{:?}", pins);
+            }
+
+            #[tokio::test]
+            async fn pins_are_listed_in_pages() {
+                use libipld::multihash::{Code, MultihashDigest};
+                use libipld::IpldCodec;
+
+                let repo = DSTestContext::with($factory).await;
+
+                let mut pinned = Vec::new();
+                for i in 0..50u32 {
+                    let cid = Cid::new_v1(
+                        IpldCodec::Raw.into(),
+                        Code::Sha2_256.digest(&i.to_be_bytes()),
+                    );
+                    if i % 5 == 0 {
+                        repo.insert_recursive_pin(&cid, futures::stream::empty().boxed())
+                            .await
+                            .unwrap();
+                        pinned.push((cid, PinMode::Recursive));
+                    } else {
+                        repo.insert_direct_pin(&cid).await.unwrap();
+                        pinned.push((cid, PinMode::Direct));
+                    }
+                }
+                pinned.sort_by_key(|(cid, _)| cid.to_bytes());
+
+                for (mode, limit) in [(None, 7), (Some(PinMode::Recursive), 3)] {
+                    let mut listed = Vec::new();
+                    let mut start_after = None;
+                    loop {
+                        let page = repo.list_page(mode, start_after, limit).await.unwrap();
+                        assert!(page.len() <= limit);
+                        match page.last() {
+                            Some((cid, _)) => start_after = Some(*cid),
+                            None => break,
+                        }
+                        listed.extend(page);
+                    }
+
+                    let expected = pinned
+                        .iter()
+                        .filter(|(_, pinned_mode)| mode.map_or(true, |mode| mode == *pinned_mode))
+                        .copied()
+                        .collect::<Vec<_>>();
+                    assert_eq!(listed, expected);
+                }
+            }
         }
     };
 }
@@ -76,7 +76,7 @@ impl DataStore for FsDataStore {
         Err(anyhow::anyhow!("not implemented"))
     }
 
-    async fn iter(&self) -> futures::stream::BoxStream<'static, (Vec<u8>, Vec<u8>)> {
+    async fn iter(&self) -> futures::stream::BoxStream<'static, Result<(Vec<u8>, Vec<u8>), Error>> {
         futures::stream::empty().boxed()
     }
 
@@ -87,6 +87,14 @@ impl DataStore for FsDataStore {
 // parent module.
 #[async_trait]
 impl PinStore for FsDataStore {
+    async fn init_pins(&self) -> Result<(), Error> {
+        DataStore::init(self).await
+    }
+
+    async fn open_pins(&self) -> Result<(), Error> {
+        DataStore::open(self).await
+    }
+
     async fn is_pinned(&self, cid: &Cid) -> Result<bool, Error> {
         let path = pin_path(self.path.join("pins"), cid);
 
@@ -158,7 +166,7 @@ impl PinStore for FsDataStore {
 
         let permit = Semaphore::acquire_owned(Arc::clone(&self.lock)).await?;
 
-        let mut path = pin_path(self.path.join("pins"), target);
+        let path = pin_path(self.path.join("pins"), target);
 
         let span = tracing::Span::current();
 
@@ -166,48 +174,7 @@ impl PinStore for FsDataStore {
             let _permit = permit; // again move to the threadpool thread
             let _entered = span.enter();
 
-            std::fs::create_dir_all(path.parent().expect("shard parent has to exist"))?;
-            let count = set.len();
-            let cids = set.into_iter().map(|cid| cid.to_string());
-
-            path.set_extension("recursive_temp");
-
-            let file = std::fs::File::create(&path)?;
-
-            match sync_write_recursive_pin(file, count, cids) {
-                Ok(_) => {
-                    let final_path = path.with_extension("recursive");
-                    std::fs::rename(&path, final_path)?
-                }
-                Err(e) => {
-                    let removed = std::fs::remove_file(&path);
-
-                    match removed {
-                        Ok(_) => debug!("cleaned up ok after botched recursive pin write"),
-                        Err(e) => warn!("failed to cleanup temporary file: {}", e),
-                    }
-
-                    return Err(e);
-                }
-            }
-
-            // if we got this far, we have now written and renamed the recursive_temp into place.
-            // now we just need to remove the direct pin, if it exists
-
-            path.set_extension("direct");
-
-            match std::fs::remove_file(&path) {
-                Ok(_) => { /* good */ }
-                Err(e) if e.kind() == std::io::ErrorKind::NotFound => { /* good as well */ }
-                Err(e) => {
-                    warn!(
-                        "failed to remove direct pin when adding recursive {:?}: {}",
-                        path, e
-                    );
-                }
-            }
-
-            Ok::<_, Error>(())
+            sync_insert_recursive_pin(path, set)
         })
         .await??;
 
@@ -302,6 +269,68 @@ impl PinStore for FsDataStore {
         Ok(())
     }
 
+    async fn downgrade_to_direct(&self, target: &Cid, _: References<'_>) -> Result<(), Error> {
+        let permit = Semaphore::acquire_owned(Arc::clone(&self.lock)).await?;
+
+        let mut path = pin_path(self.path.join("pins"), target);
+
+        let span = tracing::Span::current();
+
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit; // move into threadpool thread
+            let _entered = span.enter();
+
+            path.set_extension("recursive");
+            if !path.is_file() {
+                return Err(anyhow::anyhow!("not pinned recursively"));
+            }
+
+            // the direct pin is written before the recursive one is removed so that the target
+            // stays pinned; the indirect pins go away with the recursive pin file.
+            let f = std::fs::File::create(path.with_extension("direct"))?;
+            f.sync_all()?;
+
+            std::fs::remove_file(&path)?;
+            Ok(())
+        })
+        .await??;
+
+        Ok(())
+    }
+
+    async fn upgrade_to_recursive(
+        &self,
+        target: &Cid,
+        referenced: References<'_>,
+    ) -> Result<(), Error> {
+        let set = referenced
+            .try_collect::<std::collections::BTreeSet<_>>()
+            .await?;
+
+        let permit = Semaphore::acquire_owned(Arc::clone(&self.lock)).await?;
+
+        let path = pin_path(self.path.join("pins"), target);
+
+        let span = tracing::Span::current();
+
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit; // move into threadpool thread
+            let _entered = span.enter();
+
+            if !path.with_extension("direct").is_file()
+                || path.with_extension("recursive").is_file()
+            {
+                return Err(anyhow::anyhow!("not pinned directly"));
+            }
+
+            // the direct pin is only removed once the recursive pin is in place
+            sync_insert_recursive_pin(path, set)
+        })
+        .await??;
+
+        Ok(())
+    }
+
     async fn list(
         &self,
         requirement: Option<PinMode>,
@@ -604,6 +633,56 @@ fn sync_read_direct_or_recursive(block_path: &mut PathBuf) -> Option<PinMode> {
     None
 }
 
+/// Writes the recursive pin file of the pin at `path` through a temporary file and removes the
+/// direct pin once the recursive one is in place. Needs to be called while holding the lock.
+fn sync_insert_recursive_pin(
+    mut path: PathBuf,
+    set: std::collections::BTreeSet<Cid>,
+) -> Result<(), Error> {
+    std::fs::create_dir_all(path.parent().expect("shard parent has to exist"))?;
+    let count = set.len();
+    let cids = set.into_iter().map(|cid| cid.to_string());
+
+    path.set_extension("recursive_temp");
+
+    let file = std::fs::File::create(&path)?;
+
+    match sync_write_recursive_pin(file, count, cids) {
+        Ok(_) => {
+            let final_path = path.with_extension("recursive");
+            std::fs::rename(&path, final_path)?
+        }
+        Err(e) => {
+            let removed = std::fs::remove_file(&path);
+
+            match removed {
+                Ok(_) => debug!("cleaned up ok after botched recursive pin write"),
+                Err(e) => warn!("failed to cleanup temporary file: {}", e),
+            }
+
+            return Err(e);
+        }
+    }
+
+    // if we got this far, we have now written and renamed the recursive_temp into place.
+    // now we just need to remove the direct pin, if it exists
+
+    path.set_extension("direct");
+
+    match std::fs::remove_file(&path) {
+        Ok(_) => { /* good */ }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => { /* good as well */ }
+        Err(e) => {
+            warn!(
+                "failed to remove direct pin when adding recursive {:?}: {}",
+                path, e
+            );
+        }
+    }
+
+    Ok(())
+}
+
 fn sync_write_recursive_pin(
     file: std::fs::File,
     count: usize,
@@ -103,6 +103,53 @@ impl MemDataStore {
             Entry::Vacant(_) => Err(anyhow::anyhow!("not pinned")),
         }
     }
+
+    /// Inserts the recursive pin of `target` while holding the lock of the pins.
+    async fn insert_recursive(
+        g: &mut OwnedMutexGuard<HashMap<Vec<u8>, Vec<u8>>>,
+        target: &Cid,
+        mut refs: crate::repo::References<'_>,
+    ) -> Result<(), Error> {
+        use futures::stream::TryStreamExt;
+
+        // this must fail if it is already fully pinned
+        Self::insert_pin(g, target, &PinKind::RecursiveIntention)?;
+
+        let target_v1 = if target.version() == cid::Version::V1 {
+            target.to_owned()
+        } else {
+            // this is one more allocation
+            Cid::new_v1(target.codec(), target.hash().to_owned())
+        };
+
+        // collect these before even if they are many ... not sure if this is a good idea but, the
+        // inmem version doesn't need to be all that great. this could be for nothing, if the root
+        // was already pinned.
+
+        let mut count = 0;
+        let kind = PinKind::IndirectFrom(&target_v1);
+        while let Some(next) = refs.try_next().await? {
+            // no rollback, nothing
+            Self::insert_pin(g, &next, &kind)?;
+            count += 1;
+        }
+
+        let kind = PinKind::Recursive(count as u64);
+        Self::insert_pin(g, target, &kind)?;
+
+        Ok(())
+    }
+
+    /// Returns the pin document of `target`, if it is pinned in any way.
+    fn pin_document(
+        g: &OwnedMutexGuard<HashMap<Vec<u8>, Vec<u8>>>,
+        target: &Cid,
+    ) -> Result<Option<PinDocument>, Error> {
+        g.get(&target.to_bytes())
+            .map(|raw| serde_json::from_slice(raw))
+            .transpose()
+            .map_err(Error::from)
+    }
 }
 
 #[async_trait]
@@ -137,38 +184,10 @@ impl PinStore for MemDataStore {
     async fn insert_recursive_pin(
         &self,
         target: &Cid,
-        mut refs: crate::repo::References<'_>,
+        refs: crate::repo::References<'_>,
     ) -> Result<(), Error> {
-        use futures::stream::TryStreamExt;
-
         let mut g = Mutex::lock_owned(Arc::clone(&self.pin)).await;
-
-        // this must fail if it is already fully pinned
-        Self::insert_pin(&mut g, target, &PinKind::RecursiveIntention)?;
-
-        let target_v1 = if target.version() == cid::Version::V1 {
-            target.to_owned()
-        } else {
-            // this is one more allocation
-            Cid::new_v1(target.codec(), target.hash().to_owned())
-        };
-
-        // collect these before even if they are many ... not sure if this is a good idea but, the
-        // inmem version doesn't need to be all that great. this could be for nothing, if the root
-        // was already pinned.
-
-        let mut count = 0;
-        let kind = PinKind::IndirectFrom(&target_v1);
-        while let Some(next) = refs.try_next().await? {
-            // no rollback, nothing
-            Self::insert_pin(&mut g, &next, &kind)?;
-            count += 1;
-        }
-
-        let kind = PinKind::Recursive(count as u64);
-        Self::insert_pin(&mut g, target, &kind)?;
-
-        Ok(())
+        Self::insert_recursive(&mut g, target, refs).await
     }
 
     async fn remove_recursive_pin(
@@ -222,6 +241,57 @@ impl PinStore for MemDataStore {
         Ok(())
     }
 
+    async fn downgrade_to_direct(
+        &self,
+        target: &Cid,
+        refs: crate::repo::References<'_>,
+    ) -> Result<(), Error> {
+        use futures::TryStreamExt;
+
+        let refs = refs.try_collect::<Vec<_>>().await?;
+
+        let mut g = Mutex::lock_owned(Arc::clone(&self.pin)).await;
+
+        let kind = match Self::pin_document(&g, target)?.and_then(|doc| doc.pick_kind()) {
+            Some(Ok(kind @ PinKind::Recursive(_)))
+            | Some(Ok(kind @ PinKind::RecursiveIntention)) => kind,
+            _ => return Err(PinUpdateError::NotPinnedRecursive.into()),
+        };
+
+        // the lock is held for the whole change, so the target cannot be seen unpinned between
+        // these
+        Self::remove_pin(&mut g, target, &kind.as_ref())?;
+        Self::insert_pin(&mut g, target, &PinKind::Direct)?;
+
+        let target_v1 = if target.version() == cid::Version::V1 {
+            target.to_owned()
+        } else {
+            Cid::new_v1(target.codec(), target.hash().to_owned())
+        };
+
+        let kind = PinKind::IndirectFrom(&target_v1);
+        for next in refs {
+            Self::remove_pin(&mut g, &next, &kind)?;
+        }
+
+        Ok(())
+    }
+
+    async fn upgrade_to_recursive(
+        &self,
+        target: &Cid,
+        refs: crate::repo::References<'_>,
+    ) -> Result<(), Error> {
+        let mut g = Mutex::lock_owned(Arc::clone(&self.pin)).await;
+
+        match Self::pin_document(&g, target)? {
+            Some(doc) if doc.direct => {}
+            _ => return Err(PinUpdateError::NotPinnedDirect.into()),
+        }
+
+        Self::insert_recursive(&mut g, target, refs).await
+    }
+
     async fn list(
         &self,
         requirement: Option<PinMode>,
@@ -350,12 +420,12 @@ impl DataStore for MemDataStore {
         Ok(())
     }
 
-    async fn iter(&self) -> futures::stream::BoxStream<'static, (Vec<u8>, Vec<u8>)> {
+    async fn iter(&self) -> futures::stream::BoxStream<'static, Result<(Vec<u8>, Vec<u8>), Error>> {
         let list = self.inner.lock().await.clone();
 
         let stream = async_stream::stream! {
             for (k, v) in list {
-                yield (k, v)
+                yield Ok((k, v))
             }
         };
 
@@ -575,6 +645,9 @@ pub enum PinUpdateError {
     /// Recursive update fails as it wasn't pinned recursively.
     #[error("not pinned recursively")]
     NotPinnedRecursive,
+    /// Direct update fails as it wasn't pinned directly.
+    #[error("not pinned directly")]
+    NotPinnedDirect,
     /// Not allowed: Adding direct pin while pinned recursive.
     #[error("already pinned recursively")]
     AlreadyPinnedRecursive,
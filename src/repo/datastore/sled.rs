@@ -7,24 +7,27 @@ use libipld::cid::Cid;
 use sled::{
     self,
     transaction::{
-        ConflictableTransactionError, TransactionError, TransactionResult, TransactionalTree,
-        UnabortableTransactionError,
+        ConflictableTransactionError, TransactionError, TransactionResult, Transactional,
+        TransactionalTree, UnabortableTransactionError,
     },
-    Config as DbConfig, Db, Mode as DbMode,
+    Config as DbConfig, Db, Mode as DbMode, Tree,
 };
-use std::collections::BTreeSet;
-use std::convert::Infallible;
+use std::collections::{BTreeSet, HashMap};
 use std::path::PathBuf;
 use std::str::{self, FromStr};
-use std::sync::OnceLock;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 
-/// [`sled`] based pinstore implementation. Implements datastore which errors for each call.
+/// [`sled`] based pinstore and datastore implementation.
 /// Currently feature-gated behind `sled_data_store` feature in the [`crate::Types`], usable
 /// directly in custom type configurations.
 ///
 /// Current schema is to use the the default tree for storing pins, which are serialized as
-/// [`get_pin_key`]. Depending on the kind of pin values are generated by [`direct_value`],
-/// [`recursive_value`], and [`indirect_value`].
+/// [`get_pin_key`], the bytes of the cid suffixed with the mode, so that the mode of a cid is
+/// found with a single [`get_pinned_mode`] scan. Indirect pins have a key for each of their roots,
+/// [`get_indirect_pin_key`]. Depending on the kind of pin values are generated by
+/// [`direct_value`], [`recursive_value`], and [`indirect_value`]. The key-value pairs of the
+/// datastore are kept in the separate [`DATA_TREE`].
 ///
 /// [`sled`]: https://github.com/spacejam/sled
 #[derive(Debug)]
@@ -32,31 +35,283 @@ pub struct SledDataStore {
     path: PathBuf,
     // it is a trick for not modifying the Data:init
     db: OnceLock<Db>,
+    list_buffer: usize,
+    config: SledConfig,
+}
+
+/// Number of pins read ahead of the consumer of [`PinStore::list`] by default.
+pub const DEFAULT_LIST_BUFFER: usize = 1024;
+
+/// The settings the [`sled`] database of a [`SledDataStore`] is opened with.
+#[derive(Debug, Clone, Copy)]
+pub struct SledConfig {
+    /// Whether the database favours throughput or a small size on disk.
+    pub mode: DbMode,
+    /// Size of the page cache, in bytes.
+    pub cache_capacity: u64,
+    /// How often the database is flushed to disk, or `None` to only flush when asked to.
+    pub flush_interval: Option<Duration>,
+}
+
+impl Default for SledConfig {
+    fn default() -> Self {
+        SledConfig {
+            mode: DbMode::HighThroughput,
+            cache_capacity: 1024 * 1024 * 1024,
+            flush_interval: Some(Duration::from_millis(500)),
+        }
+    }
 }
 
 impl SledDataStore {
     pub fn new(root: PathBuf) -> SledDataStore {
+        Self::with_config(root, SledConfig::default())
+    }
+
+    /// Opens the database at `root` with the given settings instead of the default ones.
+    pub fn with_config(root: PathBuf, config: SledConfig) -> SledDataStore {
         SledDataStore {
             path: root,
             db: Default::default(),
+            list_buffer: DEFAULT_LIST_BUFFER,
+            config,
+        }
+    }
+
+    /// Reads at most `list_buffer` pins ahead of the consumer of [`PinStore::list`], pausing the
+    /// listing until the consumer catches up. Defaults to [`DEFAULT_LIST_BUFFER`].
+    pub fn with_list_buffer(mut self, list_buffer: usize) -> Self {
+        self.list_buffer = list_buffer.max(1);
+        self
+    }
+
+    fn get_db(&self) -> Result<&Db, Error> {
+        self.db
+            .get()
+            .ok_or_else(|| anyhow::anyhow!("sled datastore is not initialized"))
+    }
+
+    fn get_data_tree(&self) -> Result<Tree, Error> {
+        Ok(self.get_db()?.open_tree(DATA_TREE)?)
+    }
+}
+
+/// Name of the tree holding the key-value pairs of the datastore.
+const DATA_TREE: &str = "data";
+
+/// Name of the tree holding the state of the migrations.
+const META_TREE: &str = "meta";
+
+/// Key written to the [`META_TREE`] once [`migrate_data_tree`] has completed.
+const DATA_TREE_MIGRATED: &[u8] = b"data_tree_migrated";
+
+/// Moves the key-value pairs written to the default tree, next to the pins, by earlier versions
+/// into the [`DATA_TREE`].
+///
+/// The pairs are moved in chunks of [`MIGRATION_CHUNK`], each chunk in a transaction over both
+/// trees, so an interrupted migration leaves every pair in exactly one of them and is resumed on
+/// the next open. Only the keys around the `pin.` ones are read, and not at all once the
+/// migration has been marked done.
+fn migrate_data_tree(db: &Db) -> Result<(), Error> {
+    let meta = db.open_tree(META_TREE)?;
+    if meta.contains_key(DATA_TREE_MIGRATED)? {
+        return Ok(());
+    }
+
+    let data = db.open_tree(DATA_TREE)?;
+    let default: &Tree = db;
+    let mut migrated = 0;
+
+    loop {
+        let chunk = db
+            .range(..b"pin.".to_vec())
+            .chain(db.range(b"pin/".to_vec()..))
+            .take(MIGRATION_CHUNK)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if chunk.is_empty() {
+            break;
+        }
+
+        let res = (default, &data).transaction(|(default, data)| {
+            for (key, value) in &chunk {
+                data.insert(key, value)?;
+                default.remove(key)?;
+            }
+            Ok::<_, ConflictableTransactionError<Error>>(())
+        });
+        launder(res)?;
+
+        migrated += chunk.len();
+    }
+
+    meta.insert(DATA_TREE_MIGRATED, &[])?;
+    db.flush()?;
+
+    if migrated > 0 {
+        tracing::info!(
+            migrated,
+            "migrated the data next to the pins to its own tree"
+        );
+    }
+
+    Ok(())
+}
+
+/// Number of keys moved at once by [`migrate_data_tree`], [`migrate_pin_keys`] and
+/// [`migrate_indirect_roots`].
+const MIGRATION_CHUNK: usize = 1024;
+
+/// Rewrites the `pin.<mode>.<cid>` keys written by earlier versions as [`get_pin_key`]. No
+/// valid cid starts with the mode bytes, so the old keys never collide with the current ones.
+///
+/// The keys are rewritten in batches of [`MIGRATION_CHUNK`], each batch moving its keys
+/// atomically, so that an interrupted migration is picked up from the remaining old keys.
+fn migrate_pin_keys(db: &Db) -> Result<(), Error> {
+    let mut migrated = 0;
+
+    for prefix in ["pin.d.", "pin.r.", "pin.i."] {
+        loop {
+            let mut batch = sled::Batch::default();
+            let mut chunk = 0;
+
+            for item in db.scan_prefix(prefix).take(MIGRATION_CHUNK) {
+                let (key, value) = item?;
+                let (cid, mode) = parse_old_pin_key(&key)?;
+                batch.remove(key);
+                batch.insert(get_pin_key(&cid, &mode), value);
+                chunk += 1;
+            }
+
+            if chunk == 0 {
+                break;
+            }
+
+            db.apply_batch(batch)?;
+            migrated += chunk;
+        }
+    }
+
+    if migrated > 0 {
+        db.flush()?;
+        tracing::info!(migrated, "migrated pin keys to the current schema");
+    }
+
+    Ok(())
+}
+
+/// Key written to the [`META_TREE`] once [`migrate_indirect_roots`] has completed.
+const INDIRECT_ROOTS_MIGRATED: &[u8] = b"indirect_roots_migrated";
+
+/// Splits the indirect pins written by earlier versions, a single key with the roots joined by
+/// newlines, into a [`get_indirect_pin_key`] for each root.
+///
+/// The pins are split in batches of [`MIGRATION_CHUNK`], each batch replacing its keys atomically,
+/// so that an interrupted migration is picked up from the remaining joined keys. The pins are not
+/// read at all once the migration has been marked done.
+fn migrate_indirect_roots(db: &Db) -> Result<(), Error> {
+    let meta = db.open_tree(META_TREE)?;
+    if meta.contains_key(INDIRECT_ROOTS_MIGRATED)? {
+        return Ok(());
+    }
+
+    let mut migrated = 0;
+    let mut batch = sled::Batch::default();
+    let mut chunk = 0;
+
+    for item in db.range(b"pin.".to_vec()..b"pin/".to_vec()) {
+        let (key, value) = item?;
+        // the keys which are not pins are left for the listings to report
+        match split_pin_key(&key) {
+            Ok((cid, PinMode::Indirect, [])) => {
+                for root in roots_from_indirect_value(&value)? {
+                    batch.insert(get_indirect_pin_key(&cid, &root), indirect_value());
+                }
+                batch.remove(key);
+                chunk += 1;
+            }
+            _ => continue,
+        }
+
+        if chunk == MIGRATION_CHUNK {
+            db.apply_batch(std::mem::take(&mut batch))?;
+            migrated += chunk;
+            chunk = 0;
         }
     }
 
-    fn get_db(&self) -> &Db {
-        self.db.get().unwrap()
+    db.apply_batch(batch)?;
+    migrated += chunk;
+
+    meta.insert(INDIRECT_ROOTS_MIGRATED, &[])?;
+    db.flush()?;
+
+    if migrated > 0 {
+        tracing::info!(migrated, "migrated indirect pins to a key per root");
     }
+
+    Ok(())
+}
+
+/// Parses the newline joined roots of the indirect pins written by earlier versions, which hold
+/// a single root before that.
+fn roots_from_indirect_value(bytes: &[u8]) -> Result<Vec<Cid>, Error> {
+    str::from_utf8(bytes)
+        .map_err(Error::from)
+        .and_then(|s| {
+            s.split('\n')
+                .map(|s| Cid::from_str(s).map_err(Error::from))
+                .collect()
+        })
+        .map_err(|e| {
+            e.context(format!(
+                "failed to read indirect pin source: {:?}",
+                &*String::from_utf8_lossy(bytes)
+            ))
+        })
+}
+
+/// Parses the `pin.<mode>.<cid>` keys written by earlier versions.
+fn parse_old_pin_key(key: &[u8]) -> Result<(Cid, PinMode), Error> {
+    let mode = match key.get(4) {
+        Some(b'd') => PinMode::Direct,
+        Some(b'r') => PinMode::Recursive,
+        Some(b'i') => PinMode::Indirect,
+        _ => anyhow::bail!("invalid pin: {:?}", &*String::from_utf8_lossy(key)),
+    };
+
+    let cid = str::from_utf8(&key[6..])
+        .map_err(Error::from)
+        .and_then(|s| Cid::from_str(s).map_err(Error::from))
+        .map_err(|e| {
+            e.context(format!(
+                "failed to read pin: {:?}",
+                &*String::from_utf8_lossy(key)
+            ))
+        })?;
+
+    Ok((cid, mode))
 }
 
 #[async_trait]
 impl DataStore for SledDataStore {
     async fn init(&self) -> Result<(), Error> {
-        let config = DbConfig::new();
-
-        let db = config
-            .mode(DbMode::HighThroughput)
+        let flush_every_ms = self
+            .config
+            .flush_interval
+            .map(|interval| interval.as_millis() as u64);
+
+        let db = DbConfig::new()
+            .mode(self.config.mode)
+            .cache_capacity(self.config.cache_capacity)
+            .flush_every_ms(flush_every_ms)
             .path(self.path.as_path())
             .open()?;
 
+        migrate_data_tree(&db)?;
+        migrate_pin_keys(&db)?;
+        migrate_indirect_roots(&db)?;
+
         match self.db.set(db) {
             Ok(()) => Ok(()),
             Err(_) => Err(anyhow::anyhow!("failed to init sled")),
@@ -69,18 +324,18 @@ impl DataStore for SledDataStore {
 
     /// Checks if a key is present in the datastore.
     async fn contains(&self, key: &[u8]) -> Result<bool, Error> {
-        let db = self.get_db().to_owned();
+        let data = self.get_data_tree()?;
         let key = key.to_owned();
-        tokio::task::spawn_blocking(move || db.contains_key(key).map_err(anyhow::Error::from))
+        tokio::task::spawn_blocking(move || data.contains_key(key).map_err(anyhow::Error::from))
             .await?
     }
 
     /// Returns the value associated with a key from the datastore.
     async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
-        let db = self.get_db().to_owned();
+        let data = self.get_data_tree()?;
         let key = key.to_owned();
         tokio::task::spawn_blocking(move || {
-            db.get(key)
+            data.get(key)
                 .map_err(Error::from)
                 .map(|item| item.map(|v| v.to_vec()))
         })
@@ -89,27 +344,35 @@ impl DataStore for SledDataStore {
 
     /// Puts the value under the key in the datastore.
     async fn put(&self, key: &[u8], value: &[u8]) -> Result<(), Error> {
-        let db = self.get_db().to_owned();
+        let data = self.get_data_tree()?;
         let key = key.to_owned();
         let value = value.to_owned();
-        tokio::task::spawn_blocking(move || db.insert(key, value).map_err(Error::from).map(|_| ()))
-            .await?
+        tokio::task::spawn_blocking(move || {
+            data.insert(key, value).map_err(Error::from).map(|_| ())
+        })
+        .await?
     }
 
     /// Removes a key-value pair from the datastore.
     async fn remove(&self, key: &[u8]) -> Result<(), Error> {
-        let db = self.get_db().to_owned();
+        let data = self.get_data_tree()?;
         let key = key.to_owned();
-        tokio::task::spawn_blocking(move || db.remove(key).map_err(Error::from).map(|_| ())).await?
+        tokio::task::spawn_blocking(move || data.remove(key).map_err(Error::from).map(|_| ()))
+            .await?
     }
 
-    async fn iter(&self) -> futures::stream::BoxStream<'static, (Vec<u8>, Vec<u8>)> {
-        let db = self.get_db().to_owned();
+    async fn iter(&self) -> futures::stream::BoxStream<'static, Result<(Vec<u8>, Vec<u8>), Error>> {
+        let data = match self.get_data_tree() {
+            Ok(data) => data,
+            Err(e) => return futures::stream::once(async { Err(e) }).boxed(),
+        };
         
         let stream = async_stream::stream! {
-            let iter = db.iter();
-            for (k, v) in iter.flatten() {
-                yield (k.to_vec(), v.to_vec());
+            let iter = data.iter();
+            for item in iter {
+                yield item
+                    .map(|(k, v)| (k.to_vec(), v.to_vec()))
+                    .map_err(Error::from);
             }
         };
         
@@ -117,24 +380,46 @@ impl DataStore for SledDataStore {
     }
 
     /// Wipes the datastore.
-    async fn wipe(&self) {}
-}
+    async fn wipe(&self) {
+        let db = match self.get_db() {
+            Ok(db) => db.to_owned(),
+            Err(_) => return,
+        };
+
+        let res = tokio::task::spawn_blocking(move || {
+            for name in db.tree_names() {
+                db.open_tree(name)?.clear()?;
+            }
+            Ok::<_, Error>(())
+        })
+        .await;
 
-// in the transactional parts of the [`Infallible`] is used to signal there is no additional
-// custom error, not that the transaction was infallible in itself.
+        match res {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => error!("failed to wipe the sled datastore: {e}"),
+            Err(e) => error!("failed to wipe the sled datastore: {e}"),
+        }
+    }
+}
 
 #[async_trait]
 impl PinStore for SledDataStore {
+    async fn init_pins(&self) -> Result<(), Error> {
+        DataStore::init(self).await
+    }
+
+    async fn open_pins(&self) -> Result<(), Error> {
+        DataStore::open(self).await
+    }
+
     async fn is_pinned(&self, cid: &Cid) -> Result<bool, Error> {
         let cid = cid.to_owned();
-        let db = self.get_db().to_owned();
+        let db = self.get_db()?.to_owned();
         let span = tracing::Span::current();
         tokio::task::spawn_blocking(move || {
             let span = tracing::trace_span!(parent: &span, "blocking");
             let _g = span.enter();
-            Ok(db.transaction::<_, _, Infallible>(|tree| {
-                Ok(get_pinned_mode(tree, &cid)?.is_some())
-            })?)
+            Ok(get_pinned_mode(&db, &cid)?.is_some())
         })
         .await?
     }
@@ -142,7 +427,7 @@ impl PinStore for SledDataStore {
     async fn insert_direct_pin(&self, target: &Cid) -> Result<(), Error> {
         use ConflictableTransactionError::Abort;
         let target = target.to_owned();
-        let db = self.get_db().to_owned();
+        let db = self.get_db()?.to_owned();
 
         let span = tracing::Span::current();
 
@@ -151,22 +436,21 @@ impl PinStore for SledDataStore {
             let _g = span.enter();
 
             db.transaction(|tx_tree| {
-                let already_pinned = get_pinned_mode(tx_tree, &target)?;
-
-                match already_pinned {
+                // an indirect pin is kept alongside the direct one, so that the block stays pinned
+                // through its roots once the direct pin is removed
+                match get_direct_or_recursive_tx(tx_tree, &target)? {
                     Some((PinMode::Direct, _)) => return Ok(()),
                     Some((PinMode::Recursive, _)) => {
-                        return Err(Abort(anyhow::anyhow!("already pinned recursively")))
-                    }
-                    Some((PinMode::Indirect, key)) => {
-                        // TODO: I think the direct should live alongside the indirect?
-                        tx_tree.remove(key.as_str())?;
+                        return Err(Abort(
+                            PinConflict::new(&target, PinMode::Direct, Some(PinMode::Recursive))
+                                .into(),
+                        ))
                     }
-                    None => {}
+                    Some((PinMode::Indirect, _)) | None => {}
                 }
 
                 let direct_key = get_pin_key(&target, &PinMode::Direct);
-                tx_tree.insert(direct_key.as_str(), direct_value())?;
+                tx_tree.insert(direct_key.as_slice(), direct_value())?;
 
                 tx_tree.flush();
 
@@ -175,7 +459,7 @@ impl PinStore for SledDataStore {
         })
         .await?;
 
-        launder(res)
+        launder(res).map_err(log_conflict)
     }
 
     async fn insert_recursive_pin(
@@ -188,60 +472,64 @@ impl PinStore for SledDataStore {
         let set = referenced.try_collect::<BTreeSet<_>>().await?;
 
         let target = target.to_owned();
-        let db = self.get_db().to_owned();
+        let db = self.get_db()?.to_owned();
 
         let span = tracing::Span::current();
 
-        // the transaction is not infallible but there is no additional error we return
-        tokio::task::spawn_blocking(move || {
+        let res = tokio::task::spawn_blocking(move || {
             let span = tracing::trace_span!(parent: &span, "blocking");
             let _g = span.enter();
-            db.transaction::<_, _, Infallible>(move |tx_tree| {
-                let already_pinned = get_pinned_mode(tx_tree, &target)?;
-
-                match already_pinned {
-                    Some((PinMode::Recursive, _)) => return Ok(()),
-                    Some((PinMode::Direct, key)) | Some((PinMode::Indirect, key)) => {
-                        // FIXME: this is probably another lapse in tests that both direct and
-                        // indirect can be removed when inserting recursive?
-                        tx_tree.remove(key.as_str())?;
-                    }
-                    None => {}
-                }
 
-                let recursive_key = get_pin_key(&target, &PinMode::Recursive);
-                tx_tree.insert(recursive_key.as_str(), recursive_value())?;
+            let references = set.into_iter().collect::<Vec<_>>();
+            pin_recursively(&db, &target, &references)
+        })
+        .await?;
 
-                let target_value = indirect_value(&target);
+        launder(res)
+    }
 
-                // cannot use into_iter here as the transactions are retryable
-                for cid in set.iter() {
-                    let indirect_key = get_pin_key(cid, &PinMode::Indirect);
+    async fn remove_direct_pin(&self, target: &Cid) -> Result<(), Error> {
+        use ConflictableTransactionError::Abort;
+        let target = target.to_owned();
+        let db = self.get_db()?.to_owned();
 
-                    if get_pinned_mode(tx_tree, cid)?.is_some() {
-                        // TODO: quite costly to do the get_pinned_mode here
-                        continue;
-                    }
+        let span = tracing::Span::current();
 
-                    // value is for get information like "Qmd9WDTA2Kph4MKiDDiaZdiB4HJQpKcxjnJQfQmM5rHhYK indirect through QmXr1XZBg1CQv17BPvSWRmM7916R6NLL7jt19rhCPdVhc5"
-                    // FIXME: this will not work with multiple blocks linking to the same block? also the
-                    // test is probably missing as well
-                    tx_tree.insert(indirect_key.as_str(), target_value.as_str())?;
+        let res = tokio::task::spawn_blocking(move || {
+            let span = tracing::trace_span!(parent: &span, "blocking");
+            let _g = span.enter();
+
+            let res = db.transaction::<_, _, Error>(|tx_tree| {
+                if is_not_pinned_or_pinned_indirectly(tx_tree, &target)? {
+                    return Err(Abort(
+                        PinConflict::new(&target, PinMode::Direct, None).into(),
+                    ));
                 }
 
+                let key = get_pin_key(&target, &PinMode::Direct);
+                tx_tree.remove(key.as_slice())?;
                 tx_tree.flush();
                 Ok(())
-            })
+            });
+
+            launder(res).map_err(|e| find_indirect_conflict(&db, e))
         })
-        .await??;
+        .await?;
 
-        Ok(())
+        res.map_err(log_conflict)
     }
 
-    async fn remove_direct_pin(&self, target: &Cid) -> Result<(), Error> {
+    async fn remove_recursive_pin(
+        &self,
+        target: &Cid,
+        referenced: References<'_>,
+    ) -> Result<(), Error> {
         use ConflictableTransactionError::Abort;
+        // TODO: is this "in the same transaction" as the batch which is created?
+        let set = referenced.try_collect::<BTreeSet<_>>().await?;
+
         let target = target.to_owned();
-        let db = self.get_db().to_owned();
+        let db = self.get_db()?.to_owned();
 
         let span = tracing::Span::current();
 
@@ -249,13 +537,64 @@ impl PinStore for SledDataStore {
             let span = tracing::trace_span!(parent: &span, "blocking");
             let _g = span.enter();
 
-            db.transaction::<_, _, Error>(|tx_tree| {
+            let res = db.transaction(|tx_tree| {
                 if is_not_pinned_or_pinned_indirectly(tx_tree, &target)? {
-                    return Err(Abort(anyhow::anyhow!("not pinned or pinned indirectly")));
+                    return Err(Abort(
+                        PinConflict::new(&target, PinMode::Recursive, None).into(),
+                    ));
+                }
+
+                let recursive_key = get_pin_key(&target, &PinMode::Recursive);
+                tx_tree.remove(recursive_key.as_slice())?;
+
+                for cid in &set {
+                    // the block stays pinned for the other roots referencing it
+                    remove_indirect_root(tx_tree, cid, &target)?;
+                }
+
+                tx_tree.flush();
+                Ok(())
+            });
+
+            launder(res).map_err(|e| find_indirect_conflict(&db, e))
+        })
+        .await?;
+
+        res.map_err(log_conflict)
+    }
+
+    async fn downgrade_to_direct(
+        &self,
+        target: &Cid,
+        referenced: References<'_>,
+    ) -> Result<(), Error> {
+        use ConflictableTransactionError::Abort;
+        let set = referenced.try_collect::<BTreeSet<_>>().await?;
+
+        let target = target.to_owned();
+        let db = self.get_db()?.to_owned();
+
+        let span = tracing::Span::current();
+
+        let res = tokio::task::spawn_blocking(move || {
+            let span = tracing::trace_span!(parent: &span, "blocking");
+            let _g = span.enter();
+
+            db.transaction(|tx_tree| {
+                let recursive_key = match get_direct_or_recursive_tx(tx_tree, &target)? {
+                    Some((PinMode::Recursive, key)) => key,
+                    _ => return Err(Abort(anyhow::anyhow!("not pinned recursively"))),
+                };
+
+                tx_tree.remove(recursive_key.as_slice())?;
+
+                let direct_key = get_pin_key(&target, &PinMode::Direct);
+                tx_tree.insert(direct_key.as_slice(), direct_value())?;
+
+                for cid in &set {
+                    remove_indirect_root(tx_tree, cid, &target)?;
                 }
 
-                let key = get_pin_key(&target, &PinMode::Direct);
-                tx_tree.remove(key.as_str())?;
                 tx_tree.flush();
                 Ok(())
             })
@@ -265,17 +604,16 @@ impl PinStore for SledDataStore {
         launder(res)
     }
 
-    async fn remove_recursive_pin(
+    async fn upgrade_to_recursive(
         &self,
         target: &Cid,
         referenced: References<'_>,
     ) -> Result<(), Error> {
         use ConflictableTransactionError::Abort;
-        // TODO: is this "in the same transaction" as the batch which is created?
         let set = referenced.try_collect::<BTreeSet<_>>().await?;
 
         let target = target.to_owned();
-        let db = self.get_db().to_owned();
+        let db = self.get_db()?.to_owned();
 
         let span = tracing::Span::current();
 
@@ -284,25 +622,18 @@ impl PinStore for SledDataStore {
             let _g = span.enter();
 
             db.transaction(|tx_tree| {
-                if is_not_pinned_or_pinned_indirectly(tx_tree, &target)? {
-                    return Err(Abort(anyhow::anyhow!("not pinned or pinned indirectly")));
-                }
+                let direct_key = match get_direct_or_recursive_tx(tx_tree, &target)? {
+                    Some((PinMode::Direct, key)) => key,
+                    _ => return Err(Abort(anyhow::anyhow!("not pinned directly"))),
+                };
+
+                tx_tree.remove(direct_key.as_slice())?;
 
                 let recursive_key = get_pin_key(&target, &PinMode::Recursive);
-                tx_tree.remove(recursive_key.as_str())?;
+                tx_tree.insert(recursive_key.as_slice(), recursive_value())?;
 
-                for cid in &set {
-                    let already_pinned = get_pinned_mode(tx_tree, cid)?;
-
-                    match already_pinned {
-                        Some((PinMode::Recursive, _)) | Some((PinMode::Direct, _)) => continue, // this should be unreachable
-                        Some((PinMode::Indirect, key)) => {
-                            // FIXME: not really sure of this but it might be that recursive removed
-                            // the others...?
-                            tx_tree.remove(key.as_str())?;
-                        }
-                        None => {}
-                    }
+                for cid in set.iter() {
+                    add_indirect_root(tx_tree, cid, &target)?;
                 }
 
                 tx_tree.flush();
@@ -318,93 +649,80 @@ impl PinStore for SledDataStore {
         &self,
         requirement: Option<PinMode>,
     ) -> futures::stream::BoxStream<'static, Result<(Cid, PinMode), Error>> {
-        use tokio_stream::wrappers::UnboundedReceiverStream;
+        use tokio_stream::wrappers::ReceiverStream;
 
-        let db = self.get_db().to_owned();
+        let db = match self.get_db() {
+            Ok(db) => db.to_owned(),
+            Err(e) => return futures::stream::once(async { Err(e) }).boxed(),
+        };
 
-        // if the pins are always updated in transaction, we might get away with just tree reads.
-        // this does however mean that it is possible to witness for example a part of a larger
-        // recursive pin and then just not find anymore of the recursive pin near the end of the
-        // listing. for non-gc uses this should not be an issue.
+        // the channel is bounded so that a consumer which never reads the listing does not make
+        // the whole of it pile up in memory; the listing pauses until the consumer catches up.
+        let (tx, rx) = tokio::sync::mpsc::channel(self.list_buffer);
+
+        spawn_pin_listing(db, requirement, tx);
+
+        // we cannot know if the task was spawned successfully until it has completed, so we cannot
+        // really do anything with the _jh.
         //
-        // FIXME: the unboundedness is still quite unoptimal here: we might get gazillion http
-        // listings which all quickly fill up a lot of memory and clients never have to read any
-        // responses. using of bounded channel would require sometimes sleeping and maybe bouncing
-        // back and forth between an async task and continuation of the iteration. leaving this to
-        // a later issue.
-        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        // perhaps we could await for the first element OR cancellation OR perhaps something
+        // else. StreamExt::peekable() would be good to go, but Peekable is only usable on top of
+        // something pinned, and I cannot see how could it become a boxed stream if we pin it, peek
+        // it and ... how would we get the peeked element since Peekable::into_inner doesn't return
+        // the value which has already been read from the stream?
+        ReceiverStream::new(rx).boxed()
+    }
+
+    async fn list_page(
+        &self,
+        requirement: Option<PinMode>,
+        start_after: Option<Cid>,
+        limit: usize,
+    ) -> Result<Vec<(Cid, PinMode)>, Error> {
+        use std::ops::Bound;
+
+        let db = self.get_db()?.to_owned();
 
         let span = tracing::Span::current();
 
-        let _jh = tokio::task::spawn_blocking(move || {
+        tokio::task::spawn_blocking(move || {
             let span = tracing::trace_span!(parent: &span, "blocking");
             let _g = span.enter();
 
-            // this probably doesn't need to be transactional? well, perhaps transactional reads would
-            // be the best, not sure what is the guaratee for in-sequence key reads.
-            let iter = db.range::<String, std::ops::RangeFull>(..);
+            // the keys of a cid only differ by what follows the cid, starting with the mode byte,
+            // so seeking past the largest byte skips all of the keys of the cursor. no cid is the prefix of
+            // another, so the order of the keys is the order of the cids.
+            let start = match start_after {
+                Some(cid) => {
+                    let mut key = pin_key_prefix(&cid);
+                    key.push(u8::MAX);
+                    Bound::Excluded(key)
+                }
+                None => Bound::Included(b"pin.".to_vec()),
+            };
+            // the first key past all of the pin keys
+            let end = Bound::Excluded(b"pin/".to_vec());
 
             let requirement = PinModeRequirement::from(requirement);
 
-            let adapted =
-                iter.map(|res| res.map_err(Error::from))
-                    .filter_map(move |res| match res {
-                        Ok((k, _v)) => {
-                            if !k.starts_with(b"pin.") || k.len() < 7 {
-                                return Some(Err(anyhow::anyhow!(
-                                    "invalid pin: {:?}",
-                                    &*String::from_utf8_lossy(&k)
-                                )));
-                            }
-
-                            let mode = match k[4] {
-                                b'd' => PinMode::Direct,
-                                b'r' => PinMode::Recursive,
-                                b'i' => PinMode::Indirect,
-                                x => {
-                                    return Some(Err(anyhow::anyhow!(
-                                        "invalid pinmode: {}",
-                                        x as char
-                                    )))
-                                }
-                            };
-
-                            if !requirement.matches(&mode) {
-                                None
-                            } else {
-                                let cid = std::str::from_utf8(&k[6..]).map_err(Error::from);
-                                let cid = cid.and_then(|x| Cid::from_str(x).map_err(Error::from));
-                                let cid = cid.map_err(|e| {
-                                    e.context(format!(
-                                        "failed to read pin: {:?}",
-                                        &*String::from_utf8_lossy(&k)
-                                    ))
-                                });
-                                Some(cid.map(move |cid| (cid, mode)))
-                            }
-                        }
-                        Err(e) => Some(Err(e)),
-                    });
+            let keys = db
+                .range((start, end))
+                .map(|res| res.map(|(k, _v)| k).map_err(Error::from));
 
-            for res in adapted {
-                if tx.send(res).is_err() {
+            let mut page = Vec::with_capacity(limit.min(DEFAULT_LIST_BUFFER));
+            for item in pins_by_precedence(keys) {
+                if page.len() >= limit {
                     break;
                 }
+                let (cid, mode) = item?;
+                if requirement.matches(&mode) {
+                    page.push((cid, mode));
+                }
             }
-        });
 
-        // we cannot know if the task was spawned successfully until it has completed, so we cannot
-        // really do anything with the _jh.
-        //
-        // perhaps we could await for the first element OR cancellation OR perhaps something
-        // else. StreamExt::peekable() would be good to go, but Peekable is only usable on top of
-        // something pinned, and I cannot see how could it become a boxed stream if we pin it, peek
-        // it and ... how would we get the peeked element since Peekable::into_inner doesn't return
-        // the value which has already been read from the stream?
-        //
-        // it would be nice to make sure that the stream doesn't end before task has ended, but
-        // perhaps the unboundedness of the channel takes care of that.
-        UnboundedReceiverStream::new(rx).boxed()
+            Ok(page)
+        })
+        .await?
     }
 
     async fn query(
@@ -412,126 +730,481 @@ impl PinStore for SledDataStore {
         ids: Vec<Cid>,
         requirement: Option<PinMode>,
     ) -> Result<Vec<(Cid, PinKind<Cid>)>, Error> {
-        use ConflictableTransactionError::Abort;
         let requirement = PinModeRequirement::from(requirement);
 
-        let db = self.get_db().to_owned();
+        let db = self.get_db()?.to_owned();
 
         tokio::task::spawn_blocking(move || {
-            let res = db.transaction::<_, _, Error>(|tx_tree| {
-                // since its an Fn closure this cannot be reserved once ... not sure why it couldn't be
-                // FnMut? the vec could be cached in the "outer" scope in a refcell.
-                let mut modes = Vec::with_capacity(ids.len());
-
-                // as we might loop over an over on the tx we might need this over and over, cannot
-                // take ownership inside the transaction. TODO: perhaps the use of transaction is
-                // questionable here; if the source of the indirect pin cannot be it is already
-                // None, this could work outside of transaction similarly.
-                for id in ids.iter() {
-                    let mode_and_key = get_pinned_mode(tx_tree, id)?;
-
-                    let matched = match mode_and_key {
-                        Some((pin_mode, key)) if requirement.matches(&pin_mode) => match pin_mode {
-                            PinMode::Direct => Some(PinKind::Direct),
-                            PinMode::Recursive => Some(PinKind::Recursive(0)),
-                            PinMode::Indirect => tx_tree
-                                .get(key.as_str())?
-                                .map(|root| {
-                                    cid_from_indirect_value(&root)
-                                        .map(PinKind::IndirectFrom)
-                                        .map_err(|e| {
-                                            Abort(e.context(format!(
-                                                "failed to read indirect pin source: {:?}",
-                                                String::from_utf8_lossy(root.as_ref()).as_ref(),
-                                            )))
-                                        })
-                                })
-                                .transpose()?,
-                        },
-                        Some(_) | None => None,
-                    };
-
-                    // this might be None, or Some(PinKind); it's important there are as many cids
-                    // as there are modes
-                    modes.push(matched);
-                }
-
-                Ok(modes)
-            });
-
-            let modes = launder(res)?;
+            // the pins are read outside of a transaction as sled transactions do not support the
+            // scans of get_pinned_mode. every pin is still read with a single scan, the key of an
+            // indirect pin naming the first of its roots in the order of their bytes.
+            let mut response = Vec::with_capacity(ids.len());
+
+            for id in ids {
+                let kind = match get_pinned_mode(&db, &id)? {
+                    Some((pin_mode, key)) if requirement.matches(&pin_mode) => match pin_mode {
+                        PinMode::Direct => PinKind::Direct,
+                        PinMode::Recursive => PinKind::Recursive(0),
+                        PinMode::Indirect => PinKind::IndirectFrom(parse_indirect_root(&key)?),
+                    },
+                    Some(_) | None => continue,
+                };
+
+                response.push((id, kind));
+            }
 
-            Ok(ids
-                .into_iter()
-                .zip(modes.into_iter())
-                .filter_map(|(cid, mode)| mode.map(move |mode| (cid, mode)))
-                .collect::<Vec<_>>())
+            Ok(response)
         })
         .await?
     }
-}
-
-/// Name the empty value stored for direct pins; the pin key itself describes the mode and the cid.
-fn direct_value() -> &'static [u8] {
-    Default::default()
-}
 
-/// Name the empty value stored for recursive pins at the top.
-fn recursive_value() -> &'static [u8] {
-    Default::default()
-}
+    async fn reindex_indirect(
+        &self,
+        refs: &(dyn for<'c> Fn(&'c Cid) -> References<'static> + Send + Sync),
+    ) -> Result<(), Error> {
+        let db = self.get_db()?.to_owned();
+        let roots = self
+            .list(Some(PinMode::Recursive))
+            .await
+            .map_ok(|(cid, _)| cid)
+            .try_collect::<Vec<_>>()
+            .await?;
+
+        // the recursive pins each reference, for removing the stale ones afterwards
+        let mut referencing = HashMap::<Cid, BTreeSet<Cid>>::new();
+        let listed = Arc::new(roots.iter().copied().collect::<BTreeSet<_>>());
+
+        for root in roots {
+            let set = refs(&root).try_collect::<BTreeSet<_>>().await?;
+            for cid in &set {
+                referencing.entry(*cid).or_default().insert(root);
+            }
 
-/// Name the value stored for indirect pins, currently only the most recent recursive pin.
-fn indirect_value(recursively_pinned: &Cid) -> String {
-    recursively_pinned.to_string()
-}
+            let db = db.clone();
+            let res = tokio::task::spawn_blocking(move || {
+                db.transaction(|tx_tree| {
+                    // the root might have been unpinned since it was listed
+                    if !matches!(
+                        get_direct_or_recursive_tx(tx_tree, &root)?,
+                        Some((PinMode::Recursive, _))
+                    ) {
+                        return Ok(());
+                    }
 
-/// Inverse of [`indirect_value`].
-fn cid_from_indirect_value(bytes: &[u8]) -> Result<Cid, Error> {
-    str::from_utf8(bytes)
-        .map_err(Error::from)
-        .and_then(|s| Cid::from_str(s).map_err(Error::from))
-}
+                    for cid in &set {
+                        add_indirect_root(tx_tree, cid, &root)?;
+                    }
 
-/// Helper needed as the error cannot just `?` converted.
-fn launder<T>(res: TransactionResult<T, Error>) -> Result<T, Error> {
-    use TransactionError::*;
-    match res {
-        Ok(t) => Ok(t),
-        Err(Abort(e)) => Err(e),
-        Err(Storage(e)) => Err(e.into()),
-    }
-}
+                    tx_tree.flush();
+                    Ok(())
+                })
+            })
+            .await?;
 
-fn pin_mode_literal(pin_mode: &PinMode) -> &'static str {
-    match pin_mode {
-        PinMode::Direct => "d",
-        PinMode::Indirect => "i",
-        PinMode::Recursive => "r",
-    }
-}
+            launder(res)?;
+        }
 
-fn get_pin_key(cid: &Cid, pin_mode: &PinMode) -> String {
-    // TODO: get_pinned_mode could be range query if the pin modes were suffixes, keys would need
-    // to be cid.to_bytes().push(pin_mode_literal(pin_mode))? ... since the cid bytes
-    // representation already contains the length we should be good to go in all cases.
-    //
-    // for storing multiple targets then the last could be found by doing a query as well. in the
-    // case of multiple indirect pins they'd have to be with another suffix.
-    //
-    // TODO: check if such representation would really order properly
-    format!("pin.{}.{}", pin_mode_literal(pin_mode), cid)
+        let indirect = self
+            .list(Some(PinMode::Indirect))
+            .await
+            .map_ok(|(cid, _)| cid)
+            .try_collect::<Vec<_>>()
+            .await?;
+
+        for cid in indirect {
+            let expected = referencing.remove(&cid).unwrap_or_default();
+            let listed = listed.clone();
+            let db = db.clone();
+            let res = tokio::task::spawn_blocking(move || {
+                // the roots are scanned ahead of the transaction, which cannot scan. a root added
+                // since is left as is, and removing one removed since does nothing.
+                let roots = read_indirect_roots(&db, &cid).map_err(TransactionError::Abort)?;
+
+                db.transaction(|tx_tree| {
+                    let mut removed = false;
+                    for root in &roots {
+                        // a root pinned since the roots were listed wrote its indirect pins itself
+                        let keep = expected.contains(root)
+                            || (!listed.contains(root)
+                                && matches!(
+                                    get_direct_or_recursive_tx(tx_tree, root)?,
+                                    Some((PinMode::Recursive, _))
+                                ));
+                        if !keep {
+                            remove_indirect_root(tx_tree, &cid, root)?;
+                            removed = true;
+                        }
+                    }
+
+                    if removed {
+                        tx_tree.flush();
+                    }
+                    Ok(())
+                })
+            })
+            .await?;
+
+            launder(res)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Pins `target` recursively, writing the indirect pins of its `references` with a single
+/// [`sled::Batch`] applied in the transaction. Every root of an indirect pin has a key of its own,
+/// so the references need not be read before their pins are written.
+fn pin_recursively(db: &Db, target: &Cid, references: &[Cid]) -> TransactionResult<(), Error> {
+    let mut batch = sled::Batch::default();
+    for cid in references {
+        batch.insert(get_indirect_pin_key(cid, target), indirect_value());
+    }
+
+    db.transaction(|tx_tree| {
+        // like the direct pin, the recursive one keeps any indirect pin of the target alongside it
+        match get_direct_or_recursive_tx(tx_tree, target)? {
+            Some((PinMode::Recursive, _)) => return Ok(()),
+            Some((PinMode::Direct, key)) => {
+                tx_tree.remove(key.as_slice())?;
+            }
+            Some((PinMode::Indirect, _)) | None => {}
+        }
+
+        let recursive_key = get_pin_key(target, &PinMode::Recursive);
+        tx_tree.insert(recursive_key.as_slice(), recursive_value())?;
+
+        tx_tree.apply_batch(&batch)?;
+
+        tx_tree.flush();
+        Ok(())
+    })
+}
+
+/// Reads the pins matching `requirement` into `tx` on a blocking task, waiting for room in the
+/// channel whenever it is full and stopping once the receiver is dropped.
+fn spawn_pin_listing(
+    db: Db,
+    requirement: Option<PinMode>,
+    tx: tokio::sync::mpsc::Sender<Result<(Cid, PinMode), Error>>,
+) {
+    let span = tracing::Span::current();
+
+    let _jh = tokio::task::spawn_blocking(move || {
+        let span = tracing::trace_span!(parent: &span, "blocking");
+        let _g = span.enter();
+
+        // if the pins are always updated in transaction, we might get away with just tree reads.
+        // this does however mean that it is possible to witness for example a part of a larger
+        // recursive pin and then just not find anymore of the recursive pin near the end of the
+        // listing. for non-gc uses this should not be an issue.
+        //
+        // this probably doesn't need to be transactional? well, perhaps transactional reads would
+        // be the best, not sure what is the guaratee for in-sequence key reads.
+        let iter = db.range::<String, std::ops::RangeFull>(..);
+
+        let requirement = PinModeRequirement::from(requirement);
+
+        let keys = iter.map(|res| res.map(|(k, _v)| k).map_err(Error::from));
+        let adapted = pins_by_precedence(keys).filter(move |res| match res {
+            Ok((_, mode)) => requirement.matches(mode),
+            Err(_) => true,
+        });
+
+        for res in adapted {
+            if tx.blocking_send(res).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// Folds the keys of each cid into a single pin of the mode with the highest
+/// [`PIN_MODE_PRECEDENCE`], like [`get_pinned_mode`] does. The keys of a cid are next to each other
+/// as no cid is the prefix of another.
+fn pins_by_precedence(
+    keys: impl Iterator<Item = Result<sled::IVec, Error>>,
+) -> impl Iterator<Item = Result<(Cid, PinMode), Error>> {
+    let mut keys = keys.fuse();
+    let mut pending: Option<(Cid, PinMode)> = None;
+
+    std::iter::from_fn(move || loop {
+        let (cid, mode) = match keys.next() {
+            Some(Ok(key)) => match parse_pin_key(&key) {
+                Ok(pin) => pin,
+                Err(e) => return Some(Err(e)),
+            },
+            Some(Err(e)) => return Some(Err(e)),
+            None => return pending.take().map(Ok),
+        };
+
+        match pending.take() {
+            Some((current, current_mode)) if current == cid => {
+                let mode = match pin_mode_precedence(&mode) < pin_mode_precedence(&current_mode) {
+                    true => mode,
+                    false => current_mode,
+                };
+                pending = Some((cid, mode));
+            }
+            Some(previous) => {
+                pending = Some((cid, mode));
+                return Some(Ok(previous));
+            }
+            None => pending = Some((cid, mode)),
+        }
+    })
+}
+
+/// Name the empty value stored for direct pins; the pin key itself describes the mode and the cid.
+fn direct_value() -> &'static [u8] {
+    Default::default()
+}
+
+/// Name the empty value stored for recursive pins at the top.
+fn recursive_value() -> &'static [u8] {
+    Default::default()
+}
+
+/// Name the empty value stored for each root of an indirect pin; the root is the suffix of the
+/// key.
+fn indirect_value() -> &'static [u8] {
+    Default::default()
+}
+
+/// Returns the roots `block` is pinned indirectly through, in the order of their bytes.
+fn read_indirect_roots(tree: &Tree, block: &Cid) -> Result<Vec<Cid>, Error> {
+    tree.scan_prefix(get_pin_key(block, &PinMode::Indirect))
+        .map(|item| parse_indirect_root(&item?.0))
+        .collect()
+}
+
+/// Pins `block` indirectly through `root` in addition to any roots it is already pinned through.
+/// The indirect pin is written even if the block is pinned directly or recursively, so that it
+/// stays pinned once that pin is removed.
+fn add_indirect_root(
+    tree: &TransactionalTree,
+    block: &Cid,
+    root: &Cid,
+) -> Result<(), UnabortableTransactionError> {
+    tree.insert(get_indirect_pin_key(block, root), indirect_value())?;
+    Ok(())
+}
+
+/// Removes `root` from the roots of the indirect pin of `block`, the pin going away with the last
+/// one. A direct or recursive pin of the block kept alongside is left as is.
+fn remove_indirect_root(
+    tree: &TransactionalTree,
+    block: &Cid,
+    root: &Cid,
+) -> Result<(), UnabortableTransactionError> {
+    tree.remove(get_indirect_pin_key(block, root))?;
+    Ok(())
+}
+
+/// Helper needed as the error cannot just `?` converted.
+fn launder<T>(res: TransactionResult<T, Error>) -> Result<T, Error> {
+    use TransactionError::*;
+    match res {
+        Ok(t) => Ok(t),
+        Err(Abort(e)) => Err(e),
+        Err(Storage(e)) => Err(e.into()),
+    }
+}
+
+/// Conflicting pin state found within a pin transaction. The displayed message is kept the same
+/// as the previous plain string errors, the fields are only used for [`log_conflict`].
+#[derive(Debug)]
+struct PinConflict {
+    cid: Cid,
+    requested: PinMode,
+    current: Option<PinMode>,
+}
+
+impl PinConflict {
+    fn new(cid: &Cid, requested: PinMode, current: Option<PinMode>) -> Self {
+        PinConflict {
+            cid: cid.to_owned(),
+            requested,
+            current,
+        }
+    }
+}
+
+impl std::fmt::Display for PinConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.current {
+            Some(PinMode::Recursive) => write!(f, "already pinned recursively"),
+            _ => write!(f, "not pinned or pinned indirectly"),
+        }
+    }
+}
+
+impl std::error::Error for PinConflict {}
+
+/// Fills in the indirect pin of the cid of a [`PinConflict`] found without a direct or recursive
+/// pin, as the transaction it was found in could not scan for the roots of the indirect pin.
+fn find_indirect_conflict(tree: &Tree, e: Error) -> Error {
+    match e.downcast::<PinConflict>() {
+        Ok(mut conflict) => {
+            let prefix = get_pin_key(&conflict.cid, &PinMode::Indirect);
+            if conflict.current.is_none() && matches!(tree.scan_prefix(prefix).next(), Some(Ok(_)))
+            {
+                conflict.current = Some(PinMode::Indirect);
+            }
+            conflict.into()
+        }
+        Err(e) => e,
+    }
+}
+
+/// Emits a structured event for [`PinConflict`] errors before they are returned to the caller.
+/// Needs to be called outside of the blocking task for the event to reach the callers subscriber.
+fn log_conflict(e: Error) -> Error {
+    if let Some(conflict) = e.downcast_ref::<PinConflict>() {
+        tracing::warn!(
+            cid = %conflict.cid,
+            requested = pin_mode_name(Some(conflict.requested)),
+            mode = pin_mode_name(conflict.current),
+            "pin conflict: {}",
+            conflict
+        );
+    }
+    e
+}
+
+fn pin_mode_name(pin_mode: Option<PinMode>) -> &'static str {
+    match pin_mode {
+        Some(PinMode::Direct) => "direct",
+        Some(PinMode::Indirect) => "indirect",
+        Some(PinMode::Recursive) => "recursive",
+        None => "unpinned",
+    }
+}
+
+fn pin_mode_byte(pin_mode: &PinMode) -> u8 {
+    match pin_mode {
+        PinMode::Direct => b'd',
+        PinMode::Indirect => b'i',
+        PinMode::Recursive => b'r',
+    }
+}
+
+/// The common prefix of the keys of every mode of a pin. The bytes of a cid include the lengths
+/// of its parts, so no cid is a prefix of another and the keys of the pin are exactly the prefix
+/// followed by [`pin_mode_byte`], and by the root for the keys of indirect pins.
+fn pin_key_prefix(cid: &Cid) -> Vec<u8> {
+    let mut key = b"pin.".to_vec();
+    key.extend(cid.to_bytes());
+    key
+}
+
+/// The key of a direct or recursive pin, and the prefix of the keys of an indirect one.
+fn get_pin_key(cid: &Cid, pin_mode: &PinMode) -> Vec<u8> {
+    let mut key = pin_key_prefix(cid);
+    key.push(pin_mode_byte(pin_mode));
+    key
+}
+
+/// The key of the indirect pin of `block` through `root`, so that the roots are added and removed
+/// without reading the others. The roots of a block are found by scanning the [`get_pin_key`] of
+/// the indirect mode.
+fn get_indirect_pin_key(block: &Cid, root: &Cid) -> Vec<u8> {
+    let mut key = get_pin_key(block, &PinMode::Indirect);
+    key.extend(root.to_bytes());
+    key
+}
+
+/// Splits a pin key into the cid, the mode and the bytes following the mode.
+fn split_pin_key(key: &[u8]) -> Result<(Cid, PinMode, &[u8]), Error> {
+    let invalid = || anyhow::anyhow!("invalid pin: {:?}", &*String::from_utf8_lossy(key));
+
+    let rest = key.strip_prefix(b"pin.").ok_or_else(invalid)?;
+    let mut reader = std::io::Cursor::new(rest);
+    let cid = Cid::read_bytes(&mut reader).map_err(|e| Error::from(e).context(invalid()))?;
+
+    let (mode, suffix) = match rest[reader.position() as usize..].split_first() {
+        Some((mode, suffix)) => (*mode, suffix),
+        None => return Err(invalid()),
+    };
+
+    let mode = match mode {
+        b'd' => PinMode::Direct,
+        b'r' => PinMode::Recursive,
+        b'i' => PinMode::Indirect,
+        x => return Err(anyhow::anyhow!("invalid pinmode: {}", x as char)),
+    };
+
+    Ok((cid, mode, suffix))
+}
+
+/// Inverse of [`get_pin_key`] and [`get_indirect_pin_key`], without the root of the latter.
+fn parse_pin_key(key: &[u8]) -> Result<(Cid, PinMode), Error> {
+    let (cid, mode, suffix) = split_pin_key(key)?;
+
+    let valid = match mode {
+        PinMode::Indirect => Cid::try_from(suffix).is_ok(),
+        PinMode::Direct | PinMode::Recursive => suffix.is_empty(),
+    };
+    anyhow::ensure!(valid, "invalid pin: {:?}", &*String::from_utf8_lossy(key));
+
+    Ok((cid, mode))
+}
+
+/// Returns the root of a [`get_indirect_pin_key`].
+fn parse_indirect_root(key: &[u8]) -> Result<Cid, Error> {
+    let invalid = || anyhow::anyhow!("invalid indirect pin: {:?}", &*String::from_utf8_lossy(key));
+
+    match split_pin_key(key)? {
+        (_, PinMode::Indirect, root) => {
+            Cid::try_from(root).map_err(|e| Error::from(e).context(invalid()))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+/// The modes of a pin in the order of their precedence, the mode of a cid with keys of several
+/// modes being the first of them. Like go-ipfs, recursive comes before direct before indirect.
+const PIN_MODE_PRECEDENCE: [PinMode; 3] = [PinMode::Recursive, PinMode::Direct, PinMode::Indirect];
+
+fn pin_mode_precedence(pin_mode: &PinMode) -> usize {
+    PIN_MODE_PRECEDENCE
+        .iter()
+        .position(|mode| mode == pin_mode)
+        .expect("every mode has a precedence")
 }
 
-/// Returns a tuple of the parsed mode and the key used
-fn get_pinned_mode(
+/// Returns the mode of the pin along with the first key found for it, scanning the keys of every
+/// mode of the cid at once. The mode is picked by [`PIN_MODE_PRECEDENCE`], not by the order of
+/// the keys.
+fn get_pinned_mode(tree: &Tree, block: &Cid) -> Result<Option<(PinMode, sled::IVec)>, Error> {
+    let prefix = pin_key_prefix(block);
+    let mut found: Option<(PinMode, sled::IVec)> = None;
+
+    for item in tree.scan_prefix(&prefix) {
+        let (key, _) = item?;
+        let (_, mode) = parse_pin_key(&key)?;
+        let precedes = match &found {
+            Some((current, _)) => pin_mode_precedence(&mode) < pin_mode_precedence(current),
+            None => true,
+        };
+        if precedes {
+            found = Some((mode, key));
+        }
+    }
+
+    Ok(found)
+}
+
+/// Returns a tuple of the mode and the key of a direct or recursive pin, probing the key of each
+/// in the order of [`PIN_MODE_PRECEDENCE`]. Transactions only support reading single keys, so the
+/// indirect pins, with a key for each root, cannot be found in them.
+fn get_direct_or_recursive_tx(
     tree: &TransactionalTree,
     block: &Cid,
-) -> Result<Option<(PinMode, String)>, UnabortableTransactionError> {
-    for mode in &[PinMode::Direct, PinMode::Recursive, PinMode::Indirect] {
+) -> Result<Option<(PinMode, Vec<u8>)>, UnabortableTransactionError> {
+    for mode in &[PinMode::Recursive, PinMode::Direct] {
         let key = get_pin_key(block, mode);
 
-        if tree.get(key.as_str())?.is_some() {
+        if tree.get(key.as_slice())?.is_some() {
             return Ok(Some((*mode, key)));
         }
     }
@@ -543,10 +1216,7 @@ fn is_not_pinned_or_pinned_indirectly(
     tree: &TransactionalTree,
     block: &Cid,
 ) -> Result<bool, UnabortableTransactionError> {
-    match get_pinned_mode(tree, block)? {
-        Some((PinMode::Indirect, _)) | None => Ok(true),
-        _ => Ok(false),
-    }
+    Ok(get_direct_or_recursive_tx(tree, block)?.is_none())
 }
 
 #[cfg(test)]
@@ -557,7 +1227,117 @@ crate::pinstore_interface_tests!(
 
 #[cfg(test)]
 mod test {
-    use crate::repo::{datastore::sled::SledDataStore, DataStore};
+    use crate::repo::{datastore::sled::SledDataStore, DataStore, PinStore};
+    use futures::StreamExt;
+    use libipld::Cid;
+    use parking_lot::Mutex;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use tracing_subscriber::layer::{Context, SubscriberExt};
+
+    type CapturedEvent = (tracing::Level, HashMap<String, String>);
+
+    /// Collects the fields of every event as strings.
+    #[derive(Clone, Default)]
+    struct CapturingLayer(Arc<Mutex<Vec<CapturedEvent>>>);
+
+    struct FieldVisitor<'a>(&'a mut HashMap<String, String>);
+
+    impl tracing::field::Visit for FieldVisitor<'_> {
+        fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+            self.0.insert(field.name().to_string(), value.to_string());
+        }
+
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .insert(field.name().to_string(), format!("{value:?}"));
+        }
+    }
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for CapturingLayer {
+        fn on_event(&self, event: &tracing::Event<'_>, _: Context<'_, S>) {
+            let mut fields = HashMap::new();
+            event.record(&mut FieldVisitor(&mut fields));
+            self.0.lock().push((*event.metadata().level(), fields));
+        }
+    }
+
+    /// Initializes a store over a database closed just before, which the background threads of
+    /// sled can keep locked for a moment after it has been dropped.
+    async fn init_reopened(store: &SledDataStore) {
+        for _ in 0..100 {
+            match store.init().await {
+                Ok(()) => return,
+                Err(e) if e.to_string().contains("could not acquire lock") => {
+                    tokio::time::sleep(std::time::Duration::from_millis(10)).await
+                }
+                Err(e) => panic!("failed to init: {e}"),
+            }
+        }
+        panic!("the database stayed locked");
+    }
+
+    #[tokio::test]
+    async fn pin_conflicts_are_logged_with_fields() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let store = SledDataStore::new(tmp.path().into());
+        store.init().await.unwrap();
+
+        let layer = CapturingLayer::default();
+        let _guard =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(layer.clone()));
+
+        let empty = Cid::try_from("QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH").unwrap();
+        let referenced =
+            Cid::try_from("bafkreie7q3iidccmpvszul7kudcvvuavuo7u6gzlbobczuk5nqk3b4akba").unwrap();
+
+        store
+            .insert_recursive_pin(&empty, futures::stream::iter([Ok(referenced)]).boxed())
+            .await
+            .unwrap();
+
+        let e = store.insert_direct_pin(&empty).await.unwrap_err();
+        assert_eq!(e.to_string(), "already pinned recursively");
+
+        let other = Cid::try_from("QmX5S2xLu32K6WxWnyLeChQFbDHy79ULV9feJYH2Hy9bgp").unwrap();
+        let e = store.remove_direct_pin(&other).await.unwrap_err();
+        assert_eq!(e.to_string(), "not pinned or pinned indirectly");
+
+        let e = store
+            .remove_recursive_pin(&referenced, futures::stream::empty().boxed())
+            .await
+            .unwrap_err();
+        assert_eq!(e.to_string(), "not pinned or pinned indirectly");
+
+        let events = layer.0.lock();
+        let conflicts = events
+            .iter()
+            .filter(|(_, fields)| {
+                fields
+                    .get("message")
+                    .map(|m| m.starts_with("pin conflict"))
+                    .unwrap_or_default()
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(conflicts.len(), 3, "{events:?}");
+
+        let (level, fields) = conflicts[0];
+        assert_eq!(*level, tracing::Level::WARN);
+        assert_eq!(fields["cid"], empty.to_string());
+        assert_eq!(fields["requested"], "direct");
+        assert_eq!(fields["mode"], "recursive");
+
+        let (_, fields) = conflicts[1];
+        assert_eq!(fields["cid"], other.to_string());
+        assert_eq!(fields["mode"], "unpinned");
+
+        // the indirect pin is found after the transaction
+        let (_, fields) = conflicts[2];
+        assert_eq!(fields["cid"], referenced.to_string());
+        assert_eq!(fields["requested"], "recursive");
+        assert_eq!(fields["mode"], "indirect");
+    }
 
     #[tokio::test]
     async fn test_kv_datastore() {
@@ -589,4 +1369,771 @@ mod test {
         assert_eq!(get.await.unwrap(), None);
         drop(store);
     }
+
+    #[tokio::test]
+    async fn slow_listing_does_not_read_ahead_unboundedly() {
+        use super::spawn_pin_listing;
+        use crate::repo::PinMode;
+        use libipld::{
+            multihash::{Code, MultihashDigest},
+            IpldCodec,
+        };
+        use std::time::Duration;
+
+        let tmp = tempfile::TempDir::new().unwrap();
+        let store = SledDataStore::new(tmp.path().into()).with_list_buffer(4);
+        store.init().await.unwrap();
+
+        for i in 0..50u32 {
+            let cid = Cid::new_v1(
+                IpldCodec::Raw.into(),
+                Code::Sha2_256.digest(&i.to_be_bytes()),
+            );
+            store.insert_direct_pin(&cid).await.unwrap();
+        }
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(4);
+        let probe = tx.clone();
+        spawn_pin_listing(store.get_db().unwrap().clone(), None, tx);
+
+        rx.recv().await.unwrap().unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        // the listing waits with the buffer full instead of reading every pin
+        assert_eq!(probe.capacity(), 0);
+        drop(probe);
+
+        let mut listed = 1;
+        while let Some(res) = rx.recv().await {
+            assert_eq!(res.unwrap().1, PinMode::Direct);
+            listed += 1;
+        }
+        assert_eq!(listed, 50);
+
+        // the same through the store
+        let pins = store.list(None).await.collect::<Vec<_>>().await;
+        assert_eq!(pins.len(), 50);
+    }
+
+    #[tokio::test]
+    async fn iter_fails_before_init() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let store = SledDataStore::new(tmp.path().into());
+
+        let items = store.iter().await.collect::<Vec<_>>().await;
+        assert_eq!(items.len(), 1);
+        assert_eq!(
+            items[0].as_ref().unwrap_err().to_string(),
+            "sled datastore is not initialized"
+        );
+    }
+
+    #[tokio::test]
+    async fn data_is_kept_apart_from_pins() {
+        use futures::TryStreamExt;
+
+        let tmp = tempfile::TempDir::new().unwrap();
+        let store = SledDataStore::new(tmp.path().into());
+        store.init().await.unwrap();
+
+        let cid = Cid::try_from("QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH").unwrap();
+        store.insert_direct_pin(&cid).await.unwrap();
+        store.put(b"key", b"value").await.unwrap();
+
+        let pairs = store.iter().await.try_collect::<Vec<_>>().await.unwrap();
+        assert_eq!(pairs, vec![(b"key".to_vec(), b"value".to_vec())]);
+        assert!(store.is_pinned(&cid).await.unwrap());
+
+        store.wipe().await;
+
+        assert!(!store.contains(b"key").await.unwrap());
+        assert!(!store.is_pinned(&cid).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn pins_round_trip_with_a_small_cache() {
+        use super::SledConfig;
+        use crate::repo::PinMode;
+        use futures::TryStreamExt;
+        use sled::Mode;
+
+        let tmp = tempfile::TempDir::new().unwrap();
+        let config = SledConfig {
+            mode: Mode::LowSpace,
+            cache_capacity: 64 * 1024,
+            flush_interval: None,
+        };
+
+        let direct = Cid::try_from("QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH").unwrap();
+        let recursive = Cid::try_from("QmX5S2xLu32K6WxWnyLeChQFbDHy79ULV9feJYH2Hy9bgp").unwrap();
+
+        let store = SledDataStore::with_config(tmp.path().into(), config);
+        store.init().await.unwrap();
+        store.insert_direct_pin(&direct).await.unwrap();
+        store
+            .insert_recursive_pin(&recursive, futures::stream::empty().boxed())
+            .await
+            .unwrap();
+        drop(store);
+
+        let store = SledDataStore::with_config(tmp.path().into(), config);
+        init_reopened(&store).await;
+
+        let mut pins = store
+            .list(None)
+            .await
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        pins.sort_by_key(|(cid, _)| cid.to_string());
+        assert_eq!(
+            pins,
+            vec![(recursive, PinMode::Recursive), (direct, PinMode::Direct)]
+        );
+    }
+
+    #[tokio::test]
+    async fn data_next_to_pins_is_migrated() {
+        let tmp = tempfile::TempDir::new().unwrap();
+
+        // layout of earlier versions, with the data in the default tree
+        {
+            let db = sled::open(tmp.path()).unwrap();
+            db.insert(b"key", b"value").unwrap();
+            db.insert(b"pin.direct.cid", b"d").unwrap();
+            db.flush().unwrap();
+        }
+
+        let store = SledDataStore::new(tmp.path().into());
+        init_reopened(&store).await;
+
+        assert_eq!(store.get(b"key").await.unwrap(), Some(b"value".to_vec()));
+        assert!(!store.contains(b"pin.direct.cid").await.unwrap());
+
+        let db = store.get_db().unwrap();
+        assert!(db.contains_key(b"pin.direct.cid").unwrap());
+        assert!(!db.contains_key(b"key").unwrap());
+    }
+
+    #[tokio::test]
+    async fn interrupted_data_migration_is_resumed_once() {
+        use super::{DATA_TREE, META_TREE};
+
+        let tmp = tempfile::TempDir::new().unwrap();
+
+        // a migration interrupted after moving some of the pairs
+        {
+            let db = sled::open(tmp.path()).unwrap();
+            db.open_tree(DATA_TREE)
+                .unwrap()
+                .insert(b"moved", b"1")
+                .unwrap();
+            db.insert(b"left", b"2").unwrap();
+            db.insert(b"zzz", b"3").unwrap();
+            db.flush().unwrap();
+        }
+
+        let store = SledDataStore::new(tmp.path().into());
+        init_reopened(&store).await;
+
+        assert_eq!(store.get(b"moved").await.unwrap(), Some(b"1".to_vec()));
+        assert_eq!(store.get(b"left").await.unwrap(), Some(b"2".to_vec()));
+        assert_eq!(store.get(b"zzz").await.unwrap(), Some(b"3".to_vec()));
+
+        let db = store.get_db().unwrap();
+        assert!(db.is_empty());
+        assert!(db
+            .open_tree(META_TREE)
+            .unwrap()
+            .contains_key(super::DATA_TREE_MIGRATED)
+            .unwrap());
+
+        // once done, the default tree is no longer looked at
+        db.insert(b"stray", b"4").unwrap();
+        db.flush().unwrap();
+        drop(store);
+
+        let store = SledDataStore::new(tmp.path().into());
+        init_reopened(&store).await;
+        assert!(!store.contains(b"stray").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn old_pin_keys_are_migrated() {
+        use crate::repo::{PinKind, PinMode};
+        use futures::TryStreamExt;
+
+        let tmp = tempfile::TempDir::new().unwrap();
+
+        let root = Cid::try_from("QmX5S2xLu32K6WxWnyLeChQFbDHy79ULV9feJYH2Hy9bgp").unwrap();
+        let empty = Cid::try_from("QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH").unwrap();
+        let direct =
+            Cid::try_from("bafkreie7q3iidccmpvszul7kudcvvuavuo7u6gzlbobczuk5nqk3b4akba").unwrap();
+
+        // keys of earlier versions, with the mode ahead of the cid as a string
+        {
+            let db = sled::open(tmp.path()).unwrap();
+            db.insert(format!("pin.r.{root}"), b"").unwrap();
+            db.insert(format!("pin.i.{empty}"), root.to_string().as_bytes())
+                .unwrap();
+            db.insert(format!("pin.d.{direct}"), b"").unwrap();
+            db.flush().unwrap();
+        }
+
+        let store = SledDataStore::new(tmp.path().into());
+        init_reopened(&store).await;
+
+        let db = store.get_db().unwrap();
+        assert!(!db.contains_key(format!("pin.r.{root}")).unwrap());
+        assert!(db
+            .contains_key(super::get_pin_key(&root, &PinMode::Recursive))
+            .unwrap());
+        assert_eq!(db.scan_prefix(b"pin.").count(), 3);
+
+        let queried = store.query(vec![root, empty, direct], None).await.unwrap();
+        assert_eq!(
+            queried,
+            vec![
+                (root, PinKind::Recursive(0)),
+                (empty, PinKind::IndirectFrom(root)),
+                (direct, PinKind::Direct),
+            ]
+        );
+
+        let mut listed = store
+            .list(None)
+            .await
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        listed.sort_by_key(|(cid, _)| cid.to_string());
+        let mut expected = vec![
+            (root, PinMode::Recursive),
+            (empty, PinMode::Indirect),
+            (direct, PinMode::Direct),
+        ];
+        expected.sort_by_key(|(cid, _)| cid.to_string());
+        assert_eq!(listed, expected);
+
+        store.remove_direct_pin(&direct).await.unwrap();
+        assert!(!store.is_pinned(&direct).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn pinned_mode_scan_follows_precedence() {
+        use super::{get_indirect_pin_key, get_pin_key, get_pinned_mode, indirect_value};
+        use crate::repo::PinMode;
+        use libipld::{
+            multihash::{Code, MultihashDigest},
+            IpldCodec,
+        };
+
+        let tmp = tempfile::TempDir::new().unwrap();
+        let store = SledDataStore::new(tmp.path().into());
+        store.init().await.unwrap();
+
+        let cids = (0..8u8)
+            .map(|i| Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&[i])))
+            .chain([
+                Cid::try_from("QmX5S2xLu32K6WxWnyLeChQFbDHy79ULV9feJYH2Hy9bgp").unwrap(),
+                Cid::try_from("QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH").unwrap(),
+            ])
+            .collect::<Vec<_>>();
+
+        store.insert_direct_pin(&cids[0]).await.unwrap();
+        store.insert_direct_pin(&cids[8]).await.unwrap();
+        store
+            .insert_recursive_pin(
+                &cids[1],
+                futures::stream::iter(vec![Ok(cids[2]), Ok(cids[3]), Ok(cids[9])]).boxed(),
+            )
+            .await
+            .unwrap();
+        store
+            .insert_recursive_pin(&cids[4], futures::stream::empty().boxed())
+            .await
+            .unwrap();
+
+        // keys of several modes are found by precedence rather than by the order of the keys, in
+        // which the indirect keys come ahead of the recursive one
+        let db = store.get_db().unwrap();
+        for (cid, mode) in [(cids[6], PinMode::Recursive), (cids[7], PinMode::Direct)] {
+            db.insert(get_pin_key(&cid, &mode), b"").unwrap();
+            for root in [cids[1], cids[4]] {
+                db.insert(get_indirect_pin_key(&cid, &root), indirect_value())
+                    .unwrap();
+            }
+        }
+
+        let expected = [
+            Some(PinMode::Direct),
+            Some(PinMode::Recursive),
+            Some(PinMode::Indirect),
+            Some(PinMode::Indirect),
+            Some(PinMode::Recursive),
+            None,
+            Some(PinMode::Recursive),
+            Some(PinMode::Direct),
+            Some(PinMode::Direct),
+            Some(PinMode::Indirect),
+        ];
+
+        for (cid, expected) in cids.iter().zip(expected) {
+            let scanned = get_pinned_mode(db, cid).unwrap().map(|(mode, _)| mode);
+            assert_eq!(scanned, expected, "{cid}");
+        }
+    }
+
+    #[tokio::test]
+    async fn old_pin_keys_are_migrated_in_chunks() {
+        use super::{get_pin_key, MIGRATION_CHUNK};
+        use crate::repo::PinMode;
+        use libipld::{
+            multihash::{Code, MultihashDigest},
+            IpldCodec,
+        };
+
+        let tmp = tempfile::TempDir::new().unwrap();
+
+        let cids = (0..MIGRATION_CHUNK as u32 * 2 + 1)
+            .map(|i| {
+                Cid::new_v1(
+                    IpldCodec::Raw.into(),
+                    Code::Sha2_256.digest(&i.to_be_bytes()),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        {
+            let db = sled::open(tmp.path()).unwrap();
+            for cid in &cids {
+                db.insert(format!("pin.d.{cid}"), b"").unwrap();
+            }
+            db.flush().unwrap();
+        }
+
+        let store = SledDataStore::new(tmp.path().into());
+        init_reopened(&store).await;
+
+        let db = store.get_db().unwrap();
+        assert_eq!(db.scan_prefix(b"pin.d.").count(), 0);
+        assert_eq!(db.scan_prefix(b"pin.").count(), cids.len());
+        for cid in &cids {
+            assert!(db.contains_key(get_pin_key(cid, &PinMode::Direct)).unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn joined_indirect_roots_are_migrated() {
+        use super::{get_indirect_pin_key, get_pin_key, read_indirect_roots, MIGRATION_CHUNK};
+        use crate::repo::{PinKind, PinMode};
+        use libipld::{
+            multihash::{Code, MultihashDigest},
+            IpldCodec,
+        };
+
+        let tmp = tempfile::TempDir::new().unwrap();
+
+        let cid = |i: u32| {
+            Cid::new_v1(
+                IpldCodec::Raw.into(),
+                Code::Sha2_256.digest(&i.to_be_bytes()),
+            )
+        };
+        let (first, second) = (cid(u32::MAX), cid(u32::MAX - 1));
+        let blocks = (0..MIGRATION_CHUNK as u32 + 1).map(cid).collect::<Vec<_>>();
+
+        // the roots of the indirect pins joined by newlines, as written by earlier versions
+        {
+            let db = sled::open(tmp.path()).unwrap();
+            for root in [first, second] {
+                db.insert(get_pin_key(&root, &PinMode::Recursive), b"")
+                    .unwrap();
+            }
+            for block in &blocks {
+                db.insert(
+                    get_pin_key(block, &PinMode::Indirect),
+                    format!("{first}\n{second}").as_bytes(),
+                )
+                .unwrap();
+            }
+            db.insert(
+                get_pin_key(&first, &PinMode::Indirect),
+                second.to_string().as_bytes(),
+            )
+            .unwrap();
+            db.flush().unwrap();
+        }
+
+        let store = SledDataStore::new(tmp.path().into());
+        init_reopened(&store).await;
+
+        let db = store.get_db().unwrap();
+        let mut roots = vec![first, second];
+        roots.sort_by_key(Cid::to_bytes);
+        for block in &blocks {
+            assert!(!db
+                .contains_key(get_pin_key(block, &PinMode::Indirect))
+                .unwrap());
+            assert_eq!(read_indirect_roots(db, block).unwrap(), roots);
+        }
+        assert!(db
+            .contains_key(get_indirect_pin_key(&first, &second))
+            .unwrap());
+        assert_eq!(db.scan_prefix(b"pin.").count(), 2 + blocks.len() * 2 + 1);
+
+        store
+            .remove_recursive_pin(&first, futures::stream::iter([Ok(blocks[0])]).boxed())
+            .await
+            .unwrap();
+        assert_eq!(
+            store.query(vec![blocks[0], first], None).await.unwrap(),
+            vec![
+                (blocks[0], PinKind::IndirectFrom(second)),
+                (first, PinKind::IndirectFrom(second)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn indirect_pin_is_kept_for_every_root() {
+        use crate::repo::PinKind;
+
+        let tmp = tempfile::TempDir::new().unwrap();
+        let store = SledDataStore::new(tmp.path().into());
+        store.init().await.unwrap();
+
+        let first = Cid::try_from("QmX5S2xLu32K6WxWnyLeChQFbDHy79ULV9feJYH2Hy9bgp").unwrap();
+        let second =
+            Cid::try_from("bafkreie7q3iidccmpvszul7kudcvvuavuo7u6gzlbobczuk5nqk3b4akba").unwrap();
+        let shared = Cid::try_from("QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH").unwrap();
+
+        let refs = || futures::stream::iter(vec![Ok(shared)]).boxed();
+
+        store.insert_recursive_pin(&first, refs()).await.unwrap();
+        store.insert_recursive_pin(&second, refs()).await.unwrap();
+
+        let db = store.get_db().unwrap();
+        let mut expected = vec![first, second];
+        expected.sort_by_key(Cid::to_bytes);
+        assert_eq!(super::read_indirect_roots(db, &shared).unwrap(), expected);
+        assert_eq!(db.scan_prefix(b"pin.").count(), 4);
+
+        store.remove_recursive_pin(&first, refs()).await.unwrap();
+
+        assert!(store.is_pinned(&shared).await.unwrap());
+        assert_eq!(
+            store.query(vec![shared], None).await.unwrap(),
+            vec![(shared, PinKind::IndirectFrom(second))]
+        );
+
+        // the remaining root is downgraded and then upgraded back
+        store.downgrade_to_direct(&second, refs()).await.unwrap();
+        assert!(!store.is_pinned(&shared).await.unwrap());
+        store.upgrade_to_recursive(&second, refs()).await.unwrap();
+        store.insert_recursive_pin(&first, refs()).await.unwrap();
+
+        store.remove_recursive_pin(&second, refs()).await.unwrap();
+        assert_eq!(
+            store.query(vec![shared], None).await.unwrap(),
+            vec![(shared, PinKind::IndirectFrom(first))]
+        );
+
+        store.remove_recursive_pin(&first, refs()).await.unwrap();
+        assert!(!store.is_pinned(&shared).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn indirect_pin_is_kept_under_direct_pin() {
+        use crate::repo::{PinKind, PinMode};
+        use futures::TryStreamExt;
+
+        let tmp = tempfile::TempDir::new().unwrap();
+        let store = SledDataStore::new(tmp.path().into());
+        store.init().await.unwrap();
+
+        let root = Cid::try_from("QmX5S2xLu32K6WxWnyLeChQFbDHy79ULV9feJYH2Hy9bgp").unwrap();
+        let other =
+            Cid::try_from("bafkreie7q3iidccmpvszul7kudcvvuavuo7u6gzlbobczuk5nqk3b4akba").unwrap();
+        let block = Cid::try_from("QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH").unwrap();
+
+        let refs = || futures::stream::iter(vec![Ok(block)]).boxed();
+
+        // pinned through the root first, then directly
+        store.insert_recursive_pin(&root, refs()).await.unwrap();
+        store.insert_direct_pin(&block).await.unwrap();
+
+        assert_eq!(
+            store.query(vec![block], None).await.unwrap(),
+            vec![(block, PinKind::Direct)]
+        );
+        let mut listed = store
+            .list(None)
+            .await
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        listed.sort_by_key(|(cid, _)| cid.to_string());
+        assert_eq!(
+            listed,
+            vec![(root, PinMode::Recursive), (block, PinMode::Direct)]
+        );
+        assert_eq!(
+            store.list_page(None, None, 10).await.unwrap().len(),
+            2,
+            "the block is paged once"
+        );
+
+        store.remove_direct_pin(&block).await.unwrap();
+        assert_eq!(
+            store.query(vec![block], None).await.unwrap(),
+            vec![(block, PinKind::IndirectFrom(root))]
+        );
+
+        // pinned directly first, then through another root
+        store.insert_direct_pin(&block).await.unwrap();
+        store.insert_recursive_pin(&other, refs()).await.unwrap();
+        store.remove_recursive_pin(&root, refs()).await.unwrap();
+        store.remove_direct_pin(&block).await.unwrap();
+        assert_eq!(
+            store.query(vec![block], None).await.unwrap(),
+            vec![(block, PinKind::IndirectFrom(other))]
+        );
+
+        store.remove_recursive_pin(&other, refs()).await.unwrap();
+        assert!(!store.is_pinned(&block).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn batched_recursive_pin_matches_upgraded_pin() {
+        use libipld::{
+            multihash::{Code, MultihashDigest},
+            IpldCodec,
+        };
+        use std::collections::BTreeSet;
+
+        let cid = |i: u32| {
+            Cid::new_v1(
+                IpldCodec::Raw.into(),
+                Code::Sha2_256.digest(&i.to_be_bytes()),
+            )
+        };
+        let root = cid(u32::MAX);
+        let other = cid(u32::MAX - 1);
+        let references = (0..10_000).map(cid).collect::<BTreeSet<_>>();
+        let refs = || futures::stream::iter(references.iter().copied().map(Ok)).boxed();
+
+        let mut dumps = Vec::new();
+
+        for batched in [true, false] {
+            let tmp = tempfile::TempDir::new().unwrap();
+            let store = SledDataStore::new(tmp.path().into());
+            store.init().await.unwrap();
+
+            // some of the references already have pins of their own
+            store.insert_direct_pin(&cid(1)).await.unwrap();
+            store
+                .insert_recursive_pin(
+                    &other,
+                    futures::stream::iter((2..10).map(cid).map(Ok)).boxed(),
+                )
+                .await
+                .unwrap();
+
+            // upgrading adds the root to the references one by one
+            if batched {
+                store.insert_recursive_pin(&root, refs()).await.unwrap();
+            } else {
+                store.insert_direct_pin(&root).await.unwrap();
+                store.upgrade_to_recursive(&root, refs()).await.unwrap();
+            }
+
+            let db = store.get_db().unwrap();
+            let dump = db
+                .iter()
+                .map(|res| res.map(|(k, v)| (k.to_vec(), v.to_vec())))
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            dumps.push(dump);
+        }
+
+        // the root, the other root, the direct pin and an indirect pin for every root of every
+        // reference
+        assert_eq!(dumps[0].len(), 3 + 10_000 + 8);
+        assert_eq!(dumps[0], dumps[1]);
+    }
+
+    #[tokio::test]
+    async fn recursive_pin_keeps_pins_of_its_references() {
+        use super::read_indirect_roots;
+        use crate::repo::PinMode;
+        use futures::TryStreamExt;
+        use libipld::{
+            multihash::{Code, MultihashDigest},
+            IpldCodec,
+        };
+
+        let tmp = tempfile::TempDir::new().unwrap();
+        let store = SledDataStore::new(tmp.path().into());
+        store.init().await.unwrap();
+
+        let cid = |i: u8| Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&[i]));
+        let (first, second, shared, direct) = (cid(0), cid(1), cid(2), cid(3));
+
+        // one of the references of the first pin is shared with the second one, and the other
+        // one is pinned directly
+        store
+            .insert_recursive_pin(&second, futures::stream::iter([Ok(shared)]).boxed())
+            .await
+            .unwrap();
+        store.insert_direct_pin(&direct).await.unwrap();
+        store
+            .insert_recursive_pin(
+                &first,
+                futures::stream::iter([Ok(shared), Ok(direct)]).boxed(),
+            )
+            .await
+            .unwrap();
+
+        let db = store.get_db().unwrap();
+        let mut roots = vec![first, second];
+        roots.sort_by_key(Cid::to_bytes);
+        assert_eq!(read_indirect_roots(db, &shared).unwrap(), roots);
+
+        // the direct pin keeps the indirect one alongside, and is listed once
+        let mut listed = store
+            .list(None)
+            .await
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        listed.sort_by_key(|(cid, _)| cid.to_bytes());
+        let mut expected = vec![
+            (first, PinMode::Recursive),
+            (second, PinMode::Recursive),
+            (shared, PinMode::Indirect),
+            (direct, PinMode::Direct),
+        ];
+        expected.sort_by_key(|(cid, _)| cid.to_bytes());
+        assert_eq!(listed, expected);
+        assert_eq!(store.list_page(None, None, 10).await.unwrap(), expected);
+
+        store.remove_direct_pin(&direct).await.unwrap();
+        assert_eq!(
+            store
+                .list(Some(PinMode::Indirect))
+                .await
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn reindex_restores_indirect_pins() {
+        use super::{get_indirect_pin_key, indirect_value, read_indirect_roots};
+        use crate::repo::PinKind;
+        use libipld::{
+            multihash::{Code, MultihashDigest},
+            IpldCodec,
+        };
+
+        let tmp = tempfile::TempDir::new().unwrap();
+        let store = SledDataStore::new(tmp.path().into());
+        store.init().await.unwrap();
+
+        let cid = |i: u8| Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&[i]));
+        let (first, second, unpinned) = (cid(0), cid(1), cid(2));
+        let (a, b, c, stale) = (cid(10), cid(11), cid(12), cid(13));
+
+        let dag = HashMap::from([(first, vec![a, b, c]), (second, vec![c])]);
+        let refs = move |cid: &Cid| {
+            let refs = dag.get(cid).cloned().unwrap_or_default();
+            futures::stream::iter(refs.into_iter().map(Ok)).boxed()
+        };
+
+        store
+            .insert_recursive_pin(&first, refs(&first))
+            .await
+            .unwrap();
+        store
+            .insert_recursive_pin(&second, refs(&second))
+            .await
+            .unwrap();
+
+        // lose some of the indirect pins, and gain some which are not referenced
+        let db = store.get_db().unwrap();
+        db.remove(get_indirect_pin_key(&a, &first)).unwrap();
+        db.remove(get_indirect_pin_key(&c, &first)).unwrap();
+        db.remove(get_indirect_pin_key(&c, &second)).unwrap();
+        db.insert(get_indirect_pin_key(&b, &unpinned), indirect_value())
+            .unwrap();
+        db.insert(get_indirect_pin_key(&stale, &first), indirect_value())
+            .unwrap();
+
+        assert!(store.query(vec![a, c], None).await.unwrap().is_empty());
+
+        store.reindex_indirect(&refs).await.unwrap();
+
+        let queried = store.query(vec![a, b, c], None).await.unwrap();
+        assert_eq!(queried.len(), 3);
+        assert_eq!(queried[0], (a, PinKind::IndirectFrom(first)));
+        assert_eq!(queried[1], (b, PinKind::IndirectFrom(first)));
+        assert!(matches!(queried[2], (cid, PinKind::IndirectFrom(_)) if cid == c));
+
+        let mut expected = vec![first, second];
+        expected.sort_by_key(Cid::to_bytes);
+        assert_eq!(read_indirect_roots(db, &c).unwrap(), expected);
+        assert_eq!(read_indirect_roots(db, &b).unwrap(), vec![first]);
+
+        assert!(!store.is_pinned(&stale).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn reindex_keeps_indirect_pins_of_roots_pinned_meanwhile() {
+        use super::read_indirect_roots;
+        use libipld::{
+            multihash::{Code, MultihashDigest},
+            IpldCodec,
+        };
+
+        let tmp = tempfile::TempDir::new().unwrap();
+        let store = Arc::new(SledDataStore::new(tmp.path().into()));
+        store.init().await.unwrap();
+
+        let cid = |i: u8| Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&[i]));
+        let (first, late, shared) = (cid(0), cid(1), cid(10));
+
+        store
+            .insert_recursive_pin(&first, futures::stream::iter(vec![Ok(shared)]).boxed())
+            .await
+            .unwrap();
+
+        // pin another root referencing the same block while the listed roots are walked
+        let refs = {
+            let store = store.clone();
+            move |cid: &Cid| {
+                let store = store.clone();
+                let cid = *cid;
+                futures::stream::once(async move {
+                    if cid == first {
+                        let late_refs = futures::stream::iter(vec![Ok(shared)]).boxed();
+                        store.insert_recursive_pin(&late, late_refs).await.unwrap();
+                    }
+                    futures::stream::iter(vec![Ok(shared)])
+                })
+                .flatten()
+                .boxed()
+            }
+        };
+
+        store.reindex_indirect(&refs).await.unwrap();
+
+        let db = store.get_db().unwrap();
+        let mut expected = vec![first, late];
+        expected.sort_by_key(Cid::to_bytes);
+        assert_eq!(read_indirect_roots(db, &shared).unwrap(), expected);
+    }
 }
@@ -1,15 +1,15 @@
 //! Storage implementation(s) backing the [`crate::Ipfs`].
 use crate::error::Error;
-use crate::p2p::KadResult;
+use crate::p2p::{KadResult, Priority};
 use crate::path::IpfsPath;
-use crate::{Block, ReceiverChannel, StoragePath};
-use anyhow::anyhow;
+use crate::{Block, BlockNotFound, ReceiverChannel, StoragePath};
 use async_trait::async_trait;
 use core::fmt::Debug;
 use futures::channel::{
     mpsc::{channel, Receiver, Sender},
     oneshot,
 };
+use futures::future::{BoxFuture, FutureExt, Shared};
 use futures::sink::SinkExt;
 use futures::{StreamExt, TryStreamExt};
 use libipld::cid::Cid;
@@ -17,11 +17,12 @@ use libipld::{Ipld, IpldCodec};
 use libp2p::identity::PeerId;
 use parking_lot::{Mutex, RwLock};
 use std::borrow::Borrow;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::{error, fmt, io};
+use tokio::sync::{watch, Semaphore};
 use tracing::log;
 
 #[macro_use]
@@ -31,6 +32,7 @@ mod common_tests;
 pub mod blockstore;
 pub mod datastore;
 pub mod lock;
+pub mod pinstore;
 
 /// Path mangling done for pins and blocks
 pub(crate) mod paths;
@@ -52,6 +54,43 @@ pub enum BlockRm {
     // TODO: DownloadCancelled(Cid, Duration),
 }
 
+/// Describes the outcome of `Repo::open_with_recovery`.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RecoveryReport {
+    /// Blocks which did not match their Cid and were removed from the blockstore.
+    pub removed: Vec<Cid>,
+}
+
+/// Describes the outcome of `Repo::dedup_stat`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DedupStat {
+    /// Number of recursively pinned roots which were walked.
+    pub roots: usize,
+    /// Number of blocks referenced by only one of the roots.
+    pub unique_blocks: usize,
+    /// Total size in bytes of the blocks referenced by only one of the roots.
+    pub unique_size: u64,
+    /// Number of blocks referenced by more than one of the roots.
+    pub shared_blocks: usize,
+    /// Total size in bytes of the blocks referenced by more than one of the roots.
+    pub shared_size: u64,
+}
+
+/// Writing a block would take the repo over its storage quota even after the unpinned blocks
+/// were removed, see [`Repo::set_storage_quota`].
+#[derive(Debug, thiserror::Error)]
+#[error("storage quota of {quota} bytes exceeded")]
+pub struct QuotaExceeded {
+    pub quota: u64,
+}
+
+/// The codec of the Cid of a block written to the repo is not in the allowed codecs.
+#[derive(Debug, thiserror::Error)]
+#[error("codec {codec:#x} is not allowed")]
+pub struct CodecNotAllowed {
+    pub codec: u64,
+}
+
 // pub struct BlockNotFound(Cid);
 /// Describes the error variants for `BlockStore::remove`.
 #[derive(Debug)]
@@ -71,6 +110,16 @@ pub trait BlockStore: Debug + Send + Sync + 'static {
     async fn contains(&self, cid: &Cid) -> Result<bool, Error>;
     /// Returns a block from the blockstore.
     async fn get(&self, cid: &Cid) -> Result<Option<Block>, Error>;
+    /// Returns a block from the blockstore without verifying its data against the Cid. Used by
+    /// the stores wrapping another store, which keep other than the plain block data in it.
+    async fn get_raw(&self, cid: &Cid) -> Result<Option<Block>, Error> {
+        self.get(cid).await
+    }
+    /// Returns the size of the data of a block in the blockstore. The stores which can tell it
+    /// without reading the block, e.g. from the file metadata, do so.
+    async fn size(&self, cid: &Cid) -> Result<Option<usize>, Error> {
+        Ok(self.get(cid).await?.map(|block| block.data().len()))
+    }
     /// Inserts a block in the blockstore.
     async fn put(&self, block: Block) -> Result<(Cid, BlockPut), Error>;
     /// Removes a block from the blockstore.
@@ -94,8 +143,8 @@ pub trait DataStore: PinStore + Debug + Send + Sync + 'static {
     async fn put(&self, key: &[u8], value: &[u8]) -> Result<(), Error>;
     /// Removes a key-value pair from the datastore.
     async fn remove(&self, key: &[u8]) -> Result<(), Error>;
-    /// Iterate over the k/v of the datastore
-    async fn iter(&self) -> futures::stream::BoxStream<'static, (Vec<u8>, Vec<u8>)>;
+    /// Iterate over the k/v of the datastore, failing with the first item if they cannot be read.
+    async fn iter(&self) -> futures::stream::BoxStream<'static, Result<(Vec<u8>, Vec<u8>), Error>>;
     /// Wipes the datastore.
     async fn wipe(&self) {}
 }
@@ -139,6 +188,9 @@ impl error::Error for LockError {
     }
 }
 
+/// Removal of the unpinned blocks started to make room within the storage quota.
+type QuotaGc = Shared<BoxFuture<'static, Result<usize, Arc<Error>>>>;
+
 /// A trait for describing repository locking.
 ///
 /// This ensures no two IPFS nodes can be started with the same peer ID, as exclusive access to the
@@ -152,6 +204,17 @@ type References<'a> = futures::stream::BoxStream<'a, Result<Cid, crate::refs::Ip
 
 #[async_trait]
 pub trait PinStore: Debug + Send + Sync + Unpin + 'static {
+    /// Called by [`Repo::init`] on a pin store given in place of the data store. Named apart from
+    /// [`DataStore::init`] as the data stores are pin stores as well.
+    async fn init_pins(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Called by [`Repo::open`] on a pin store given in place of the data store.
+    async fn open_pins(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
     async fn is_pinned(&self, block: &Cid) -> Result<bool, Error>;
 
     async fn insert_direct_pin(&self, target: &Cid) -> Result<(), Error>;
@@ -170,11 +233,55 @@ pub trait PinStore: Debug + Send + Sync + Unpin + 'static {
         referenced: References<'_>,
     ) -> Result<(), Error>;
 
+    /// Changes the recursive pin of `target` into a direct pin in a single step, so that `target`
+    /// stays pinned throughout. The `referenced` blocks lose their indirect pins through `target`.
+    /// Fails if `target` is not pinned recursively.
+    async fn downgrade_to_direct(
+        &self,
+        target: &Cid,
+        referenced: References<'_>,
+    ) -> Result<(), Error>;
+
+    /// Changes the direct pin of `target` into a recursive pin in a single step, pinning the
+    /// `referenced` blocks indirectly. Fails if `target` is not pinned directly.
+    async fn upgrade_to_recursive(
+        &self,
+        target: &Cid,
+        referenced: References<'_>,
+    ) -> Result<(), Error>;
+
     async fn list(
         &self,
         mode: Option<PinMode>,
     ) -> futures::stream::BoxStream<'static, Result<(Cid, PinMode), Error>>;
 
+    /// Lists at most `limit` pins matching `mode` in the order of the binary representation of
+    /// their cids, starting after the `start_after` cid. Passing the last cid of a page as
+    /// `start_after` resumes the listing with the next page.
+    ///
+    /// The default implementation reads and sorts all of the pins on every call.
+    async fn list_page(
+        &self,
+        mode: Option<PinMode>,
+        start_after: Option<Cid>,
+        limit: usize,
+    ) -> Result<Vec<(Cid, PinMode)>, Error> {
+        let start_after = start_after.map(|cid| cid.to_bytes());
+        let mut pins = self
+            .list(mode)
+            .await
+            .map_ok(|(cid, mode)| (cid.to_bytes(), (cid, mode)))
+            .try_filter(|(key, _)| {
+                let after = start_after.as_ref().map_or(true, |start| key > start);
+                futures::future::ready(after)
+            })
+            .try_collect::<Vec<_>>()
+            .await?;
+
+        pins.sort_by(|(left, _), (right, _)| left.cmp(right));
+        Ok(pins.into_iter().take(limit).map(|(_, pin)| pin).collect())
+    }
+
     // here we should have resolved ids
     // go-ipfs: doesnt start fetching the paths
     // js-ipfs: starts fetching paths
@@ -187,6 +294,91 @@ pub trait PinStore: Debug + Send + Sync + Unpin + 'static {
         ids: Vec<Cid>,
         requirement: Option<PinMode>,
     ) -> Result<Vec<(Cid, PinKind<Cid>)>, Error>;
+
+    /// Adds and removes direct and recursive pins until the pins match `desired`, returning the
+    /// changes made. The references of recursive pins which are added or removed are obtained
+    /// through `refs`. Indirect pins follow their recursive roots and cannot be given in
+    /// `desired`.
+    ///
+    /// Changing the mode of a pin between direct and recursive is done in place, so that the pin
+    /// is kept throughout.
+    async fn sync(
+        &self,
+        desired: Vec<(Cid, PinMode)>,
+        refs: &(dyn for<'c> Fn(&'c Cid) -> References<'static> + Send + Sync),
+    ) -> Result<SyncReport, Error> {
+        let mut current = HashMap::new();
+        for mode in [PinMode::Recursive, PinMode::Direct] {
+            let mut pins = self.list(Some(mode)).await;
+            while let Some((cid, mode)) = pins.try_next().await? {
+                current.entry(cid).or_insert(mode);
+            }
+        }
+
+        let mut wanted = HashMap::with_capacity(desired.len());
+        for (cid, mode) in desired {
+            if mode == PinMode::Indirect {
+                anyhow::bail!("{cid} cannot be synced as an indirect pin");
+            }
+            wanted.insert(cid, mode);
+        }
+
+        let mut report = SyncReport::default();
+
+        // removals are done before the additions as some of the stores keep only a single source
+        // for an indirect pin; removing a recursive pin after adding another one sharing the same
+        // references would leave those references unpinned.
+        for (cid, mode) in &current {
+            match (mode, wanted.get(cid)) {
+                (PinMode::Direct, None) => self.remove_direct_pin(cid).await?,
+                (PinMode::Recursive, None) => self.remove_recursive_pin(cid, refs(cid)).await?,
+                (PinMode::Recursive, Some(PinMode::Direct)) => {
+                    self.downgrade_to_direct(cid, refs(cid)).await?
+                }
+                _ => continue,
+            }
+            report.removed.push((*cid, *mode));
+        }
+
+        for (cid, mode) in wanted {
+            match (current.get(&cid), mode) {
+                (Some(current), mode) if *current == mode => continue,
+                // downgraded along with the removals
+                (Some(PinMode::Recursive), PinMode::Direct) => {}
+                (_, PinMode::Direct) => self.insert_direct_pin(&cid).await?,
+                (current, _) => {
+                    self.insert_recursive_pin(&cid, refs(&cid)).await?;
+                    if let Some(PinMode::Direct) = current {
+                        report.removed.push((cid, PinMode::Direct));
+                    }
+                }
+            }
+            report.added.push((cid, mode));
+        }
+
+        Ok(report)
+    }
+
+    /// Rebuilds the indirect pins from the recursive pins, walking the references of every
+    /// recursive pin through `refs`. Pins the references missing their indirect pin and removes
+    /// the indirect pins through blocks which are no longer recursive pins referencing them.
+    ///
+    /// Meant for repairing the pins after a crash or an upgrade; the store is walked in full.
+    async fn reindex_indirect(
+        &self,
+        _refs: &(dyn for<'c> Fn(&'c Cid) -> References<'static> + Send + Sync),
+    ) -> Result<(), Error> {
+        anyhow::bail!("reindexing the indirect pins is not supported by {self:?}")
+    }
+}
+
+/// Describes the changes made by [`PinStore::sync`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SyncReport {
+    /// Pins which were added, including pins which changed their mode.
+    pub added: Vec<(Cid, PinMode)>,
+    /// Pins which were removed, including the previous mode of pins which changed their mode.
+    pub removed: Vec<(Cid, PinMode)>,
 }
 
 /// `PinMode` is the description of pin type for quering purposes.
@@ -285,14 +477,100 @@ impl<C: Borrow<Cid>> PinKind<C> {
 pub struct Repo {
     online: Arc<AtomicBool>,
     initialized: Arc<AtomicBool>,
+    ready: Arc<watch::Sender<bool>>,
     block_store: Arc<dyn BlockStore>,
     data_store: Arc<dyn DataStore>,
+    pin_store: Arc<dyn PinStore>,
+    storage_quota: Arc<Mutex<Option<u64>>>,
+    /// Total size of the blocks, counted once the first block is written under a quota and kept
+    /// up to date by the writes and removals after that.
+    stored_size: Arc<Mutex<Option<u64>>>,
+    /// Held by the writes under a quota from checking the room for the block until it is written.
+    quota_writes: Arc<tokio::sync::Mutex<()>>,
+    /// Collection started by a write over the quota, shared by the writes waiting for room.
+    quota_gc: Arc<Mutex<Option<QuotaGc>>>,
+    /// Blocks being written which are not collected, see [`Repo::pending_writes`].
+    pending_writes: Arc<Mutex<HashMap<Cid, usize>>>,
+    preferred_providers: Arc<Mutex<Vec<PeerId>>>,
+    allowed_codecs: Option<Arc<HashSet<u64>>>,
     events: Arc<RwLock<Option<Sender<RepoEvent>>>>,
-    pub(crate) subscriptions:
-        Arc<Mutex<HashMap<Cid, Vec<futures::channel::oneshot::Sender<Result<Block, String>>>>>>,
+    pub(crate) subscriptions: Arc<
+        Mutex<HashMap<Cid, Vec<futures::channel::oneshot::Sender<Result<Block, BlockNotFound>>>>>,
+    >,
+    session_priorities: Arc<Mutex<HashMap<u64, Priority>>>,
+    block_sources: Arc<Mutex<HashMap<u64, BlockSources>>>,
     lockfile: Arc<dyn Lock>,
 }
 
+/// Keeps the priority of a session set with [`Repo::prioritize_session`] until dropped.
+pub(crate) struct SessionPriority {
+    session: u64,
+    priorities: Arc<Mutex<HashMap<u64, Priority>>>,
+}
+
+impl Drop for SessionPriority {
+    fn drop(&mut self) {
+        self.priorities.lock().remove(&self.session);
+    }
+}
+
+/// Keeps the blocks written through it from being collected until dropped, created with
+/// [`Repo::pending_writes`].
+pub(crate) struct PendingWrites {
+    blocks: Mutex<HashSet<Cid>>,
+    pending: Arc<Mutex<HashMap<Cid, usize>>>,
+}
+
+impl PendingWrites {
+    /// Keeps `cid` from being collected, before writing it.
+    pub(crate) fn protect(&self, cid: Cid) {
+        if self.blocks.lock().insert(cid) {
+            *self.pending.lock().entry(cid).or_default() += 1;
+        }
+    }
+}
+
+impl Drop for PendingWrites {
+    fn drop(&mut self) {
+        let mut pending = self.pending.lock();
+        for cid in self.blocks.get_mut().drain() {
+            if let std::collections::hash_map::Entry::Occupied(mut entry) = pending.entry(cid) {
+                *entry.get_mut() -= 1;
+                if *entry.get() == 0 {
+                    entry.remove();
+                }
+            }
+        }
+    }
+}
+
+/// Collects the peer which served each of the blocks fetched from the network by a get, see
+/// [`crate::unixfs::GetOptions::block_sources`]. Blocks which were already in the repo are not
+/// included.
+#[derive(Debug, Clone, Default)]
+pub struct BlockSources(Arc<Mutex<HashMap<Cid, PeerId>>>);
+
+impl BlockSources {
+    /// Returns the peer which served each of the blocks fetched so far.
+    pub fn get(&self) -> HashMap<Cid, PeerId> {
+        self.0.lock().clone()
+    }
+}
+
+/// Records the sources of the blocks fetched in a bitswap session, created with
+/// [`Repo::record_block_sources`]. The recording stops once dropped.
+#[derive(Debug)]
+pub(crate) struct BitswapSession {
+    id: u64,
+    sources: Arc<Mutex<HashMap<u64, BlockSources>>>,
+}
+
+impl Drop for BitswapSession {
+    fn drop(&mut self) {
+        self.sources.lock().remove(&self.id);
+    }
+}
+
 #[async_trait]
 impl beetle_bitswap_next::Store for Repo {
     async fn get_size(&self, cid: &Cid) -> anyhow::Result<usize> {
@@ -319,8 +597,8 @@ impl beetle_bitswap_next::Store for Repo {
 /// Events used to communicate to the swarm on repo changes.
 #[derive(Debug)]
 pub enum RepoEvent {
-    /// Signals a desired block.
-    WantBlock(Option<u64>, Cid, Vec<PeerId>),
+    /// Signals a desired block, wanted at the given priority.
+    WantBlock(Option<u64>, Cid, Vec<PeerId>, Priority),
     /// Signals a desired block is no longer wanted.
     UnwantBlock(Cid),
     /// Signals the posession of a new block.
@@ -352,16 +630,37 @@ impl Repo {
     ) -> Self {
         Repo {
             initialized: Arc::default(),
+            ready: Arc::new(watch::channel(false).0),
             online: Arc::default(),
             block_store,
+            pin_store: Arc::new(DataStorePins(data_store.clone())),
+            storage_quota: Arc::default(),
+            stored_size: Arc::default(),
+            quota_writes: Arc::default(),
+            quota_gc: Arc::default(),
+            pending_writes: Arc::default(),
+            preferred_providers: Arc::default(),
+            allowed_codecs: None,
             data_store,
             events: Arc::default(),
             subscriptions: Default::default(),
+            session_priorities: Default::default(),
+            block_sources: Default::default(),
             lockfile,
         }
     }
 
     pub fn new_fs(path: impl AsRef<Path>) -> Self {
+        Self::new_fs_with_sled_config(path, Default::default())
+    }
+
+    /// Like [`Repo::new_fs`], opening the data store with the given settings when it is the sled
+    /// one of the `sled_data_store` feature. The settings are not used otherwise.
+    #[cfg_attr(not(feature = "sled_data_store"), allow(unused_variables))]
+    pub fn new_fs_with_sled_config(
+        path: impl AsRef<Path>,
+        sled_config: datastore::sled::SledConfig,
+    ) -> Self {
         let path = path.as_ref().to_path_buf();
         let mut blockstore_path = path.clone();
         let mut datastore_path = path.clone();
@@ -374,7 +673,10 @@ impl Repo {
         #[cfg(not(feature = "sled_data_store"))]
         let data_store = Arc::new(datastore::flatfs::FsDataStore::new(datastore_path));
         #[cfg(feature = "sled_data_store")]
-        let data_store = Arc::new(datastore::sled::SledDataStore::new(datastore_path));
+        let data_store = Arc::new(datastore::sled::SledDataStore::with_config(
+            datastore_path,
+            sled_config,
+        ));
         let lockfile = Arc::new(lock::FsLock::new(lockfile_path));
         Self::new_raw(block_store, data_store, lockfile)
     }
@@ -386,6 +688,86 @@ impl Repo {
         Self::new_raw(block_store, data_store, lockfile)
     }
 
+    /// Replaces the block store of the repo. Needs to be done before the repo is initialized.
+    pub(crate) fn with_block_store(mut self, block_store: Arc<dyn BlockStore>) -> Self {
+        self.block_store = block_store;
+        self
+    }
+
+    /// Encrypts the blocks written to the block store with the given key. Needs to be done before
+    /// the repo is initialized.
+    pub(crate) fn with_block_encryption(self, key: [u8; 32]) -> Self {
+        let store = blockstore::encrypted::EncryptedBlockStore::new(self.block_store.clone(), key);
+        self.with_block_store(Arc::new(store))
+    }
+
+    /// Passes the blocks written to and read from the block store through the given transform.
+    /// Needs to be done before the repo is initialized.
+    pub(crate) fn with_block_transform(
+        self,
+        transform: Arc<dyn blockstore::transform::BlockTransform>,
+    ) -> Self {
+        let store =
+            blockstore::transform::TransformedBlockStore::new(self.block_store.clone(), transform);
+        self.with_block_store(Arc::new(store))
+    }
+
+    /// Keeps the pins in the given store instead of the data store. Needs to be done before the
+    /// repo is initialized.
+    pub(crate) fn with_pin_store(mut self, pin_store: Arc<dyn PinStore>) -> Self {
+        self.pin_store = pin_store;
+        self
+    }
+
+    /// Bounds the number of recursive pins inserted at the same time to `limit`, which is at least
+    /// one. Any further recursive pins wait for one of the running ones to complete. Needs to be
+    /// done after [`Repo::with_pin_store`].
+    pub(crate) fn with_max_concurrent_recursive_pins(mut self, limit: usize) -> Self {
+        self.pin_store = Arc::new(LimitedPins {
+            pins: self.pin_store,
+            permits: Semaphore::new(limit.max(1)),
+        });
+        self
+    }
+
+    /// Rejects the blocks whose Cid has a codec other than the given ones with
+    /// [`CodecNotAllowed`] when written to the repo.
+    pub(crate) fn with_allowed_codecs(mut self, codecs: HashSet<u64>) -> Self {
+        self.allowed_codecs = Some(Arc::new(codecs));
+        self
+    }
+
+    /// Limits the total size of the blocks in the repo to `quota` bytes, or removes the limit
+    /// with `None`.
+    ///
+    /// Writing a block which would take the repo over the quota waits for the blocks which are
+    /// not reachable from a pin to be removed in the background, and fails with [`QuotaExceeded`]
+    /// if the repo would still be over it. The writes which fit within the quota do not wait.
+    /// Blocks which are written but not yet pinned can be removed to make room, except for the
+    /// ones of the files still being added.
+    ///
+    /// The first write under a quota counts the size of every block, see
+    /// [`Repo::get_total_size`].
+    pub fn set_storage_quota(&self, quota: Option<u64>) {
+        *self.storage_quota.lock() = quota;
+    }
+
+    /// Returns the storage quota set with [`Repo::set_storage_quota`].
+    pub fn storage_quota(&self) -> Option<u64> {
+        *self.storage_quota.lock()
+    }
+
+    /// Sets the peers asked for the blocks fetched without any explicitly given providers, ahead
+    /// of the providers discovered otherwise. An empty list removes the preference.
+    pub fn set_preferred_providers(&self, providers: Vec<PeerId>) {
+        *self.preferred_providers.lock() = providers;
+    }
+
+    /// Returns the providers set with [`Repo::set_preferred_providers`].
+    pub fn preferred_providers(&self) -> Vec<PeerId> {
+        self.preferred_providers.lock().clone()
+    }
+
     pub async fn migrate(&self, repo: &Self) -> Result<(), Error> {
         if self.is_online() || repo.is_online() {
             anyhow::bail!("Repository cannot be online");
@@ -398,7 +780,7 @@ impl Repo {
                     for cid in list {
                         match this.get_block_now(&cid).await {
                             Ok(Some(block)) => match external.block_store.put(block).await {
-                                Ok(_) => {}
+                                Ok(_) => *external.stored_size.lock() = None,
                                 Err(e) => error!("Error migrating {cid}: {e}"),
                             },
                             Ok(None) => error!("{cid} doesnt exist"),
@@ -414,7 +796,14 @@ impl Repo {
             let external = repo.clone();
             async move {
                 let mut data_stream = this.data_store().iter().await;
-                while let Some((k, v)) = data_stream.next().await {
+                while let Some(item) = data_stream.next().await {
+                    let (k, v) = match item {
+                        Ok(item) => item,
+                        Err(e) => {
+                            error!("Unable to read the data to migrate: {e}");
+                            break;
+                        }
+                    };
                     if let Err(e) = external.data_store().put(&k, &v).await {
                         error!("Unable to migrate {k:?} into repo: {e}");
                     }
@@ -426,15 +815,13 @@ impl Repo {
             let this = self.clone();
             let external = repo.clone();
             async move {
-                let mut stream = this.data_store().list(None).await;
+                let mut stream = this.list_pins(None).await;
                 while let Some(Ok((cid, pin_mode))) = stream.next().await {
                     match pin_mode {
-                        PinMode::Direct => {
-                            match external.data_store().insert_direct_pin(&cid).await {
-                                Ok(_) => {}
-                                Err(e) => error!("Unable to migrate pin {cid}: {e}"),
-                            }
-                        }
+                        PinMode::Direct => match external.insert_direct_pin(&cid).await {
+                            Ok(_) => {}
+                            Err(e) => error!("Unable to migrate pin {cid}: {e}"),
+                        },
                         PinMode::Indirect => {
                             //No need to track since we will be obtaining the reference from the pin that is recursive
                             continue;
@@ -533,35 +920,97 @@ impl Repo {
 
         let f1 = self.block_store.init();
         let f2 = self.data_store.init();
-        let (r1, r2) = futures::future::join(f1, f2).await;
+        let f3 = self.pin_store.init_pins();
+        let (r1, r2, r3) = futures::future::join3(f1, f2, f3).await;
         let init = self.initialized.clone();
-        if r1.is_err() {
-            r1.map(|_| {
-                init.store(true, Ordering::SeqCst);
-            })
-        } else {
-            r2.map(|_| {
-                init.store(true, Ordering::SeqCst);
-            })
-        }
+        r1.and(r2).and(r3).map(|_| {
+            init.store(true, Ordering::SeqCst);
+        })
     }
 
     pub async fn open(&self) -> Result<(), Error> {
         let f1 = self.block_store.open();
         let f2 = self.data_store.open();
-        let (r1, r2) = futures::future::join(f1, f2).await;
-        if r1.is_err() {
-            r1
-        } else {
-            r2
+        let f3 = self.pin_store.open_pins();
+        let (r1, r2, r3) = futures::future::join3(f1, f2, f3).await;
+        r1.and(r2).and(r3)?;
+
+        if self.initialized.load(Ordering::SeqCst) {
+            self.ready.send_replace(true);
         }
+        Ok(())
+    }
+
+    /// Resolves once the stores of the repo have been initialized with [`Repo::init`] and opened
+    /// with [`Repo::open`], after which they are safe to use.
+    pub async fn ready(&self) {
+        let mut ready = self.ready.subscribe();
+        // the sender is owned by the repo, so it cannot be dropped while waiting
+        let _ = ready.wait_for(|ready| *ready).await;
+    }
+
+    /// Opens the repo like [`Repo::open`], and when `scan` is true, verifies every block in the
+    /// block store against its Cid. Blocks which are truncated or otherwise do not hash to their
+    /// Cid are removed from the block store and returned in the [`RecoveryReport`]. Pins to the
+    /// removed blocks are kept, so that the blocks can be fetched again.
+    pub async fn open_with_recovery(&self, scan: bool) -> Result<RecoveryReport, Error> {
+        self.open().await?;
+
+        let mut report = RecoveryReport::default();
+
+        if !scan {
+            return Ok(report);
+        }
+
+        for cid in self.block_store.list().await? {
+            let verified = match self.block_store.get(&cid).await {
+                Ok(Some(block)) => {
+                    let (cid, data) = block.into_inner();
+                    Block::new(cid, data).map(|_| ())
+                }
+                Ok(None) => continue,
+                Err(e) => Err(e),
+            };
+
+            match verified {
+                Ok(_) => continue,
+                Err(e) if e.is::<libipld::error::InvalidMultihash>() => {
+                    log::warn!("removing corrupt block {cid}: {e}");
+                }
+                Err(e) => return Err(e),
+            }
+
+            if let Ok(BlockRm::Removed(cid)) = self.block_store.remove(&cid).await? {
+                *self.stored_size.lock() = None;
+                report.removed.push(cid);
+            }
+        }
+
+        Ok(report)
     }
 
     /// Puts a block into the block store.
     pub async fn put_block(&self, block: Block) -> Result<(Cid, BlockPut), Error> {
+        if let Some(codecs) = &self.allowed_codecs {
+            let codec = block.cid().codec();
+            if !codecs.contains(&codec) {
+                return Err(CodecNotAllowed { codec }.into());
+            }
+        }
+
+        // concurrent writes could otherwise all find room for their block
+        let _quota_write = match self.storage_quota() {
+            Some(quota) => Some(self.reserve_storage(&block, quota).await?),
+            None => None,
+        };
+
         let (cid, res) = self.block_store.put(block.clone()).await?;
 
         if let BlockPut::NewBlock = res {
+            if let Some(size) = self.stored_size.lock().as_mut() {
+                *size += block.data().len() as u64;
+            }
+
             let list = self.subscriptions.lock().remove(&cid);
             if let Some(mut list) = list {
                 for ch in list.drain(..) {
@@ -576,6 +1025,86 @@ impl Repo {
         Ok((cid, res))
     }
 
+    /// Makes room for the block within the quota by removing the unpinned blocks if needed,
+    /// returning the guard to hold until the block is written.
+    ///
+    /// The unpinned blocks are removed in the background, so only the writes which would go over
+    /// the quota wait for the removal; the other writes carry on in the meantime.
+    async fn reserve_storage(
+        &self,
+        block: &Block,
+        quota: u64,
+    ) -> Result<tokio::sync::MutexGuard<'_, ()>, Error> {
+        let needed = block.data().len() as u64;
+
+        let quota_write = self.quota_writes.lock().await;
+        if self.contains(block.cid()).await? || self.stored_size().await? + needed <= quota {
+            return Ok(quota_write);
+        }
+        drop(quota_write);
+
+        // the other writes waiting for the same removal can take up the room it made, in which
+        // case the blocks they wrote can be removed in turn unless they were pinned meanwhile
+        loop {
+            let removed = self.collect_for_quota().await?;
+
+            let quota_write = self.quota_writes.lock().await;
+            if self.stored_size().await? + needed <= quota {
+                return Ok(quota_write);
+            }
+            if removed == 0 {
+                return Err(QuotaExceeded { quota }.into());
+            }
+        }
+    }
+
+    /// Removes the unpinned blocks in a task of its own, joining the removal already running if
+    /// any, and returns the number of blocks removed. The removal carries on even if the write
+    /// waiting for it is dropped.
+    async fn collect_for_quota(&self) -> Result<usize, Error> {
+        let gc = {
+            let mut running = self.quota_gc.lock();
+            match running.as_ref() {
+                Some(gc) if gc.peek().is_none() => gc.clone(),
+                _ => {
+                    let repo = self.clone();
+                    let task =
+                        tokio::spawn(async move { repo.gc(1).await.map(|removed| removed.len()) });
+                    let gc = async move {
+                        match task.await {
+                            Ok(res) => res.map_err(Arc::new),
+                            Err(e) => Err(Arc::new(e.into())),
+                        }
+                    }
+                    .boxed()
+                    .shared();
+                    *running = Some(gc.clone());
+                    gc
+                }
+            }
+        };
+
+        gc.await.map_err(|e| anyhow::anyhow!("{e:#}"))
+    }
+
+    /// Returns the total size of the blocks, counting it only the first time.
+    async fn stored_size(&self) -> Result<u64, Error> {
+        if let Some(size) = *self.stored_size.lock() {
+            return Ok(size);
+        }
+        let size = self.get_total_size().await?;
+        *self.stored_size.lock() = Some(size);
+        Ok(size)
+    }
+
+    /// Fails the pending requests for a block which none of the peers asked has.
+    pub(crate) fn block_not_found(&self, cid: &Cid) {
+        let list = self.subscriptions.lock().remove(cid);
+        for ch in list.into_iter().flatten() {
+            let _ = ch.send(Err(BlockNotFound(*cid)));
+        }
+    }
+
     /// Retrives a block from the block store, or starts fetching it from the network and awaits
     /// until it has been fetched.
     #[inline]
@@ -605,7 +1134,12 @@ impl Repo {
 
             let (tx, rx) = futures::channel::oneshot::channel();
 
-            self.subscriptions.lock().entry(*cid).or_default().push(tx);
+            {
+                let mut subscriptions = self.subscriptions.lock();
+                let waiting = subscriptions.entry(*cid).or_default();
+                waiting.retain(|tx| !tx.is_canceled());
+                waiting.push(tx);
+            }
 
             // sending only fails if no one is listening anymore
             // and that is okay with us.
@@ -614,12 +1148,68 @@ impl Repo {
                 .repo_channel()
                 .ok_or(anyhow::anyhow!("Channel is not available"))?;
 
+            let priority = session
+                .and_then(|session| self.session_priorities.lock().get(&session).copied())
+                .unwrap_or_default();
+
+            // a block already being fetched for a request of any session isn't fetched again, the
+            // task shares the fetch with this request, asking its peers as well
             events
-                .send(RepoEvent::WantBlock(session, *cid, peers.to_vec()))
+                .send(RepoEvent::WantBlock(
+                    session,
+                    *cid,
+                    peers.to_vec(),
+                    priority,
+                ))
                 .await
                 .ok();
 
-            rx.await?.map_err(|e| anyhow!("{e}"))
+            Ok(rx.await??)
+        }
+    }
+
+    /// Fetches the blocks wanted in the session at `priority` instead of the default of zero,
+    /// until the returned guard is dropped.
+    pub(crate) fn prioritize_session(&self, session: u64, priority: Priority) -> SessionPriority {
+        self.session_priorities.lock().insert(session, priority);
+        SessionPriority {
+            session,
+            priorities: self.session_priorities.clone(),
+        }
+    }
+
+    /// Keeps the blocks protected through the returned [`PendingWrites`] from being collected
+    /// until it is dropped, for the blocks of a DAG being written before its root is pinned.
+    pub(crate) fn pending_writes(&self) -> PendingWrites {
+        PendingWrites {
+            blocks: Default::default(),
+            pending: self.pending_writes.clone(),
+        }
+    }
+
+    /// Starts recording the peer which served each block fetched in the session into `sources`,
+    /// until the returned [`BitswapSession`] is dropped.
+    pub(crate) fn record_block_sources(
+        &self,
+        session: u64,
+        sources: BlockSources,
+    ) -> BitswapSession {
+        self.block_sources.lock().insert(session, sources);
+        BitswapSession {
+            id: session,
+            sources: self.block_sources.clone(),
+        }
+    }
+
+    /// Whether the sources of the blocks fetched in the session are being recorded.
+    pub(crate) fn records_block_sources(&self, session: u64) -> bool {
+        self.block_sources.lock().contains_key(&session)
+    }
+
+    /// Records `source` as the peer which served `cid` in the session, if it is being recorded.
+    pub(crate) fn record_block_source(&self, session: u64, cid: Cid, source: PeerId) {
+        if let Some(sources) = self.block_sources.lock().get(&session) {
+            sources.0.lock().insert(cid, source);
         }
     }
 
@@ -638,12 +1228,31 @@ impl Repo {
         self.block_store.list().await
     }
 
+    /// Returns the total size in bytes of the blocks in the block store, asking the block store
+    /// for the size of each block.
+    pub async fn get_total_size(&self) -> Result<u64, Error> {
+        let mut size = 0;
+        for cid in self.list_blocks().await? {
+            if let Some(len) = self.block_store.size(&cid).await? {
+                size += len as u64;
+            }
+        }
+        Ok(size)
+    }
+
     /// Remove block from the block store.
     pub async fn remove_block(&self, cid: &Cid) -> Result<Cid, Error> {
         if self.is_pinned(cid).await? {
             return Err(anyhow::anyhow!("block to remove is pinned"));
         }
 
+        let counted = self.stored_size.lock().is_some();
+        let size = match counted {
+            true => self.get_block_now(cid).await.ok().flatten(),
+            false => None,
+        }
+        .map(|block| block.data().len() as u64);
+
         // FIXME: Need to change location of pinning logic.
         // I like this pattern of the repo abstraction being some sort of
         // "clearing house" for the underlying result enums, but this
@@ -651,6 +1260,13 @@ impl Repo {
         match self.block_store.remove(cid).await? {
             Ok(success) => match success {
                 BlockRm::Removed(_cid) => {
+                    {
+                        // the size of an unreadable block isn't known, so it is counted again
+                        let mut stored = self.stored_size.lock();
+                        *stored = stored
+                            .zip(size)
+                            .map(|(stored, size)| stored.saturating_sub(size));
+                    }
                     // sending only fails if the background task has exited
                     if let Some(mut events) = self.repo_channel() {
                         events.send(RepoEvent::RemovedBlock(*cid)).await.ok();
@@ -729,23 +1345,26 @@ impl Repo {
 
     /// Inserts a direct pin for a `Cid`.
     pub async fn insert_direct_pin(&self, cid: &Cid) -> Result<(), Error> {
-        self.data_store.insert_direct_pin(cid).await
+        self.pin_store.insert_direct_pin(cid).await
     }
 
     /// Inserts a recursive pin for a `Cid`.
+    ///
+    /// Waits for one of the running recursive pins to complete first when the number of concurrent
+    /// recursive pins is limited.
     pub async fn insert_recursive_pin(&self, cid: &Cid, refs: References<'_>) -> Result<(), Error> {
-        self.data_store.insert_recursive_pin(cid, refs).await
+        self.pin_store.insert_recursive_pin(cid, refs).await
     }
 
     /// Removes a direct pin for a `Cid`.
     pub async fn remove_direct_pin(&self, cid: &Cid) -> Result<(), Error> {
-        self.data_store.remove_direct_pin(cid).await
+        self.pin_store.remove_direct_pin(cid).await
     }
 
     /// Removes a recursive pin for a `Cid`.
     pub async fn remove_recursive_pin(&self, cid: &Cid, refs: References<'_>) -> Result<(), Error> {
         // FIXME: not really sure why is there not an easier way to to transfer control
-        self.data_store.remove_recursive_pin(cid, refs).await
+        self.pin_store.remove_recursive_pin(cid, refs).await
     }
 
     /// Function to perform a basic cleanup of unpinned blocks
@@ -762,16 +1381,148 @@ impl Repo {
         Ok(removed_blocks)
     }
 
+    /// Removes the blocks which are not reachable from any pin.
+    ///
+    /// The DAGs of the recursive pins are walked by up to `concurrency` concurrent tasks to mark
+    /// the reachable blocks, after which the unmarked blocks are removed by as many tasks. Blocks
+    /// are checked to still be unpinned right before their removal, so that blocks pinned while the
+    /// collection is running are kept, as are the blocks of the DAGs still being written, see
+    /// [`Repo::pending_writes`]. Missing blocks of the pinned DAGs are skipped.
+    pub async fn gc(&self, concurrency: usize) -> Result<Vec<Cid>, Error> {
+        let concurrency = concurrency.max(1);
+        let blocks = self.list_blocks().await?;
+
+        let mut marked = std::collections::HashSet::new();
+        let mut frontier = Vec::new();
+
+        let mut pins = self.list_pins(None).await;
+        while let Some((cid, mode)) = pins.try_next().await? {
+            match mode {
+                PinMode::Recursive => {
+                    if marked.insert(cid) {
+                        frontier.push(cid);
+                    }
+                }
+                PinMode::Direct | PinMode::Indirect => {
+                    marked.insert(cid);
+                }
+            }
+        }
+
+        while !frontier.is_empty() {
+            let links = futures::stream::iter(frontier.drain(..))
+                .map(|cid| async move {
+                    let mut links = Vec::new();
+                    if let Some(block) = self.get_block_now(&cid).await? {
+                        block.references(&mut links)?;
+                    }
+                    Ok::<_, Error>(links)
+                })
+                .buffer_unordered(concurrency)
+                .try_collect::<Vec<_>>()
+                .await?;
+
+            frontier.extend(
+                links
+                    .into_iter()
+                    .flatten()
+                    .filter(|link| marked.insert(*link)),
+            );
+        }
+
+        futures::stream::iter(blocks.into_iter().filter(|cid| !marked.contains(cid)))
+            .map(|cid| async move {
+                if self.pending_writes.lock().contains_key(&cid) || self.is_pinned(&cid).await? {
+                    return Ok(None);
+                }
+                Ok::<_, Error>(self.remove_block(&cid).await.ok())
+            })
+            .buffer_unordered(concurrency)
+            .try_filter_map(|removed| futures::future::ready(Ok(removed)))
+            .try_collect()
+            .await
+    }
+
+    /// Reports how many of the blocks of the recursively pinned DAGs are shared between the roots
+    /// and how many are referenced by a single root, which is the data unpinning that root would
+    /// free. The roots themselves are counted as blocks of their own DAG.
+    ///
+    /// All the blocks of the pinned DAGs need to be available locally.
+    pub async fn dedup_stat(&self) -> Result<DedupStat, Error> {
+        let roots = self
+            .list_pins(Some(PinMode::Recursive))
+            .await
+            .map_ok(|(cid, _)| cid)
+            .try_collect::<Vec<_>>()
+            .await?;
+
+        let mut referencing_roots: HashMap<Cid, usize> = HashMap::new();
+
+        for root in &roots {
+            let block = self
+                .get_block_now(root)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("block {root} is not available locally"))?;
+            let ipld = block.decode::<IpldCodec, Ipld>()?;
+
+            let refs = crate::refs::IpldRefs::default()
+                .with_only_unique()
+                .with_existing_blocks()
+                .refs_of_resolved(self, vec![(*root, ipld)])
+                .map_ok(|crate::refs::Edge { destination, .. }| destination)
+                .try_collect::<std::collections::HashSet<_>>()
+                .await?;
+
+            *referencing_roots.entry(*root).or_default() += 1;
+            for cid in refs.into_iter().filter(|cid| cid != root) {
+                *referencing_roots.entry(cid).or_default() += 1;
+            }
+        }
+
+        let mut stat = DedupStat {
+            roots: roots.len(),
+            ..Default::default()
+        };
+
+        for (cid, count) in referencing_roots {
+            let size = match self.get_block_now(&cid).await? {
+                Some(block) => block.data().len() as u64,
+                None => 0,
+            };
+
+            if count > 1 {
+                stat.shared_blocks += 1;
+                stat.shared_size += size;
+            } else {
+                stat.unique_blocks += 1;
+                stat.unique_size += size;
+            }
+        }
+
+        Ok(stat)
+    }
+
     /// Checks if a `Cid` is pinned.
     pub async fn is_pinned(&self, cid: &Cid) -> Result<bool, Error> {
-        self.data_store.is_pinned(cid).await
+        self.pin_store.is_pinned(cid).await
     }
 
     pub async fn list_pins(
         &self,
         mode: Option<PinMode>,
     ) -> futures::stream::BoxStream<'static, Result<(Cid, PinMode), Error>> {
-        self.data_store.list(mode).await
+        self.pin_store.list(mode).await
+    }
+
+    /// Lists a page of at most `limit` pins following the `start_after` cid, see
+    /// [`PinStore::list_page`].
+    pub async fn list_pins_page(
+        &self,
+        mode: Option<PinMode>,
+        start_after: Option<Cid>,
+        limit: usize,
+    ) -> Result<Vec<(Cid, PinMode)>, Error> {
+        self.pin_store.list_page(mode, start_after, limit).await
     }
 
     pub async fn query_pins(
@@ -779,7 +1530,7 @@ impl Repo {
         cids: Vec<Cid>,
         requirement: Option<PinMode>,
     ) -> Result<Vec<(Cid, PinKind<Cid>)>, Error> {
-        self.data_store.query(cids, requirement).await
+        self.pin_store.query(cids, requirement).await
     }
 }
 
@@ -787,4 +1538,205 @@ impl Repo {
     pub fn data_store(&self) -> &dyn DataStore {
         &*self.data_store
     }
+
+    pub fn pin_store(&self) -> &dyn PinStore {
+        &*self.pin_store
+    }
+}
+
+/// Keeps the pins in the [`DataStore`] of the repo, unless another [`PinStore`] was given.
+#[derive(Debug)]
+struct DataStorePins(Arc<dyn DataStore>);
+
+#[async_trait]
+impl PinStore for DataStorePins {
+    async fn is_pinned(&self, block: &Cid) -> Result<bool, Error> {
+        self.0.is_pinned(block).await
+    }
+
+    async fn insert_direct_pin(&self, target: &Cid) -> Result<(), Error> {
+        self.0.insert_direct_pin(target).await
+    }
+
+    async fn insert_recursive_pin(
+        &self,
+        target: &Cid,
+        referenced: References<'_>,
+    ) -> Result<(), Error> {
+        self.0.insert_recursive_pin(target, referenced).await
+    }
+
+    async fn remove_direct_pin(&self, target: &Cid) -> Result<(), Error> {
+        self.0.remove_direct_pin(target).await
+    }
+
+    async fn remove_recursive_pin(
+        &self,
+        target: &Cid,
+        referenced: References<'_>,
+    ) -> Result<(), Error> {
+        self.0.remove_recursive_pin(target, referenced).await
+    }
+
+    async fn downgrade_to_direct(
+        &self,
+        target: &Cid,
+        referenced: References<'_>,
+    ) -> Result<(), Error> {
+        self.0.downgrade_to_direct(target, referenced).await
+    }
+
+    async fn upgrade_to_recursive(
+        &self,
+        target: &Cid,
+        referenced: References<'_>,
+    ) -> Result<(), Error> {
+        self.0.upgrade_to_recursive(target, referenced).await
+    }
+
+    async fn list(
+        &self,
+        mode: Option<PinMode>,
+    ) -> futures::stream::BoxStream<'static, Result<(Cid, PinMode), Error>> {
+        self.0.list(mode).await
+    }
+
+    async fn list_page(
+        &self,
+        mode: Option<PinMode>,
+        start_after: Option<Cid>,
+        limit: usize,
+    ) -> Result<Vec<(Cid, PinMode)>, Error> {
+        self.0.list_page(mode, start_after, limit).await
+    }
+
+    async fn query(
+        &self,
+        ids: Vec<Cid>,
+        requirement: Option<PinMode>,
+    ) -> Result<Vec<(Cid, PinKind<Cid>)>, Error> {
+        self.0.query(ids, requirement).await
+    }
+
+    async fn sync(
+        &self,
+        desired: Vec<(Cid, PinMode)>,
+        refs: &(dyn for<'c> Fn(&'c Cid) -> References<'static> + Send + Sync),
+    ) -> Result<SyncReport, Error> {
+        self.0.sync(desired, refs).await
+    }
+
+    async fn reindex_indirect(
+        &self,
+        refs: &(dyn for<'c> Fn(&'c Cid) -> References<'static> + Send + Sync),
+    ) -> Result<(), Error> {
+        self.0.reindex_indirect(refs).await
+    }
+}
+
+/// Limits the number of the walks of recursive pins done at the same time, set up through
+/// [`Repo::with_max_concurrent_recursive_pins`]. Besides inserting recursive pins, upgrading a
+/// direct pin, syncing and reindexing the pins walk the references and wait for a permit as well.
+#[derive(Debug)]
+struct LimitedPins {
+    pins: Arc<dyn PinStore>,
+    permits: Semaphore,
+}
+
+#[async_trait]
+impl PinStore for LimitedPins {
+    async fn init_pins(&self) -> Result<(), Error> {
+        self.pins.init_pins().await
+    }
+
+    async fn open_pins(&self) -> Result<(), Error> {
+        self.pins.open_pins().await
+    }
+
+    async fn is_pinned(&self, block: &Cid) -> Result<bool, Error> {
+        self.pins.is_pinned(block).await
+    }
+
+    async fn insert_direct_pin(&self, target: &Cid) -> Result<(), Error> {
+        self.pins.insert_direct_pin(target).await
+    }
+
+    async fn insert_recursive_pin(
+        &self,
+        target: &Cid,
+        referenced: References<'_>,
+    ) -> Result<(), Error> {
+        let _permit = self.permits.acquire().await?;
+        self.pins.insert_recursive_pin(target, referenced).await
+    }
+
+    async fn remove_direct_pin(&self, target: &Cid) -> Result<(), Error> {
+        self.pins.remove_direct_pin(target).await
+    }
+
+    async fn remove_recursive_pin(
+        &self,
+        target: &Cid,
+        referenced: References<'_>,
+    ) -> Result<(), Error> {
+        self.pins.remove_recursive_pin(target, referenced).await
+    }
+
+    async fn downgrade_to_direct(
+        &self,
+        target: &Cid,
+        referenced: References<'_>,
+    ) -> Result<(), Error> {
+        self.pins.downgrade_to_direct(target, referenced).await
+    }
+
+    async fn upgrade_to_recursive(
+        &self,
+        target: &Cid,
+        referenced: References<'_>,
+    ) -> Result<(), Error> {
+        let _permit = self.permits.acquire().await?;
+        self.pins.upgrade_to_recursive(target, referenced).await
+    }
+
+    async fn list(
+        &self,
+        mode: Option<PinMode>,
+    ) -> futures::stream::BoxStream<'static, Result<(Cid, PinMode), Error>> {
+        self.pins.list(mode).await
+    }
+
+    async fn list_page(
+        &self,
+        mode: Option<PinMode>,
+        start_after: Option<Cid>,
+        limit: usize,
+    ) -> Result<Vec<(Cid, PinMode)>, Error> {
+        self.pins.list_page(mode, start_after, limit).await
+    }
+
+    async fn query(
+        &self,
+        ids: Vec<Cid>,
+        requirement: Option<PinMode>,
+    ) -> Result<Vec<(Cid, PinKind<Cid>)>, Error> {
+        self.pins.query(ids, requirement).await
+    }
+
+    async fn sync(
+        &self,
+        desired: Vec<(Cid, PinMode)>,
+        refs: &(dyn for<'c> Fn(&'c Cid) -> References<'static> + Send + Sync),
+    ) -> Result<SyncReport, Error> {
+        let _permit = self.permits.acquire().await?;
+        self.pins.sync(desired, refs).await
+    }
+
+    async fn reindex_indirect(
+        &self,
+        refs: &(dyn for<'c> Fn(&'c Cid) -> References<'static> + Send + Sync),
+    ) -> Result<(), Error> {
+        let _permit = self.permits.acquire().await?;
+        self.pins.reindex_indirect(refs).await
+    }
 }
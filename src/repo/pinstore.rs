@@ -0,0 +1,223 @@
+//! Pin store answering for the pins of several stores at once.
+use super::{PinKind, PinMode, PinStore, References, SyncReport};
+use crate::error::Error;
+use async_trait::async_trait;
+use futures::stream::{self, BoxStream, StreamExt};
+use libipld::Cid;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// Describes a pin store layered over several others.
+///
+/// A block counts as pinned when it is pinned in any of the stores, and the pins are listed and
+/// queried across all of them, each pin once. Pins are only ever written to the primary store,
+/// so the pins of the other stores cannot be removed through the composite.
+#[derive(Debug)]
+pub struct CompositePinStore {
+    primary: Arc<dyn PinStore>,
+    others: Vec<Arc<dyn PinStore>>,
+}
+
+impl CompositePinStore {
+    /// Creates a store writing to `primary` and reading from `primary` and `others`, in order.
+    pub fn new(primary: Arc<dyn PinStore>, others: Vec<Arc<dyn PinStore>>) -> Self {
+        CompositePinStore { primary, others }
+    }
+
+    fn stores(&self) -> impl Iterator<Item = &Arc<dyn PinStore>> {
+        std::iter::once(&self.primary).chain(&self.others)
+    }
+}
+
+#[async_trait]
+impl PinStore for CompositePinStore {
+    async fn init_pins(&self) -> Result<(), Error> {
+        for store in self.stores() {
+            store.init_pins().await?;
+        }
+        Ok(())
+    }
+
+    async fn open_pins(&self) -> Result<(), Error> {
+        for store in self.stores() {
+            store.open_pins().await?;
+        }
+        Ok(())
+    }
+
+    async fn is_pinned(&self, block: &Cid) -> Result<bool, Error> {
+        for store in self.stores() {
+            if store.is_pinned(block).await? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    async fn insert_direct_pin(&self, target: &Cid) -> Result<(), Error> {
+        self.primary.insert_direct_pin(target).await
+    }
+
+    async fn insert_recursive_pin(
+        &self,
+        target: &Cid,
+        referenced: References<'_>,
+    ) -> Result<(), Error> {
+        self.primary.insert_recursive_pin(target, referenced).await
+    }
+
+    async fn remove_direct_pin(&self, target: &Cid) -> Result<(), Error> {
+        self.primary.remove_direct_pin(target).await
+    }
+
+    async fn remove_recursive_pin(
+        &self,
+        target: &Cid,
+        referenced: References<'_>,
+    ) -> Result<(), Error> {
+        self.primary.remove_recursive_pin(target, referenced).await
+    }
+
+    async fn downgrade_to_direct(
+        &self,
+        target: &Cid,
+        referenced: References<'_>,
+    ) -> Result<(), Error> {
+        self.primary.downgrade_to_direct(target, referenced).await
+    }
+
+    async fn upgrade_to_recursive(
+        &self,
+        target: &Cid,
+        referenced: References<'_>,
+    ) -> Result<(), Error> {
+        self.primary.upgrade_to_recursive(target, referenced).await
+    }
+
+    async fn list(
+        &self,
+        mode: Option<PinMode>,
+    ) -> BoxStream<'static, Result<(Cid, PinMode), Error>> {
+        let mut lists = Vec::with_capacity(1 + self.others.len());
+        for store in self.stores() {
+            lists.push(store.list(mode).await);
+        }
+
+        // a block pinned in several stores is listed as pinned in the first one
+        let mut seen = HashSet::new();
+        stream::iter(lists)
+            .flatten()
+            .filter(move |res| {
+                let keep = match res {
+                    Ok((cid, _)) => seen.insert(*cid),
+                    Err(_) => true,
+                };
+                futures::future::ready(keep)
+            })
+            .boxed()
+    }
+
+    async fn query(
+        &self,
+        ids: Vec<Cid>,
+        requirement: Option<PinMode>,
+    ) -> Result<Vec<(Cid, PinKind<Cid>)>, Error> {
+        let mut seen = HashSet::with_capacity(ids.len());
+        let mut response = Vec::with_capacity(ids.len());
+
+        'ids: for id in ids {
+            if !seen.insert(id) {
+                continue;
+            }
+
+            let mut error = None;
+            for store in self.stores() {
+                match store.query(vec![id], requirement).await {
+                    Ok(mut found) if !found.is_empty() => {
+                        response.push(found.remove(0));
+                        continue 'ids;
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        error.get_or_insert(e);
+                    }
+                }
+            }
+
+            return Err(error.unwrap_or_else(|| anyhow::anyhow!("{id} is not pinned")));
+        }
+
+        Ok(response)
+    }
+
+    async fn sync(
+        &self,
+        desired: Vec<(Cid, PinMode)>,
+        refs: &(dyn for<'c> Fn(&'c Cid) -> References<'static> + Send + Sync),
+    ) -> Result<SyncReport, Error> {
+        self.primary.sync(desired, refs).await
+    }
+
+    async fn reindex_indirect(
+        &self,
+        refs: &(dyn for<'c> Fn(&'c Cid) -> References<'static> + Send + Sync),
+    ) -> Result<(), Error> {
+        self.primary.reindex_indirect(refs).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::datastore::{memory::MemDataStore, sled::SledDataStore};
+    use crate::repo::DataStore;
+    use futures::TryStreamExt;
+    use libipld::{
+        multihash::{Code, MultihashDigest},
+        IpldCodec,
+    };
+
+    #[tokio::test]
+    async fn pins_are_the_union_of_the_stores() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let sled = Arc::new(SledDataStore::new(tmp.path().into()));
+        sled.init().await.unwrap();
+        let memory = Arc::new(MemDataStore::new(Default::default()));
+
+        let store = CompositePinStore::new(memory.clone(), vec![sled.clone() as Arc<dyn PinStore>]);
+
+        let empty = Cid::try_from("QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH").unwrap();
+        let other = Cid::try_from("QmX5S2xLu32K6WxWnyLeChQFbDHy79ULV9feJYH2Hy9bgp").unwrap();
+        let unpinned = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(b"unpinned"));
+
+        sled.insert_direct_pin(&other).await.unwrap();
+        sled.insert_direct_pin(&empty).await.unwrap();
+        // pinned through the composite, so only in the primary store
+        store.insert_direct_pin(&empty).await.unwrap();
+        assert!(memory.is_pinned(&empty).await.unwrap());
+        assert!(!memory.is_pinned(&other).await.unwrap());
+
+        assert!(store.is_pinned(&empty).await.unwrap());
+        assert!(store.is_pinned(&other).await.unwrap());
+        assert!(!store.is_pinned(&unpinned).await.unwrap());
+
+        let mut listed = store
+            .list(Some(PinMode::Direct))
+            .await
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        listed.sort_by_key(|(cid, _)| cid.to_string());
+        let mut expected = vec![(empty, PinMode::Direct), (other, PinMode::Direct)];
+        expected.sort_by_key(|(cid, _)| cid.to_string());
+        assert_eq!(listed, expected);
+
+        let queried = store.query(vec![other, empty, other], None).await.unwrap();
+        assert_eq!(
+            queried,
+            vec![(other, PinKind::Direct), (empty, PinKind::Direct)]
+        );
+
+        store.query(vec![empty, unpinned], None).await.unwrap_err();
+    }
+}
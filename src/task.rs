@@ -13,26 +13,32 @@ use futures::{
 use crate::TSwarmEvent;
 use crate::{
     p2p::{addr::extract_peer_id_from_multiaddr, MultiaddrExt},
-    Channel, InnerPubsubEvent,
+    Channel, ConnectionEvent, DiscoveredPeer, DiscoverySource, InnerPubsubEvent, ListenerEvent,
 };
-use beetle_bitswap_next::BitswapEvent;
+use beetle_bitswap_next::{message::Priority, BitswapEvent, BlockNotFound};
 use tokio::task::JoinHandle;
 
 use wasm_timer::Interval;
 
 use std::{
-    collections::{hash_map::Entry, HashMap, HashSet},
+    cmp::Ordering,
+    collections::{hash_map::Entry, BinaryHeap, HashMap, HashSet},
     io,
-    time::Duration,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
-use crate::{config::BOOTSTRAP_NODES, IpfsEvent, TSwarmEventFn};
+use crate::{config::BOOTSTRAP_NODES, IpfsEvent, TSwarmEventFn, TSwarmEventSubscriberFn};
 
 use crate::{
-    p2p::TSwarm,
+    p2p::{
+        autorelay::AutoRelay, gossipsub::GossipsubStream, identify_request, CircuitInfo,
+        KadBucketInfo, KadTableInfo, ListenerDescriptor, ProvideQueue, ProviderKeyScheme,
+        RelayConfig, ReprovideSchedule, ReservationInfo, TSwarm, TransportKind,
+    },
     repo::{Repo, RepoEvent},
 };
 
@@ -45,13 +51,14 @@ pub use crate::{
 };
 
 use libipld::multibase::{self, Base};
+use libipld::Cid;
 pub use libp2p::{
     self,
     core::transport::ListenerId,
     gossipsub::{MessageId, PublishError},
     identity::Keypair,
     identity::PublicKey,
-    kad::{record::Key, Quorum},
+    kad::Quorum,
     multiaddr::multiaddr,
     multiaddr::Protocol,
     swarm::NetworkBehaviour,
@@ -62,11 +69,13 @@ use libp2p::{
     autonat,
     identify::{Event as IdentifyEvent, Info as IdentifyInfo},
     kad::{
-        AddProviderError, AddProviderOk, BootstrapError, BootstrapOk, GetClosestPeersError,
-        GetClosestPeersOk, GetProvidersError, GetProvidersOk, GetRecordError, GetRecordOk,
-        KademliaEvent::*, PutRecordError, PutRecordOk, QueryId, QueryResult::*, Record,
+        record::store::RecordStore, AddProviderError, AddProviderOk, BootstrapError, BootstrapOk,
+        GetClosestPeersError, GetClosestPeersOk, GetProvidersError, GetProvidersOk, GetRecordError,
+        GetRecordOk, KademliaEvent::*, PutRecordError, PutRecordOk, QueryId, QueryResult::*,
+        Record,
     },
     mdns::Event as MdnsEvent,
+    relay::{client::Event as RelayClientEvent, Event as RelayEvent},
     swarm::SwarmEvent,
 };
 
@@ -85,18 +94,186 @@ pub(crate) struct IpfsTask<C: NetworkBehaviour<ToSwarm = void::Void>> {
     pub(crate) record_stream: HashMap<QueryId, UnboundedSender<Record>>,
     pub(crate) repo: Repo,
     pub(crate) kad_subscriptions: HashMap<QueryId, Channel<KadResult>>,
+    /// Bootstrap queries reporting their progress, with the peers added to the routing table
+    /// since the last step of the query.
+    pub(crate) bootstrap_progress:
+        HashMap<QueryId, (UnboundedSender<Result<KadResult, Error>>, Vec<PeerId>)>,
     pub(crate) dht_peer_lookup: HashMap<PeerId, Vec<Channel<libp2p::identify::Info>>>,
+    /// Requests for a fresh identify info of the peer, resolved by the next one received.
+    pub(crate) identify_refresh: HashMap<PeerId, Vec<Channel<libp2p::identify::Info>>>,
     pub(crate) listener_subscriptions:
         HashMap<ListenerId, oneshot::Sender<Either<Multiaddr, Result<(), io::Error>>>>,
     pub(crate) bootstraps: HashSet<Multiaddr>,
     pub(crate) swarm_event: Option<TSwarmEventFn<C>>,
+    pub(crate) swarm_event_subscribers: Vec<TSwarmEventSubscriberFn<C>>,
     pub(crate) bitswap_sessions: HashMap<u64, Vec<(oneshot::Sender<()>, JoinHandle<()>)>>,
+    /// The fetch of each block wanted, shared by the requests of all the sessions wanting it.
+    pub(crate) block_fetches: HashMap<Cid, BlockFetch>,
+    pub(crate) want_permits: Option<WantQueue>,
     pub(crate) disconnect_confirmation: HashMap<PeerId, Vec<Channel<()>>>,
     pub(crate) pubsub_event_stream: Vec<UnboundedSender<InnerPubsubEvent>>,
+    pub(crate) connection_event_stream: Vec<UnboundedSender<ConnectionEvent>>,
+    pub(crate) peer_discovery_stream: Vec<UnboundedSender<DiscoveredPeer>>,
+    pub(crate) listener_event_stream: Vec<UnboundedSender<ListenerEvent>>,
+    /// Reasons the peers are being disconnected for, reported once the last connection closes.
+    pub(crate) disconnect_reasons: HashMap<PeerId, String>,
     pub(crate) external_listener: Vec<oneshot::Sender<Vec<Multiaddr>>>,
     pub(crate) local_listener: Vec<oneshot::Sender<Vec<Multiaddr>>>,
     pub(crate) timer: TaskTimer,
     pub(crate) local_external_addr: bool,
+    pub(crate) verify_external_addresses: bool,
+    pub(crate) persist_dht_peers: bool,
+    pub(crate) provide_queue: ProvideQueue,
+    pub(crate) provide_batch_window: Option<Duration>,
+    pub(crate) reprovide: Option<ReprovideSchedule>,
+    pub(crate) provider_key_scheme: ProviderKeyScheme,
+    pub(crate) peer_exchange: bool,
+    pub(crate) exchanged_peers: HashSet<PeerId>,
+    pub(crate) push_external_addr_updates: bool,
+    pub(crate) known_external_addrs: HashSet<Multiaddr>,
+    pub(crate) relay_reservations: HashMap<ListenerId, RelayReservation>,
+    pub(crate) lost_relay_reservations: Vec<(Instant, RelayReservation)>,
+    pub(crate) autorelay: Option<AutoRelay>,
+    pub(crate) relay_server_config: RelayConfig,
+    pub(crate) client_reservations: HashMap<PeerId, ReservationInfo>,
+    pub(crate) relayed_circuits: Vec<CircuitInfo>,
+}
+
+/// Delay before listening again on a relay circuit whose reservation was lost, doubled on every
+/// consecutive loss up to [`MAX_RELAY_RETRY_DELAY`].
+const RELAY_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RELAY_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// How often the provided keys are checked for being due to be republished.
+const REPROVIDE_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Circuit listened on through a relay.
+///
+/// The relay client renews the reservation ahead of its expiry on its own, but once a renewal
+/// fails or the connection to the relay is lost the listener closes, after which the task listens
+/// on the circuit again.
+pub(crate) struct RelayReservation {
+    addr: Multiaddr,
+    /// `None` until the reservation is first accepted, as a failure before that is reported to
+    /// the caller of `Ipfs::add_listening_address` instead of being retried.
+    retry_delay: Option<Duration>,
+}
+
+/// Limits the number of blocks wanted at the same time. The queued wants are let in by their
+/// priority, and the ones of the same priority in the order they were made.
+#[derive(Clone)]
+pub(crate) struct WantQueue {
+    state: Arc<parking_lot::Mutex<WantQueueState>>,
+}
+
+struct WantQueueState {
+    available: usize,
+    queued: BinaryHeap<QueuedWant>,
+    next_seq: u64,
+}
+
+struct QueuedWant {
+    priority: Priority,
+    seq: u64,
+    ready: oneshot::Sender<WantPermit>,
+}
+
+impl PartialEq for QueuedWant {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for QueuedWant {}
+
+impl PartialOrd for QueuedWant {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueuedWant {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+/// Sessions waiting for a block fetched in another one, with the peers asked and the priority.
+type WaitingSessions = Vec<(u64, Vec<PeerId>, Priority)>;
+
+/// Fetch of a block in one session, shared with the requests of the other sessions wanting the
+/// block. Handed over to one of them if the session fetching it stops.
+pub(crate) struct BlockFetch {
+    session: u64,
+    waiting: Arc<parking_lot::Mutex<WaitingSessions>>,
+    worker: tokio::task::AbortHandle,
+}
+
+/// Slot of an active want, handed over to the next queued want once dropped.
+pub(crate) struct WantPermit {
+    queue: WantQueue,
+}
+
+impl Drop for WantPermit {
+    fn drop(&mut self) {
+        self.queue.release();
+    }
+}
+
+impl WantQueue {
+    pub(crate) fn new(limit: usize) -> Self {
+        Self {
+            state: Arc::new(parking_lot::Mutex::new(WantQueueState {
+                available: limit.max(1),
+                queued: BinaryHeap::new(),
+                next_seq: 0,
+            })),
+        }
+    }
+
+    pub(crate) async fn acquire(&self, priority: Priority) -> Option<WantPermit> {
+        let rx = {
+            let mut state = self.state.lock();
+            if state.available > 0 {
+                state.available -= 1;
+                return Some(WantPermit {
+                    queue: self.clone(),
+                });
+            }
+            let (tx, rx) = oneshot::channel();
+            let seq = state.next_seq;
+            state.next_seq += 1;
+            state.queued.push(QueuedWant {
+                priority,
+                seq,
+                ready: tx,
+            });
+            rx
+        };
+
+        rx.await.ok()
+    }
+
+    fn release(&self) {
+        let next = {
+            let mut state = self.state.lock();
+            match state.queued.pop() {
+                Some(next) => next,
+                None => {
+                    state.available += 1;
+                    return;
+                }
+            }
+        };
+
+        // when the queued want was dropped in the meantime, the returned permit is dropped in
+        // turn, releasing the slot to the next one
+        let _ = next.ready.send(WantPermit {
+            queue: self.clone(),
+        });
+    }
 }
 
 pub(crate) struct TaskTimer {
@@ -104,6 +281,10 @@ pub(crate) struct TaskTimer {
     pub(crate) event_cleanup: Interval,
     pub(crate) external_check: Interval,
     pub(crate) local_check: Interval,
+    pub(crate) relay_check: Interval,
+    pub(crate) reprovide_check: Interval,
+    /// Ends the batching window of the queued provides, created on the first poll
+    pub(crate) provide_flush: Option<Interval>,
 }
 
 impl Default for TaskTimer {
@@ -119,12 +300,17 @@ impl Default for TaskTimer {
             std::time::Instant::now() + Duration::from_secs(1),
             Duration::from_secs(1),
         );
+        let relay_check = Interval::new(Duration::from_secs(1));
+        let reprovide_check = Interval::new(REPROVIDE_CHECK_INTERVAL);
 
         Self {
             session_cleanup,
             event_cleanup,
             external_check,
             local_check,
+            relay_check,
+            reprovide_check,
+            provide_flush: None,
         }
     }
 }
@@ -157,6 +343,19 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> futures::Future for IpfsTask<C>
 
         if self.timer.event_cleanup.poll_next_unpin(cx).is_ready() {
             self.pubsub_event_stream.retain(|ch| !ch.is_closed());
+            self.connection_event_stream.retain(|ch| !ch.is_closed());
+            self.peer_discovery_stream.retain(|ch| !ch.is_closed());
+            self.listener_event_stream.retain(|ch| !ch.is_closed());
+        }
+
+        if let Some(window) = self.provide_batch_window {
+            let provide_flush = self
+                .timer
+                .provide_flush
+                .get_or_insert_with(|| Interval::new(window));
+            if provide_flush.poll_next_unpin(cx).is_ready() {
+                self.flush_provide_queue();
+            }
         }
 
         if self.timer.session_cleanup.poll_next_unpin(cx).is_ready() {
@@ -175,6 +374,9 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> futures::Future for IpfsTask<C>
                 }
             }
 
+            self.block_fetches
+                .retain(|_, fetch| !fetch.worker.is_finished());
+
             for id in to_remove {
                 let (tx, _rx) = oneshot::channel();
                 self.destroy_bs_session(id, tx);
@@ -201,6 +403,14 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> futures::Future for IpfsTask<C>
             }
         }
 
+        if self.timer.relay_check.poll_next_unpin(cx).is_ready() {
+            self.retry_lost_relay_reservations();
+        }
+
+        if self.timer.reprovide_check.poll_next_unpin(cx).is_ready() {
+            self.reprovide();
+        }
+
         Poll::Pending
     }
 }
@@ -218,6 +428,11 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
             tokio::time::Instant::now() + Duration::from_secs(1),
             Duration::from_secs(1),
         );
+        let mut relay_check = tokio::time::interval(Duration::from_secs(1));
+        let mut reprovide_check = tokio::time::interval(REPROVIDE_CHECK_INTERVAL);
+        let mut provide_flush =
+            tokio::time::interval(self.provide_batch_window.unwrap_or(Duration::from_secs(1)));
+        let batching = self.provide_batch_window.is_some();
         loop {
             tokio::select! {
                 Some(swarm) = self.swarm.next() => {
@@ -240,6 +455,12 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                 },
                 _ = event_cleanup.tick() => {
                     self.pubsub_event_stream.retain(|ch| !ch.is_closed());
+                    self.connection_event_stream.retain(|ch| !ch.is_closed());
+                    self.peer_discovery_stream.retain(|ch| !ch.is_closed());
+                    self.listener_event_stream.retain(|ch| !ch.is_closed());
+                }
+                _ = provide_flush.tick(), if batching => {
+                    self.flush_provide_queue();
                 }
                 _ = session_cleanup.tick() => {
                     let mut to_remove = Vec::new();
@@ -257,6 +478,9 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                         }
                     }
 
+                    self.block_fetches
+                        .retain(|_, fetch| !fetch.worker.is_finished());
+
                     for id in to_remove {
                         let (tx, _rx) = oneshot::channel();
                         self.destroy_bs_session(id, tx);
@@ -288,11 +512,201 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                         }
                     }
                 }
+                _ = relay_check.tick() => {
+                    self.retry_lost_relay_reservations();
+                }
+                _ = reprovide_check.tick(), if self.reprovide.is_some() => {
+                    self.reprovide();
+                }
+            }
+        }
+    }
+
+    /// Listens again on the relay circuits whose reservation was lost once their retry delay has
+    /// passed.
+    fn retry_lost_relay_reservations(&mut self) {
+        if self.lost_relay_reservations.is_empty() {
+            return;
+        }
+
+        let now = Instant::now();
+        let (due, pending) = std::mem::take(&mut self.lost_relay_reservations)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(at, _)| *at <= now);
+        self.lost_relay_reservations = pending;
+
+        for (_, reservation) in due {
+            match self.swarm.listen_on(reservation.addr.clone()) {
+                Ok(id) => {
+                    debug!("relay: listening again on {}", reservation.addr);
+                    self.listeners.insert(id);
+                    self.relay_reservations.insert(id, reservation);
+                }
+                Err(e) => {
+                    warn!("relay: failed to listen again on {}: {e}", reservation.addr);
+                    self.schedule_relay_reservation(reservation);
+                }
+            }
+        }
+    }
+
+    /// Listens on circuits through further relays, while the node is behind a NAT and holds fewer
+    /// reservations than configured.
+    fn select_relays(&mut self) {
+        let Some(autorelay) = self.autorelay.as_mut() else {
+            return;
+        };
+
+        for (relay, circuit) in autorelay.next_circuits() {
+            match self.swarm.listen_on(circuit.clone()) {
+                Ok(id) => {
+                    debug!("autorelay: listening on {circuit}");
+                    self.listeners.insert(id);
+                    autorelay.insert_listener(id, relay);
+                }
+                Err(e) => {
+                    warn!("autorelay: failed to listen on {circuit}: {e}");
+                    autorelay.mark_failed(relay);
+                }
+            }
+        }
+    }
+
+    fn schedule_relay_reservation(&mut self, mut reservation: RelayReservation) {
+        let delay = reservation.retry_delay.unwrap_or(RELAY_RETRY_DELAY);
+        reservation.retry_delay = Some((delay * 2).min(MAX_RELAY_RETRY_DELAY));
+        self.lost_relay_reservations
+            .push((Instant::now() + delay, reservation));
+    }
+
+    /// Pushes the identify info to all connected peers when the external addresses have changed
+    /// since the last push, so that peers learn the new addresses without waiting for the next
+    /// identify interval. Called only after the events which can change the addresses.
+    fn push_identify_on_external_change(&mut self) {
+        if !self.push_external_addr_updates {
+            return;
+        }
+
+        let changed = self.swarm.external_addresses().count() != self.known_external_addrs.len()
+            || self
+                .swarm
+                .external_addresses()
+                .any(|addr| !self.known_external_addrs.contains(addr));
+
+        if !changed {
+            return;
+        }
+
+        self.known_external_addrs = self.swarm.external_addresses().cloned().collect();
+
+        let peers = self.swarm.connected_peers().copied().collect::<Vec<_>>();
+        if let Some(identify) = self.swarm.behaviour_mut().identify.as_mut() {
+            debug!(
+                "identify: external addresses changed, pushing to {} peers",
+                peers.len()
+            );
+            identify.push(peers);
+        }
+    }
+
+    /// Advertises `addr` as an external address. When the addresses have to be verified, the
+    /// address is handed to autonat instead, which confirms it once a server dialed back to it.
+    fn promote_external_address(&mut self, addr: Multiaddr) {
+        if self.verify_external_addresses {
+            debug!("autonat: verifying external address {addr}");
+            self.swarm.behaviour_mut().autonat.probe_address(addr);
+            return;
+        }
+
+        self.swarm.add_external_address(addr);
+    }
+
+    fn flush_provide_queue(&mut self) {
+        if self.provide_queue.is_empty() {
+            return;
+        }
+
+        debug!("kad: providing {} queued keys", self.provide_queue.len());
+
+        let keys = self.provide_queue.take();
+
+        let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() else {
+            return;
+        };
+
+        for key in keys {
+            if let Err(e) = kad.start_providing(key) {
+                error!("kad: can't provide a key: {:?}", e);
+            }
+        }
+    }
+
+    /// Republishes the provided keys which are due according to the [`ReprovideSchedule`].
+    fn reprovide(&mut self) {
+        let Some(schedule) = self.reprovide.as_mut() else {
+            return;
+        };
+
+        let local_peer_id = *self.swarm.local_peer_id();
+        let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() else {
+            return;
+        };
+
+        let now = Instant::now();
+        let due = schedule.due(now);
+        if due.is_empty() {
+            return;
+        }
+
+        debug!("kad: republishing {} provided keys", due.len());
+
+        for key in due {
+            // the key may have stopped being provided while it was last provided
+            let provided = kad
+                .store_mut()
+                .providers(&key)
+                .iter()
+                .any(|record| record.provider == local_peer_id);
+            if !provided {
+                schedule.remove(&key);
+                continue;
+            }
+            if let Err(e) = kad.start_providing(key.clone()) {
+                error!("kad: can't republish a key: {:?}", e);
+                schedule.failed(key, now);
             }
         }
     }
 
     fn destroy_bs_session(&mut self, ctx: u64, ret: oneshot::Sender<anyhow::Result<()>>) {
+        // the blocks fetched in the session are fetched on in one of the others still waiting
+        // for them, and the session no longer waits for the blocks fetched in the others
+        let handed_over = self
+            .block_fetches
+            .iter()
+            .filter(|(_, fetch)| {
+                fetch.waiting.lock().retain(|(session, ..)| *session != ctx);
+                fetch.session == ctx && !fetch.worker.is_finished()
+            })
+            .map(|(cid, _)| *cid)
+            .collect::<Vec<_>>();
+        for cid in handed_over {
+            let Some(fetch) = self.block_fetches.remove(&cid) else {
+                continue;
+            };
+            let mut waiting = std::mem::take(&mut *fetch.waiting.lock());
+            if waiting.is_empty() {
+                continue;
+            }
+            let (session, mut peers, priority) = waiting.remove(0);
+            for peer in waiting.iter().flat_map(|(_, others, _)| others) {
+                if !peers.contains(peer) {
+                    peers.push(*peer);
+                }
+            }
+            self.fetch_block(session, cid, peers, priority, waiting);
+        }
+
         if let Some(bitswap) = self.swarm.behaviour().bitswap.as_ref() {
             let client = bitswap.client().clone();
             let workers: Option<Vec<(oneshot::Sender<()>, JoinHandle<()>)>> =
@@ -320,6 +734,104 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
         }
     }
 
+    /// Fetches the block in the session `ctx` for the requests of it and of the `waiting` ones.
+    fn fetch_block(
+        &mut self,
+        ctx: u64,
+        cid: Cid,
+        peers: Vec<PeerId>,
+        priority: Priority,
+        waiting: WaitingSessions,
+    ) {
+        let Some(bitswap) = self.swarm.behaviour().bitswap.as_ref() else {
+            return;
+        };
+        let client = bitswap.client().clone();
+        let repo = self.repo.clone();
+        let (closer_s, closer_r) = oneshot::channel();
+        let permits = self.want_permits.clone();
+        let waiting = Arc::new(parking_lot::Mutex::new(waiting));
+        let sessions = waiting.clone();
+
+        let worker = tokio::task::spawn(async move {
+            let want = async {
+                let _permit = match permits {
+                    Some(permits) => permits.acquire(priority).await,
+                    None => None,
+                };
+                let _want_priority = client.prioritize_want(cid, priority);
+                let record_sources = std::iter::once(ctx)
+                    .chain(sessions.lock().iter().map(|(session, ..)| *session))
+                    .any(|session| repo.records_block_sources(session));
+                if record_sources {
+                    client.record_block_sources(ctx).await;
+                }
+                // the preferred providers only stand in for the providers not given explicitly
+                let preferred = if peers.is_empty() {
+                    repo.preferred_providers()
+                } else {
+                    Vec::new()
+                };
+                if preferred.is_empty() {
+                    client.get_block_with_session_id(ctx, &cid, &peers).await
+                } else {
+                    client.get_block_preferring(ctx, &cid, &preferred).await
+                }
+            };
+
+            tokio::select! {
+                _ = closer_r => {
+                    // Explicit sesssion stop.
+                    debug!("session {}: stopped: closed", ctx);
+                }
+                block = want => match block {
+                    Ok(block) => {
+                        info!("Found {cid}");
+                        if let Some(source) = client.take_block_source(ctx, &cid).await {
+                            let waiting = sessions.lock().iter().map(|(session, ..)| *session).collect::<Vec<_>>();
+                            for session in std::iter::once(ctx).chain(waiting) {
+                                if repo.records_block_sources(session) {
+                                    repo.record_block_source(session, cid, source);
+                                }
+                            }
+                        }
+                        let block = libipld::Block::new_unchecked(block.cid, block.data.to_vec());
+                        let res = repo.put_block(block).await;
+                        if let Err(e) = res {
+                            error!("Got block {} but failed to store it: {}", cid, e);
+                        }
+
+                    }
+                    Err(err) => {
+                        error!("Failed to get {}: {}", cid, err);
+                        if err.is::<BlockNotFound>() {
+                            repo.block_not_found(&cid);
+                        }
+                    }
+                },
+            }
+        });
+        self.block_fetches.insert(
+            cid,
+            BlockFetch {
+                session: ctx,
+                waiting,
+                worker: worker.abort_handle(),
+            },
+        );
+        self.bitswap_sessions
+            .entry(ctx)
+            .or_default()
+            .push((closer_s, worker));
+    }
+
+    fn pubsub(&mut self) -> Result<&mut GossipsubStream, Error> {
+        self.swarm
+            .behaviour_mut()
+            .pubsub()
+            .ok_or_else(|| anyhow!("pubsub is disabled"))
+    }
+
     fn emit_pubsub_event(&self, event: InnerPubsubEvent) {
         for ch in &self.pubsub_event_stream {
             let ch = ch.clone();
@@ -330,10 +842,51 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
         }
     }
 
+    fn emit_connection_event(&self, event: ConnectionEvent) {
+        for ch in &self.connection_event_stream {
+            let _ = ch.unbounded_send(event.clone());
+        }
+    }
+
+    fn emit_listener_event(&self, event: ListenerEvent) {
+        for ch in &self.listener_event_stream {
+            let _ = ch.unbounded_send(event.clone());
+        }
+    }
+
+    fn emit_discovered_peer(
+        &self,
+        peer_id: PeerId,
+        source: DiscoverySource,
+        addresses: Vec<Multiaddr>,
+    ) {
+        let discovered = DiscoveredPeer {
+            peer_id,
+            source,
+            addresses,
+        };
+        for ch in &self.peer_discovery_stream {
+            let _ = ch.unbounded_send(discovered.clone());
+        }
+    }
+
     fn handle_swarm_event(&mut self, swarm_event: TSwarmEvent<C>) {
         if let Some(handler) = self.swarm_event.as_ref() {
             handler(&mut self.swarm, &swarm_event)
         }
+        for subscriber in &self.swarm_event_subscribers {
+            subscriber(&swarm_event);
+        }
+        // the changes of the local protocols are pushed by identify itself
+        let addresses_changed = matches!(
+            swarm_event,
+            SwarmEvent::NewListenAddr { .. }
+                | SwarmEvent::ExpiredListenAddr { .. }
+                | SwarmEvent::ListenerClosed { .. }
+                | SwarmEvent::Behaviour(BehaviourEvent::Autonat(autonat::Event::OutboundProbe(
+                    autonat::OutboundProbeEvent::Response { .. }
+                )))
+        );
         match swarm_event {
             SwarmEvent::NewListenAddr {
                 listener_id,
@@ -342,6 +895,14 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                 self.listening_addresses
                     .insert(address.clone(), listener_id);
 
+                if let Some(reservation) = self.relay_reservations.get_mut(&listener_id) {
+                    reservation.retry_delay = Some(RELAY_RETRY_DELAY);
+                }
+
+                if let Some(relay) = circuit_relay(&address) {
+                    self.emit_listener_event(ListenerEvent::CircuitListenActive { relay });
+                }
+
                 for ch in self.local_listener.drain(..) {
                     tokio::spawn({
                         let addr = address.clone();
@@ -355,19 +916,52 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                     && !address.is_relay()
                     && (address.is_loopback() || address.is_private())
                 {
-                    self.swarm.add_external_address(address.clone());
+                    self.promote_external_address(address.clone());
                 }
 
                 if !address.is_loopback() && !address.is_private() {
                     // We will assume that the address is global and reachable externally
-                    self.swarm.add_external_address(address.clone());
+                    self.promote_external_address(address.clone());
                 }
 
                 if let Some(ret) = self.listener_subscriptions.remove(&listener_id) {
                     let _ = ret.send(Either::Left(address));
                 }
             }
-            SwarmEvent::ConnectionClosed { peer_id, .. } => {
+            SwarmEvent::ConnectionEstablished {
+                peer_id,
+                num_established,
+                ..
+            } => {
+                if num_established.get() == 1 {
+                    self.emit_connection_event(ConnectionEvent::PeerConnected { peer_id });
+                }
+            }
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                num_established,
+                cause,
+                ..
+            } => {
+                if num_established == 0 {
+                    self.exchanged_peers.remove(&peer_id);
+                    if let Some(autorelay) = self.autorelay.as_mut() {
+                        autorelay.on_disconnected(&peer_id);
+                    }
+                    self.client_reservations.remove(&peer_id);
+                    let cause = self
+                        .disconnect_reasons
+                        .remove(&peer_id)
+                        .or_else(|| cause.map(|e| e.to_string()));
+                    match &cause {
+                        Some(cause) => debug!("{peer_id} disconnected: {cause}"),
+                        None => debug!("{peer_id} disconnected"),
+                    }
+                    self.emit_connection_event(ConnectionEvent::PeerDisconnected {
+                        peer_id,
+                        cause,
+                    });
+                }
                 if let Some(ch) = self.disconnect_confirmation.remove(&peer_id) {
                     tokio::spawn(async move {
                         for ch in ch {
@@ -398,12 +992,44 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                 addresses,
             } => {
                 self.listeners.remove(&listener_id);
+                let mut circuit = self
+                    .relay_reservations
+                    .get(&listener_id)
+                    .and_then(|reservation| circuit_relay(&reservation.addr));
                 for address in addresses {
+                    if let Some(relay) = circuit_relay(&address) {
+                        self.client_reservations.remove(&relay);
+                    }
                     self.listening_addresses.remove(&address);
                     if self.swarm.external_addresses().any(|addr| address.eq(addr)) {
                         self.swarm.remove_external_address(&address);
                     }
                 }
+                if let Some(reservation) = self.relay_reservations.remove(&listener_id) {
+                    if let Some(delay) = reservation.retry_delay {
+                        warn!(
+                            "relay: reservation for {} was lost, retrying in {delay:?}",
+                            reservation.addr
+                        );
+                        self.schedule_relay_reservation(reservation);
+                    }
+                }
+                if let Some(autorelay) = self.autorelay.as_mut() {
+                    if let Some(relay) = autorelay.remove_listener(&listener_id) {
+                        debug!("autorelay: circuit through {relay} was closed");
+                        autorelay.mark_failed(relay);
+                        circuit = Some(relay);
+                        self.select_relays();
+                    }
+                }
+                // the circuits removed on request are no longer tracked at this point
+                if let Some(relay) = circuit {
+                    let error = match &reason {
+                        Ok(()) => String::from("listener closed"),
+                        Err(e) => e.to_string(),
+                    };
+                    self.emit_listener_event(ListenerEvent::CircuitListenFailed { relay, error });
+                }
                 if let Some(ret) = self.listener_subscriptions.remove(&listener_id) {
                     let _ = ret.send(Either::Right(reason));
                 }
@@ -416,8 +1042,13 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
             }
             SwarmEvent::Behaviour(BehaviourEvent::Mdns(event)) => match event {
                 MdnsEvent::Discovered(list) => {
+                    let mut discovered: HashMap<PeerId, Vec<Multiaddr>> = HashMap::new();
                     for (peer, addr) in list {
-                        self.swarm.behaviour_mut().add_peer(peer, addr);
+                        self.swarm.behaviour_mut().add_peer(peer, addr.clone());
+                        discovered.entry(peer).or_default().push(addr);
+                    }
+                    for (peer, addrs) in discovered {
+                        self.emit_discovered_peer(peer, DiscoverySource::Mdns, addrs);
                     }
                 }
                 MdnsEvent::Expired(list) => {
@@ -471,10 +1102,26 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                                     "kad: bootstrapped with {}, {} peers remain",
                                     peer, num_remaining
                                 );
+
+                                if let Some((tx, discovered)) = self.bootstrap_progress.get_mut(&id)
+                                {
+                                    let discovered = std::mem::take(discovered);
+                                    let _ = tx.unbounded_send(Ok(KadResult::Peers(discovered)));
+                                    if step.last {
+                                        let _ = tx.unbounded_send(Ok(KadResult::Complete));
+                                        self.bootstrap_progress.remove(&id);
+                                    }
+                                }
                             }
                             Bootstrap(Err(BootstrapError::Timeout { .. })) => {
                                 warn!("kad: timed out while trying to bootstrap");
 
+                                if let Some((tx, _)) = self.bootstrap_progress.remove(&id) {
+                                    let _ = tx.unbounded_send(Err(anyhow::anyhow!(
+                                        "kad: timed out while trying to bootstrap"
+                                    )));
+                                }
+
                                 if self
                                     .swarm
                                     .behaviour()
@@ -562,7 +1209,19 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                                     }
                                 }
                                 if let Entry::Occupied(entry) = self.provider_stream.entry(id) {
-                                    if !providers.is_empty() {
+                                    if entry.get().is_closed() {
+                                        // the stream ended, e.g. with enough providers found
+                                        entry.remove();
+                                        if let Some(mut query) = self
+                                            .swarm
+                                            .behaviour_mut()
+                                            .kademlia
+                                            .as_mut()
+                                            .and_then(|kad| kad.query_mut(&id))
+                                        {
+                                            query.finish();
+                                        }
+                                    } else if !providers.is_empty() {
                                         tokio::spawn({
                                             let mut tx = entry.get().clone();
                                             async move {
@@ -604,10 +1263,16 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                                 }
                             }
                             StartProviding(Ok(AddProviderOk { key })) => {
+                                if let Some(schedule) = self.reprovide.as_mut() {
+                                    schedule.provided(key.clone(), Instant::now());
+                                }
                                 let key = multibase::encode(Base::Base32Lower, key);
                                 debug!("kad: providing {}", key);
                             }
                             StartProviding(Err(AddProviderError::Timeout { key })) => {
+                                if let Some(schedule) = self.reprovide.as_mut() {
+                                    schedule.failed(key.clone(), Instant::now());
+                                }
                                 let key = multibase::encode(Base::Base32Lower, key);
                                 warn!("kad: timed out while trying to provide {}", key);
 
@@ -786,12 +1451,30 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                     }
                     RoutingUpdated {
                         peer,
-                        is_new_peer: _,
+                        is_new_peer,
                         addresses,
                         bucket_range: _,
                         old_peer: _,
                     } => {
                         trace!("kad: routing updated; {}: {:?}", peer, addresses);
+                        self.emit_discovered_peer(
+                            peer,
+                            DiscoverySource::Kad,
+                            addresses.iter().cloned().collect(),
+                        );
+                        if is_new_peer {
+                            for (_, discovered) in self.bootstrap_progress.values_mut() {
+                                discovered.push(peer);
+                            }
+                        }
+                        if self.peer_exchange {
+                            for addr in addresses.iter() {
+                                self.swarm
+                                    .behaviour_mut()
+                                    .addressbook
+                                    .add_address(peer, addr.clone());
+                            }
+                        }
                     }
                     UnroutablePeer { peer } => {
                         trace!("kad: peer {} is unroutable", peer);
@@ -806,16 +1489,22 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
             }
             SwarmEvent::Behaviour(BehaviourEvent::Bitswap(event)) => match event {
                 BitswapEvent::Provide { key } => {
-                    if let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() {
-                        let key = key.hash().to_bytes();
-                        let _id = kad.start_providing(key.into()).ok();
+                    let key = self.provider_key_scheme.key(&key);
+                    match self.provide_batch_window {
+                        Some(_) => {
+                            self.provide_queue.push(key);
+                        }
+                        None => {
+                            if let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() {
+                                let _id = kad.start_providing(key).ok();
+                            }
+                        }
                     }
                 }
                 BitswapEvent::FindProviders { key, response, .. } => {
                     if let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() {
                         info!("Looking for providers for {key}");
-                        let key = key.hash().to_bytes();
-                        let id = kad.get_providers(key.into());
+                        let id = kad.get_providers(self.provider_key_scheme.key(&key));
                         self.bitswap_provider_stream.insert(id, response);
                     }
                 }
@@ -839,8 +1528,8 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
             SwarmEvent::Behaviour(BehaviourEvent::Ping(event)) => match event {
                 libp2p::ping::Event {
                     peer,
+                    connection,
                     result: Result::Ok(rtt),
-                    ..
                 } => {
                     trace!(
                         "ping: rtt to {} is {} ms",
@@ -848,6 +1537,9 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                         rtt.as_millis()
                     );
                     self.swarm.behaviour_mut().peerbook.set_peer_rtt(peer, rtt);
+                    if let Some(dedup) = self.swarm.behaviour_mut().dedup.as_mut() {
+                        dedup.on_ping(peer, connection, rtt);
+                    }
                 }
                 libp2p::ping::Event { .. } => {
                     //TODO: Determine if we should continue handling ping errors and if we should disconnect/close connection.
@@ -866,6 +1558,12 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                         }
                     }
 
+                    self.emit_discovered_peer(
+                        peer_id,
+                        DiscoverySource::Identify,
+                        info.listen_addrs.clone(),
+                    );
+
                     let IdentifyInfo {
                         listen_addrs,
                         protocols,
@@ -876,11 +1574,36 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                         bitswap.on_identify(&peer_id, &protocols)
                     }
 
+                    if let Some(autorelay) = self.autorelay.as_mut() {
+                        autorelay.on_identify(peer_id, &listen_addrs, &protocols);
+                        self.select_relays();
+                    }
+
+                    // the addresses the peer still listens on are kept for another ttl
+                    for addr in &listen_addrs {
+                        self.swarm
+                            .behaviour_mut()
+                            .addressbook
+                            .confirm_address(peer_id, addr.clone());
+                    }
+
                     if let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() {
                         if protocols.iter().any(|p| libp2p::kad::PROTOCOL_NAME.eq(p)) {
                             for addr in &listen_addrs {
                                 kad.add_address(&peer_id, addr.clone());
                             }
+
+                            if self.persist_dht_peers {
+                                store_dht_peer(&self.repo, peer_id, &listen_addrs);
+                            }
+
+                            // looking up our own id makes the peer share the peers closest to us
+                            if self.peer_exchange && self.exchanged_peers.insert(peer_id) {
+                                let local_peer_id = *self.swarm.local_peer_id();
+                                if let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() {
+                                    kad.get_closest_peers(local_peer_id);
+                                }
+                            }
                         }
                     }
 
@@ -898,16 +1621,127 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                 }
                 event => trace!("identify: {:?}", event),
             },
+            SwarmEvent::Behaviour(BehaviourEvent::IdentifyRequest(event)) => match event {
+                identify_request::Event::Received { peer_id, info } => {
+                    self.swarm
+                        .behaviour_mut()
+                        .peerbook
+                        .inject_peer_info((*info).clone());
+
+                    for ret in self.identify_refresh.remove(&peer_id).unwrap_or_default() {
+                        let _ = ret.send(Ok((*info).clone()));
+                    }
+                }
+                identify_request::Event::Failed { peer_id, error } => {
+                    debug!("identify: request to {peer_id} failed: {error}");
+                    for ret in self.identify_refresh.remove(&peer_id).unwrap_or_default() {
+                        let _ = ret.send(Err(anyhow!("identify request failed: {error}")));
+                    }
+                }
+            },
+            SwarmEvent::Behaviour(BehaviourEvent::RelayClient(event)) => match event {
+                RelayClientEvent::ReservationReqAccepted {
+                    relay_peer_id,
+                    renewal,
+                    limit,
+                } => {
+                    if renewal {
+                        debug!("relay: renewed reservation with {relay_peer_id}");
+                        self.emit_listener_event(ListenerEvent::ReservationRenewed {
+                            relay: relay_peer_id,
+                        });
+                    }
+                    let reservation = ReservationInfo {
+                        relay: relay_peer_id,
+                        renewed_at: Instant::now(),
+                        circuit_duration: limit.as_ref().and_then(|limit| limit.duration()),
+                        circuit_bytes: limit.as_ref().and_then(|limit| limit.data_in_bytes()),
+                    };
+                    self.client_reservations.insert(relay_peer_id, reservation);
+                }
+                RelayClientEvent::ReservationReqFailed {
+                    relay_peer_id,
+                    renewal,
+                    error,
+                } => {
+                    if renewal {
+                        warn!("relay: failed to renew reservation with {relay_peer_id}: {error:?}");
+                        self.emit_listener_event(ListenerEvent::ReservationRenewalFailed {
+                            relay: relay_peer_id,
+                            error: error.to_string(),
+                        });
+                    }
+                    self.client_reservations.remove(&relay_peer_id);
+                }
+                event => trace!("relay client: {:?}", event),
+            },
+            SwarmEvent::Behaviour(BehaviourEvent::Relay(event)) => match event {
+                RelayEvent::CircuitReqAccepted {
+                    src_peer_id,
+                    dst_peer_id,
+                } => {
+                    let established_at = Instant::now();
+                    self.relayed_circuits.push(CircuitInfo {
+                        src: src_peer_id,
+                        dst: dst_peer_id,
+                        established_at,
+                        expires_at: established_at + self.relay_server_config.max_circuit_duration,
+                        max_bytes: self.relay_server_config.max_circuit_bytes,
+                    });
+                }
+                RelayEvent::CircuitClosed {
+                    src_peer_id,
+                    dst_peer_id,
+                    ..
+                } => {
+                    if let Some(index) = self.relayed_circuits.iter().position(|circuit| {
+                        circuit.src == src_peer_id && circuit.dst == dst_peer_id
+                    }) {
+                        self.relayed_circuits.swap_remove(index);
+                    }
+                }
+                event => trace!("relay: {:?}", event),
+            },
+            SwarmEvent::Behaviour(BehaviourEvent::Autonat(autonat::Event::OutboundProbe(
+                autonat::OutboundProbeEvent::Response { address, .. },
+            ))) => {
+                // autonat confirms the address only after this event, without an event of its own
+                self.swarm.add_external_address(address);
+            }
             SwarmEvent::Behaviour(BehaviourEvent::Autonat(autonat::Event::StatusChanged {
                 old,
                 new,
             })) => {
-                //TODO: Use status to indicate if we should use a relay or not
                 debug!("Old Nat Status: {:?}", old);
                 debug!("New Nat Status: {:?}", new);
+
+                // an unknown status keeps the relays until the node is known to be public
+                if let Some(autorelay) = self.autorelay.as_mut() {
+                    match new {
+                        autonat::NatStatus::Private => {
+                            autorelay.set_private(true);
+                            self.select_relays();
+                        }
+                        autonat::NatStatus::Public(_) => {
+                            autorelay.set_private(false);
+                            let listeners = autorelay.take_listeners();
+                            if !listeners.is_empty() {
+                                debug!("autorelay: node is public, closing the relay circuits");
+                            }
+                            for id in listeners {
+                                self.listeners.remove(&id);
+                                self.swarm.remove_listener(id);
+                            }
+                        }
+                        autonat::NatStatus::Unknown => {}
+                    }
+                }
             }
             _ => trace!("Swarm event: {:?}", swarm_event),
         }
+        if addresses_changed {
+            self.push_identify_on_external_change();
+        }
     }
 
     fn handle_event(&mut self, event: IpfsEvent) {
@@ -936,6 +1770,62 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                 };
                 ret.send(Ok(res)).ok();
             }
+            IpfsEvent::RelayReservations(ret) => {
+                if !self.swarm.behaviour().relay_client.is_enabled() {
+                    let _ = ret.send(Err(anyhow!("relay client is disabled")));
+                } else {
+                    let reservations = self.client_reservations.values().cloned().collect();
+                    let _ = ret.send(Ok(reservations));
+                }
+            }
+            IpfsEvent::RelayedCircuits(ret) => {
+                if !self.swarm.behaviour().relay.is_enabled() {
+                    let _ = ret.send(Err(anyhow!("relay server is disabled")));
+                } else {
+                    let _ = ret.send(Ok(self.relayed_circuits.clone()));
+                }
+            }
+            IpfsEvent::KadRoutingTable(ret) => {
+                let table = match self.swarm.behaviour_mut().kademlia.as_mut() {
+                    Some(kad) => {
+                        let buckets = kad
+                            .kbuckets()
+                            .filter_map(|bucket| {
+                                let (low, _) = bucket.range();
+                                Some(KadBucketInfo {
+                                    distance: low.ilog2()?,
+                                    peers: bucket.num_entries(),
+                                })
+                            })
+                            .collect::<Vec<_>>();
+                        let peers = buckets.iter().map(|bucket| bucket.peers).sum();
+                        Ok(KadTableInfo { buckets, peers })
+                    }
+                    None => Err(anyhow!("kad protocol is disabled")),
+                };
+                let _ = ret.send(table);
+            }
+            IpfsEvent::ListenerDescriptors(ret) => {
+                let mut listeners: HashMap<ListenerId, Vec<Multiaddr>> = HashMap::new();
+                for (addr, id) in &self.listening_addresses {
+                    listeners.entry(*id).or_default().push(addr.clone());
+                }
+
+                let mut descriptors = listeners
+                    .into_iter()
+                    .map(|(id, mut addresses)| {
+                        addresses.sort();
+                        ListenerDescriptor {
+                            id,
+                            transport: TransportKind::from(&addresses[0]),
+                            addresses,
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                descriptors.sort_by(|a, b| a.addresses.cmp(&b.addresses));
+
+                let _ = ret.send(descriptors);
+            }
             IpfsEvent::ExternalAddresses(ret) => {
                 let external = self
                     .swarm
@@ -961,12 +1851,16 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                 let connections = self.swarm.connected_peers().copied();
                 ret.send(Ok(connections.collect())).ok();
             }
-            IpfsEvent::Disconnect(peer, ret) => {
+            IpfsEvent::Disconnect(peer, reason, ret) => {
                 let recv = self.swarm.behaviour_mut().peerbook.disconnect(peer);
                 let (tx, rx) = oneshot::channel();
                 if !self.swarm.is_connected(&peer) {
                     let _ = tx.send(Err(anyhow::anyhow!("Peer not connected")));
                 } else {
+                    if let Some(reason) = reason {
+                        info!("disconnecting {peer}: {reason}");
+                        self.disconnect_reasons.insert(peer, reason);
+                    }
                     self.disconnect_confirmation
                         .entry(peer)
                         .or_default()
@@ -975,6 +1869,9 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                 let _ = ret.send((recv, rx));
             }
             IpfsEvent::Ban(peer, ret) => {
+                if self.swarm.is_connected(&peer) {
+                    self.disconnect_reasons.insert(peer, "banned".into());
+                }
                 self.swarm.behaviour_mut().block_list.block_peer(peer);
                 let _ = ret.send(Ok(()));
             }
@@ -983,22 +1880,25 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                 let _ = ret.send(Ok(()));
             }
             IpfsEvent::PubsubSubscribe(topic, ret) => {
-                let _ = ret.send(self.swarm.behaviour_mut().pubsub().subscribe(topic).ok());
+                let _ = ret.send(self.pubsub().map(|pubsub| pubsub.subscribe(topic).ok()));
             }
             IpfsEvent::PubsubUnsubscribe(topic, ret) => {
-                let _ = ret.send(self.swarm.behaviour_mut().pubsub().unsubscribe(topic));
+                let _ = ret.send(self.pubsub().and_then(|pubsub| pubsub.unsubscribe(topic)));
             }
             IpfsEvent::PubsubPublish(topic, data, ret) => {
-                let _ = ret.send(self.swarm.behaviour_mut().pubsub().publish(topic, data));
+                let _ =
+                    ret.send(self.pubsub().and_then(|pubsub| {
+                        pubsub.publish(topic, data).map_err(anyhow::Error::from)
+                    }));
             }
             IpfsEvent::PubsubPeers(Some(topic), ret) => {
-                let _ = ret.send(self.swarm.behaviour_mut().pubsub().subscribed_peers(&topic));
+                let _ = ret.send(self.pubsub().map(|pubsub| pubsub.subscribed_peers(&topic)));
             }
             IpfsEvent::PubsubPeers(None, ret) => {
-                let _ = ret.send(self.swarm.behaviour_mut().pubsub().known_peers());
+                let _ = ret.send(self.pubsub().map(|pubsub| pubsub.known_peers()));
             }
             IpfsEvent::PubsubSubscribed(ret) => {
-                let _ = ret.send(self.swarm.behaviour_mut().pubsub().subscribed_topics());
+                let _ = ret.send(self.pubsub().map(|pubsub| pubsub.subscribed_topics()));
             }
             // IpfsEvent::WantList(peer, ret) => {
             //     let list = if let Some(peer) = peer {
@@ -1023,9 +1923,36 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                 self.pubsub_event_stream.push(tx);
                 let _ = ret.send(rx);
             }
-            IpfsEvent::AddListeningAddress(addr, ret) => match self.swarm.listen_on(addr) {
+            IpfsEvent::ConnectionEventStream(ret) => {
+                let (tx, rx) = unbounded();
+                self.connection_event_stream.push(tx);
+                let _ = ret.send(rx);
+            }
+            IpfsEvent::PeerDiscoveryStream(ret) => {
+                let (tx, rx) = unbounded();
+                self.peer_discovery_stream.push(tx);
+                let _ = ret.send(rx);
+            }
+            IpfsEvent::ListenerEventStream(ret) => {
+                let (tx, rx) = unbounded();
+                self.listener_event_stream.push(tx);
+                let _ = ret.send(rx);
+            }
+            IpfsEvent::AddExternalAddress(addr, ret) => {
+                self.promote_external_address(addr);
+                self.push_identify_on_external_change();
+                let _ = ret.send(Ok(()));
+            }
+            IpfsEvent::AddListeningAddress(addr, ret) => match self.swarm.listen_on(addr.clone()) {
                 Ok(id) => {
                     self.listeners.insert(id);
+                    if addr.is_relay() {
+                        let reservation = RelayReservation {
+                            addr,
+                            retry_delay: None,
+                        };
+                        self.relay_reservations.insert(id, reservation);
+                    }
                     let (tx, rx) = oneshot::channel();
                     self.listener_subscriptions.insert(id, tx);
                     let _ = ret.send(Ok(rx));
@@ -1039,6 +1966,7 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                     match self.swarm.remove_listener(id) {
                         true => {
                             self.listeners.remove(&id);
+                            self.relay_reservations.remove(&id);
                             let (tx, rx) = oneshot::channel();
                             self.listener_subscriptions.insert(id, tx);
                             let _ = ret.send(Ok(rx));
@@ -1050,6 +1978,16 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                             )));
                         }
                     }
+                } else if let Some(index) = self
+                    .lost_relay_reservations
+                    .iter()
+                    .position(|(_, reservation)| reservation.addr == addr)
+                {
+                    // the circuit is not listened on until the next retry, which is cancelled
+                    self.lost_relay_reservations.swap_remove(index);
+                    let (tx, rx) = oneshot::channel();
+                    let _ = tx.send(Either::Right(Ok(())));
+                    let _ = ret.send(Ok(rx));
                 } else {
                     let _ = ret.send(Err(format_err!(
                         "Address was not listened to before: {}",
@@ -1078,6 +2016,27 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                 };
                 let _ = ret.send(future);
             }
+            IpfsEvent::BootstrapProgress(ret) => {
+                let stream = match self
+                    .swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .as_mut()
+                    .map(|kad| kad.bootstrap())
+                {
+                    Some(Ok(id)) => {
+                        let (tx, rx) = unbounded();
+                        self.bootstrap_progress.insert(id, (tx, Vec::new()));
+                        Ok(rx.boxed())
+                    }
+                    Some(Err(e)) => {
+                        error!("kad: can't bootstrap the node: {:?}", e);
+                        Err(anyhow!("kad: can't bootstrap the node: {:?}", e))
+                    }
+                    None => Err(anyhow!("kad protocol is disabled")),
+                };
+                let _ = ret.send(stream);
+            }
             IpfsEvent::AddPeer(peer_id, addr, ret) => {
                 let result = match self
                     .swarm
@@ -1093,6 +2052,14 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
 
                 let _ = ret.send(result);
             }
+            IpfsEvent::AddressTtl(peer_id, addr, ret) => {
+                let _ = ret.send(
+                    self.swarm
+                        .behaviour()
+                        .addressbook
+                        .remaining_ttl(&peer_id, &addr),
+                );
+            }
             IpfsEvent::RemovePeer(peer_id, addr, ret) => {
                 let result = match addr {
                     Some(addr) => Ok(self
@@ -1147,6 +2114,37 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                     let _ = ret.send(futures::future::ready(vec![]).boxed());
                 }
             }
+            IpfsEvent::SendBitswapMessage(peer, message, ret) => {
+                let sent = self
+                    .swarm
+                    .behaviour_mut()
+                    .send_bitswap_message(peer, message);
+                let _ = ret.send(sent);
+            }
+            IpfsEvent::BitswapDroppedMessages(ret) => {
+                let dropped = self
+                    .swarm
+                    .behaviour()
+                    .bitswap
+                    .as_ref()
+                    .map(|bitswap| bitswap.dropped_messages())
+                    .unwrap_or_default();
+                let _ = ret.send(dropped);
+            }
+            IpfsEvent::SetBitswapServer(enabled, ret) => {
+                self.swarm.behaviour_mut().set_bitswap_server(enabled);
+                let _ = ret.send(());
+            }
+            IpfsEvent::BitswapUnservedWants(ret) => {
+                let unserved = self
+                    .swarm
+                    .behaviour()
+                    .bitswap
+                    .as_ref()
+                    .map(|bitswap| bitswap.unserved_wants())
+                    .unwrap_or_default();
+                let _ = ret.send(unserved);
+            }
             IpfsEvent::GetBitswapPeers(ret) => {
                 if let Some(bitswap) = self.swarm.behaviour().bitswap.as_ref() {
                     let client = bitswap.client().clone();
@@ -1164,6 +2162,9 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                     Some(info) => {
                         let _ = tx.send(Ok(info.clone()));
                     }
+                    None if !self.swarm.behaviour().identify.is_enabled() => {
+                        let _ = tx.send(Err(anyhow!("identify protocol is disabled")));
+                    }
                     None => {
                         self.swarm
                             .behaviour_mut()
@@ -1177,6 +2178,30 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
 
                 let _ = ret.send(rx);
             }
+            IpfsEvent::RefreshIdentify(peer_id, ret) => {
+                let (tx, rx) = oneshot::channel();
+
+                let requested = self
+                    .swarm
+                    .behaviour_mut()
+                    .identify_request
+                    .as_mut()
+                    .map(|identify| identify.request(peer_id));
+
+                match requested {
+                    None => {
+                        let _ = tx.send(Err(anyhow!("identify protocol is disabled")));
+                    }
+                    Some(false) => {
+                        let _ = tx.send(Err(anyhow!("peer is not connected")));
+                    }
+                    Some(true) => {
+                        self.identify_refresh.entry(peer_id).or_default().push(tx);
+                    }
+                }
+
+                let _ = ret.send(rx);
+            }
             IpfsEvent::FindPeer(peer_id, local_only, ret) => {
                 let listener_addrs = self
                     .swarm
@@ -1228,8 +2253,36 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                 self.swarm.behaviour_mut().peerbook.remove(peer_id);
                 let _ = ret.send(Ok(()));
             }
-            IpfsEvent::GetProviders(cid, ret) => {
-                let key = Key::from(cid.hash().to_bytes());
+            IpfsEvent::TagPeer(peer_id, tag, ret) => {
+                self.swarm.behaviour_mut().peerbook.tag_peer(peer_id, tag);
+                let _ = ret.send(Ok(()));
+            }
+            IpfsEvent::UntagPeer(peer_id, tag, ret) => {
+                self.swarm
+                    .behaviour_mut()
+                    .peerbook
+                    .untag_peer(peer_id, &tag);
+                let _ = ret.send(Ok(()));
+            }
+            IpfsEvent::PeersWithTag(tag, ret) => {
+                let _ = ret.send(self.swarm.behaviour().peerbook.peers_with_tag(&tag));
+            }
+            IpfsEvent::ConnectionInfo(peer_id, ret) => {
+                let _ = ret.send(self.swarm.behaviour().peerbook.connection_info(peer_id));
+            }
+            IpfsEvent::ConnectionLimits(ret) => {
+                let _ = ret.send(self.swarm.behaviour().peerbook.connection_limit());
+            }
+            IpfsEvent::UpdateConnectionLimits(limits, ret) => {
+                let previous = self
+                    .swarm
+                    .behaviour_mut()
+                    .peerbook
+                    .update_connection_limit(limits);
+                let _ = ret.send(previous);
+            }
+            IpfsEvent::GetProviders(cid, max_results, ret) => {
+                let key = self.provider_key_scheme.key(&cid);
                 let id = self
                     .swarm
                     .behaviour_mut()
@@ -1243,7 +2296,11 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                 if let Some(id) = id {
                     let stream = async_stream::stream! {
                         let mut current_providers: HashSet<PeerId> = Default::default();
-                        while let Some(provider) = rx.next().await {
+                        // ending the stream drops `rx`, which lets the query be finished early
+                        while Some(current_providers.len()) != max_results {
+                            let Some(provider) = rx.next().await else {
+                                break;
+                            };
                             if current_providers.insert(provider) {
                                 yield provider;
                             }
@@ -1256,25 +2313,15 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                 let _ = ret.send(provider_stream);
             }
             IpfsEvent::Provide(cid, ret) => {
-                let key = Key::from(cid.hash().to_bytes());
-                let future = match self
+                let future = self
                     .swarm
                     .behaviour_mut()
-                    .kademlia
-                    .as_mut()
-                    .map(|kad| kad.start_providing(key))
-                {
-                    Some(Ok(id)) => {
+                    .start_providing_block(&cid, self.provider_key_scheme)
+                    .map(|id| {
                         let (tx, rx) = oneshot::channel();
                         self.kad_subscriptions.insert(id, tx);
-                        Ok(rx)
-                    }
-                    Some(Err(e)) => {
-                        error!("kad: can't provide a key: {:?}", e);
-                        Err(anyhow!("kad: can't provide the key: {:?}", e))
-                    }
-                    None => Err(anyhow!("kad protocol is disabled")),
-                };
+                        rx
+                    });
                 let _ = ret.send(future);
             }
             IpfsEvent::DhtMode(mode, ret) => {
@@ -1449,38 +2496,32 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
 
     fn handle_repo_event(&mut self, event: RepoEvent) {
         match event {
-            RepoEvent::WantBlock(session, cid, peers) => {
-                if let Some(bitswap) = self.swarm.behaviour().bitswap.as_ref() {
-                    let client = bitswap.client().clone();
-                    let repo = self.repo.clone();
-                    let (closer_s, closer_r) = oneshot::channel();
-                    //If there is no session context defined, we will use 0 as its root context
-                    let ctx = session.unwrap_or(0);
-                    let entry = self.bitswap_sessions.entry(ctx).or_default();
-
-                    let worker = tokio::task::spawn(async move {
-                        tokio::select! {
-                            _ = closer_r => {
-                                // Explicit sesssion stop.
-                                debug!("session {}: stopped: closed", ctx);
-                            }
-                            block = client.get_block_with_session_id(ctx, &cid, &peers) => match block {
-                                Ok(block) => {
-                                    info!("Found {cid}");
-                                    let block = libipld::Block::new_unchecked(block.cid, block.data.to_vec());
-                                    let res = repo.put_block(block).await;
-                                    if let Err(e) = res {
-                                        error!("Got block {} but failed to store it: {}", cid, e);
-                                    }
-
+            RepoEvent::WantBlock(session, cid, peers, priority) => {
+                //If there is no session context defined, we will use 0 as its root context
+                let ctx = session.unwrap_or(0);
+                match self.block_fetches.get(&cid) {
+                    Some(fetch) if !fetch.worker.is_finished() => {
+                        // the block is already being fetched, ask the peers of this request too
+                        if let Some(bitswap) = self.swarm.behaviour().bitswap.as_ref() {
+                            let client = bitswap.client().clone();
+                            let fetching = fetch.session;
+                            let providers = peers.clone();
+                            let record_sources = self.repo.records_block_sources(ctx);
+                            tokio::task::spawn(async move {
+                                let session = client.get_or_create_session(fetching).await;
+                                if record_sources {
+                                    session.record_block_sources();
                                 }
-                                Err(err) => {
-                                    error!("Failed to get {}: {}", cid, err);
+                                for provider in providers {
+                                    session.add_provider(&cid, provider).await;
                                 }
-                            },
+                            });
                         }
-                    });
-                    entry.push((closer_s, worker));
+                        if fetch.session != ctx {
+                            fetch.waiting.lock().push((ctx, peers, priority));
+                        }
+                    }
+                    _ => self.fetch_block(ctx, cid, peers, priority, Vec::new()),
                 }
             }
             RepoEvent::UnwantBlock(_cid) => {}
@@ -1505,7 +2546,110 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                 }
                 let _ = ret.send(Err(anyhow!("not actively providing blocks yet")));
             }
-            RepoEvent::RemovedBlock(cid) => self.swarm.behaviour_mut().stop_providing_block(&cid),
+            RepoEvent::RemovedBlock(cid) => {
+                if let Some(schedule) = self.reprovide.as_mut() {
+                    schedule.remove(&self.provider_key_scheme.key(&cid));
+                }
+                self.swarm
+                    .behaviour_mut()
+                    .stop_providing_block_with_scheme(&cid, self.provider_key_scheme)
+            }
         }
     }
 }
+
+/// Returns the relay of a circuit address, the peer preceding `/p2p-circuit`.
+fn circuit_relay(addr: &Multiaddr) -> Option<PeerId> {
+    let mut relay = None;
+    for protocol in addr.iter() {
+        match protocol {
+            Protocol::P2p(peer_id) => relay = Some(peer_id),
+            Protocol::P2pCircuit => return relay,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Datastore key prefix for the peers found to support the DHT protocol
+const DHT_PEER_PREFIX: &str = "/dht/peers/";
+
+fn store_dht_peer(repo: &Repo, peer_id: PeerId, addrs: &[Multiaddr]) {
+    let addrs = addrs.iter().map(ToString::to_string).collect::<Vec<_>>();
+    let value = match serde_json::to_vec(&addrs) {
+        Ok(value) => value,
+        Err(e) => {
+            warn!("failed to encode addresses for {peer_id}: {e}");
+            return;
+        }
+    };
+    let repo = repo.clone();
+    tokio::spawn(async move {
+        let key = format!("{DHT_PEER_PREFIX}{peer_id}");
+        if let Err(e) = repo.data_store().put(key.as_bytes(), &value).await {
+            warn!("failed to persist dht peer {peer_id}: {e}");
+        }
+    });
+}
+
+/// Loads the peers persisted by previous sessions along with their addresses
+pub(crate) async fn load_dht_peers(repo: &Repo) -> Vec<(PeerId, Vec<Multiaddr>)> {
+    repo.data_store()
+        .iter()
+        .await
+        .filter_map(|item| async move {
+            let (key, value) = match item {
+                Ok(item) => item,
+                Err(e) => {
+                    warn!("failed to read the persisted dht peers: {e}");
+                    return None;
+                }
+            };
+            let key = String::from_utf8(key).ok()?;
+            let peer_id = key.strip_prefix(DHT_PEER_PREFIX)?.parse::<PeerId>().ok()?;
+            let addrs = serde_json::from_slice::<Vec<String>>(&value)
+                .ok()?
+                .iter()
+                .filter_map(|addr| addr.parse().ok())
+                .collect::<Vec<Multiaddr>>();
+            Some((peer_id, addrs))
+        })
+        .collect()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::FutureExt;
+
+    use super::WantQueue;
+
+    #[tokio::test]
+    async fn queued_wants_are_let_in_by_priority() {
+        let queue = WantQueue::new(1);
+        let first = queue.acquire(0).await.unwrap();
+
+        let mut queued = Vec::new();
+        for priority in [0, 0, 10] {
+            let mut want = Box::pin(queue.acquire(priority));
+            assert!(futures::poll!(&mut want).is_pending());
+            queued.push(want);
+        }
+
+        let mut order = Vec::new();
+        let mut permit = first;
+        while order.len() < queued.len() {
+            drop(permit);
+            let (i, next) = queued
+                .iter_mut()
+                .enumerate()
+                .filter(|(i, _)| !order.contains(i))
+                .find_map(|(i, want)| Some((i, want.now_or_never()?)))
+                .expect("a queued want was let in");
+            order.push(i);
+            permit = next.unwrap();
+        }
+
+        assert_eq!(order, [2, 0, 1]);
+    }
+}
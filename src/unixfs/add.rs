@@ -1,6 +1,10 @@
 use std::path::Path;
 
-use crate::{repo::Repo, Block};
+use crate::{
+    p2p::BitswapConfig,
+    repo::{PendingWrites, Repo},
+    Block,
+};
 use either::Either;
 use futures::{stream::BoxStream, Stream, StreamExt};
 use rust_unixfs::file::adder::{Chunker, FileAdderBuilder};
@@ -16,8 +20,18 @@ pub struct AddOption {
     pub pin: bool,
     pub provide: bool,
     pub wrap: bool,
+    /// Skip writing the blocks which are already in the repo, e.g. when re-adding a mostly
+    /// unchanged file.
+    pub skip_existing: bool,
+    /// Largest block the content may consist of. The leaves are chunked to fit under it while
+    /// adding fails on any other block exceeding it. Defaults to the largest block the bitswap
+    /// messages of the node can carry, so that the content can be served to other peers.
+    pub max_block_size: Option<usize>,
 }
 
+/// Room left in a leaf block for the dag-pb and unixfs wrapping around the chunk.
+const LEAF_OVERHEAD: usize = 64;
+
 impl Default for AddOption {
     fn default() -> Self {
         Self {
@@ -25,6 +39,8 @@ impl Default for AddOption {
             pin: false,
             provide: false,
             wrap: false,
+            skip_existing: true,
+            max_block_size: None,
         }
     }
 }
@@ -65,13 +81,37 @@ pub async fn add<'a>(
         Either::Right(repo) => (None, repo.clone()),
     };
 
+    let skip_existing = opt.unwrap_or_default().skip_existing;
+
+    let max_block_size = opt
+        .and_then(|o| o.max_block_size)
+        .or(ipfs.as_ref().map(|ipfs| ipfs.max_block_size))
+        .unwrap_or_else(|| BitswapConfig::default().max_block_size());
+
+    if max_block_size <= LEAF_OVERHEAD {
+        anyhow::bail!("maximum block size of {max_block_size} bytes leaves no room for data");
+    }
+
+    let chunker = match opt.map(|o| o.chunk.unwrap_or_default()).unwrap_or_default() {
+        Chunker::Size(size) => Chunker::Size(size.min(max_block_size - LEAF_OVERHEAD)),
+    };
+
     let stream = async_stream::stream! {
 
+        // the blocks are not pinned until the root is, so keep the quota from collecting them
+        let pending = repo.pending_writes();
+
         let mut adder = FileAdderBuilder::default()
-            .with_chunker(opt.map(|o| o.chunk.unwrap_or_default()).unwrap_or_default())
+            .with_chunker(chunker)
             .build();
 
         let mut written = 0;
+        // cumulative size of the blocks, used as the size of the link when wrapping
+        let mut dag_size = 0;
+        // a file of a single chunk is complete once pushed, leaving nothing for `finish`
+        let mut last_cid = None;
+        // blocks which were already present in the repo
+        let mut deduplicated = 0;
         yield UnixfsStatus::ProgressStatus { written, total_size };
 
         while let Some(buffer) = stream.next().await {
@@ -87,6 +127,7 @@ pub async fn add<'a>(
             while total < buffer.len() {
                 let (blocks, consumed) = adder.push(&buffer[total..]);
                 for (cid, block) in blocks {
+                    dag_size += block.len();
                     let block = match Block::new(cid, block) {
                         Ok(block) => block,
                         Err(e) => {
@@ -94,13 +135,15 @@ pub async fn add<'a>(
                             return;
                         }
                     };
-                    let _cid = match repo.put_block(block).await {
-                        Ok(cid) => cid,
+                    match store_block(&repo, &pending, block, skip_existing, max_block_size).await {
+                        Ok(true) => {}
+                        Ok(false) => deduplicated += 1,
                         Err(e) => {
                             yield UnixfsStatus::FailedStatus { written, total_size, error: Some(anyhow::anyhow!("{e}")) };
                             return;
                         }
                     };
+                    last_cid = Some(cid);
                 }
                 total += consumed;
                 written += consumed;
@@ -110,9 +153,9 @@ pub async fn add<'a>(
         }
 
         let blocks = adder.finish();
-        let mut last_cid = None;
 
         for (cid, block) in blocks {
+            dag_size += block.len();
             let block = match Block::new(cid, block) {
                 Ok(block) => block,
                 Err(e) => {
@@ -120,8 +163,9 @@ pub async fn add<'a>(
                     return;
                 }
             };
-            let _cid = match repo.put_block(block).await {
-                Ok(cid) => cid,
+            match store_block(&repo, &pending, block, skip_existing, max_block_size).await {
+                Ok(true) => {}
+                Ok(false) => deduplicated += 1,
                 Err(e) => {
                     yield UnixfsStatus::FailedStatus { written, total_size, error: Some(anyhow::anyhow!("{e}")) };
                     return;
@@ -146,33 +190,40 @@ pub async fn add<'a>(
                 if let Some(name) = name {
                     let result = {
                         let repo = repo.clone();
+                        let pending = &pending;
                         async move {
                             let mut opts = rust_unixfs::dir::builder::TreeOptions::default();
                             opts.wrap_with_directory();
 
                             let mut tree = rust_unixfs::dir::builder::BufferingTreeBuilder::new(opts);
-                            tree.put_link(&name, cid, written as _)?;
+                            tree.put_link(&name, cid, dag_size as _)?;
 
                             let mut iter = tree.build();
                             let mut cids = Vec::new();
+                            let mut deduplicated = 0;
 
                             while let Some(node) = iter.next_borrowed() {
                                 let node = node?;
                                 let block = Block::new(node.cid.to_owned(), node.block.into())?;
 
-                                repo.put_block(block).await?;
+                                if !store_block(&repo, pending, block, skip_existing, max_block_size).await? {
+                                    deduplicated += 1;
+                                }
 
                                 cids.push(*node.cid);
                             }
                             let cid = cids.last().ok_or(anyhow::anyhow!("no cid available"))?;
                             let path = IpfsPath::from(*cid).sub_path(&name)?;
 
-                            Ok::<_, anyhow::Error>(path)
+                            Ok::<_, anyhow::Error>((path, deduplicated))
                         }
                     };
 
                     path = match result.await {
-                        Ok(path) => path,
+                        Ok((path, wrapper_deduplicated)) => {
+                            deduplicated += wrapper_deduplicated;
+                            path
+                        }
                         Err(e) => {
                             yield UnixfsStatus::FailedStatus { written, total_size, error: Some(anyhow::anyhow!("{e}")) };
                             return;
@@ -206,8 +257,132 @@ pub async fn add<'a>(
             });
         }
 
+        yield UnixfsStatus::DeduplicatedStatus { deduplicated };
         yield UnixfsStatus::CompletedStatus { path, written, total_size }
     };
 
     Ok(stream.boxed())
 }
+
+/// Stores the block unless `skip_existing` is set and the block is already in the repo. Returns
+/// whether the block was written, or an error if the block is larger than `max_block_size`.
+async fn store_block(
+    repo: &Repo,
+    pending: &PendingWrites,
+    block: Block,
+    skip_existing: bool,
+    max_block_size: usize,
+) -> anyhow::Result<bool> {
+    let size = block.data().len();
+    if size > max_block_size {
+        anyhow::bail!(
+            "block {} of {size} bytes exceeds the maximum block size of {max_block_size} bytes",
+            block.cid()
+        );
+    }
+    pending.protect(*block.cid());
+    if skip_existing && repo.contains(block.cid()).await? {
+        return Ok(false);
+    }
+    repo.put_block(block).await?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use either::Either;
+    use futures::StreamExt;
+    use rust_unixfs::file::adder::Chunker;
+
+    use super::{add, AddOption};
+    use crate::{repo::Repo, unixfs::UnixfsStatus};
+
+    /// Adds `data` to the repo, returning the number of deduplicated blocks.
+    async fn add_bytes(repo: &Repo, data: &[u8], opt: AddOption) -> usize {
+        let stream = futures::stream::iter(vec![Ok(data.to_vec())]);
+        let mut status = add(Either::Right(repo), None, None, stream.boxed(), Some(opt))
+            .await
+            .unwrap();
+
+        while let Some(status) = status.next().await {
+            match status {
+                UnixfsStatus::DeduplicatedStatus { deduplicated } => return deduplicated,
+                UnixfsStatus::FailedStatus { error, .. } => panic!("adding failed: {error:?}"),
+                UnixfsStatus::ProgressStatus { .. } | UnixfsStatus::CompletedStatus { .. } => {}
+            }
+        }
+        unreachable!("add reports the deduplicated blocks unless it fails")
+    }
+
+    #[tokio::test]
+    async fn readding_skips_existing_blocks() {
+        let repo = Repo::new_memory();
+        let opt = AddOption {
+            chunk: Some(Chunker::Size(1024)),
+            ..Default::default()
+        };
+        let data = (0..1250u32).flat_map(u32::to_be_bytes).collect::<Vec<_>>();
+
+        assert_eq!(add_bytes(&repo, &data, opt).await, 0);
+        let blocks = repo.list_blocks().await.unwrap().len();
+        // five leaves and the root
+        assert_eq!(blocks, 6);
+
+        assert_eq!(add_bytes(&repo, &data, opt).await, blocks);
+        assert_eq!(repo.list_blocks().await.unwrap().len(), blocks);
+
+        let opt = AddOption {
+            skip_existing: false,
+            ..opt
+        };
+        assert_eq!(add_bytes(&repo, &data, opt).await, 0);
+    }
+
+    #[tokio::test]
+    async fn added_blocks_are_kept_from_the_quota() {
+        use crate::Block;
+        use libipld::{
+            multihash::{Code, MultihashDigest},
+            Cid, IpldCodec,
+        };
+
+        let repo = Repo::new_memory();
+        let data = vec![1u8; 4000];
+        let unpinned = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+        repo.put_block(Block::new(unpinned, data).unwrap())
+            .await
+            .unwrap();
+
+        repo.set_storage_quota(Some(8000));
+        let opt = AddOption {
+            chunk: Some(Chunker::Size(1024)),
+            pin: true,
+            ..Default::default()
+        };
+        let data = (0..1250u32).flat_map(u32::to_be_bytes).collect::<Vec<_>>();
+        add_bytes(&repo, &data, opt).await;
+
+        // only the unpinned block made room for the file
+        assert!(!repo.contains(&unpinned).await.unwrap());
+        assert_eq!(repo.list_blocks().await.unwrap().len(), 6);
+    }
+
+    #[tokio::test]
+    async fn leaves_are_chunked_under_the_max_block_size() {
+        let repo = Repo::new_memory();
+        let opt = AddOption {
+            max_block_size: Some(1024),
+            ..Default::default()
+        };
+        let data = (0..1250u32).flat_map(u32::to_be_bytes).collect::<Vec<_>>();
+
+        add_bytes(&repo, &data, opt).await;
+
+        let cids = repo.list_blocks().await.unwrap();
+        assert!(cids.len() > 5, "the default chunk size was not reduced");
+        for cid in cids {
+            let block = repo.get_block_now(&cid).await.unwrap().unwrap();
+            assert!(block.data().len() <= 1024);
+        }
+    }
+}
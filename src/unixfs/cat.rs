@@ -1,14 +1,19 @@
+use super::SymlinkError;
 use crate::{
     dag::{IpldDag, ResolveError, UnexpectedResolved},
     repo::Repo,
     Block, Error, Ipfs,
 };
 use async_stream::stream;
+use bytes::Bytes;
 use either::Either;
-use futures::stream::Stream;
+use futures::stream::{BoxStream, Stream, StreamExt};
 use libipld::Cid;
 use libp2p::PeerId;
-use rust_unixfs::file::{visit::IdleFileVisit, FileReadFailed};
+use rust_unixfs::{
+    file::{visit::IdleFileVisit, FileReadFailed},
+    walk::{ContinuedWalk, Walker},
+};
 use std::borrow::Borrow;
 use std::ops::Range;
 
@@ -17,13 +22,15 @@ use std::ops::Range;
 /// and an owned value. Passing an owned value allows the return value to be `'static`, which can
 /// be helpful in some contexts, like the http.
 ///
-/// Returns a stream of bytes on the file pointed with the Cid.
+/// Returns a stream of bytes on the file pointed with the Cid. When `follow_symlinks` is set and
+/// the path ends at a symlink, the content of the symlink target is returned instead.
 pub async fn cat<'a>(
     which: Either<&Ipfs, &Repo>,
     starting_point: impl Into<StartingPoint>,
     range: Option<Range<u64>>,
     providers: &'a [PeerId],
     local_only: bool,
+    follow_symlinks: bool,
 ) -> Result<impl Stream<Item = Result<Vec<u8>, TraversalFailed>> + Send + 'a, TraversalFailed> {
     let (repo, dag, session) = match which {
         Either::Left(ipfs) => (
@@ -48,13 +55,8 @@ pub async fn cat<'a>(
     // metadata. To get to it the user needs to create a Visitor over the first block.
     let block = match starting_point.into() {
         StartingPoint::Left(path) => {
-            let (resolved, _) = dag
-                .resolve_with_session(session, path, true, providers, local_only)
-                .await
-                .map_err(TraversalFailed::Resolving)?;
-            resolved
-                .into_unixfs_block()
-                .map_err(TraversalFailed::Path)?
+            super::symlink::resolve(&dag, session, path, providers, local_only, follow_symlinks)
+                .await?
         }
         StartingPoint::Right(block) => block,
     };
@@ -131,6 +133,84 @@ pub async fn cat<'a>(
     })
 }
 
+/// Streams the bytes `range` of the UnixFS file at `path`, without fetching the blocks entirely
+/// outside of it. A range extending beyond the end of the file is clamped to it, and an empty
+/// range yields no bytes.
+///
+/// Unlike [`cat`], the walk fails with an error when `path` does not lead to a file.
+pub async fn cat_range<'a>(
+    which: Either<&Ipfs, &Repo>,
+    path: crate::IpfsPath,
+    range: Range<u64>,
+    providers: &'a [PeerId],
+    local_only: bool,
+    follow_symlinks: bool,
+) -> anyhow::Result<BoxStream<'a, anyhow::Result<Bytes>>> {
+    if range.is_empty() {
+        return Ok(futures::stream::empty().boxed());
+    }
+
+    let (repo, dag, session) = match which {
+        Either::Left(ipfs) => (
+            ipfs.repo().clone(),
+            ipfs.dag(),
+            Some(crate::BITSWAP_ID.fetch_add(1, std::sync::atomic::Ordering::SeqCst)),
+        ),
+        Either::Right(repo) => {
+            let session = repo
+                .is_online()
+                .then_some(crate::BITSWAP_ID.fetch_add(1, std::sync::atomic::Ordering::SeqCst));
+            (repo.clone(), IpldDag::from(repo.clone()), session)
+        }
+    };
+
+    let block =
+        super::symlink::resolve(&dag, session, path, providers, local_only, follow_symlinks)
+            .await?;
+
+    let cid = *block.cid();
+    let mut walker = Walker::new(cid, String::new()).with_target_range(range);
+
+    let stream = stream! {
+        let mut cache = None;
+        // the root block was already loaded while resolving the path
+        let mut root = Some(block);
+        while walker.should_continue() {
+            let block = match root.take() {
+                Some(block) => block,
+                None => {
+                    let (next, _) = walker.pending_links();
+                    match repo.get_block_with_session(session, next, providers, local_only).await {
+                        Ok(block) => block,
+                        Err(e) => {
+                            yield Err(e);
+                            return;
+                        }
+                    }
+                }
+            };
+
+            match walker.next(block.data(), &mut cache) {
+                Ok(ContinuedWalk::File(segment, ..)) => {
+                    if !segment.as_ref().is_empty() {
+                        yield Ok(Bytes::copy_from_slice(segment.as_ref()));
+                    }
+                }
+                Ok(_) => {
+                    yield Err(anyhow::anyhow!("{cid} is not a file"));
+                    return;
+                }
+                Err(e) => {
+                    yield Err(e.into());
+                    return;
+                }
+            }
+        }
+    };
+
+    Ok(stream.boxed())
+}
+
 /// The starting point for unixfs walks. Can be converted from IpfsPath and Blocks, and Cids can be
 /// converted to IpfsPath.
 pub enum StartingPoint {
@@ -169,4 +249,83 @@ pub enum TraversalFailed {
     /// Processing of the block failed
     #[error("walk failed on {}", .0)]
     Walking(Cid, #[source] FileReadFailed),
+
+    /// Following a symlink at the end of the path failed
+    #[error("following symlink failed")]
+    Symlink(#[from] SymlinkError),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+
+    use either::Either;
+    use futures::TryStreamExt;
+    use hex_literal::hex;
+    use libipld::Cid;
+
+    use crate::{
+        repo::Repo,
+        unixfs::tests::{put_v0, FOOBAR_BLOCKS},
+        IpfsPath,
+    };
+
+    /// "foobar\n" in a single block
+    const FOOBAR: &[u8] = &hex!("0a0d08021207666f6f6261720a1807");
+
+    async fn cat_range(repo: &Repo, cid: Cid, range: Range<u64>) -> anyhow::Result<Vec<u8>> {
+        let stream = super::cat_range(
+            Either::Right(repo),
+            IpfsPath::from(cid),
+            range,
+            &[],
+            true,
+            false,
+        )
+        .await?;
+
+        let chunks = stream.try_collect::<Vec<_>>().await?;
+        Ok(chunks.concat())
+    }
+
+    #[tokio::test]
+    async fn range_of_a_single_block() {
+        let repo = Repo::new_memory();
+        let cid = put_v0(&repo, FOOBAR).await;
+
+        assert_eq!(cat_range(&repo, cid, 0..7).await.unwrap(), b"foobar\n");
+        assert_eq!(cat_range(&repo, cid, 1..4).await.unwrap(), b"oob");
+        // clamped to the end of the file
+        assert_eq!(cat_range(&repo, cid, 3..100).await.unwrap(), b"bar\n");
+        assert!(cat_range(&repo, cid, 10..20).await.unwrap().is_empty());
+        assert!(cat_range(&repo, cid, 5..5).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn range_of_several_blocks() {
+        let repo = Repo::new_memory();
+        let cid = put_v0(&repo, FOOBAR_BLOCKS[0]).await;
+        for leaf in &FOOBAR_BLOCKS[1..5] {
+            put_v0(&repo, leaf).await;
+        }
+
+        assert_eq!(cat_range(&repo, cid, 0..100).await.unwrap(), b"foobar\n");
+        assert_eq!(cat_range(&repo, cid, 1..5).await.unwrap(), b"ooba");
+        assert_eq!(cat_range(&repo, cid, 6..7).await.unwrap(), b"\n");
+        assert!(cat_range(&repo, cid, 7..10).await.unwrap().is_empty());
+        assert!(cat_range(&repo, cid, 3..3).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn leaves_outside_of_the_range_are_not_fetched() {
+        let repo = Repo::new_memory();
+        let cid = put_v0(&repo, FOOBAR_BLOCKS[0]).await;
+        // only "ob" and "ar", the walk would fail on loading any of the other leaves
+        for leaf in &FOOBAR_BLOCKS[2..4] {
+            put_v0(&repo, leaf).await;
+        }
+
+        assert_eq!(cat_range(&repo, cid, 2..6).await.unwrap(), b"obar");
+        assert!(cat_range(&repo, cid, 0..6).await.is_err());
+    }
 }
@@ -1,23 +1,81 @@
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use either::Either;
 use futures::{stream::BoxStream, StreamExt};
+use libipld::Cid;
 use libp2p::PeerId;
-use rust_unixfs::walk::{ContinuedWalk, Walker};
+use rust_unixfs::{
+    file::visit::IdleFileVisit,
+    walk::{ContinuedWalk, Walker},
+};
 use tokio::io::AsyncWriteExt;
 
-use crate::{dag::IpldDag, repo::Repo, Ipfs, IpfsPath};
+use crate::{
+    dag::IpldDag,
+    p2p::Priority,
+    repo::{BlockSources, Repo},
+    Ipfs, IpfsPath,
+};
 
 use super::UnixfsStatus;
 
+/// Failures specific to writing out a file with [`get`].
+#[derive(Debug, thiserror::Error)]
+pub enum GetError {
+    /// The walk completed but the amount of bytes written differs from the size declared by the
+    /// root UnixFS node. The incomplete output is removed.
+    #[error("expected {expected} bytes but got {got}")]
+    SizeMismatch { expected: usize, got: usize },
+}
+
+/// Options of [`get`].
+#[derive(Debug, Clone, Default)]
+pub struct GetOptions {
+    /// Writes out what a symlink at the end of the path points to instead of the symlink.
+    pub follow_symlinks: bool,
+    /// Priority the blocks are wanted at, letting them in ahead of the blocks of lower priority
+    /// operations waiting for [`crate::p2p::BitswapConfig::with_max_wantlist_size`].
+    pub priority: Priority,
+    /// Yields at most one progress update per interval while a file is written, besides the
+    /// first and the last one.
+    pub progress_interval: Option<Duration>,
+    /// Records which peer served each of the blocks fetched from the network into the given
+    /// [`BlockSources`].
+    pub block_sources: Option<BlockSources>,
+    /// Continues a partial download in `dest` instead of starting over. When `dest` exists, is no
+    /// longer than the file and its last bytes match those of the file, the blocks holding the
+    /// bytes before them are skipped and the rest of the file is appended. Otherwise `dest` is
+    /// truncated and written from the beginning.
+    pub resume: bool,
+}
+
+/// Writes out the file at `path` to `dest`, yielding the progress as it goes. A directory is
+/// written out as a directory at `dest` along with all of its entries, and a symlink as a
+/// symlink. A progress update follows each directory and symlink created.
+///
+/// When the root is a file declaring its size, the first progress update carries that size and
+/// is yielded before any of the other blocks are fetched.
+///
+/// A file is synced to the disk only once it has been written out in full. The bytes reported by
+/// the progress updates before that may still be lost on a crash of the system, though not on
+/// one of the process, which [`GetOptions::resume`] can continue from.
 pub async fn get<'a, P: AsRef<Path>>(
     which: Either<&Ipfs, &Repo>,
     path: IpfsPath,
     dest: P,
     providers: &'a [PeerId],
     local_only: bool,
+    options: GetOptions,
 ) -> anyhow::Result<BoxStream<'a, UnixfsStatus>> {
-    let mut file = tokio::fs::File::create(dest).await?;
+    let GetOptions {
+        follow_symlinks,
+        priority,
+        progress_interval,
+        block_sources,
+        resume,
+    } = options;
+    let dest = dest.as_ref().to_path_buf();
 
     let (repo, dag, session) = match which {
         Either::Left(ipfs) => (
@@ -33,27 +91,90 @@ pub async fn get<'a, P: AsRef<Path>>(
         }
     };
 
-    let (resolved, _) = dag
-        .resolve_with_session(session, path.clone(), true, providers, local_only)
-        .await?;
+    // kept for as long as the stream so every block of the walk is wanted at the priority
+    let priority = session.map(|session| repo.prioritize_session(session, priority));
+    let sources = session
+        .zip(block_sources)
+        .map(|(session, sources)| repo.record_block_sources(session, sources));
 
-    let block = resolved.into_unixfs_block()?;
+    let block = super::symlink::resolve(
+        &dag,
+        session,
+        path.clone(),
+        providers,
+        local_only,
+        follow_symlinks,
+    )
+    .await?;
 
     let cid = block.cid();
     let root_name = block.cid().to_string();
 
-    let mut walker = Walker::new(*cid, root_name);
+    let existing = match resume {
+        true => match tokio::fs::metadata(&dest).await {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e.into()),
+        },
+        false => 0,
+    };
+
+    // a raw leaf or a directory does not declare a size
+    let root_size = IdleFileVisit::default()
+        .start(block.data())
+        .ok()
+        .map(|(_, file_size, _, _)| file_size);
+
+    // only a file which could be the start of the content is continued
+    let offset = match root_size {
+        Some(file_size) if existing <= file_size => existing,
+        _ => 0,
+    };
+    // nor one which does not end like the content at its length
+    let offset = if offset > 0
+        && ends_like_content(&repo, session, providers, local_only, cid, &dest, offset).await?
+    {
+        offset
+    } else {
+        0
+    };
+
+    let mut walker = Walker::new(*cid, root_name.clone());
+
+    let mut resumed = if offset > 0 {
+        walker = walker.with_target_range(offset..u64::MAX);
+        let file = tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(&dest)
+            .await?;
+        Some(file)
+    } else {
+        None
+    };
 
     let stream = async_stream::stream! {
+        let _priority = priority;
+        let _sources = sources;
         let mut cache = None;
-        let mut total_size = None;
-        let mut written = 0;
+        // only known when getting a single file
+        let mut total_size = root_size.map(|size| size as usize);
+        let mut written = offset as usize;
+        if total_size.is_some() {
+            yield UnixfsStatus::ProgressStatus { written, total_size };
+        }
+        let mut last_progress: Option<Instant> = None;
+        // the file being written out along with its path, declared size and the bytes written to
+        // it so far
+        let mut file = None;
+        let mut file_path = dest.clone();
+        let mut file_size = 0;
+        let mut file_written = offset as usize;
         while walker.should_continue() {
             let (next, _) = walker.pending_links();
             let block = match repo.get_block_with_session(session, next, providers, local_only).await {
                 Ok(block) => block,
                 Err(e) => {
-                    yield UnixfsStatus::FailedStatus { written, total_size, error: Some(anyhow::anyhow!("{e}")) };
+                    yield UnixfsStatus::FailedStatus { written, total_size, error: Some(e) };
                     return;
                 }
             };
@@ -61,12 +182,43 @@ pub async fn get<'a, P: AsRef<Path>>(
 
             match walker.next(block_data, &mut cache) {
                 Ok(ContinuedWalk::Bucket(..)) => {}
-                Ok(ContinuedWalk::File(segment, _, _, _, size)) => {
+                Ok(ContinuedWalk::File(segment, _, path, _, size)) => {
 
                     if segment.is_first() {
-                        total_size = Some(size as usize);
-                        yield UnixfsStatus::ProgressStatus { written, total_size };
+                        let target = match target_path(&dest, &root_name, path).await {
+                            Ok(target) => target,
+                            Err(e) => {
+                                yield UnixfsStatus::FailedStatus { written, total_size, error: Some(e) };
+                                return;
+                            }
+                        };
+                        let opened = match resumed.take() {
+                            Some(resumed) => Ok(resumed),
+                            // a symlink under the same name was refused by `target_path` already
+                            None => tokio::fs::File::create(&target).await,
+                        };
+                        match opened {
+                            Ok(opened) => file = Some(opened),
+                            Err(e) => {
+                                yield UnixfsStatus::FailedStatus { written, total_size, error: Some(anyhow::anyhow!("{e}")) };
+                                return;
+                            }
+                        }
+                        // the size of a root file was reported before the walk already
+                        let announced = target == dest && total_size.is_some();
+                        if target == dest {
+                            total_size = Some(size as usize);
+                        } else {
+                            file_written = 0;
+                        }
+                        file_path = target;
+                        file_size = size as usize;
+                        last_progress = Some(Instant::now());
+                        if !announced {
+                            yield UnixfsStatus::ProgressStatus { written, total_size };
+                        }
                     }
+                    let file = file.as_mut().expect("a file is opened on its first segment");
                     // even if the largest of files can have 256 kB blocks and about the same
                     // amount of content, try to consume it in small parts not to grow the buffers
                     // too much.
@@ -82,21 +234,65 @@ pub async fn get<'a, P: AsRef<Path>>(
                             yield UnixfsStatus::FailedStatus { written, total_size, error: Some(anyhow::anyhow!("{e}")) };
                             return;
                         }
-                        if let Err(e) = file.sync_all().await {
+                        // hands the bytes over to the os without waiting for them to reach the disk
+                        if let Err(e) = file.flush().await {
                             yield UnixfsStatus::FailedStatus { written, total_size, error: Some(anyhow::anyhow!("{e}")) };
                             return;
                         }
 
                         written += n;
-                        yield UnixfsStatus::ProgressStatus { written, total_size };
+                        file_written += n;
+
+                        let due = match progress_interval {
+                            None => true,
+                            // the last segment is followed by its own update
+                            Some(_) if segment.is_last() => false,
+                            Some(interval) => last_progress.map_or(true, |last| last.elapsed() >= interval),
+                        };
+                        if due {
+                            last_progress = Some(Instant::now());
+                            yield UnixfsStatus::ProgressStatus { written, total_size };
+                        }
                     }
 
                     if segment.is_last() {
+                        if file_size != file_written {
+                            if let Err(e) = tokio::fs::remove_file(&file_path).await {
+                                warn!("failed to remove incomplete {}: {e}", file_path.display());
+                            }
+                            let error = GetError::SizeMismatch { expected: file_size, got: file_written };
+                            yield UnixfsStatus::FailedStatus { written, total_size, error: Some(error.into()) };
+                            return;
+                        }
+                        if let Err(e) = file.sync_all().await {
+                            yield UnixfsStatus::FailedStatus { written, total_size, error: Some(anyhow::anyhow!("{e}")) };
+                            return;
+                        }
                         yield UnixfsStatus::ProgressStatus { written, total_size };
                     }
                 },
-                Ok(ContinuedWalk::Directory( .. )) | Ok(ContinuedWalk::RootDirectory( .. )) => {}, //TODO
-                Ok(ContinuedWalk::Symlink( .. )) => {},
+                Ok(ContinuedWalk::Directory(_, path, _)) | Ok(ContinuedWalk::RootDirectory(_, path, _)) => {
+                    let created = match target_path(&dest, &root_name, path).await {
+                        Ok(target) => tokio::fs::create_dir_all(target).await.map_err(anyhow::Error::from),
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = created {
+                        yield UnixfsStatus::FailedStatus { written, total_size, error: Some(e) };
+                        return;
+                    }
+                    yield UnixfsStatus::ProgressStatus { written, total_size };
+                },
+                Ok(ContinuedWalk::Symlink(link, _, path, _)) => {
+                    let created = match target_path(&dest, &root_name, path).await {
+                        Ok(target) => create_symlink(link, &target).await,
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = created {
+                        yield UnixfsStatus::FailedStatus { written, total_size, error: Some(e) };
+                        return;
+                    }
+                    yield UnixfsStatus::ProgressStatus { written, total_size };
+                },
                 Err(e) => {
                     yield UnixfsStatus::FailedStatus { written, total_size, error: Some(anyhow::anyhow!("{e}")) };
                     return;
@@ -109,3 +305,627 @@ pub async fn get<'a, P: AsRef<Path>>(
 
     Ok(stream.boxed())
 }
+
+/// The number of bytes at the end of a partial download compared with the content before it is
+/// continued, as many as the default chunker puts in a leaf.
+const RESUME_CHECK_LEN: u64 = 256 * 1024;
+
+/// Returns whether the last bytes of the first `len` bytes of `dest` match those of the file
+/// `cid`, fetching the leaves holding them.
+async fn ends_like_content(
+    repo: &Repo,
+    session: Option<u64>,
+    providers: &[PeerId],
+    local_only: bool,
+    cid: &Cid,
+    dest: &Path,
+    len: u64,
+) -> anyhow::Result<bool> {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+    let start = len.saturating_sub(RESUME_CHECK_LEN);
+    let mut existing = tokio::fs::File::open(dest).await?;
+    existing.seek(std::io::SeekFrom::Start(start)).await?;
+
+    let mut walker = Walker::new(*cid, String::new()).with_target_range(start..len);
+    let mut cache = None;
+    let mut buf = Vec::new();
+    while walker.should_continue() {
+        let (next, _) = walker.pending_links();
+        let block = repo
+            .get_block_with_session(session, next, providers, local_only)
+            .await?;
+        let walked = walker
+            .next(block.data(), &mut cache)
+            .map_err(|e| anyhow::anyhow!("{e}"))?;
+        if let ContinuedWalk::File(segment, ..) = walked {
+            buf.resize(segment.as_ref().len(), 0);
+            if existing.read_exact(&mut buf).await.is_err() || buf != segment.as_ref() {
+                return Ok(false);
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// Returns where the entry at `path` of the walk is written out to under `dest`, where the root
+/// itself is written. Fails if it would be written to or through a symlink under `dest`, which
+/// an earlier entry of the same name could have created.
+async fn target_path(dest: &Path, root_name: &str, path: &Path) -> anyhow::Result<PathBuf> {
+    let relative = path.strip_prefix(root_name).unwrap_or(path);
+    // the names are not allowed to contain a separator but could still lead outside of `dest`
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        anyhow::bail!(
+            "entry {} would be written outside of the destination",
+            path.display()
+        );
+    }
+    let mut target = dest.to_path_buf();
+    for component in relative.components() {
+        target.push(component);
+        if let Ok(metadata) = tokio::fs::symlink_metadata(&target).await {
+            if metadata.file_type().is_symlink() {
+                anyhow::bail!(
+                    "entry {} would be written through the symlink {}",
+                    path.display(),
+                    target.display()
+                );
+            }
+        }
+    }
+    Ok(target)
+}
+
+async fn create_symlink(link: &[u8], path: &Path) -> anyhow::Result<()> {
+    let link = std::str::from_utf8(link)?;
+    #[cfg(unix)]
+    {
+        tokio::fs::symlink(link, path).await?;
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    {
+        anyhow::bail!(
+            "cannot create symlink {} to {link} on this platform",
+            path.display()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use either::Either;
+    use futures::StreamExt;
+    use hex_literal::hex;
+    use libipld::Cid;
+
+    use super::{GetError, GetOptions};
+    use crate::{
+        repo::Repo,
+        unixfs::{
+            tests::{put_v0, FOOBAR_BLOCKS},
+            UnixfsStatus,
+        },
+        IpfsPath,
+    };
+
+    async fn get_bytes(repo: &Repo, cid: Cid) -> Vec<u8> {
+        let dir = tempfile::TempDir::new().unwrap();
+        let dest = dir.path().join(cid.to_string());
+
+        get_into(repo, cid, &dest).await;
+
+        tokio::fs::read(dest).await.unwrap()
+    }
+
+    /// Writes out `cid` to `dest`, returning the number of progress updates.
+    async fn get_into(repo: &Repo, cid: Cid, dest: &std::path::Path) -> usize {
+        let mut stream = super::get(
+            Either::Right(repo),
+            IpfsPath::from(cid),
+            dest,
+            &[],
+            true,
+            Default::default(),
+        )
+        .await
+        .unwrap();
+
+        let mut progress = 0;
+        while let Some(status) = stream.next().await {
+            match status {
+                UnixfsStatus::FailedStatus { error, .. } => panic!("get failed: {error:?}"),
+                UnixfsStatus::CompletedStatus { .. } => break,
+                UnixfsStatus::ProgressStatus { .. } => progress += 1,
+                UnixfsStatus::DeduplicatedStatus { .. } => {}
+            }
+        }
+        progress
+    }
+
+    #[tokio::test]
+    async fn trickle_layout_matches_balanced() {
+        let repo = Repo::new_memory();
+
+        for block in FOOBAR_BLOCKS {
+            put_v0(&repo, block).await;
+        }
+        let balanced = put_v0(&repo, FOOBAR_BLOCKS[0]).await;
+        let trickle = put_v0(&repo, FOOBAR_BLOCKS[5]).await;
+
+        assert_eq!(
+            trickle.to_string(),
+            "QmWfQ48ChJUj4vWKFsUDe4646xCBmXgdmNfhjz9T7crywd"
+        );
+
+        let balanced = get_bytes(&repo, balanced).await;
+        let trickle = get_bytes(&repo, trickle).await;
+
+        assert_eq!(balanced, b"foobar\n");
+        assert_eq!(trickle, balanced);
+    }
+
+    #[tokio::test]
+    async fn root_size_is_reported_before_the_leaves_are_fetched() {
+        let repo = Repo::new_memory();
+        // the balanced and the trickle roots, without any of their leaves
+        for root in [FOOBAR_BLOCKS[0], FOOBAR_BLOCKS[5]] {
+            let cid = put_v0(&repo, root).await;
+            let dir = tempfile::TempDir::new().unwrap();
+
+            let mut stream = super::get(
+                Either::Right(&repo),
+                IpfsPath::from(cid),
+                dir.path().join(cid.to_string()),
+                &[],
+                true,
+                Default::default(),
+            )
+            .await
+            .unwrap();
+
+            match stream.next().await {
+                Some(UnixfsStatus::ProgressStatus {
+                    written: 0,
+                    total_size: Some(7),
+                }) => {}
+                other => panic!("unexpected first status: {other:?}"),
+            }
+            // the leaves are missing from the repo
+            assert!(matches!(
+                stream.next().await,
+                Some(UnixfsStatus::FailedStatus { .. })
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn nested_directories_are_written_out() {
+        let repo = Repo::new_memory();
+        for block in FOOBAR_BLOCKS {
+            put_v0(&repo, block).await;
+        }
+        // directory of the two files: QmVkvLsSEm2uJx1h5Fqukje8mMPYg393o5C2kMCkF2bBTA
+        put_v0(&repo, &hex!("12380a2212202bf7f75b76e336f34a04abd86af423b5063628ffd91e5392444078851dc31655120f666f6f6261722e62616c616e63656418dd0112370a2212207baaf5e250ba1352f97eddc95840705890dc5d3fc37084a4c1aa052abcf4ac58120e666f6f6261722e747269636b6c6518dd010a020801")).await;
+        // directory of the above directory
+        let root = put_v0(&repo, &hex!("12570a2212206e396cd762f0ab55cc48e10b3c9d5a8428fc2888f4ccda86b72d6aa9fc020cb5122e516d566b764c7353456d32754a783168354671756b6a65386d4d5059673339336f3543326b4d436b46326242544118b1040a020801")).await;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let dest = dir.path().join("root");
+        let progress = get_into(&repo, root, &dest).await;
+
+        let nested = dest.join("QmVkvLsSEm2uJx1h5Fqukje8mMPYg393o5C2kMCkF2bBTA");
+        assert!(nested.is_dir());
+        for name in ["foobar.balanced", "foobar.trickle"] {
+            assert_eq!(
+                tokio::fs::read(nested.join(name)).await.unwrap(),
+                b"foobar\n"
+            );
+        }
+        assert_eq!(std::fs::read_dir(&dest).unwrap().count(), 1);
+        assert_eq!(std::fs::read_dir(&nested).unwrap().count(), 2);
+        // the two directories were reported besides the files
+        assert!(progress >= 2);
+    }
+
+    #[tokio::test]
+    async fn directories_can_be_written_out_again() {
+        let repo = Repo::new_memory();
+        for block in &FOOBAR_BLOCKS[..5] {
+            put_v0(&repo, block).await;
+        }
+        let file = put_v0(&repo, FOOBAR_BLOCKS[0]).await;
+        let root = put_v0(&repo, &directory(&[("foobar", file)])).await;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let dest = dir.path().join("root");
+        get_into(&repo, root, &dest).await;
+        // longer than the file, so anything left over shows
+        tokio::fs::write(dest.join("foobar"), b"something else entirely")
+            .await
+            .unwrap();
+        get_into(&repo, root, &dest).await;
+
+        assert_eq!(
+            tokio::fs::read(dest.join("foobar")).await.unwrap(),
+            b"foobar\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn sharded_directories_are_written_out() {
+        let repo = Repo::new_memory();
+        // sharded directory with a single link to a directory holding a single block "foobar\n"
+        let root = put_v0(&repo, &hex!("12390a2212209b04586b8bdc01a7e0db04b8358a3717954572720f6b6803af5eec781cf73801121146416e6f6e5f736861726465645f64697218430a290805122004000000000000000000000000000000000000000000000000000000000000002822308002")).await;
+        put_v0(&repo, &hex!("122e0a22122031c3d57080d8463a3c63b2923df5a1d40ad7a73eae5a14af584213e5f504ac331206666f6f626172180f0a020801")).await;
+        put_v0(&repo, &hex!("0a0d08021207666f6f6261720a1807")).await;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let dest = dir.path().join("root");
+        get_into(&repo, root, &dest).await;
+
+        let file = dest.join("non_sharded_dir").join("foobar");
+        assert_eq!(tokio::fs::read(file).await.unwrap(), b"foobar\n");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn symlinks_are_written_out() {
+        let repo = Repo::new_memory();
+        // symlink linking to "foobar"
+        let root = put_v0(&repo, &hex!("0a0a08041206666f6f626172")).await;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let dest = dir.path().join("link");
+        get_into(&repo, root, &dest).await;
+
+        assert_eq!(
+            tokio::fs::read_link(&dest).await.unwrap(),
+            std::path::Path::new("foobar")
+        );
+    }
+
+    /// Returns a directory node linking to each of the `links` by its name.
+    fn directory(links: &[(&str, Cid)]) -> Vec<u8> {
+        let mut node = vec![];
+        for (name, cid) in links {
+            let hash = cid.hash().to_bytes();
+            let link = [
+                &[0x0a, hash.len() as u8][..],
+                &hash,
+                &[0x12, name.len() as u8],
+                name.as_bytes(),
+                &[0x18, 0],
+            ]
+            .concat();
+            node.extend_from_slice(&[0x12, link.len() as u8]);
+            node.extend_from_slice(&link);
+        }
+        node.extend_from_slice(&hex!("0a020801"));
+        node
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn entries_are_not_written_through_symlinks() {
+        let outside = tempfile::TempDir::new().unwrap();
+
+        let repo = Repo::new_memory();
+        // file with the content "pwned"
+        let file = put_v0(&repo, &hex!("0a0b0802120570776e65641805")).await;
+        let nested = put_v0(&repo, &directory(&[("pwned", file)])).await;
+
+        // a symlink out of the destination followed by a file or a directory of the same name
+        let cases = [
+            ("pwned", outside.path().join("pwned"), file),
+            ("a", outside.path().to_path_buf(), nested),
+        ];
+        for (name, target, entry) in cases {
+            let target = target.to_str().unwrap().as_bytes();
+            let symlink = [
+                &[
+                    0x0a,
+                    target.len() as u8 + 4,
+                    0x08,
+                    0x04,
+                    0x12,
+                    target.len() as u8,
+                ],
+                target,
+            ]
+            .concat();
+            let symlink = put_v0(&repo, &symlink).await;
+            let root = put_v0(&repo, &directory(&[(name, symlink), (name, entry)])).await;
+
+            let dir = tempfile::TempDir::new().unwrap();
+            let dest = dir.path().join("root");
+            let mut stream = super::get(
+                Either::Right(&repo),
+                IpfsPath::from(root),
+                &dest,
+                &[],
+                true,
+                Default::default(),
+            )
+            .await
+            .unwrap();
+
+            let mut failed = false;
+            while let Some(status) = stream.next().await {
+                match status {
+                    UnixfsStatus::FailedStatus { .. } => failed = true,
+                    UnixfsStatus::CompletedStatus { .. } => panic!("get should not complete"),
+                    _ => {}
+                }
+            }
+
+            assert!(failed);
+            assert!(std::fs::read_link(dest.join(name)).is_ok());
+            assert_eq!(std::fs::read_dir(outside.path()).unwrap().count(), 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn declared_size_mismatch_fails() {
+        let repo = Repo::new_memory();
+
+        // file with the content "fo" but a declared filesize of 5
+        let cid = put_v0(&repo, &hex!("0a0808021202666f1805")).await;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let dest = dir.path().join(cid.to_string());
+
+        let mut stream = super::get(
+            Either::Right(&repo),
+            IpfsPath::from(cid),
+            &dest,
+            &[],
+            true,
+            Default::default(),
+        )
+        .await
+        .unwrap();
+
+        let mut failure = None;
+        while let Some(status) = stream.next().await {
+            match status {
+                UnixfsStatus::FailedStatus { error, .. } => {
+                    failure = error;
+                    break;
+                }
+                UnixfsStatus::CompletedStatus { .. } => panic!("get should not complete"),
+                _ => {}
+            }
+        }
+
+        match failure.unwrap().downcast::<GetError>().unwrap() {
+            GetError::SizeMismatch { expected, got } => {
+                assert_eq!(expected, 5);
+                assert_eq!(got, 2);
+            }
+        }
+
+        assert!(!dest.exists());
+    }
+
+    /// Adds `data` to the repo in chunks of 1024 bytes.
+    async fn add_chunked(repo: &Repo, data: &[u8]) -> Cid {
+        use crate::unixfs::{add, AddOption};
+        use rust_unixfs::file::adder::Chunker;
+
+        let opt = AddOption {
+            chunk: Some(Chunker::Size(1024)),
+            ..Default::default()
+        };
+        let chunks = futures::stream::iter(vec![Ok(data.to_vec())]);
+        let mut added = add(Either::Right(repo), None, None, chunks.boxed(), Some(opt))
+            .await
+            .unwrap();
+        while let Some(status) = added.next().await {
+            if let UnixfsStatus::CompletedStatus { path, .. } = status {
+                return path.root().cid().copied().unwrap();
+            }
+        }
+        unreachable!("add finishes with either completed or failed status")
+    }
+
+    /// Resumes the get of `cid` into `dest`, returning the first progress reported.
+    async fn resume(repo: &Repo, cid: Cid, dest: &std::path::Path) -> usize {
+        let mut stream = super::get(
+            Either::Right(repo),
+            IpfsPath::from(cid),
+            dest,
+            &[],
+            true,
+            GetOptions {
+                resume: true,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let mut first = None;
+        while let Some(status) = stream.next().await {
+            match status {
+                UnixfsStatus::ProgressStatus { written, .. } => {
+                    first.get_or_insert(written);
+                }
+                UnixfsStatus::FailedStatus { error, .. } => panic!("get failed: {error:?}"),
+                UnixfsStatus::CompletedStatus { .. } => break,
+                UnixfsStatus::DeduplicatedStatus { .. } => {}
+            }
+        }
+        first.unwrap()
+    }
+
+    #[tokio::test]
+    async fn large_file_is_written_out() {
+        let repo = Repo::new_memory();
+        let data = (0..4 * 1024 * 1024)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        let cid = add_chunked(&repo, &data).await;
+
+        assert_eq!(get_bytes(&repo, cid).await, data);
+    }
+
+    #[tokio::test]
+    async fn resumed_get_continues_an_interrupted_one() {
+        let repo = Repo::new_memory();
+        let data = (0..64 * 1024).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let cid = add_chunked(&repo, &data).await;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let dest = dir.path().join(cid.to_string());
+
+        let mut stream = super::get(
+            Either::Right(&repo),
+            IpfsPath::from(cid),
+            &dest,
+            &[],
+            true,
+            Default::default(),
+        )
+        .await
+        .unwrap();
+        while let Some(status) = stream.next().await {
+            match status {
+                UnixfsStatus::ProgressStatus { written, .. } if written >= 10 * 1024 => break,
+                UnixfsStatus::ProgressStatus { .. } => {}
+                status => panic!("get should have been interrupted: {status:?}"),
+            }
+        }
+        drop(stream);
+
+        let partial = tokio::fs::metadata(&dest).await.unwrap().len() as usize;
+        assert!(partial >= 10 * 1024 && partial < data.len());
+
+        assert_eq!(resume(&repo, cid, &dest).await, partial);
+        assert_eq!(
+            tokio::fs::read(&dest).await.unwrap(),
+            get_bytes(&repo, cid).await
+        );
+    }
+
+    #[tokio::test]
+    async fn resumed_get_starts_over_unless_the_file_fits() {
+        let repo = Repo::new_memory();
+        let data = (0..8 * 1024).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let cid = add_chunked(&repo, &data).await;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let dest = dir.path().join(cid.to_string());
+
+        // a prefix ending in the middle of a leaf
+        tokio::fs::write(&dest, &data[..3000]).await.unwrap();
+        assert_eq!(resume(&repo, cid, &dest).await, 3000);
+        assert_eq!(tokio::fs::read(&dest).await.unwrap(), data);
+
+        // already complete
+        assert_eq!(resume(&repo, cid, &dest).await, data.len());
+        assert_eq!(tokio::fs::read(&dest).await.unwrap(), data);
+
+        // the same length as the start of the file but ending with other bytes
+        let mut tampered = data[..3000].to_vec();
+        tampered[2999] ^= 0xff;
+        tokio::fs::write(&dest, &tampered).await.unwrap();
+        assert_eq!(resume(&repo, cid, &dest).await, 0);
+        assert_eq!(tokio::fs::read(&dest).await.unwrap(), data);
+
+        // longer than the file so it cannot be the start of it
+        tokio::fs::write(&dest, [&data[..], b"trailing"].concat())
+            .await
+            .unwrap();
+        assert_eq!(resume(&repo, cid, &dest).await, 0);
+        assert_eq!(tokio::fs::read(&dest).await.unwrap(), data);
+    }
+
+    #[tokio::test]
+    async fn progress_updates_are_throttled() {
+        use crate::unixfs::{add, AddOption};
+        use rust_unixfs::file::adder::Chunker;
+        use std::time::{Duration, Instant};
+
+        let repo = Repo::new_memory();
+
+        // a file of 1024 chunks
+        let data = (0..1024 * 1024).map(|i| i as u8).collect::<Vec<_>>();
+        let opt = AddOption {
+            chunk: Some(Chunker::Size(1024)),
+            ..Default::default()
+        };
+        let chunks = futures::stream::iter(data.chunks(64 * 1024).map(|c| Ok(c.to_vec())));
+        let mut added = add(
+            Either::Right(&repo),
+            None,
+            Some(data.len()),
+            chunks.boxed(),
+            Some(opt),
+        )
+        .await
+        .unwrap();
+        let mut cid = None;
+        while let Some(status) = added.next().await {
+            if let UnixfsStatus::CompletedStatus { path, .. } = status {
+                cid = path.root().cid().copied();
+            }
+        }
+        let cid = cid.unwrap();
+
+        let count_progress = |interval| {
+            let repo = repo.clone();
+            async move {
+                let dir = tempfile::TempDir::new().unwrap();
+                let dest = dir.path().join(cid.to_string());
+                let started = Instant::now();
+                let mut stream = super::get(
+                    Either::Right(&repo),
+                    IpfsPath::from(cid),
+                    &dest,
+                    &[],
+                    true,
+                    GetOptions {
+                        progress_interval: interval,
+                        ..Default::default()
+                    },
+                )
+                .await
+                .unwrap();
+
+                let mut progress = 0;
+                let mut last = None;
+                while let Some(status) = stream.next().await {
+                    match status {
+                        UnixfsStatus::ProgressStatus { written, .. } => {
+                            progress += 1;
+                            last = Some(written);
+                        }
+                        UnixfsStatus::FailedStatus { error, .. } => panic!("get failed: {error:?}"),
+                        UnixfsStatus::CompletedStatus { .. } => break,
+                        UnixfsStatus::DeduplicatedStatus { .. } => {}
+                    }
+                }
+                assert_eq!(last, Some(1024 * 1024));
+                (progress, started.elapsed())
+            }
+        };
+
+        let (unthrottled, _) = count_progress(None).await;
+        assert!(unthrottled >= 1024, "{unthrottled} progress updates");
+
+        let interval = Duration::from_millis(20);
+        let (throttled, elapsed) = count_progress(Some(interval)).await;
+        // the first and the last updates, and at most one per elapsed interval in between
+        let bound = 2 + (elapsed.as_millis() / interval.as_millis()) as usize;
+        assert!(throttled >= 2);
+        assert!(
+            throttled <= bound,
+            "{throttled} progress updates over {elapsed:?}"
+        );
+        assert!(throttled < unthrottled);
+    }
+}
@@ -15,10 +15,12 @@ mod add;
 mod cat;
 mod get;
 mod ls;
+mod symlink;
 pub use add::{add, add_file, AddOption};
-pub use cat::{cat, StartingPoint, TraversalFailed};
-pub use get::get;
+pub use cat::{cat, cat_range, StartingPoint, TraversalFailed};
+pub use get::{get, GetError, GetOptions};
 pub use ls::{ls, NodeItem};
+pub use symlink::SymlinkError;
 
 use crate::{Ipfs, IpfsPath};
 
@@ -84,11 +86,43 @@ impl IpfsUnixfs {
         range: Option<Range<u64>>,
         peers: &'a [PeerId],
         local: bool,
+        follow_symlinks: bool,
     ) -> Result<impl Stream<Item = Result<Vec<u8>, TraversalFailed>> + Send + 'a, TraversalFailed>
     {
         // convert early not to worry about the lifetime of parameter
         let starting_point = starting_point.into();
-        cat(Either::Left(&self.ipfs), starting_point, range, peers, local).await
+        cat(
+            Either::Left(&self.ipfs),
+            starting_point,
+            range,
+            peers,
+            local,
+            follow_symlinks,
+        )
+        .await
+    }
+
+    /// Creates a stream of the bytes `range` of an UnixFS file, fetching only the blocks
+    /// overlapping the range. A range beyond the end of the file is clamped to it.
+    ///
+    /// To create an owned version of the stream, please use `ipfs::unixfs::cat_range` directly.
+    pub async fn cat_range<'a>(
+        &self,
+        path: IpfsPath,
+        range: Range<u64>,
+        peers: &'a [PeerId],
+        local: bool,
+        follow_symlinks: bool,
+    ) -> Result<BoxStream<'a, Result<bytes::Bytes, Error>>, Error> {
+        cat_range(
+            Either::Left(&self.ipfs),
+            path,
+            range,
+            peers,
+            local,
+            follow_symlinks,
+        )
+        .await
     }
 
     /// Add a file from either a file or stream
@@ -120,8 +154,9 @@ impl IpfsUnixfs {
         dest: P,
         peers: &'a [PeerId],
         local: bool,
+        options: GetOptions,
     ) -> Result<BoxStream<'a, UnixfsStatus>, Error> {
-        get(Either::Left(&self.ipfs), path, dest, peers, local).await
+        get(Either::Left(&self.ipfs), path, dest, peers, local, options).await
     }
 
     /// List directory contents
@@ -146,15 +181,54 @@ pub enum UnixfsStatus {
         written: usize,
         total_size: Option<usize>,
     },
+    /// Yielded by an add right before it completes.
+    DeduplicatedStatus {
+        /// Number of blocks which were already in the repo and were not written again, see
+        /// [`AddOption::skip_existing`].
+        deduplicated: usize,
+    },
     FailedStatus {
         written: usize,
         total_size: Option<usize>,
+        /// When a block could not be fetched because every peer asked declined to have it, the
+        /// error is a [`crate::BlockNotFound`].
         error: Option<anyhow::Error>,
     },
 }
 
 #[cfg(test)]
 mod tests {
+    use hex_literal::hex;
+    use libipld::{
+        multihash::{Code, MultihashDigest},
+        Cid,
+    };
+
+    use crate::{repo::Repo, Block};
+
+    /// "foobar\n" from go-ipfs 0.5 add -s size-2, the balanced root and leaves followed by the
+    /// same with --trickle
+    pub(super) const FOOBAR_BLOCKS: &[&[u8]] = &[
+        &hex!("12280a221220fef9fe1804942b35e19e145a03f9c9d5ca9c997dda0a9416f3f515a52f1b3ce11200180a12280a221220dfb94b75acb208fd4873d84872af58bd65c731770a7d4c0deeb4088e87390bfe1200180a12280a221220054497ae4e89812c83276a48e3e679013a788b7c0eb02712df15095c02d6cd2c1200180a12280a221220cc332ceb37dea7d3d7c00d1393117638d3ed963575836c6d44a24951e444cf5d120018090a0c080218072002200220022001"),
+        &hex!("0a0808021202666f1802"),
+        &hex!("0a08080212026f621802"),
+        &hex!("0a080802120261721802"),
+        &hex!("0a07080212010a1801"),
+        &hex!("12280a2212200f20a024ce0152161bc23e7234573374dfc3999143deaebf9b07b9c67318f9bd1200180a12280a221220b424253c25b5a7345fc7945732e363a12a790341b7c2d758516bbad5bbaab4461200180a12280a221220b7ab6350c604a885be9bd72d833f026b1915d11abe7e8dda5d0bca689342b7411200180a12280a221220a8a826652c2a3e93a751456e71139df086a1fedfd3bd9f232ad52ea1d813720e120018090a0c080218072002200220022001"),
+        &hex!("0a0808001202666f1802"),
+        &hex!("0a08080012026f621802"),
+        &hex!("0a080800120261721802"),
+        &hex!("0a07080012010a1801"),
+    ];
+
+    pub(super) async fn put_v0(repo: &Repo, data: &[u8]) -> Cid {
+        let cid = Cid::new_v0(Code::Sha2_256.digest(data)).unwrap();
+        repo.put_block(Block::new(cid, data.to_vec()).unwrap())
+            .await
+            .unwrap()
+            .0
+    }
+
     #[test]
     fn test_file_cid() {
         // note: old versions of `ipfs::unixfs::File` was an interface where user would provide the
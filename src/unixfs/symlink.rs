@@ -0,0 +1,207 @@
+use std::collections::HashSet;
+
+use libp2p::PeerId;
+use rust_unixfs::walk::{ContinuedWalk, Walker};
+
+use crate::{dag::IpldDag, Block, IpfsPath};
+
+use super::TraversalFailed;
+
+/// Upper bound on the symlinks followed for a single path, matching the limit used by Linux.
+const MAX_SYMLINKS: usize = 40;
+
+/// Failures which can occur while following UnixFS symlinks.
+#[derive(Debug, thiserror::Error)]
+pub enum SymlinkError {
+    /// The symlink points back to a path that was already visited.
+    #[error("symlink loop detected at {0}")]
+    Loop(IpfsPath),
+
+    /// More symlinks were followed than allowed.
+    #[error("too many levels of symlinks")]
+    TooManyLinks,
+
+    /// The symlink target is not a relative path inside of the same tree.
+    #[error("symlink target {0:?} cannot be resolved")]
+    InvalidTarget(String),
+}
+
+/// Resolves the path into an UnixFS block, optionally following the symlink the path ends at
+/// until it reaches something other than a symlink. Symlink targets are resolved relative to
+/// the directory containing the symlink.
+pub(crate) async fn resolve(
+    dag: &IpldDag,
+    session: Option<u64>,
+    mut path: IpfsPath,
+    providers: &[PeerId],
+    local_only: bool,
+    follow_symlinks: bool,
+) -> Result<Block, TraversalFailed> {
+    let mut visited = HashSet::new();
+
+    loop {
+        let (resolved, _) = dag
+            .resolve_with_session(session, path.clone(), true, providers, local_only)
+            .await
+            .map_err(TraversalFailed::Resolving)?;
+
+        let block = resolved
+            .into_unixfs_block()
+            .map_err(TraversalFailed::Path)?;
+
+        if !follow_symlinks {
+            return Ok(block);
+        }
+
+        let Some(target) = symlink_target(&block) else {
+            return Ok(block);
+        };
+
+        if visited.len() >= MAX_SYMLINKS {
+            return Err(SymlinkError::TooManyLinks.into());
+        }
+
+        if !visited.insert(path.clone()) {
+            return Err(SymlinkError::Loop(path).into());
+        }
+
+        path = join_target(&path, &target)?;
+    }
+}
+
+/// Returns the target of the symlink, or `None` if the block is not a symlink.
+fn symlink_target(block: &Block) -> Option<String> {
+    let mut walker = Walker::new(*block.cid(), String::new());
+    match walker.next(block.data(), &mut None) {
+        Ok(ContinuedWalk::Symlink(target, ..)) => Some(String::from_utf8_lossy(target).into()),
+        _ => None,
+    }
+}
+
+fn join_target(link: &IpfsPath, target: &str) -> Result<IpfsPath, SymlinkError> {
+    let invalid = || SymlinkError::InvalidTarget(target.to_owned());
+
+    if target.is_empty() || target.starts_with('/') {
+        return Err(invalid());
+    }
+
+    let mut segments = link.iter().collect::<Vec<_>>();
+    // the symlink itself needs a containing directory
+    segments.pop().ok_or_else(invalid)?;
+
+    for segment in target.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop().ok_or_else(invalid)?;
+            }
+            segment => segments.push(segment),
+        }
+    }
+
+    let mut path = IpfsPath::new(link.root().clone());
+    for segment in segments {
+        path.push_str(segment).map_err(|_| invalid())?;
+    }
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use either::Either;
+    use futures::{StreamExt, TryStreamExt};
+    use libipld::Cid;
+    use rust_unixfs::dir::builder::{BufferingTreeBuilder, TreeOptions};
+
+    use super::SymlinkError;
+    use crate::{
+        repo::Repo,
+        unixfs::{tests::put_v0, TraversalFailed},
+        IpfsPath,
+    };
+
+    async fn put_symlink(repo: &Repo, target: &str) -> Cid {
+        let mut block = Vec::new();
+        rust_unixfs::symlink::serialize_symlink_block(target, &mut block);
+        put_v0(repo, &block).await
+    }
+
+    /// Builds a directory with the given links, returning the root
+    async fn put_dir(repo: &Repo, links: &[(&str, Cid)]) -> Cid {
+        let mut tree = BufferingTreeBuilder::new(TreeOptions::default());
+        for (path, cid) in links {
+            tree.put_link(&format!("root/{path}"), *cid, 0).unwrap();
+        }
+
+        let mut root = None;
+        for node in tree.build() {
+            let node = node.unwrap();
+            root = Some(put_v0(repo, &node.block).await);
+        }
+        root.expect("tree has a root")
+    }
+
+    async fn cat_path(repo: &Repo, path: IpfsPath) -> Result<Vec<u8>, TraversalFailed> {
+        let bytes = crate::unixfs::cat(Either::Right(repo), path, None, &[], true, true)
+            .await?
+            .try_concat()
+            .await?;
+        Ok(bytes)
+    }
+
+    #[tokio::test]
+    async fn follow_symlink_to_content() {
+        let repo = Repo::new_memory();
+
+        // single block "foobar\n"
+        let file = put_v0(&repo, &hex_literal::hex!("0a0d08021207666f6f6261720a1807")).await;
+        // symlink linking to "foobar": QmNgQEdXVdLw79nH2bnxLMxnyWMaXrijfqMTiDVat3iyuz
+        let link = put_symlink(&repo, "foobar").await;
+        assert_eq!(
+            link.to_string(),
+            "QmNgQEdXVdLw79nH2bnxLMxnyWMaXrijfqMTiDVat3iyuz"
+        );
+        let up = put_symlink(&repo, "../link").await;
+
+        let root = put_dir(&repo, &[("foobar", file), ("link", link), ("sub/up", up)]).await;
+
+        let path = IpfsPath::from(root).sub_path("sub/up").unwrap();
+
+        assert_eq!(cat_path(&repo, path.clone()).await.unwrap(), b"foobar\n");
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let dest = dir.path().join("up");
+        let options = crate::unixfs::GetOptions {
+            follow_symlinks: true,
+            ..Default::default()
+        };
+        let statuses = crate::unixfs::get(Either::Right(&repo), path, &dest, &[], true, options)
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+
+        assert!(matches!(
+            statuses.last(),
+            Some(crate::unixfs::UnixfsStatus::CompletedStatus { .. })
+        ));
+        assert_eq!(tokio::fs::read(dest).await.unwrap(), b"foobar\n");
+    }
+
+    #[tokio::test]
+    async fn symlink_loop_is_detected() {
+        let repo = Repo::new_memory();
+
+        let a = put_symlink(&repo, "b").await;
+        let b = put_symlink(&repo, "./a").await;
+        let root = put_dir(&repo, &[("a", a), ("b", b)]).await;
+
+        let path = IpfsPath::from(root).sub_path("a").unwrap();
+
+        match cat_path(&repo, path).await {
+            Err(TraversalFailed::Symlink(SymlinkError::Loop(_))) => {}
+            other => panic!("expected a symlink loop, got {other:?}"),
+        }
+    }
+}
@@ -67,3 +67,262 @@ async fn bitswap_stress_test() {
         }
     }
 }
+
+#[tokio::test]
+async fn wantlist_size_is_capped() {
+    use libipld::multihash::{Code, MultihashDigest};
+    use rust_ipfs::{p2p::BitswapConfig, IpfsOptions, Node};
+
+    let provider = Node::new("provider").await;
+
+    let mut cids = Vec::new();
+    for i in 0..8u8 {
+        let data = vec![i; 16];
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+        provider
+            .put_block(Block::new(cid, data).unwrap())
+            .await
+            .unwrap();
+        cids.push(cid);
+    }
+
+    let mut opts = IpfsOptions::inmemory_with_generated_keys();
+    opts.bitswap_config = Some(BitswapConfig::default().with_max_wantlist_size(2));
+    let fetcher = Node::with_options(opts).await;
+    fetcher.connect(provider.addrs[0].clone()).await.unwrap();
+
+    let fetch = futures::future::join_all(cids.iter().map(|cid| fetcher.get_block(cid)));
+    let mut peak = 0;
+    let watch = async {
+        loop {
+            peak = peak.max(fetcher.wantlist_len().await.unwrap());
+            time::sleep(Duration::from_millis(5)).await;
+        }
+    };
+
+    let blocks = tokio::select! {
+        blocks = time::timeout(Duration::from_secs(30), fetch) => blocks.expect("timeout"),
+        _ = watch => unreachable!(),
+    };
+
+    for block in blocks {
+        block.unwrap();
+    }
+    assert!(peak <= 2, "wantlist grew to {peak}");
+}
+
+#[tokio::test]
+async fn concurrent_gets_share_the_fetch_of_a_block() {
+    use futures::StreamExt;
+    use rust_ipfs::{
+        repo::BlockSources,
+        unixfs::{GetOptions, UnixfsStatus},
+        Node,
+    };
+
+    let provider = Node::new("provider").await;
+
+    // two files sharing their first chunk
+    let mut paths = Vec::new();
+    for last in 1..=2u8 {
+        let data = [vec![7u8; 256 * 1024], vec![last]].concat();
+        let data = futures::stream::once(async move { Ok(data) }).boxed();
+        let mut statuses = provider.add_unixfs(data).await.unwrap();
+        while let Some(status) = statuses.next().await {
+            if let UnixfsStatus::CompletedStatus { path, .. } = status {
+                paths.push(path);
+            }
+        }
+    }
+
+    // the fetcher, not connected yet, only misses the shared chunk
+    let fetcher = Node::new("fetcher").await;
+    let mut shared = None;
+    for cid in provider.refs_local().await.unwrap() {
+        let block = provider.repo().get_block_now(&cid).await.unwrap().unwrap();
+        if block.data().len() >= 256 * 1024 {
+            shared = Some(cid);
+        } else {
+            fetcher.put_block(block).await.unwrap();
+        }
+    }
+    let shared = shared.expect("the files share a chunk");
+
+    let dir = tempfile::tempdir().unwrap();
+    let gets = paths
+        .into_iter()
+        .enumerate()
+        .map(|(i, path)| {
+            let ipfs = fetcher.ipfs.clone();
+            let dest = dir.path().join(i.to_string());
+            tokio::spawn(async move {
+                let sources = BlockSources::default();
+                let mut statuses = ipfs
+                    .unixfs()
+                    .get(
+                        path,
+                        dest,
+                        &[],
+                        false,
+                        GetOptions {
+                            block_sources: Some(sources.clone()),
+                            ..Default::default()
+                        },
+                    )
+                    .await
+                    .unwrap();
+                while let Some(status) = statuses.next().await {
+                    match status {
+                        UnixfsStatus::CompletedStatus { .. } => return sources.get(),
+                        UnixfsStatus::FailedStatus { error, .. } => panic!("get failed: {error:?}"),
+                        _ => {}
+                    }
+                }
+                unreachable!("get finishes with either completed or failed status")
+            })
+        })
+        .collect::<Vec<_>>();
+
+    time::timeout(Duration::from_secs(10), async {
+        while fetcher
+            .get_subscriptions()
+            .lock()
+            .get(&shared)
+            .map_or(0, Vec::len)
+            < 2
+        {
+            time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("both gets wait for the shared chunk");
+
+    fetcher.connect(provider.addrs[0].clone()).await.unwrap();
+
+    // both gets are served the chunk by the single fetch of it
+    for get in gets {
+        let sources = time::timeout(Duration::from_secs(30), get)
+            .await
+            .expect("get did not complete in time")
+            .unwrap();
+        assert_eq!(sources.get(&shared), Some(&provider.id));
+    }
+}
+
+#[tokio::test]
+async fn raw_message_reaches_the_peer_server() {
+    use libipld::multihash::{Code, MultihashDigest};
+    use rust_ipfs::p2p::{BitswapMessage, WantType};
+
+    let nodes = spawn_nodes::<2>(Topology::Line).await;
+
+    let data = b"wanted by a crafted message\n".to_vec();
+    let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+
+    async fn peer_wantlist_eventually(node: &rust_ipfs::Node, peer: libp2p::PeerId, want: &[Cid]) {
+        for _ in 0..50 {
+            if node.bitswap_wantlist(Some(peer)).await.unwrap() == want {
+                return;
+            }
+            time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("wantlist of {peer} never became {want:?}");
+    }
+
+    let mut message = BitswapMessage::new(false);
+    message.add_entry(cid, 1, WantType::Have, true);
+    nodes[0]
+        .send_bitswap_message(nodes[1].id, message)
+        .await
+        .unwrap();
+
+    peer_wantlist_eventually(&nodes[1], nodes[0].id, &[cid]).await;
+
+    let mut message = BitswapMessage::new(false);
+    message.cancel(cid);
+    nodes[0]
+        .send_bitswap_message(nodes[1].id, message)
+        .await
+        .unwrap();
+
+    peer_wantlist_eventually(&nodes[1], nodes[0].id, &[]).await;
+}
+
+#[tokio::test]
+async fn inbound_message_rate_is_capped() {
+    use libipld::multihash::{Code, MultihashDigest};
+    use rust_ipfs::{
+        p2p::{BitswapConfig, BitswapMessage, WantType},
+        IpfsOptions, Node,
+    };
+    use std::num::NonZeroU32;
+
+    let mut opts = IpfsOptions::inmemory_with_generated_keys();
+    opts.bitswap_config =
+        Some(BitswapConfig::default().with_max_inbound_message_rate(NonZeroU32::new(5).unwrap()));
+    let node = Node::with_options(opts).await;
+    let flooder = Node::new("flooder").await;
+    flooder.connect(node.addrs[0].clone()).await.unwrap();
+
+    let want = |i: u32| {
+        let data = i.to_be_bytes();
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+        let mut message = BitswapMessage::new(false);
+        message.add_entry(cid, 1, WantType::Have, false);
+        flooder.send_bitswap_message(node.id, message)
+    };
+
+    for result in futures::future::join_all((0..100).map(want)).await {
+        result.unwrap();
+    }
+
+    let mut dropped = 0;
+    for _ in 0..50 {
+        dropped = node.bitswap_dropped_messages().await.unwrap();
+        if dropped > 0 {
+            break;
+        }
+        time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(dropped > 0);
+
+    let wantlist = time::timeout(
+        Duration::from_secs(1),
+        node.bitswap_wantlist(Some(flooder.id)),
+    )
+    .await
+    .expect("node stays responsive")
+    .unwrap();
+    assert!(
+        wantlist.len() <= 5,
+        "{} wants were received",
+        wantlist.len()
+    );
+}
+
+#[tokio::test]
+async fn disabled_server_leaves_wants_unanswered() {
+    use libipld::multihash::{Code, MultihashDigest};
+
+    let nodes = spawn_nodes::<2>(Topology::Line).await;
+
+    let data = b"hello block\n".to_vec();
+    let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+    nodes[0]
+        .put_block(Block::new(cid, data).unwrap())
+        .await
+        .unwrap();
+    nodes[0].set_bitswap_server(false).await.unwrap();
+
+    let unanswered = time::timeout(Duration::from_secs(3), nodes[1].get_block(&cid)).await;
+    assert!(unanswered.is_err(), "the block was served");
+    assert!(nodes[0].bitswap_unserved_wants().await.unwrap() > 0);
+
+    nodes[0].set_bitswap_server(true).await.unwrap();
+
+    let block = time::timeout(Duration::from_secs(10), nodes[1].get_block(&cid))
+        .await
+        .expect("the block was not served after re-enabling")
+        .unwrap();
+    assert_eq!(block.cid(), &cid);
+}
@@ -47,3 +47,440 @@ async fn long_get_block() {
     nodes[0].put_block(block.clone()).await.unwrap();
     nodes[N - 1].get_block(block.cid()).await.unwrap();
 }
+
+// verify that a dag split over two providers is fetched completely
+#[tokio::test]
+async fn fetch_dag_from_two_providers() {
+    use libipld::ipld;
+
+    let nodes = spawn_nodes::<3>(Topology::Star).await;
+
+    let first = nodes[1].put_dag(ipld!("first")).await.unwrap();
+    let second = nodes[2].put_dag(ipld!("second")).await.unwrap();
+    let middle = nodes[2].put_dag(ipld!({ "second": second })).await.unwrap();
+    let root = nodes[1]
+        .put_dag(ipld!({ "first": first, "middle": middle }))
+        .await
+        .unwrap();
+
+    let report = timeout(
+        Duration::from_secs(10),
+        nodes[0].fetch_dag(root, &[nodes[1].id, nodes[2].id]),
+    )
+    .await
+    .expect("fetch_dag did not complete in time")
+    .unwrap();
+
+    assert_eq!(report.blocks, 4);
+    assert_eq!(report.fetched, 4);
+
+    for cid in [root, first, middle, second] {
+        assert!(nodes[0].repo().contains(&cid).await.unwrap());
+    }
+
+    // everything is local now
+    let report = nodes[0].fetch_dag(root, &[]).await.unwrap();
+    assert_eq!(report.blocks, 4);
+    assert_eq!(report.fetched, 0);
+}
+
+// verify that a dag split over two providers is pinned once fetched completely
+#[tokio::test]
+async fn pin_remote_dag_from_two_providers() {
+    use futures::StreamExt;
+    use libipld::ipld;
+    use rust_ipfs::PinProgress;
+
+    let nodes = spawn_nodes::<3>(Topology::Star).await;
+
+    let first = nodes[1].put_dag(ipld!("first")).await.unwrap();
+    let second = nodes[2].put_dag(ipld!("second")).await.unwrap();
+    let middle = nodes[2].put_dag(ipld!({ "second": second })).await.unwrap();
+    let root = nodes[1]
+        .put_dag(ipld!({ "first": first, "middle": middle }))
+        .await
+        .unwrap();
+
+    let progress = timeout(
+        Duration::from_secs(10),
+        nodes[0]
+            .pin_remote(root, true, &[nodes[1].id, nodes[2].id])
+            .collect::<Vec<_>>(),
+    )
+    .await
+    .expect("pin_remote did not complete in time");
+
+    assert_eq!(progress.len(), 5);
+    assert!(matches!(
+        progress.last(),
+        Some(PinProgress::Pinned {
+            blocks: 4,
+            fetched: 4
+        })
+    ));
+
+    for cid in [root, first, middle, second] {
+        assert!(nodes[0].repo().contains(&cid).await.unwrap());
+        assert!(nodes[0].is_pinned(&cid).await.unwrap());
+    }
+}
+
+// verify that a dag with a block nobody has is not pinned
+#[tokio::test]
+async fn pin_remote_fails_on_unreachable_block() {
+    use futures::StreamExt;
+    use libipld::ipld;
+    use rust_ipfs::PinProgress;
+
+    let nodes = spawn_nodes::<3>(Topology::Star).await;
+
+    let missing = *create_block().cid();
+    let root = nodes[1]
+        .put_dag(ipld!({ "missing": missing }))
+        .await
+        .unwrap();
+
+    let progress = timeout(
+        Duration::from_secs(10),
+        nodes[0]
+            .pin_remote(root, true, &[nodes[1].id, nodes[2].id])
+            .collect::<Vec<_>>(),
+    )
+    .await
+    .expect("pin_remote did not fail in time");
+
+    match progress.last() {
+        Some(PinProgress::Failed {
+            blocks: 1,
+            fetched: 1,
+            error,
+        }) => {
+            assert_eq!(
+                error.downcast_ref::<rust_ipfs::BlockNotFound>(),
+                Some(&rust_ipfs::BlockNotFound(missing))
+            );
+        }
+        other => panic!("unexpected progress: {other:?}"),
+    }
+
+    assert!(nodes[0].repo().contains(&root).await.unwrap());
+    assert!(!nodes[0].is_pinned(&root).await.unwrap());
+}
+
+// verify that a block declined by every provider fails promptly instead of timing out
+#[tokio::test]
+async fn block_not_found_when_providers_decline() {
+    let nodes = spawn_nodes::<3>(Topology::Star).await;
+    let block = create_block();
+
+    let err = timeout(
+        Duration::from_secs(5),
+        nodes[0]
+            .repo()
+            .get_block(block.cid(), &[nodes[1].id, nodes[2].id], false),
+    )
+    .await
+    .expect("get_block did not fail in time")
+    .unwrap_err();
+
+    assert_eq!(
+        err.downcast_ref::<rust_ipfs::BlockNotFound>(),
+        Some(&rust_ipfs::BlockNotFound(*block.cid()))
+    );
+}
+
+// verify that a block declined by the providers is still found through the broadcast
+#[tokio::test]
+async fn declined_block_is_broadcast_before_failing() {
+    let nodes = spawn_nodes::<3>(Topology::Star).await;
+    let block = create_block();
+
+    nodes[2].put_block(block.clone()).await.unwrap();
+    let found_block = timeout(
+        Duration::from_secs(10),
+        nodes[0]
+            .repo()
+            .get_block(block.cid(), &[nodes[1].id], false),
+    )
+    .await
+    .expect("get_block did not complete in time")
+    .unwrap();
+
+    assert_eq!(block.data(), found_block.data());
+}
+
+// verify that the preferred providers are asked for a block fetched without explicit providers,
+// and that once they declined it the block is looked for like any other
+#[tokio::test]
+async fn preferred_providers_are_asked_by_default() {
+    let nodes = spawn_nodes::<4>(Topology::Star).await;
+    let block = create_block();
+
+    nodes[0].set_preferred_providers(vec![nodes[1].id, nodes[2].id]);
+    assert_eq!(
+        nodes[0].preferred_providers(),
+        vec![nodes[1].id, nodes[2].id]
+    );
+
+    // declined by the preferred providers and found by the broadcast
+    nodes[3].put_block(block.clone()).await.unwrap();
+    let found_block = timeout(Duration::from_secs(10), nodes[0].get_block(block.cid()))
+        .await
+        .expect("get_block did not complete in time")
+        .unwrap();
+
+    assert_eq!(block.data(), found_block.data());
+
+    // declined by every peer
+    let missing = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(b"missing"));
+    let err = timeout(Duration::from_secs(10), nodes[0].get_block(&missing))
+        .await
+        .expect("get_block did not fail in time")
+        .unwrap_err();
+
+    assert_eq!(
+        err.downcast_ref::<rust_ipfs::BlockNotFound>(),
+        Some(&rust_ipfs::BlockNotFound(missing))
+    );
+}
+
+// verify that the preferred providers are asked ahead of the other providers
+#[tokio::test]
+async fn preferred_providers_are_asked_first() {
+    use futures::StreamExt;
+    use rust_ipfs::repo::BlockSources;
+    use rust_ipfs::unixfs::{AddOpt, GetOptions, UnixfsStatus};
+
+    let nodes = spawn_nodes::<3>(Topology::Star).await;
+
+    let stream = futures::stream::once(async { Ok(b"preferred".to_vec()) }).boxed();
+    let mut statuses = nodes[1]
+        .unixfs()
+        .add(AddOpt::Stream(stream), None)
+        .await
+        .unwrap();
+    let mut path = None;
+    while let Some(status) = statuses.next().await {
+        if let UnixfsStatus::CompletedStatus { path: added, .. } = status {
+            path = Some(added);
+        }
+    }
+    let path = path.expect("file was not added");
+    let root = *path.root().cid().unwrap();
+    let block = nodes[1].repo().get_block_now(&root).await.unwrap().unwrap();
+    nodes[2].put_block(block).await.unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    for preferred in [1, 2] {
+        nodes[0].set_preferred_providers(vec![nodes[preferred].id]);
+
+        let sources = BlockSources::default();
+        let mut statuses = nodes[0]
+            .unixfs()
+            .get(
+                path.clone(),
+                dir.path().join(preferred.to_string()),
+                &[],
+                false,
+                GetOptions {
+                    block_sources: Some(sources.clone()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        let sources = timeout(Duration::from_secs(10), async {
+            while let Some(status) = statuses.next().await {
+                match status {
+                    UnixfsStatus::CompletedStatus { .. } => return sources.get(),
+                    UnixfsStatus::FailedStatus { error, .. } => panic!("get failed: {error:?}"),
+                    _ => {}
+                }
+            }
+            unreachable!("get finishes with either completed or failed status")
+        })
+        .await
+        .expect("get did not complete in time");
+
+        assert_eq!(sources[&root], nodes[preferred].id);
+        nodes[0].remove_block(root).await.unwrap();
+    }
+}
+
+// verify that getting a file split over two providers attributes each block to the peer which
+// served it
+#[tokio::test]
+async fn get_reports_the_source_of_each_block() {
+    use futures::StreamExt;
+    use rust_ipfs::repo::BlockSources;
+    use rust_ipfs::unixfs::{
+        ll::file::adder::Chunker, AddOpt, AddOption, GetOptions, UnixfsStatus,
+    };
+
+    let nodes = spawn_nodes::<3>(Topology::Star).await;
+
+    let data = (0..64u8).collect::<Vec<_>>();
+    let opt = AddOption {
+        chunk: Some(Chunker::Size(16)),
+        ..Default::default()
+    };
+    let stream = futures::stream::once(async move { Ok(data) }).boxed();
+    let mut statuses = nodes[1]
+        .unixfs()
+        .add(AddOpt::Stream(stream), Some(opt))
+        .await
+        .unwrap();
+    let mut path = None;
+    while let Some(status) = statuses.next().await {
+        if let UnixfsStatus::CompletedStatus { path: added, .. } = status {
+            path = Some(added);
+        }
+    }
+    let path = path.expect("file was not added");
+    let root = *path.root().cid().unwrap();
+
+    // move every other leaf over to the second provider
+    let mut leaves = nodes[1].refs_local().await.unwrap();
+    leaves.retain(|cid| *cid != root);
+    leaves.sort();
+    assert_eq!(leaves.len(), 4);
+    let moved = leaves.iter().step_by(2).copied().collect::<Vec<_>>();
+    for cid in &moved {
+        let block = nodes[1].repo().get_block_now(cid).await.unwrap().unwrap();
+        nodes[2].put_block(block).await.unwrap();
+        nodes[1].remove_block(*cid).await.unwrap();
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    let providers = [nodes[1].id, nodes[2].id];
+    let sources = BlockSources::default();
+    let mut statuses = nodes[0]
+        .unixfs()
+        .get(
+            path,
+            dir.path().join("file"),
+            &providers,
+            false,
+            GetOptions {
+                block_sources: Some(sources.clone()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+    let sources = timeout(Duration::from_secs(10), async {
+        while let Some(status) = statuses.next().await {
+            match status {
+                UnixfsStatus::CompletedStatus { .. } => return sources.get(),
+                UnixfsStatus::FailedStatus { error, .. } => panic!("get failed: {error:?}"),
+                _ => {}
+            }
+        }
+        unreachable!("get finishes with either completed or failed status")
+    })
+    .await
+    .expect("get did not complete in time");
+
+    assert_eq!(sources.len(), 5);
+    assert_eq!(sources[&root], nodes[1].id);
+    for cid in leaves {
+        let expected = if moved.contains(&cid) {
+            nodes[2].id
+        } else {
+            nodes[1].id
+        };
+        assert_eq!(sources[&cid], expected, "wrong source for {cid}");
+    }
+}
+
+// verify that content added within the block size of the bitswap messages can be transferred
+#[tokio::test]
+async fn content_within_the_max_block_size_is_transferable() {
+    use futures::{StreamExt, TryStreamExt};
+    use rust_ipfs::{
+        p2p::BitswapConfig,
+        unixfs::{AddOpt, UnixfsStatus},
+        IpfsOptions, Node,
+    };
+
+    let config = BitswapConfig::default().with_max_buf_size(16 * 1024);
+    let mut nodes = Vec::new();
+    for _ in 0..2 {
+        let mut opts = IpfsOptions::inmemory_with_generated_keys();
+        opts.bitswap_config = Some(config.clone());
+        nodes.push(Node::with_options(opts).await);
+    }
+    nodes[0].connect(nodes[1].addrs[0].clone()).await.unwrap();
+
+    let data = (0..10_000u32)
+        .flat_map(u32::to_be_bytes)
+        .collect::<Vec<_>>();
+    let stream = futures::stream::once({
+        let data = data.clone();
+        async move { Ok(data) }
+    })
+    .boxed();
+    let mut statuses = nodes[1]
+        .unixfs()
+        .add(AddOpt::Stream(stream), None)
+        .await
+        .unwrap();
+    let mut path = None;
+    while let Some(status) = statuses.next().await {
+        if let UnixfsStatus::CompletedStatus { path: added, .. } = status {
+            path = Some(added);
+        }
+    }
+    let path = path.expect("file was not added");
+
+    let cids = nodes[1].refs_local().await.unwrap();
+    assert!(cids.len() > 3);
+    for cid in cids {
+        let block = nodes[1].repo().get_block_now(&cid).await.unwrap().unwrap();
+        assert!(block.data().len() <= config.max_block_size());
+    }
+
+    let fetched = timeout(Duration::from_secs(10), async {
+        nodes[0]
+            .unixfs()
+            .cat(path, None, &[], false, false)
+            .await
+            .unwrap()
+            .try_concat()
+            .await
+            .unwrap()
+    })
+    .await
+    .expect("cat did not complete in time");
+
+    assert_eq!(fetched, data);
+}
+
+// verify that blocks received over bitswap are only stored when their codec is allowed
+#[tokio::test]
+async fn received_blocks_with_disallowed_codecs_are_not_stored() {
+    use libipld::{cbor::DagCborCodec, ipld};
+    use rust_ipfs::{IpfsOptions, Node};
+
+    let provider = Node::new("provider").await;
+    let mut opts = IpfsOptions::inmemory_with_generated_keys();
+    opts.allowed_codecs = Some([IpldCodec::Raw.into()].into());
+    let fetcher = Node::with_options(opts).await;
+    fetcher.connect(provider.addrs[0].clone()).await.unwrap();
+
+    let block = create_block();
+    provider.put_block(block.clone()).await.unwrap();
+    let found_block = timeout(Duration::from_secs(10), fetcher.get_block(block.cid()))
+        .await
+        .expect("get_block did not complete in time")
+        .unwrap();
+    assert_eq!(block.data(), found_block.data());
+    assert!(fetcher.repo().contains(block.cid()).await.unwrap());
+
+    let block = Block::encode(DagCborCodec, Code::Sha2_256, &ipld!({ "a": 1 })).unwrap();
+    provider.put_block(block.clone()).await.unwrap();
+    let fetched = timeout(Duration::from_secs(3), fetcher.get_block(block.cid())).await;
+    assert!(!matches!(fetched, Ok(Ok(_))), "the block was returned");
+    assert!(!fetcher.repo().contains(block.cid()).await.unwrap());
+}
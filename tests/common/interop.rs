@@ -5,6 +5,40 @@ pub use common::{api_call, ForeignNode};
 #[allow(dead_code)]
 pub struct ForeignNode;
 
+/// Adds `data` as a file named `name` to an in-memory repo the same way `ipfs add` would, returning the
+/// resulting path. Comparing it to the CIDs go-ipfs produces for the same input needs no
+/// foreign node.
+#[allow(dead_code)]
+pub async fn add_bytes(
+    name: &str,
+    data: &[u8],
+    opt: rust_ipfs::unixfs::AddOption,
+) -> rust_ipfs::IpfsPath {
+    use futures::StreamExt;
+    use rust_ipfs::{repo::Repo, unixfs::UnixfsStatus};
+
+    let repo = Repo::new_memory();
+    let stream = futures::stream::iter(vec![Ok(data.to_vec())]);
+    let mut status = rust_ipfs::unixfs::add(
+        either::Either::Right(&repo),
+        Some(name.into()),
+        Some(data.len()),
+        stream.boxed(),
+        Some(opt),
+    )
+    .await
+    .unwrap();
+
+    while let Some(status) = status.next().await {
+        match status {
+            UnixfsStatus::CompletedStatus { path, .. } => return path,
+            UnixfsStatus::FailedStatus { error, .. } => panic!("adding failed: {error:?}"),
+            _ => {}
+        }
+    }
+    unreachable!("add finishes with either completed or failed status")
+}
+
 #[cfg(any(feature = "test_go_interop", feature = "test_js_interop"))]
 pub mod common {
     use libp2p::{core::PublicKey, Multiaddr, PeerId};
@@ -99,6 +99,98 @@ async fn connect_two_nodes_with_two_connections_doesnt_panic() {
     );
 }
 
+#[tokio::test]
+async fn connection_dedup_keeps_a_single_connection() {
+    use rust_ipfs::{p2p::ConnectionDedupPolicy, IpfsOptions};
+
+    let node_a = Node::new("a").await;
+
+    let mut opts = IpfsOptions::inmemory_with_generated_keys();
+    opts.connection_dedup = ConnectionDedupPolicy::KeepFirst;
+    let node_b = Node::with_options(opts).await;
+
+    node_a
+        .add_listening_address("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let addresses = node_a.listening_addresses().await.unwrap();
+    assert_eq!(addresses.len(), 2);
+
+    for mut addr in addresses.into_iter() {
+        addr.push(Protocol::P2p(node_a.id));
+
+        timeout(TIMEOUT, node_b.connect(addr))
+            .await
+            .expect("timeout")
+            .expect("should have connected");
+    }
+
+    timeout(TIMEOUT, async {
+        loop {
+            let connections = node_b.connection_info(node_a.id).await.unwrap();
+            if connections.len() == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("the redundant connection was not closed");
+
+    // the kept connection is not closed along with the redundant one
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let connections = node_b.connection_info(node_a.id).await.unwrap();
+    assert_eq!(connections.len(), 1);
+}
+
+#[tokio::test]
+async fn connection_dedup_on_both_peers_keeps_the_same_connection() {
+    use rust_ipfs::{
+        p2p::{ConnectionDedupPolicy, TransportConfig},
+        IpfsOptions,
+    };
+
+    let new_node = || async {
+        let mut opts = IpfsOptions::inmemory_with_generated_keys();
+        opts.connection_dedup = ConnectionDedupPolicy::KeepFirst;
+        // dialing from the listening ports would have both dials use the same pair of ports
+        opts.transport_configuration = Some(TransportConfig {
+            port_reuse: false,
+            ..Default::default()
+        });
+        Node::with_options(opts).await
+    };
+    let node_a = new_node().await;
+    let node_b = new_node().await;
+
+    // both dial each other at once, each of the connections being dialed by one of them
+    let (a, b) = tokio::join!(
+        timeout(TIMEOUT, node_a.connect(node_b.addrs[0].clone())),
+        timeout(TIMEOUT, node_b.connect(node_a.addrs[0].clone())),
+    );
+    a.expect("timeout").expect("should have connected");
+    b.expect("timeout").expect("should have connected");
+
+    timeout(TIMEOUT, async {
+        loop {
+            let at_a = node_a.connection_info(node_b.id).await.unwrap();
+            let at_b = node_b.connection_info(node_a.id).await.unwrap();
+            if at_a.len() == 1 && at_b.len() == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("the redundant connection was not closed");
+
+    // neither closes the connection kept by the other
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(node_a.connection_info(node_b.id).await.unwrap().len(), 1);
+    assert_eq!(node_b.connection_info(node_a.id).await.unwrap().len(), 1);
+}
+
 #[tokio::test]
 async fn connect_to_wrong_peer() {
     let a = Node::new("a").await;
@@ -125,3 +217,815 @@ async fn connect_to_wrong_peer() {
         .expect("connect timed out")
         .expect_err("connection should had failed (wrong peer id)");
 }
+
+// Make sure a subscriber that can't keep up with the swarm events doesn't stall the swarm.
+#[tokio::test]
+async fn slow_swarm_event_subscriber_drops_events() {
+    use futures::StreamExt;
+    use rust_ipfs::{
+        p2p::{swarm_event_buffer, DropPolicy},
+        UninitializedIpfsNoop,
+    };
+
+    let (tx, mut rx) = swarm_event_buffer(1, DropPolicy::DropOldest);
+    let ipfs = UninitializedIpfsNoop::empty()
+        .add_listening_addr("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .swarm_event_subscriber(tx, |event| Some(format!("{event:?}")))
+        .disable_delay()
+        .start()
+        .await
+        .unwrap();
+
+    let mut nodes = Vec::new();
+    let flood = async {
+        for _ in 0..4 {
+            let node = Node::new("peer").await;
+            timeout(TIMEOUT, ipfs.connect(node.addrs[0].clone()))
+                .await
+                .expect("swarm kept running")
+                .unwrap();
+            nodes.push(node);
+        }
+    };
+
+    let slow_subscriber = async {
+        while rx.next().await.is_some() {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    };
+
+    tokio::select! {
+        _ = flood => {}
+        _ = slow_subscriber => unreachable!("the buffer outlives the node"),
+    }
+
+    assert_eq!(ipfs.connected().await.unwrap().len(), nodes.len());
+    assert!(rx.dropped() > 0);
+}
+
+// Make sure a connected peer learns about a new external address without waiting for the
+// identify interval.
+#[tokio::test]
+async fn identify_pushed_on_external_address_change() {
+    use rust_ipfs::{p2p::IdentifyConfiguration, UninitializedIpfsNoop};
+
+    let ipfs = UninitializedIpfsNoop::empty()
+        .add_listening_addr("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .set_identify_configuration(IdentifyConfiguration {
+            // only the external address changes should be pushed
+            push_update: false,
+            ..Default::default()
+        })
+        .listen_as_external_addr()
+        .disable_delay()
+        .start()
+        .await
+        .unwrap();
+    let id = ipfs.keypair().unwrap().public().to_peer_id();
+
+    let peer = Node::new("peer").await;
+    timeout(TIMEOUT, ipfs.connect(peer.addrs[0].clone()))
+        .await
+        .expect("connect timed out")
+        .unwrap();
+
+    let listen_addrs = || async {
+        match peer.identity(Some(id)).await {
+            Ok(info) => info.listen_addrs,
+            Err(_) => vec![],
+        }
+    };
+
+    // wait for the initial identify exchange
+    timeout(TIMEOUT, async {
+        while listen_addrs().await.is_empty() {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("peer identified the node");
+
+    let new_addr = ipfs
+        .add_listening_address("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+
+    timeout(TIMEOUT, async {
+        while !listen_addrs().await.contains(&new_addr) {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("new external address was pushed to the peer");
+}
+
+// Make sure connections without activity are closed once the idle timeout elapses.
+#[tokio::test]
+async fn idle_connection_is_closed() {
+    use rust_ipfs::IpfsOptions;
+
+    let mut opts = IpfsOptions::inmemory_with_generated_keys();
+    opts.idle_connection_timeout = Some(Duration::from_secs(1));
+    let node_a = Node::with_options(opts).await;
+    let node_b = Node::new("b").await;
+
+    timeout(TIMEOUT, node_a.connect(node_b.addrs[0].clone()))
+        .await
+        .expect("timeout")
+        .expect("should have connected");
+    assert!(node_a.is_connected(node_b.id).await.unwrap());
+
+    timeout(TIMEOUT, async {
+        while node_a.is_connected(node_b.id).await.unwrap() {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("idle connection was not closed");
+}
+
+#[tokio::test]
+async fn only_verified_external_addresses_are_advertised() {
+    use libp2p::autonat::Config as AutonatConfig;
+    use rust_ipfs::{p2p::TransportConfig, IpfsOptions};
+
+    let autonat_config = AutonatConfig {
+        boot_delay: Duration::from_secs(1),
+        retry_interval: Duration::from_secs(1),
+        only_global_ips: false,
+        ..Default::default()
+    };
+
+    let mut opts = IpfsOptions::inmemory_with_generated_keys();
+    opts.verify_external_addresses = true;
+    opts.autonat_configuration = Some(autonat_config.clone());
+    let node_a = Node::with_options(opts).await;
+
+    // the dial back would otherwise reuse the port of the connection already established between
+    // the two nodes
+    let mut opts = IpfsOptions::inmemory_with_generated_keys();
+    opts.autonat_configuration = Some(autonat_config);
+    opts.transport_configuration = Some(TransportConfig {
+        port_reuse: false,
+        ..Default::default()
+    });
+    let node_b = Node::with_options(opts).await;
+
+    let verifiable = node_a.listening_addresses().await.unwrap()[0].clone();
+    let unverifiable: libp2p::Multiaddr = "/ip4/127.0.0.1/tcp/1".parse().unwrap();
+
+    node_a
+        .add_external_address(verifiable.clone())
+        .await
+        .unwrap();
+    node_a
+        .add_external_address(unverifiable.clone())
+        .await
+        .unwrap();
+
+    // nothing is advertised before an autonat server dialed back
+    assert!(node_a.external_addresses().await.is_err());
+
+    timeout(TIMEOUT, node_a.connect(node_b.addrs[0].clone()))
+        .await
+        .expect("timeout")
+        .expect("should have connected");
+
+    let external = timeout(Duration::from_secs(30), async {
+        loop {
+            if let Ok(addrs) = node_a.external_addresses().await {
+                break addrs;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("external address was not verified");
+
+    // the confirmed addresses carry the peer id of the node
+    let external = external
+        .into_iter()
+        .map(|mut addr| {
+            if matches!(addr.iter().last(), Some(Protocol::P2p(_))) {
+                addr.pop();
+            }
+            addr
+        })
+        .collect::<Vec<_>>();
+
+    assert!(external.contains(&verifiable));
+    assert!(!external.contains(&unverifiable));
+}
+
+// Make sure the host names of dns multiaddrs are resolved with a custom resolver.
+#[tokio::test]
+async fn connect_by_dns_addr_with_custom_resolver() {
+    use rust_ipfs::{p2p::CustomDnsResolver, IpfsOptions};
+    use std::{
+        io,
+        net::{IpAddr, Ipv4Addr},
+        sync::Arc,
+    };
+
+    #[derive(Debug)]
+    struct StubResolver;
+
+    #[async_trait::async_trait]
+    impl CustomDnsResolver for StubResolver {
+        async fn lookup_ip(&self, name: &str) -> io::Result<Vec<IpAddr>> {
+            match name {
+                "node-b.ipfs.test" => Ok(vec![Ipv4Addr::LOCALHOST.into()]),
+                _ => Err(io::ErrorKind::NotFound.into()),
+            }
+        }
+    }
+
+    let node_b = Node::new("b").await;
+    let port = node_b.addrs[0]
+        .iter()
+        .find_map(|protocol| match protocol {
+            Protocol::Tcp(port) => Some(port),
+            _ => None,
+        })
+        .unwrap();
+
+    let mut opts = IpfsOptions::inmemory_with_generated_keys();
+    opts.dns_resolver = Some(Arc::new(StubResolver));
+    let node_a = Node::with_options(opts).await;
+
+    let addr = libp2p::build_multiaddr!(Dns4("node-b.ipfs.test"), Tcp(port), P2p(node_b.id));
+
+    timeout(TIMEOUT, node_a.connect(addr))
+        .await
+        .expect("timeout")
+        .expect("should have connected");
+
+    assert!(node_a.is_connected(node_b.id).await.unwrap());
+}
+
+// Make sure a relay reservation is renewed before it expires.
+#[tokio::test]
+async fn relay_reservation_is_renewed_before_expiry() {
+    use futures::StreamExt;
+    use rust_ipfs::{p2p::RelayConfig, IpfsOptions, ListenerEvent, UninitializedIpfsNoop};
+
+    let reservation_duration = Duration::from_secs(4);
+
+    let mut opts = IpfsOptions::inmemory_with_generated_keys();
+    opts.relay_server = true;
+    opts.relay_server_config = Some(RelayConfig {
+        reservation_duration,
+        ..Default::default()
+    });
+    let relay = Node::with_options(opts).await;
+    // reservations are only accepted by a relay with a known external address
+    for addr in relay.listening_addresses().await.unwrap() {
+        relay.add_external_address(addr).await.unwrap();
+    }
+
+    let ipfs = UninitializedIpfsNoop::empty()
+        .enable_relay(false)
+        .disable_delay()
+        .start()
+        .await
+        .unwrap();
+    let mut events = ipfs.listener_events().await.unwrap();
+
+    let circuit = relay.addrs[0].clone().with(Protocol::P2pCircuit);
+    ipfs.add_listening_address(circuit).await.unwrap();
+
+    assert_eq!(
+        timeout(TIMEOUT, events.next())
+            .await
+            .expect("reservation accepted"),
+        Some(ListenerEvent::CircuitListenActive { relay: relay.id })
+    );
+    assert_eq!(
+        timeout(reservation_duration, events.next())
+            .await
+            .expect("reservation renewed before expiry"),
+        Some(ListenerEvent::ReservationRenewed { relay: relay.id })
+    );
+
+    // a lost reservation is made again
+    ipfs.disconnect(relay.id).await.unwrap();
+    loop {
+        match timeout(TIMEOUT, events.next())
+            .await
+            .expect("reservation made again")
+        {
+            Some(ListenerEvent::CircuitListenActive { relay: active }) => {
+                assert_eq!(active, relay.id);
+                break;
+            }
+            Some(ListenerEvent::CircuitListenFailed { .. }) => {}
+            event => panic!("unexpected event {event:?}"),
+        }
+    }
+}
+
+// Make sure a node behind a NAT listens through a relay it picked on its own.
+#[tokio::test]
+async fn autorelay_reserves_a_circuit_when_behind_nat() {
+    use libp2p::autonat::Config as AutonatConfig;
+    use rust_ipfs::{
+        p2p::{AutoRelayConfig, MultiaddrExt},
+        IpfsOptions, UninitializedIpfsNoop,
+    };
+
+    // the relay is also the autonat server the node asks to dial it back
+    let mut opts = IpfsOptions::inmemory_with_generated_keys();
+    opts.relay_server = true;
+    opts.autonat_configuration = Some(AutonatConfig {
+        only_global_ips: false,
+        ..Default::default()
+    });
+    let relay = Node::with_options(opts).await;
+    // reservations are only accepted by a relay with a known external address
+    for addr in relay.listening_addresses().await.unwrap() {
+        relay.add_external_address(addr).await.unwrap();
+    }
+
+    // the node does not listen on anything, so it cannot be dialed back
+    let ipfs = UninitializedIpfsNoop::empty()
+        .enable_autorelay(AutoRelayConfig::default())
+        .set_autonat_configuration(AutonatConfig {
+            boot_delay: Duration::from_millis(100),
+            retry_interval: Duration::from_secs(1),
+            only_global_ips: false,
+            ..Default::default()
+        })
+        .verify_external_addresses()
+        .disable_delay()
+        .start()
+        .await
+        .unwrap();
+
+    ipfs.connect(relay.addrs[0].clone()).await.unwrap();
+    // an address nothing listens on, which the relay fails to dial back
+    ipfs.add_external_address("/ip4/127.0.0.1/tcp/1".parse().unwrap())
+        .await
+        .unwrap();
+
+    let circuit = timeout(Duration::from_secs(20), async {
+        loop {
+            let addrs = ipfs.listening_addresses().await.unwrap();
+            if let Some(addr) = addrs.into_iter().find(|addr| addr.is_relay()) {
+                break addr;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("listening through the relay");
+
+    assert!(circuit.iter().any(|p| p == Protocol::P2p(relay.id)));
+}
+
+// Make sure the reservations with a relay and the circuits it relays are reported on both sides.
+#[tokio::test]
+async fn relay_reservations_and_circuits_are_reported() {
+    use rust_ipfs::IpfsOptions;
+
+    let mut opts = IpfsOptions::inmemory_with_generated_keys();
+    opts.relay_server = true;
+    let relay = Node::with_options(opts).await;
+    // reservations are only accepted by a relay with a known external address
+    for addr in relay.listening_addresses().await.unwrap() {
+        relay.add_external_address(addr).await.unwrap();
+    }
+
+    let mut opts = IpfsOptions::inmemory_with_generated_keys();
+    opts.relay = true;
+    let client = Node::with_options(opts.clone()).await;
+    let dialer = Node::with_options(opts).await;
+
+    // the node relays nothing itself, and the relay holds no reservations with other relays
+    client.relayed_circuits().await.unwrap_err();
+    relay.relay_reservations().await.unwrap_err();
+
+    let circuit = relay.addrs[0].clone().with(Protocol::P2pCircuit);
+    client.add_listening_address(circuit.clone()).await.unwrap();
+
+    let reservations = client.relay_reservations().await.unwrap();
+    assert_eq!(reservations.len(), 1);
+    assert_eq!(reservations[0].relay, relay.id);
+    assert!(relay.relayed_circuits().await.unwrap().is_empty());
+
+    timeout(
+        TIMEOUT,
+        dialer.connect(circuit.with(Protocol::P2p(client.id))),
+    )
+    .await
+    .expect("timeout")
+    .expect("connected through the relay");
+
+    let circuits = relay.relayed_circuits().await.unwrap();
+    assert_eq!(circuits.len(), 1);
+    assert_eq!(circuits[0].src, dialer.id);
+    assert_eq!(circuits[0].dst, client.id);
+    assert!(circuits[0].expires_at > circuits[0].established_at);
+
+    // the circuit is no longer reported once closed
+    dialer.disconnect(client.id).await.unwrap();
+    timeout(TIMEOUT, async {
+        while !relay.relayed_circuits().await.unwrap().is_empty() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("circuit closed");
+}
+
+// Make sure listening on a relay circuit reports whether the circuit became usable.
+#[tokio::test]
+async fn circuit_listen_events_are_reported() {
+    use futures::StreamExt;
+    use rust_ipfs::{IpfsOptions, ListenerEvent};
+
+    let mut opts = IpfsOptions::inmemory_with_generated_keys();
+    opts.relay_server = true;
+    let relay = Node::with_options(opts).await;
+
+    let mut opts = IpfsOptions::inmemory_with_generated_keys();
+    opts.relay = true;
+    let client = Node::with_options(opts).await;
+    let mut events = client.listener_events().await.unwrap();
+
+    // reservations are refused by a relay without a known external address
+    let circuit = relay.addrs[0].clone().with(Protocol::P2pCircuit);
+    client
+        .add_listening_address(circuit.clone())
+        .await
+        .unwrap_err();
+    match timeout(TIMEOUT, events.next()).await.expect("timeout") {
+        Some(ListenerEvent::CircuitListenFailed { relay: failed, .. }) => {
+            assert_eq!(failed, relay.id)
+        }
+        event => panic!("unexpected event {event:?}"),
+    }
+
+    for addr in relay.listening_addresses().await.unwrap() {
+        relay.add_external_address(addr).await.unwrap();
+    }
+    client.add_listening_address(circuit).await.unwrap();
+    assert_eq!(
+        timeout(TIMEOUT, events.next()).await.expect("timeout"),
+        Some(ListenerEvent::CircuitListenActive { relay: relay.id })
+    );
+}
+
+#[tokio::test]
+async fn connect_two_nodes_without_identify() {
+    use rust_ipfs::IpfsOptions;
+
+    let mut opts = IpfsOptions::inmemory_with_generated_keys();
+    opts.disable_identify = true;
+    let node_a = Node::with_options(opts.clone()).await;
+
+    let mut opts = IpfsOptions::inmemory_with_generated_keys();
+    opts.disable_identify = true;
+    let node_b = Node::with_options(opts).await;
+
+    timeout(TIMEOUT, node_a.connect(node_b.addrs[0].clone()))
+        .await
+        .expect("timeout")
+        .expect("should have connected");
+
+    assert!(node_a.is_connected(node_b.id).await.unwrap());
+    assert!(node_b.is_connected(node_a.id).await.unwrap());
+
+    let error = node_a.identity(Some(node_b.id)).await.unwrap_err();
+    assert_eq!(error.to_string(), "identify protocol is disabled");
+    let error = node_b.identity(Some(node_a.id)).await.unwrap_err();
+    assert_eq!(error.to_string(), "identify protocol is disabled");
+}
+
+#[tokio::test]
+async fn disconnect_with_reason_is_reported() {
+    use futures::StreamExt;
+    use rust_ipfs::ConnectionEvent;
+
+    let node_a = Node::new("a").await;
+    let node_b = Node::new("b").await;
+
+    let mut events = node_a.connection_events().await.unwrap();
+
+    node_a.connect(node_b.addrs[0].clone()).await.unwrap();
+    let event = timeout(TIMEOUT, events.next()).await.expect("timeout");
+    assert_eq!(
+        event,
+        Some(ConnectionEvent::PeerConnected { peer_id: node_b.id })
+    );
+
+    node_a
+        .disconnect_with_reason(node_b.id, "no longer needed")
+        .await
+        .unwrap();
+    let event = timeout(TIMEOUT, events.next()).await.expect("timeout");
+    assert_eq!(
+        event,
+        Some(ConnectionEvent::PeerDisconnected {
+            peer_id: node_b.id,
+            cause: Some("no longer needed".into()),
+        })
+    );
+
+    node_a.connect(node_b.addrs[0].clone()).await.unwrap();
+    timeout(TIMEOUT, events.next()).await.expect("timeout");
+
+    node_a.ban_peer(node_b.id).await.unwrap();
+    let event = timeout(TIMEOUT, events.next()).await.expect("timeout");
+    assert_eq!(
+        event,
+        Some(ConnectionEvent::PeerDisconnected {
+            peer_id: node_b.id,
+            cause: Some("banned".into()),
+        })
+    );
+}
+
+#[tokio::test]
+async fn wait_connected_resolves_on_connection() {
+    let node_a = Node::new("a").await;
+    let node_b = Node::new("b").await;
+    let node_c = Node::new("c").await;
+
+    let waiting = {
+        let ipfs = node_a.ipfs.clone();
+        let peer_id = node_b.id;
+        tokio::spawn(async move { ipfs.wait_connected(peer_id, TIMEOUT).await })
+    };
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!waiting.is_finished());
+
+    let addr = node_a.addrs[0].clone();
+    let ipfs = node_b.ipfs.clone();
+    tokio::spawn(async move { ipfs.connect(addr).await.unwrap() });
+
+    timeout(TIMEOUT, waiting)
+        .await
+        .expect("timeout")
+        .unwrap()
+        .expect("should have connected");
+
+    // already connected
+    node_a
+        .wait_connected(node_b.id, Duration::ZERO)
+        .await
+        .unwrap();
+
+    node_a
+        .wait_connected(node_c.id, Duration::from_millis(100))
+        .await
+        .unwrap_err();
+}
+
+#[tokio::test]
+async fn connection_info_reports_the_negotiated_protocols() {
+    use rust_ipfs::p2p::{MultiPlexOption, MuxerProtocol, SecurityProtocol, TransportConfig};
+    use rust_ipfs::IpfsOptions;
+
+    let mut opts = IpfsOptions::inmemory_with_generated_keys();
+    opts.transport_configuration = Some(TransportConfig {
+        multiplex_option: MultiPlexOption::YmuxAndMplex,
+        ..Default::default()
+    });
+    let a = Node::with_options(opts).await;
+    let b = Node::new("b").await;
+
+    assert!(a.connection_info(b.id).await.unwrap().is_empty());
+
+    a.connect(b.addrs[0].clone()).await.unwrap();
+
+    for (node, peer) in [(&a, b.id), (&b, a.id)] {
+        let info = node.connection_info(peer).await.unwrap();
+        assert_eq!(info.len(), 1);
+        assert_eq!(info[0].security, Some(SecurityProtocol::Noise));
+        // yamux is preferred over mplex
+        assert_eq!(info[0].muxer, Some(MuxerProtocol::Yamux));
+    }
+}
+
+#[tokio::test]
+async fn connection_limits_are_updated_at_runtime() {
+    use rust_ipfs::p2p::{ConnectionLimits, SwarmConfig};
+    use rust_ipfs::IpfsOptions;
+
+    let limits = ConnectionLimits::default().with_max_established_incoming(Some(1));
+    let mut opts = IpfsOptions::inmemory_with_generated_keys();
+    opts.swarm_configuration = Some(SwarmConfig {
+        connection: limits,
+        ..Default::default()
+    });
+    let a = Node::with_options(opts).await;
+    let b = Node::new("b").await;
+    let c = Node::new("c").await;
+    let d = Node::new("d").await;
+
+    assert_eq!(a.connection_limits().await.unwrap(), limits);
+
+    b.connect(a.addrs[0].clone()).await.unwrap();
+    // the dialer may see the connection established before it is denied
+    let _ = c.connect(a.addrs[0].clone()).await;
+    assert!(!a.is_connected(c.id).await.unwrap());
+
+    let raised = limits.with_max_established_incoming(Some(2));
+    assert_eq!(a.update_connection_limits(raised).await.unwrap(), limits);
+    assert_eq!(a.connection_limits().await.unwrap(), raised);
+
+    d.connect(a.addrs[0].clone()).await.unwrap();
+    assert!(a.is_connected(b.id).await.unwrap());
+    assert!(a.is_connected(d.id).await.unwrap());
+}
+
+// Make sure a peer opening a burst of streams which never finish negotiating only holds up to
+// the configured number of them, leaving the node responsive.
+#[tokio::test]
+async fn inbound_stream_negotiations_are_limited() {
+    use futures::future::poll_fn;
+    use futures::{AsyncReadExt, AsyncWriteExt};
+    use libp2p::core::muxing::{StreamMuxerBox, StreamMuxerExt};
+    use libp2p::core::transport::upgrade::Version;
+    use libp2p::{identity::Keypair, noise, tcp, yamux, Transport};
+    use rust_ipfs::{p2p::SwarmConfig, IpfsOptions};
+
+    const LIMIT: usize = 4;
+    const BURST: usize = 16;
+
+    let mut opts = IpfsOptions::inmemory_with_generated_keys();
+    opts.swarm_configuration = Some(SwarmConfig {
+        max_inbound_stream: LIMIT,
+        ..Default::default()
+    });
+    let node = Node::with_options(opts).await;
+    let addr = node
+        .addrs
+        .iter()
+        .find(|addr| addr.iter().any(|p| matches!(p, Protocol::Tcp(_))))
+        .unwrap()
+        .clone();
+
+    let keypair = Keypair::generate_ed25519();
+    let mut transport = tcp::tokio::Transport::new(tcp::Config::default())
+        .upgrade(Version::V1)
+        .authenticate(noise::Config::new(&keypair).unwrap())
+        .multiplex(yamux::Config::default())
+        .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)))
+        .boxed();
+
+    let (_, mut muxer) = transport.dial(addr).unwrap().await.unwrap();
+
+    let mut streams = Vec::with_capacity(BURST);
+    while streams.len() < BURST {
+        let stream = poll_fn(|cx| {
+            let _ = muxer.poll_unpin(cx);
+            muxer.poll_outbound_unpin(cx)
+        })
+        .await
+        .unwrap();
+        streams.push(stream);
+    }
+
+    // drive the connection, declining the streams the node opens
+    tokio::spawn(async move {
+        loop {
+            let res = poll_fn(|cx| {
+                if let std::task::Poll::Ready(res) = muxer.poll_inbound_unpin(cx) {
+                    return std::task::Poll::Ready(res.map(drop));
+                }
+                muxer.poll_unpin(cx).map_ok(drop)
+            })
+            .await;
+            if res.is_err() {
+                break;
+            }
+        }
+    });
+
+    // only send the multistream-select header, never proposing a protocol
+    let header = b"\x13/multistream/1.0.0\n";
+    let answered = futures::future::join_all(streams.iter_mut().map(|stream| async move {
+        stream.write_all(header).await.ok()?;
+        stream.flush().await.ok()?;
+        let mut buf = [0u8; 20];
+        timeout(Duration::from_secs(2), stream.read_exact(&mut buf))
+            .await
+            .ok()?
+            .ok()?;
+        Some(buf)
+    }))
+    .await;
+
+    let negotiating = answered.iter().flatten().count();
+    assert!(answered.iter().flatten().all(|buf| buf == header));
+    assert_eq!(negotiating, LIMIT);
+
+    // the stalled negotiations do not hold up other peers
+    let other = Node::new("other").await;
+    timeout(TIMEOUT, other.connect(node.addrs[0].clone()))
+        .await
+        .expect("timeout")
+        .expect("should have connected");
+    timeout(TIMEOUT, other.identity(Some(node.id)))
+        .await
+        .expect("timeout")
+        .expect("should have identified");
+}
+
+#[tokio::test]
+async fn connect_fails_within_the_policy_deadline() {
+    use rust_ipfs::{IpfsOptions, OperationTimedOut, TimeoutPolicy};
+    use std::time::Instant;
+
+    let mut opts = IpfsOptions::inmemory_with_generated_keys();
+    opts.timeout_policy = TimeoutPolicy {
+        connect: Some(Duration::from_secs(1)),
+        ..Default::default()
+    };
+    let node = Node::with_options(opts).await;
+
+    // accepts the tcp connection but never negotiates anything on it
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let addr: libp2p::Multiaddr = format!("/ip4/127.0.0.1/tcp/{port}").parse().unwrap();
+
+    let started = Instant::now();
+    let error = timeout(TIMEOUT, node.connect(addr))
+        .await
+        .expect("connect was not bounded by the policy")
+        .expect_err("connected to an unreachable address");
+
+    assert!(started.elapsed() < Duration::from_secs(3));
+    assert!(error.is::<OperationTimedOut>(), "{error}");
+}
+
+#[tokio::test]
+async fn peer_discovery_reports_mdns_peers() {
+    use futures::StreamExt;
+    use rust_ipfs::{DiscoverySource, IpfsOptions};
+
+    let mut opts = IpfsOptions::inmemory_with_generated_keys();
+    opts.mdns = true;
+    let node_a = Node::with_options(opts).await;
+    let discoveries = node_a.peer_discovery().await.unwrap();
+
+    let mut opts = IpfsOptions::inmemory_with_generated_keys();
+    opts.mdns = true;
+    let node_b = Node::with_options(opts).await;
+
+    let discovered = timeout(
+        Duration::from_secs(15),
+        discoveries
+            .filter(|peer| {
+                futures::future::ready(
+                    peer.source == DiscoverySource::Mdns && peer.peer_id == node_b.id,
+                )
+            })
+            .next(),
+    )
+    .await
+    .expect("timeout")
+    .unwrap();
+
+    assert!(!discovered.addresses.is_empty());
+}
+
+#[tokio::test]
+async fn refreshed_identify_reports_the_changed_protocols() {
+    use libp2p::{kad::PROTOCOL_NAME, PeerId};
+    use rust_ipfs::DhtMode;
+
+    let node_a = Node::new("a").await;
+    let node_b = Node::new("b").await;
+
+    let error = node_b.refresh_identify(PeerId::random()).await.unwrap_err();
+    assert_eq!(error.to_string(), "peer is not connected");
+
+    node_a.dht_mode(DhtMode::Client).await.unwrap();
+
+    timeout(TIMEOUT, node_b.connect(node_a.addrs[0].clone()))
+        .await
+        .expect("timeout")
+        .expect("should have connected");
+
+    let info = timeout(TIMEOUT, node_b.identity(Some(node_a.id)))
+        .await
+        .expect("timeout")
+        .expect("should have identified");
+    assert!(!info.protocols.contains(&PROTOCOL_NAME));
+
+    node_a.dht_mode(DhtMode::Server).await.unwrap();
+
+    let info = timeout(TIMEOUT, node_b.refresh_identify(node_a.id))
+        .await
+        .expect("timeout")
+        .expect("should have refreshed");
+    assert_eq!(info.peer_id, node_a.id);
+    assert!(info.protocols.contains(&PROTOCOL_NAME));
+
+    // the cache holds the refreshed info
+    let info = node_b.identity(Some(node_a.id)).await.unwrap();
+    assert!(info.protocols.contains(&PROTOCOL_NAME));
+}
@@ -0,0 +1,67 @@
+//! Golden CIDs produced by go-ipfs for the inputs also found in the `rust-unixfs` test fixtures,
+//! guarding against changes in the chunking or encoding of the added DAGs.
+use rust_ipfs::unixfs::{ll::file::adder::Chunker, AddOption};
+
+mod common;
+use common::interop::add_bytes;
+
+const FOOBAR: &[u8] = b"foobar\n";
+
+fn chunked(size: usize) -> AddOption {
+    AddOption {
+        chunk: Some(Chunker::Size(size)),
+        ..Default::default()
+    }
+}
+
+async fn added_cid(data: &[u8], opt: AddOption) -> String {
+    let path = add_bytes("foobar", data, opt).await;
+    path.root().cid().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn single_block_file() {
+    assert_eq!(
+        added_cid(FOOBAR, AddOption::default()).await,
+        "QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL"
+    );
+}
+
+#[tokio::test]
+async fn empty_file() {
+    assert_eq!(
+        added_cid(b"", AddOption::default()).await,
+        "QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH"
+    );
+}
+
+// go-ipfs 0.5 add -s size-2
+#[tokio::test]
+async fn balanced_size_2() {
+    assert_eq!(
+        added_cid(FOOBAR, chunked(2)).await,
+        "QmRJHYTNvC3hmd9gJQARxLR1QMEincccBV53bBw524yyq6"
+    );
+}
+
+// go-ipfs 0.5 add -s size-2 of just "fo", the first leaf of the above
+#[tokio::test]
+async fn single_chunk() {
+    assert_eq!(
+        added_cid(b"fo", chunked(2)).await,
+        "QmfVyMoStzTvdnUR7Uotzh82gmL427q9z3xW5Y8fUoszi4"
+    );
+}
+
+// the directory linking "foobar" to the single block file, as created by `add -w`
+#[tokio::test]
+async fn wrapped_with_directory() {
+    let opt = AddOption {
+        wrap: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        added_cid(FOOBAR, opt).await,
+        "QmYmmkD3dGZjuozuqSzDYjU4ZyhAgc4T4P4SUgY6qjzBi8"
+    );
+}
@@ -212,3 +212,325 @@ async fn dht_get_put() {
         .iter()
         .any(|x| x.value == value));
 }
+
+/// Check that peers persisted by a previous session are used to query the DHT without bootstrapping.
+#[tokio::test]
+async fn dht_seeded_from_persisted_peers() {
+    use rust_ipfs::{repo::Repo, DhtMode, UninitializedIpfsNoop};
+
+    let server = Node::new("server").await;
+    let target = Node::new("target").await;
+    server.dht_mode(DhtMode::Server).await.unwrap();
+    target.dht_mode(DhtMode::Server).await.unwrap();
+    target.connect(server.addrs[0].clone()).await.unwrap();
+
+    let keypair = libp2p::identity::Keypair::generate_ed25519();
+    let repo = Repo::new_memory();
+
+    let start = || {
+        UninitializedIpfsNoop::empty()
+            .set_keypair(keypair.clone())
+            .set_repo(repo.clone())
+            .add_listening_addr("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+            .seed_dht_from_addressbook(true)
+            .disable_delay()
+            .start()
+    };
+
+    let ipfs = start().await.unwrap();
+    ipfs.connect(server.addrs[0].clone()).await.unwrap();
+
+    let key = format!("/dht/peers/{}", server.id);
+    timeout(Duration::from_secs(10), async {
+        while !repo.data_store().contains(key.as_bytes()).await.unwrap() {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("server persisted as a dht peer");
+
+    ipfs.exit_daemon().await;
+
+    let ipfs = start().await.unwrap();
+
+    let found_addrs = timeout(Duration::from_secs(10), ipfs.find_peer(target.id))
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(found_addrs, vec![strip_peer_id(target.addrs[0].clone())]);
+}
+
+/// Check that a node connected to a single peer learns about the peers that one knows.
+#[tokio::test]
+async fn peer_exchange_learns_neighbours() {
+    use rust_ipfs::{DhtMode, IpfsOptions};
+
+    let mut opts = IpfsOptions::inmemory_with_generated_keys();
+    opts.peer_exchange = true;
+    let a = Node::with_options(opts).await;
+    let b = Node::new("b").await;
+    let c = Node::new("c").await;
+
+    b.dht_mode(DhtMode::Server).await.unwrap();
+    c.dht_mode(DhtMode::Server).await.unwrap();
+    c.connect(b.addrs[0].clone()).await.unwrap();
+    a.connect(b.addrs[0].clone()).await.unwrap();
+
+    timeout(Duration::from_secs(10), async {
+        loop {
+            let addrs = a.addrs().await.unwrap();
+            if addrs
+                .iter()
+                .any(|(peer_id, addrs)| *peer_id == c.id && addrs.contains(&c.addrs[0]))
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("a learned the address of c through b");
+}
+
+/// Check that a bootstrap which fails for lack of peers succeeds once a peer becomes known.
+#[tokio::test]
+async fn bootstrap_with_retry_waits_for_peers() {
+    use rust_ipfs::{DhtMode, RetryPolicy};
+
+    let nodes = spawn_nodes::<2>(Topology::None).await;
+    nodes[1].dht_mode(DhtMode::Server).await.unwrap();
+
+    // no peers are known, so a single attempt fails
+    assert!(nodes[0].bootstrap().await.is_err());
+
+    let policy = RetryPolicy {
+        max_attempts: 50,
+        initial_backoff: Duration::from_millis(50),
+        max_backoff: Duration::from_millis(200),
+        multiplier: 2,
+    };
+
+    let ipfs = nodes[0].clone();
+    let bootstrap = tokio::spawn(async move { ipfs.bootstrap_with_retry(policy).await });
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(!bootstrap.is_finished());
+
+    nodes[0]
+        .add_bootstrap(nodes[1].addrs[0].clone())
+        .await
+        .unwrap();
+
+    timeout(Duration::from_secs(10), bootstrap)
+        .await
+        .expect("bootstrap completed")
+        .unwrap()
+        .expect("bootstrap succeeded");
+}
+
+/// Check that bootstrapping reports its steps before completing.
+#[tokio::test]
+async fn bootstrap_progress_is_reported() {
+    use rust_ipfs::{DhtMode, KadResult};
+
+    let nodes = spawn_nodes::<2>(Topology::None).await;
+    nodes[1].dht_mode(DhtMode::Server).await.unwrap();
+    nodes[0]
+        .add_bootstrap(nodes[1].addrs[0].clone())
+        .await
+        .unwrap();
+
+    let progress = nodes[0].bootstrap_progress().await.unwrap();
+    let results = timeout(Duration::from_secs(10), progress.collect::<Vec<_>>())
+        .await
+        .expect("bootstrap completed");
+    let results = results
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()
+        .expect("bootstrap succeeded");
+
+    assert_eq!(results.last(), Some(&KadResult::Complete));
+    let steps = &results[..results.len() - 1];
+    assert!(!steps.is_empty());
+    assert!(steps
+        .iter()
+        .all(|result| matches!(result, KadResult::Peers(_))));
+}
+
+/// Check that a provided Cid is only found by peers deriving the provider key the same way.
+#[tokio::test]
+async fn provider_key_scheme() {
+    use rust_ipfs::{p2p::ProviderKeyScheme, DhtMode, UninitializedIpfsNoop};
+
+    let server = Node::new("server").await;
+    server.dht_mode(DhtMode::Server).await.unwrap();
+
+    let start = |scheme| {
+        UninitializedIpfsNoop::empty()
+            .add_listening_addr("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+            .set_provider_key_scheme(scheme)
+            .disable_delay()
+            .start()
+    };
+
+    let provider = start(ProviderKeyScheme::CidBytes).await.unwrap();
+    let same_scheme = start(ProviderKeyScheme::CidBytes).await.unwrap();
+    let other_scheme = start(ProviderKeyScheme::Multihash).await.unwrap();
+
+    for ipfs in [&provider, &same_scheme, &other_scheme] {
+        ipfs.dht_mode(DhtMode::Server).await.unwrap();
+        ipfs.add_bootstrap(server.addrs[0].clone()).await.unwrap();
+        ipfs.bootstrap().await.unwrap();
+    }
+
+    let data = b"hello block\n".to_vec();
+    let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+    provider
+        .put_block(Block::new(cid, data).unwrap())
+        .await
+        .unwrap();
+    provider.provide(cid).await.unwrap();
+
+    let provider_id = provider.keypair().unwrap().public().to_peer_id();
+
+    let found = timeout(
+        Duration::from_secs(10),
+        same_scheme
+            .get_providers(cid)
+            .await
+            .unwrap()
+            .filter(|peer_id| futures::future::ready(*peer_id == provider_id))
+            .next(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(found, Some(provider_id));
+
+    let found = timeout(
+        Duration::from_secs(5),
+        other_scheme
+            .get_providers(cid)
+            .await
+            .unwrap()
+            .filter(|peer_id| futures::future::ready(*peer_id == provider_id))
+            .next(),
+    )
+    .await;
+    assert!(!matches!(found, Ok(Some(_))));
+}
+
+/// Check that a bounded provider lookup ends once enough providers were found.
+#[tokio::test]
+async fn get_providers_stops_at_max_results() {
+    use rust_ipfs::DhtMode;
+
+    let server = Node::new("server").await;
+    server.dht_mode(DhtMode::Server).await.unwrap();
+
+    let providers = [Node::new("a").await, Node::new("b").await];
+    let seeker = Node::new("seeker").await;
+    for node in providers.iter().chain([&seeker]) {
+        node.dht_mode(DhtMode::Server).await.unwrap();
+        node.add_bootstrap(server.addrs[0].clone()).await.unwrap();
+        node.bootstrap().await.unwrap();
+    }
+
+    let data = b"hello block\n".to_vec();
+    let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+    for node in &providers {
+        node.put_block(Block::new(cid, data.clone()).unwrap())
+            .await
+            .unwrap();
+        node.provide(cid).await.unwrap();
+    }
+
+    let found = timeout(
+        Duration::from_secs(15),
+        seeker
+            .get_providers_bounded(cid, Some(1), Some(Duration::from_secs(10)))
+            .await
+            .unwrap()
+            .collect::<Vec<_>>(),
+    )
+    .await
+    .expect("the lookup did not end");
+
+    assert_eq!(found.len(), 1);
+    assert!(providers.iter().any(|node| node.id == found[0]));
+}
+
+#[tokio::test]
+async fn peer_discovery_reports_kad_peers() {
+    use rust_ipfs::DiscoverySource;
+
+    let node_a = Node::new("a").await;
+    let node_b = Node::new("b").await;
+
+    let discoveries = node_a.peer_discovery().await.unwrap();
+    node_a.add_bootstrap(node_b.addrs[0].clone()).await.unwrap();
+
+    let discovered = timeout(
+        Duration::from_secs(5),
+        discoveries
+            .filter(|peer| futures::future::ready(peer.source == DiscoverySource::Kad))
+            .next(),
+    )
+    .await
+    .expect("timeout")
+    .unwrap();
+
+    assert_eq!(discovered.peer_id, node_b.id);
+    assert!(!discovered.addresses.is_empty());
+}
+
+#[tokio::test]
+async fn kad_routing_table_counts_the_known_peers() {
+    let nodes = [
+        Node::new("a").await,
+        Node::new("b").await,
+        Node::new("c").await,
+        Node::new("d").await,
+    ];
+
+    assert_eq!(nodes[0].kad_routing_table().await.unwrap().peers, 0);
+
+    for node in &nodes[1..] {
+        nodes[0].add_bootstrap(node.addrs[0].clone()).await.unwrap();
+    }
+
+    let table = nodes[0].kad_routing_table().await.unwrap();
+    assert_eq!(table.peers, 3);
+    assert!(!table.buckets.is_empty());
+    assert_eq!(
+        table
+            .buckets
+            .iter()
+            .map(|bucket| bucket.peers)
+            .sum::<usize>(),
+        3
+    );
+    // the distances fit a 256-bit key
+    assert!(table.buckets.iter().all(|bucket| bucket.distance < 256));
+}
+
+#[tokio::test]
+async fn provide_fails_without_kad() {
+    use rust_ipfs::UninitializedIpfsNoop;
+
+    let ipfs = UninitializedIpfsNoop::empty()
+        .disable_kad()
+        .disable_delay()
+        .start()
+        .await
+        .unwrap();
+
+    let data = b"hello block\n".to_vec();
+    let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+    ipfs.put_block(Block::new(cid, data).unwrap())
+        .await
+        .unwrap();
+
+    let err = ipfs.provide(cid).await.unwrap_err();
+    assert_eq!(err.to_string(), "kad protocol is disabled");
+}
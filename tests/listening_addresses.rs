@@ -139,3 +139,43 @@ async fn pre_configured_listening_addrs() {
         "pre-configured listening addr not found; is port 4001 available to listen on?; listening addrs: {addrs:?}"
     );
 }
+
+#[tokio::test]
+async fn listeners_are_described_by_transport() {
+    use rust_ipfs::p2p::{TransportConfig, TransportKind};
+    use rust_ipfs::{IpfsOptions, Node};
+
+    // listens on tcp by default
+    let mut opts = IpfsOptions::inmemory_with_generated_keys();
+    opts.transport_configuration = Some(TransportConfig {
+        enable_quic: true,
+        ..Default::default()
+    });
+    let node = Node::with_options(opts).await;
+
+    let tcp = node.listening_addresses().await.unwrap().remove(0);
+    let quic = node
+        .add_listening_address(libp2p::build_multiaddr!(
+            Ip4([127, 0, 0, 1]),
+            Udp(0u16),
+            QuicV1
+        ))
+        .await
+        .unwrap();
+
+    let listeners = node.listeners().await.unwrap();
+    assert_eq!(listeners.len(), 2, "{listeners:?}");
+
+    let tcp_listener = listeners
+        .iter()
+        .find(|listener| listener.transport == TransportKind::Tcp)
+        .unwrap();
+    let quic_listener = listeners
+        .iter()
+        .find(|listener| listener.transport == TransportKind::Quic)
+        .unwrap();
+
+    assert_ne!(tcp_listener.id, quic_listener.id);
+    assert_eq!(tcp_listener.addresses, vec![tcp]);
+    assert_eq!(quic_listener.addresses, vec![quic]);
+}
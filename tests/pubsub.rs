@@ -297,6 +297,91 @@ async fn publish_between_two_nodes_different_topics() {
     assert!(disappeared, "timed out before a saw b's unsubscription");
 }
 
+#[tokio::test]
+async fn subscribe_many_tags_messages_with_topic() {
+    let nodes = spawn_nodes::<2>(Topology::Line).await;
+    let node_a = &nodes[0];
+    let node_b = &nodes[1];
+
+    let topic_a = "room-a".to_owned();
+    let topic_b = "room-b".to_owned();
+
+    let mut msgs = node_a
+        .pubsub_subscribe_many(vec![topic_a.clone(), topic_b.clone()])
+        .await
+        .unwrap();
+
+    // need to wait for node b to see the subscriptions so that the messages will get through
+    let mut appeared = false;
+    for _ in 0..100usize {
+        if node_b
+            .pubsub_peers(Some(topic_a.clone()))
+            .await
+            .unwrap()
+            .contains(&node_a.id)
+            && node_b
+                .pubsub_peers(Some(topic_b.clone()))
+                .await
+                .unwrap()
+                .contains(&node_a.id)
+        {
+            appeared = true;
+            break;
+        }
+        timeout(Duration::from_millis(100), pending::<()>())
+            .await
+            .unwrap_err();
+    }
+
+    assert!(appeared, "timed out before node a appeared as pubsub peer");
+
+    node_b
+        .pubsub_publish(topic_a.clone(), b"foobar".to_vec())
+        .await
+        .unwrap();
+    node_b
+        .pubsub_publish(topic_b.clone(), b"barfoo".to_vec())
+        .await
+        .unwrap();
+
+    let mut actual = timeout(
+        Duration::from_secs(2),
+        msgs.by_ref()
+            .take(2)
+            .map(|(topic, msg)| (topic, msg.source, msg.data))
+            .collect::<Vec<_>>(),
+    )
+    .await
+    .unwrap();
+    actual.sort_unstable();
+
+    let expected = vec![
+        (topic_a.clone(), Some(node_b.id), b"foobar".to_vec()),
+        (topic_b.clone(), Some(node_b.id), b"barfoo".to_vec()),
+    ];
+    assert_eq!(actual, expected);
+
+    // removing a topic leaves the subscription of the other one in place
+    assert!(msgs.remove_topic(&topic_a));
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    assert_eq!(
+        node_a.pubsub_subscribed().await.unwrap(),
+        vec![topic_b.clone()]
+    );
+
+    node_b
+        .pubsub_publish(topic_b.clone(), b"again".to_vec())
+        .await
+        .unwrap();
+
+    let (topic, msg) = timeout(Duration::from_secs(2), msgs.next())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(topic, topic_b);
+    assert_eq!(msg.data, b"again");
+}
+
 #[cfg(any(feature = "test_go_interop", feature = "test_js_interop"))]
 #[tokio::test]
 #[ignore = "doesn't work yet"]
@@ -349,3 +434,106 @@ async fn pubsub_interop() {
         "timed out before both nodes appeared as pubsub peers"
     );
 }
+
+#[tokio::test]
+async fn pubsub_can_be_disabled() {
+    use rust_ipfs::IpfsOptions;
+
+    let mut opts = IpfsOptions::inmemory_with_generated_keys();
+    opts.disable_pubsub = true;
+    let a = Node::with_options(opts).await;
+    let b = Node::new("b").await;
+
+    a.connect(b.addrs[0].clone()).await.unwrap();
+    assert!(a.is_connected(b.id).await.unwrap());
+
+    let error = a.pubsub_subscribe("topic".into()).await.unwrap_err();
+    assert_eq!(error.to_string(), "pubsub is disabled");
+    a.pubsub_publish("topic".into(), vec![1]).await.unwrap_err();
+}
+
+#[tokio::test]
+async fn publishes_beyond_the_rate_limit_are_rejected() {
+    use rust_ipfs::p2p::{PubsubConfig, RateLimit};
+    use rust_ipfs::{IpfsOptions, PubsubPublishError};
+
+    let mut opts = IpfsOptions::inmemory_with_generated_keys();
+    opts.pubsub_config = Some(PubsubConfig {
+        publish_rate: Some(RateLimit::PerPeer {
+            limit: 3.try_into().unwrap(),
+            interval: Duration::from_secs(3600),
+        }),
+        ..Default::default()
+    });
+    let a = Node::with_options(opts).await;
+
+    let mut limited = 0;
+    for i in 0..10u8 {
+        if let Err(e) = a.pubsub_publish("topic".into(), vec![i]).await {
+            if let Some(PubsubPublishError::RateLimited { topic }) = e.downcast_ref() {
+                assert_eq!(topic, "topic");
+                limited += 1;
+            }
+        }
+    }
+    assert_eq!(limited, 7);
+}
+
+#[tokio::test]
+async fn explicit_unsubscribe_is_observed_by_peers() {
+    use rust_ipfs::PubsubEvent;
+
+    let nodes = spawn_nodes::<2>(Topology::Line).await;
+    let topic = "leaving".to_owned();
+
+    let mut events = nodes[1].pubsub_events(&topic).await.unwrap();
+
+    let a_msgs = nodes[0].pubsub_subscribe(topic.clone()).await.unwrap();
+    let mut b_msgs = nodes[1].pubsub_subscribe(topic.clone()).await.unwrap();
+    assert_eq!(a_msgs.topic(), topic);
+
+    let event = timeout(Duration::from_secs(5), events.next())
+        .await
+        .expect("timed out waiting for the subscription");
+    assert!(
+        matches!(event, Some(PubsubEvent::Subscribe { peer_id }) if peer_id == nodes[0].id),
+        "{event:?}"
+    );
+
+    // the publisher needs to see the other side as well
+    for _ in 0..100usize {
+        let peers = nodes[0].pubsub_peers(Some(topic.clone())).await.unwrap();
+        if peers.contains(&nodes[1].id) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    nodes[0]
+        .pubsub_publish(topic.clone(), b"bye".to_vec())
+        .await
+        .unwrap();
+    let msg = timeout(Duration::from_secs(5), b_msgs.next())
+        .await
+        .expect("timed out waiting for the message")
+        .unwrap();
+    assert_eq!(msg.data, b"bye");
+
+    assert!(a_msgs.unsubscribe().await.unwrap());
+    let empty: &[&str] = &[];
+    assert_eq!(nodes[0].pubsub_subscribed().await.unwrap(), empty);
+
+    let event = timeout(Duration::from_secs(5), events.next())
+        .await
+        .expect("timed out waiting for the unsubscription");
+    assert!(
+        matches!(event, Some(PubsubEvent::Unsubscribe { peer_id }) if peer_id == nodes[0].id),
+        "{event:?}"
+    );
+
+    // the explicit unsubscription does not leave a later subscription when the handle goes
+    let again = nodes[0].pubsub_subscribe(topic.clone()).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    assert_eq!(nodes[0].pubsub_subscribed().await.unwrap(), &[topic]);
+    drop(again);
+}
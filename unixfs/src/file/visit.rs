@@ -223,12 +223,13 @@ fn to_pending(
 }
 
 /// Returns true if the blocks byte offsets are interesting for our target range, false otherwise.
-/// If there is no target, all blocks are of interest.
+/// If there is no target, all blocks are of interest. A block ending where the target starts, or
+/// starting where it ends, has none of the bytes of interest.
 fn block_is_in_target_range(block: &Range<u64>, target: Option<&Range<u64>>) -> bool {
     use core::cmp::{max, min};
 
     if let Some(target) = target {
-        max(block.start, target.start) <= min(block.end, target.end)
+        max(block.start, target.start) < min(block.end, target.end)
     } else {
         true
     }
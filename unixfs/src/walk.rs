@@ -6,6 +6,7 @@ use crate::{InvalidCidInLink, Metadata, UnexpectedNodeType};
 use alloc::borrow::Cow;
 use core::convert::TryFrom;
 use core::fmt;
+use core::ops::Range;
 use either::Either;
 use libipld::Cid;
 use std::path::{Path, PathBuf};
@@ -26,6 +27,8 @@ pub struct Walker {
     // tried to recycle the names but that was consistently as fast and used more memory than just
     // cloning the strings
     should_continue: bool,
+    /// Byte range of the root file to visit, see `Walker::with_target_range`.
+    range: Option<Range<u64>>,
 }
 
 /// Converts a link of specifically a Directory (and not a link of a HAMTShard).
@@ -82,6 +85,16 @@ impl Walker {
             next,
             pending: Vec::new(),
             should_continue: true,
+            range: None,
+        }
+    }
+
+    /// Limits the walk of a root file to the given byte range, skipping the blocks outside of
+    /// it. The files found under a root directory are always walked over in full.
+    pub fn with_target_range(self, range: Range<u64>) -> Walker {
+        Walker {
+            range: Some(range),
+            ..self
         }
     }
 
@@ -127,6 +140,7 @@ impl Walker {
             next,
             pending,
             should_continue,
+            range,
         } = self;
 
         *should_continue = false;
@@ -236,8 +250,11 @@ impl Walker {
                 })
             }
             UnixFsType::Raw | UnixFsType::File => {
-                let (bytes, file_size, metadata, step) =
-                    IdleFileVisit::default().start_from_parsed(flat, cache)?;
+                let visit = match (current.is_none(), range.clone()) {
+                    (true, Some(range)) => IdleFileVisit::default().with_target_range(range),
+                    _ => IdleFileVisit::default(),
+                };
+                let (bytes, file_size, metadata, step) = visit.start_from_parsed(flat, cache)?;
                 let (cid, name, depth) = next.take().expect("validated at new and earlier");
                 let file_continues = step.is_some();
 